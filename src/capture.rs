@@ -0,0 +1,101 @@
+use crate::event_dispatcher;
+use crate::event_handler::{Bind, ControllerAction};
+use crate::loader;
+use crate::shutdown;
+use crate::types::{Event, Key, KeyState};
+
+use interception as ic;
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc;
+
+// For --capture: prints each key, mouse button and wheel notch with the bind for it, until
+// Escape. With a config to append to, asks for an action for each and adds the bind to it
+pub fn run(rx: mpsc::Receiver<Event>, append: Option<&Path>) {
+    println!("press keys, mouse buttons or the wheel to see their binds, Escape to stop");
+
+    // Only the first press is printed, not the repeats
+    let mut held = HashSet::new();
+
+    for event in rx.iter() {
+        let bind = match event {
+            Event::Keyboard(key, KeyState::Up) => {
+                held.remove(&key);
+                continue;
+            }
+
+            Event::Keyboard(key, KeyState::Down) => {
+                if !held.insert(key) {
+                    continue;
+                }
+
+                if key.code == ic::ScanCode::Esc {
+                    break;
+                }
+
+                let code = key.code as u16;
+                let e0 = if key.e0 { " with E0" } else { "" };
+                print!("{}: scancode 0x{:02X}{}, ", key, code, e0);
+
+                // Only keys sharing their scancode need the flag
+                Bind::Keyboard(key.code, Key::has_e0_twin(key.code).then_some(key.e0))
+            }
+
+            Event::MouseButton(button, KeyState::Down) => Bind::Mouse(button),
+            Event::MouseWheel(direction, _) => Bind::MouseWheel(direction),
+            Event::Exit => return,
+            _ => continue,
+        };
+
+        let snippet = match ron::to_string(&bind) {
+            Ok(snippet) => snippet.replace(',', ", "),
+            Err(error) => {
+                error!("could not write {} as RON: {}", bind, error);
+                continue;
+            }
+        };
+
+        println!("{}:", snippet);
+
+        if let Some(path) = append {
+            ask_action(path, &snippet);
+        }
+    }
+
+    shutdown::request();
+}
+
+// Reads an action for the bind from the console and adds the bind to the config at path. Input
+// reaches the console meanwhile
+fn ask_action(path: &Path, bind: &str) {
+    event_dispatcher::set_passthrough(true);
+
+    loop {
+        print!("action for {} (e.g. Button(A), nothing to skip): ", bind);
+        if io::stdout().flush().is_err() {
+            break;
+        }
+
+        let mut action = String::new();
+        if io::stdin().read_line(&mut action).is_err() || action.trim().is_empty() {
+            break;
+        }
+
+        let action = action.trim();
+        if let Err(error) = ron::from_str::<ControllerAction>(action) {
+            println!("that's not an action: {}", error);
+            continue;
+        }
+
+        match loader::append_bind::<crate::Config>(path, bind, action) {
+            Ok(()) => println!("added {}: {}", bind, action),
+            Err(error) => println!("could not add the bind: {}", error),
+        }
+
+        break;
+    }
+
+    event_dispatcher::set_passthrough(false);
+}
@@ -1,21 +1,47 @@
+use crate::shutdown;
 use crate::types::*;
 
 use interception as ic;
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
+use std::time::Duration;
+
+// Rolling value of a single wheel notch (WHEEL_DELTA)
+const WHEEL_DELTA: u16 = 120;
+
+// How often keystrokes from the event handler are sent when there is no input
+const INJECT_PERIOD: Duration = Duration::from_millis(1);
+
+// Stroke information marking keystrokes we sent ourselves, should they come back around
+const INJECTED: u32 = 0x524c_4d32;
+
+// Set by the event handler while a passthrough profile is in use, input then reaches Windows as
+// if toggled off, the toggle key aside
+static PASSTHROUGH: AtomicBool = AtomicBool::new(false);
+
+pub fn set_passthrough(passthrough: bool) {
+    PASSTHROUGH.store(passthrough, Ordering::Relaxed);
+}
 
 #[derive(Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
     toggle_key: ic::ScanCode,
+    // Swallow the Windows keys while active (they can be bound), otherwise they reach Windows
+    block_windows_key: bool,
+    // Swap the wheel directions (Up/Down and Left/Right) for natural scrolling
+    wheel_invert: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             toggle_key: ic::ScanCode::Grave,
+            block_windows_key: true,
+            wheel_invert: false,
         }
     }
 }
@@ -24,16 +50,30 @@ pub struct EventDispatcher {
     config: Config,
 
     tx: mpsc::Sender<Event>,
+    inject_rx: mpsc::Receiver<Keystroke>,
     interception: ic::Interception,
 
+    // The keyboard injected keystrokes are sent as, the last one used
+    keyboard: Option<ic::Device>,
+
     active: bool,
+    // PASSTHROUGH as of the last input
+    passthrough: bool,
+    // For --capture, the toggle key is sent on like any other
+    capture_all: bool,
 
-    key_states: HashMap<(ic::Device, ic::ScanCode), KeyState>,
+    key_states: HashMap<(ic::Device, Key), KeyState>,
     mouse_button_states: HashMap<(ic::Device, MouseButton), KeyState>,
+    // Mice that have moved, each is logged the first time so it can be bound
+    mice: HashSet<ic::Device>,
 }
 
 impl EventDispatcher {
-    pub fn new(tx: mpsc::Sender<Event>, config: Config) -> Option<Self> {
+    pub fn new(
+        tx: mpsc::Sender<Event>,
+        inject_rx: mpsc::Receiver<Keystroke>,
+        config: Config,
+    ) -> Option<Self> {
         let interception = match ic::Interception::new() {
             Some(interception) => interception,
             None => {
@@ -52,21 +92,36 @@ impl EventDispatcher {
             ic::Filter::KeyFilter(ic::KeyFilter::UP | ic::KeyFilter::DOWN),
         );
 
-        info!("toggle_key: {:?}", config.toggle_key);
+        info!(
+            "toggle_key: {:?}, block_windows_key: {}, wheel_invert: {}",
+            config.toggle_key, config.block_windows_key, config.wheel_invert
+        );
 
         Some(EventDispatcher {
             config,
 
             tx,
+            inject_rx,
             interception,
 
+            keyboard: None,
+
             active: false,
+            passthrough: false,
+            capture_all: false,
 
             key_states: HashMap::new(),
             mouse_button_states: HashMap::new(),
+            mice: HashSet::new(),
         })
     }
 
+    // Takes input from the start and sends on every key, the toggle key included
+    pub fn capture_all(&mut self) {
+        self.active = true;
+        self.capture_all = true;
+    }
+
     pub fn run(&mut self) {
         let mut strokes = [ic::Stroke::Keyboard {
             code: ic::ScanCode::Esc,
@@ -74,8 +129,22 @@ impl EventDispatcher {
             information: 0,
         }; 10];
 
-        loop {
-            let device = self.interception.wait();
+        while !shutdown::requested() {
+            let device = self.interception.wait_with_timeout(INJECT_PERIOD);
+            self.inject_keystrokes();
+
+            if ic::is_invalid(device) {
+                continue;
+            }
+
+            // Keys held down in another window are let go there before they're taken
+            let passthrough = PASSTHROUGH.load(Ordering::Relaxed);
+            if passthrough != self.passthrough {
+                self.passthrough = passthrough;
+                if self.active && !passthrough {
+                    self.release_held();
+                }
+            }
 
             let num_strokes = self.interception.receive(device, &mut strokes);
             let num_strokes = num_strokes as usize;
@@ -98,28 +167,51 @@ impl EventDispatcher {
 
     fn process_stroke(&mut self, device: ic::Device, stroke: ic::Stroke) -> bool {
         match stroke {
+            ic::Stroke::Keyboard {
+                information: INJECTED,
+                ..
+            } => true,
+
             ic::Stroke::Keyboard {
                 code,
                 state,
                 information: _,
-            } => self.process_key(device, code, state.into()),
+            } => {
+                let key = Key {
+                    code,
+                    e0: state.contains(ic::KeyState::E0),
+                };
+
+                self.keyboard = Some(device);
+                self.process_key(device, key, state.into())
+            }
 
             ic::Stroke::Mouse {
                 state,
-                flags: _,
-                rolling: _,
+                flags,
+                rolling,
                 x,
                 y,
                 information: _,
             } => {
                 self.process_mouse_state(device, state);
 
-                if !self.active {
+                if !self.capturing() {
                     return true;
                 }
 
-                if x != 0 || y != 0 {
-                    self.tx.send(Event::MouseMove(x, y)).unwrap();
+                if flags.contains(ic::MouseFlags::MOVE_ABSOLUTE) {
+                    self.send(Event::MouseMoveAbsolute(x, y));
+                } else if x != 0 || y != 0 {
+                    if self.mice.insert(device) {
+                        info!("mouse {0} moved, bind it with MouseMoveDevice({0})", device);
+                    }
+
+                    self.send(Event::MouseMove(device, x, y));
+                }
+
+                if rolling != 0 {
+                    self.process_wheel(state, rolling);
                 }
 
                 false
@@ -127,26 +219,43 @@ impl EventDispatcher {
         }
     }
 
+    // The event handler only goes away when something went wrong with it or while shutting down,
+    // either way there is no point reading more input
+    fn send(&self, event: Event) {
+        if self.tx.send(event).is_err() {
+            shutdown::request();
+        }
+    }
+
+    fn capturing(&self) -> bool {
+        self.active && !self.passthrough
+    }
+
     fn toggle_active(&mut self) {
         self.active = !self.active;
 
         if !self.active {
-            self.tx.send(Event::Reset).unwrap();
+            self.send(Event::Reset);
             return;
         }
 
-        for (&(device, code), &state) in self.key_states.iter() {
-            if code == self.config.toggle_key || state == KeyState::Up {
+        self.send(Event::Resume);
+
+        if !self.passthrough {
+            self.release_held();
+        }
+    }
+
+    // Sends Windows the release of every key and mouse button held, as they're about to be
+    // taken from it
+    fn release_held(&mut self) {
+        for (&(device, key), &state) in self.key_states.iter() {
+            if key.code == self.config.toggle_key || state == KeyState::Up {
                 continue;
             }
 
-            let stroke = [ic::Stroke::Keyboard {
-                code,
-                state: ic::KeyState::UP,
-                information: 0,
-            }];
-
-            self.interception.send(device, &stroke);
+            let stroke = key_stroke(key, KeyState::Up);
+            self.interception.send(device, &[stroke]);
         }
 
         for (&(device, button), &state) in self.mouse_button_states.iter() {
@@ -175,13 +284,22 @@ impl EventDispatcher {
         }
     }
 
-    fn process_key(&mut self, device: ic::Device, code: ic::ScanCode, state: KeyState) -> bool {
-        let changed_state = match self.key_states.insert((device, code), state) {
+    fn process_key(&mut self, device: ic::Device, key: Key, state: KeyState) -> bool {
+        // Keyboards wrap some keys in fake E0 shift presses (depending on NumLock and the real
+        // shift state), passing those on would press and release the shift binds
+        let fake_shift =
+            key.e0 && (key.code == ic::ScanCode::LeftShift || key.code == ic::ScanCode::RightShift);
+
+        if fake_shift {
+            return !self.capturing();
+        }
+
+        let changed_state = match self.key_states.insert((device, key), state) {
             Some(old_state) => state != old_state,
             None => true,
         };
 
-        if code == self.config.toggle_key {
+        if key.code == self.config.toggle_key && !self.capture_all {
             if changed_state && state == KeyState::Down {
                 self.toggle_active();
             }
@@ -189,10 +307,13 @@ impl EventDispatcher {
             return false;
         }
 
-        if self.active {
-            if changed_state {
-                self.tx.send(Event::Keyboard(code, state)).unwrap();
-            }
+        if self.capturing() && key.is_windows_key() && !self.config.block_windows_key {
+            return true;
+        }
+
+        if self.capturing() {
+            // Repeats are passed on too, the event handler decides whether to drop them
+            self.send(Event::Keyboard(key, state));
 
             false
         } else {
@@ -200,6 +321,42 @@ impl EventDispatcher {
         }
     }
 
+    fn inject_keystrokes(&mut self) {
+        for keystroke in self.inject_rx.try_iter() {
+            let device = match self.keyboard {
+                Some(device) => device,
+                None => {
+                    warn!("no keyboard seen yet to send {} as", keystroke.key);
+                    continue;
+                }
+            };
+
+            let stroke = key_stroke(keystroke.key, keystroke.state);
+            self.interception.send(device, &[stroke]);
+        }
+    }
+
+    fn process_wheel(&mut self, state: ic::MouseState, rolling: i16) {
+        let mut direction = match (state.contains(ic::MouseState::HWHEEL), rolling > 0) {
+            (false, true) => WheelDirection::Up,
+            (false, false) => WheelDirection::Down,
+            (true, true) => WheelDirection::Right,
+            (true, false) => WheelDirection::Left,
+        };
+
+        if self.config.wheel_invert {
+            direction = direction.opposite();
+        }
+
+        // High resolution wheels report fractions of a notch, count those as one
+        let notches = (rolling.unsigned_abs() / WHEEL_DELTA).max(1);
+
+        let inverted = self.config.wheel_invert;
+        for _ in 0..notches {
+            self.send(Event::MouseWheel(direction, inverted));
+        }
+    }
+
     fn process_mouse_state(&mut self, device: ic::Device, state: ic::MouseState) {
         let table = [
             (
@@ -243,9 +400,26 @@ impl EventDispatcher {
 
             self.mouse_button_states.insert((device, button), key_state);
 
-            if self.active {
-                self.tx.send(Event::MouseButton(button, key_state)).unwrap();
+            if self.capturing() {
+                self.send(Event::MouseButton(button, key_state));
             }
         }
     }
 }
+
+fn key_stroke(key: Key, state: KeyState) -> ic::Stroke {
+    let mut key_state = match state {
+        KeyState::Down => ic::KeyState::DOWN,
+        KeyState::Up => ic::KeyState::UP,
+    };
+
+    if key.e0 {
+        key_state |= ic::KeyState::E0;
+    }
+
+    ic::Stroke::Keyboard {
+        code: key.code,
+        state: key_state,
+        information: INJECTED,
+    }
+}
@@ -0,0 +1,70 @@
+use winapi::um::utilapiset::Beep;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Beep blocks for as long as it sounds, so the alert is a run of beeps this long and stops
+// within one of being switched off
+const BEEP_MS: u32 = 100;
+// How often the thread looks again while the alert is off
+const IDLE_PERIOD: Duration = Duration::from_millis(10);
+
+// Sounds the oversteer alert through the Windows Beep API, for when there's no audio device
+pub struct Beeper {
+    active: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Beeper {
+    pub fn start(frequency: f32) -> Self {
+        let active = Arc::new(AtomicBool::new(false));
+        let muted = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // The range Beep takes
+        let frequency = frequency.clamp(37.0, 32767.0) as u32;
+
+        let thread = {
+            let (active, muted, stop) = (active.clone(), muted.clone(), stop.clone());
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if active.load(Ordering::Relaxed) && !muted.load(Ordering::Relaxed) {
+                        unsafe { Beep(frequency, BEEP_MS) };
+                    } else {
+                        thread::sleep(IDLE_PERIOD);
+                    }
+                }
+            })
+        };
+
+        Beeper {
+            active,
+            muted,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn enable(&mut self, active: bool) {
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    pub fn mute(&mut self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Beeper {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use super::{Bind, BindOptions, ControllerAction};
+
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+// The maps keyed by Bind, read from a map as in RON or from a list of entries with a key for
+// formats that only have string keys, e.g. [[event_handler.binds]] tables in TOML or an array
+// of objects in JSON, where the key can also be called bind. Either way a bind given twice is an
+// error, rather than the last one quietly winning. The key and action of an entry can be written
+// as in RON in a string, like key = "Keyboard(W)", or as a table with the variant as its only key
+
+pub fn actions<'de, D>(deserializer: D) -> Result<HashMap<Bind, ControllerAction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(MapOrList::<ActionEntry, _>(PhantomData))
+}
+
+pub fn options<'de, D>(deserializer: D) -> Result<HashMap<Bind, BindOptions>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(MapOrList::<OptionsEntry, _>(PhantomData))
+}
+
+trait Entry<V> {
+    fn split(self) -> (Bind, V);
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionEntry {
+    #[serde(alias = "bind", deserialize_with = "ron_or_native")]
+    key: Bind,
+    #[serde(deserialize_with = "ron_or_native")]
+    action: ControllerAction,
+}
+
+impl Entry<ControllerAction> for ActionEntry {
+    fn split(self) -> (Bind, ControllerAction) {
+        (self.key, self.action)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OptionsEntry {
+    #[serde(alias = "bind", deserialize_with = "ron_or_native")]
+    key: Bind,
+    options: BindOptions,
+}
+
+impl Entry<BindOptions> for OptionsEntry {
+    fn split(self) -> (Bind, BindOptions) {
+        (self.key, self.options)
+    }
+}
+
+struct MapOrList<E, V>(PhantomData<(E, V)>);
+
+impl<'de, E, V> Visitor<'de> for MapOrList<E, V>
+where
+    E: Deserialize<'de> + Entry<V>,
+    V: Deserialize<'de>,
+{
+    type Value = HashMap<Bind, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of binds, or a list of entries with a key")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut binds = HashMap::new();
+        while let Some((bind, value)) = map.next_entry()? {
+            insert(&mut binds, bind, value)?;
+        }
+
+        Ok(binds)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut binds = HashMap::new();
+        while let Some(entry) = seq.next_element::<E>()? {
+            let (bind, value) = entry.split();
+            insert(&mut binds, bind, value)?;
+        }
+
+        Ok(binds)
+    }
+}
+
+fn insert<V, E: de::Error>(binds: &mut HashMap<Bind, V>, bind: Bind, value: V) -> Result<(), E> {
+    match binds.insert(bind, value) {
+        Some(_) => Err(E::custom(format!("{} is given twice", bind))),
+        None => Ok(()),
+    }
+}
+
+// TOML and JSON can only write tuple variants such as Keyboard(W) the first way
+fn ron_or_native<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    deserializer.deserialize_any(RonOrNative(PhantomData))
+}
+
+struct RonOrNative<T>(PhantomData<T>);
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for RonOrNative<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string in RON, or a table with the variant as its key")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        ron::from_str(value).map_err(|error| E::custom(format!("in \"{}\": {}", value, error)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+        T::deserialize(MapAccessDeserializer::new(map))
+    }
+}
@@ -0,0 +1,211 @@
+// The binds as a table for the log, at startup, on reloading and from a PrintBinds bind
+use super::{presets, AnalogType, Bind, BindOptions, Config, ControllerAction};
+use crate::types::Key;
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+impl Config {
+    // The binds in use (a profile's and layers' included) one to a line, then the binds each
+    // profile and each layer changes
+    pub fn bind_table(&self) -> String {
+        let mut out = match &self.preset {
+            Some(name) => format!(
+                "{} binds, preset \"{}\" then the config's own on top:\n",
+                self.binds.len(),
+                name
+            ),
+            None => format!("{} binds:\n", self.binds.len()),
+        };
+
+        self.write_binds(&self.binds, &mut out);
+
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+
+        for name in names {
+            let profile = &self.profiles[name];
+            let passthrough = match profile.passthrough {
+                true => ", passes input through",
+                false => "",
+            };
+
+            writeln!(out, "profile \"{}\"{}:", name, passthrough).unwrap();
+            self.write_binds(&self.binds_changed_by(profile), &mut out);
+        }
+
+        let mut names: Vec<&String> = self.layers.keys().collect();
+        names.sort();
+
+        for name in names {
+            writeln!(out, "layer \"{}\":", name).unwrap();
+            self.write_binds(&self.binds_changed_by(&self.layers[name]), &mut out);
+        }
+
+        out
+    }
+
+    // The binds of a profile or layer that aren't the same as these
+    fn binds_changed_by(&self, overlay: &Config) -> HashMap<Bind, ControllerAction> {
+        let changed = overlay
+            .binds
+            .iter()
+            .filter(|&(bind, action)| self.binds.get(bind) != Some(action));
+
+        changed
+            .map(|(&bind, action)| (bind, action.clone()))
+            .collect()
+    }
+
+    fn write_binds(&self, binds: &HashMap<Bind, ControllerAction>, out: &mut String) {
+        let preset = self.preset.as_deref().and_then(presets::binds);
+
+        let mut rows: Vec<(String, String, String)> = binds
+            .iter()
+            .map(|(bind, action)| {
+                let mut described = describe(action);
+                if let Some(routing) = self.routing(bind) {
+                    described += &routing;
+                }
+
+                let mut options = self.bind_options.get(bind).map(options).unwrap_or_default();
+                if preset.as_ref().and_then(|preset| preset.get(bind)) == Some(action) {
+                    options = match options.is_empty() {
+                        true => "from the preset".to_owned(),
+                        false => format!("from the preset, {}", options),
+                    };
+                }
+
+                (bind.to_string(), described, options)
+            })
+            .collect();
+
+        rows.sort();
+
+        let input_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        let action_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+
+        for (input, action, options) in rows {
+            let line = format!(
+                "    {:iw$}  {:aw$}  {}",
+                input,
+                action,
+                options,
+                iw = input_width,
+                aw = action_width
+            );
+
+            *out += line.trim_end();
+            out.push('\n');
+        }
+    }
+
+    // For mouse movement binds, the sensitivity they end up with and what flips them
+    fn routing(&self, bind: &Bind) -> Option<String> {
+        if !matches!(bind, Bind::MouseMove | Bind::MouseMoveDevice(_)) {
+            return None;
+        }
+
+        let (x, y) = self.sensitivity(bind);
+        let mut routing = match x == y {
+            true => format!(", sensitivity {}", x),
+            false => format!(", sensitivity {} x {}", x, y),
+        };
+
+        let inverted = match (self.invert_mouse_x, self.invert_mouse_y) {
+            (true, true) => Some("x and y"),
+            (true, false) => Some("x"),
+            (false, true) => Some("y"),
+            (false, false) => None,
+        };
+
+        if let Some(axes) = inverted {
+            routing += &format!(", {} inverted", axes);
+        }
+
+        if self.swap_analogs {
+            routing += ", sticks swapped";
+        }
+
+        Some(routing)
+    }
+}
+
+// What the bind is pressed with, keys by the name of the key rather than the scancode's
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Bind::Keyboard(code, Some(e0)) => write!(f, "{}", Key { code, e0 }),
+            Bind::Keyboard(code, None) => match Key::e0_name(code) {
+                Some(name) => write!(f, "{:?} or {}", code, name),
+                None => write!(f, "{:?}", code),
+            },
+
+            Bind::Modifier(modifier) => write!(f, "{:?}", modifier),
+            Bind::Mouse(button) => write!(f, "Mouse {}", button),
+            Bind::MouseWheel(direction) => write!(f, "Wheel {}", direction),
+            Bind::MouseMove => write!(f, "MouseMove"),
+            Bind::MouseMoveDevice(device) => write!(f, "MouseMove of mouse {}", device),
+        }
+    }
+}
+
+pub(super) fn describe(action: &ControllerAction) -> String {
+    let stick = |stick: AnalogType, x: f64, y: f64| format!("{} stick ({}, {})", stick, x, y);
+
+    match action {
+        ControllerAction::Button(button) => button.to_string(),
+        ControllerAction::Buttons(buttons) => {
+            let buttons: Vec<String> = buttons.iter().map(ToString::to_string).collect();
+            buttons.join(" + ")
+        }
+
+        &ControllerAction::AnalogLeft(x, y, _) => stick(AnalogType::Left, x, y),
+        &ControllerAction::AnalogRight(x, y, _) => stick(AnalogType::Right, x, y),
+        ControllerAction::OnRelease(inner) => format!("{} on release", describe(inner)),
+        ControllerAction::TapHold { tap, hold } => {
+            format!("{} on tap, {} on hold", describe(tap), describe(hold))
+        }
+        ControllerAction::DoubleTap { single, double } => {
+            format!(
+                "{} on tap, {} on double tap",
+                describe(single),
+                describe(double)
+            )
+        }
+        &ControllerAction::KeyRemap(code, e0) => format!("key {}", Key { code, e0 }),
+
+        ControllerAction::SendKeys(codes) => {
+            let keys: Vec<String> = codes.iter().map(|code| format!("{:?}", code)).collect();
+            format!("types {}", keys.join(" "))
+        }
+
+        ControllerAction::Profile(name) => format!("profile \"{}\"", name),
+        ControllerAction::Layer(name) => format!("layer \"{}\" while held", name),
+        ControllerAction::ToggleLayer(name) => format!("toggles layer \"{}\"", name),
+        action => format!("{:?}", action),
+    }
+}
+
+fn options(options: &BindOptions) -> String {
+    let mut out = Vec::new();
+
+    let durations = [
+        ("max hold", options.max_hold_ms),
+        ("tap", options.tap_threshold_ms),
+        ("double tap", options.double_tap_window_ms),
+        ("pulse", options.pulse_duration_ms),
+    ];
+
+    for (name, ms) in durations {
+        if let Some(ms) = ms {
+            out.push(format!("{} {}ms", name, ms));
+        }
+    }
+
+    if let Some(wheel_invert) = options.wheel_invert {
+        out.push(format!("wheel_invert {}", wheel_invert));
+    }
+
+    out.join(", ")
+}
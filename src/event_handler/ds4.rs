@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use vigem::*;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerType {
+    #[default]
+    Xbox360,
+    // Sent as the Xbox report translated onto the DualShock 4 layout
+    DualShock4,
+}
+
+impl ControllerType {
+    pub fn target_type(self) -> TargetType {
+        match self {
+            ControllerType::Xbox360 => TargetType::Xbox360,
+            ControllerType::DualShock4 => TargetType::DualShock4,
+        }
+    }
+}
+
+const BUTTONS: [(XButton, DS4Button); 10] = [
+    (XButton::A, DS4Button::Cross),
+    (XButton::B, DS4Button::Circle),
+    (XButton::X, DS4Button::Square),
+    (XButton::Y, DS4Button::Triangle),
+    (XButton::LeftShoulder, DS4Button::ShoulderLeft),
+    (XButton::RightShoulder, DS4Button::ShoulderRight),
+    (XButton::LeftThumb, DS4Button::ThumbLeft),
+    (XButton::RightThumb, DS4Button::ThumbRight),
+    (XButton::Start, DS4Button::Options),
+    (XButton::Back, DS4Button::Share),
+];
+
+// The DualShock 4 sticks are 0 to 255 centred on 128, y grows downwards so it is passed negated
+pub fn stick(value: i32) -> u8 {
+    ((value - i16::MIN as i32) >> 8).min(u8::MAX as i32) as u8
+}
+
+// The dpad is a hat switch in the low bits of the buttons rather than a button each
+fn dpad(buttons: XButton) -> DS4Dpad {
+    let up = buttons.contains(XButton::DpadUp);
+    let down = buttons.contains(XButton::DpadDown);
+    let left = buttons.contains(XButton::DpadLeft);
+    let right = buttons.contains(XButton::DpadRight);
+
+    // Opposite directions cancel out
+    match (up && !down, down && !up, left && !right, right && !left) {
+        (true, _, true, _) => DS4Dpad::Northwest,
+        (true, _, _, true) => DS4Dpad::Northeast,
+        (true, ..) => DS4Dpad::North,
+        (_, true, true, _) => DS4Dpad::Southwest,
+        (_, true, _, true) => DS4Dpad::Southeast,
+        (_, true, ..) => DS4Dpad::South,
+        (.., true, _) => DS4Dpad::West,
+        (.., true) => DS4Dpad::East,
+        _ => DS4Dpad::None,
+    }
+}
+
+pub fn report(xusb: &XUSBReport) -> DSReport {
+    let mut buttons = DS4Button::Nothing;
+
+    for &(xbox, ds4) in BUTTONS.iter() {
+        if xusb.w_buttons.contains(xbox) {
+            buttons |= ds4;
+        }
+    }
+
+    // The triggers have a button each as well as their travel
+    if xusb.b_left_trigger > 0 {
+        buttons |= DS4Button::TriggerLeft;
+    }
+
+    if xusb.b_right_trigger > 0 {
+        buttons |= DS4Button::TriggerRight;
+    }
+
+    // DS4Button has no flags for the hat values, but they are only passed on as bits
+    let bits = buttons.bits() | dpad(xusb.w_buttons) as u16;
+    let w_buttons = unsafe { DS4Button::from_bits_unchecked(bits) };
+
+    let b_special = match xusb.w_buttons.contains(XButton::Guide) {
+        true => SpecialButton::PS,
+        false => SpecialButton::Nothing,
+    };
+
+    DSReport {
+        b_thumb_lx: stick(xusb.s_thumb_lx as i32),
+        b_thumb_ly: stick(-(xusb.s_thumb_ly as i32)),
+        b_thumb_rx: stick(xusb.s_thumb_rx as i32),
+        b_thumb_ry: stick(-(xusb.s_thumb_ry as i32)),
+        w_buttons,
+        b_special,
+        b_trigger_l: xusb.b_left_trigger,
+        b_trigger_r: xusb.b_right_trigger,
+    }
+}
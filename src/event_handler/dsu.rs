@@ -0,0 +1,407 @@
+use super::ds4;
+use super::output::ControllerOutput;
+use super::DsuConfig;
+
+use vigem::{XButton, XUSBReport};
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const PROTOCOL_VERSION: u16 = 1001;
+
+const MESSAGE_VERSION: u32 = 0x100000;
+const MESSAGE_PORTS: u32 = 0x100001;
+const MESSAGE_DATA: u32 = 0x100002;
+
+// The protocol only has room for this many controllers
+pub const SLOTS: usize = 4;
+
+// Clients ask for pad data again every second or so, and stop getting it this long after they
+// last asked
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often the server thread looks up from the socket to see if it should stop
+const POLL_PERIOD: Duration = Duration::from_millis(100);
+
+// Client address and slot to when it last asked for that slot's data
+type Clients = HashMap<(SocketAddr, u8), Instant>;
+
+// CRC-32 as used by zlib, which the packet checksums are
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+fn packet(server_id: u32, message_type: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(20 + payload.len());
+
+    // The length counts everything after the 16 byte header, the message type included
+    packet.extend_from_slice(b"DSUS");
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    packet.extend_from_slice(&(payload.len() as u16 + 4).to_le_bytes());
+    packet.extend_from_slice(&[0; 4]);
+    packet.extend_from_slice(&server_id.to_le_bytes());
+    packet.extend_from_slice(&message_type.to_le_bytes());
+    packet.extend_from_slice(payload);
+
+    let crc = crc32(&packet);
+    packet[8..12].copy_from_slice(&crc.to_le_bytes());
+
+    packet
+}
+
+// The message type and what follows it, if data is a well formed client packet
+fn parse_request(data: &[u8]) -> Option<(u32, &[u8])> {
+    if data.len() < 20 || &data[0..4] != b"DSUC" {
+        return None;
+    }
+
+    let length = u16::from_le_bytes([data[6], data[7]]) as usize;
+    if data.len() < 16 + length {
+        return None;
+    }
+
+    let data = &data[..16 + length];
+
+    let mut unsigned = data.to_vec();
+    unsigned[8..12].fill(0);
+
+    if crc32(&unsigned).to_le_bytes() != data[8..12] {
+        return None;
+    }
+
+    let message_type = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
+    Some((message_type, &data[20..]))
+}
+
+fn mac(slot: u8) -> [u8; 6] {
+    [0, 0, 0, 0, 0, slot + 1]
+}
+
+// Slot, state, model, connection type, MAC address and battery, which starts both the port
+// info and the pad data
+fn slot_info(slot: u8, connected: bool, motion: bool) -> Vec<u8> {
+    if !connected {
+        return [slot].into_iter().chain([0; 10]).collect();
+    }
+
+    let model = match motion {
+        true => 2,
+        false => 1,
+    };
+
+    // Connected over USB with a full battery
+    let mut info = vec![slot, 2, model, 1];
+    info.extend_from_slice(&mac(slot));
+    info.push(0x05);
+
+    info
+}
+
+// What goes in a pad data packet besides the report
+struct PadState {
+    packet_number: u32,
+    timestamp: u64,
+    // Pitch, yaw and roll in degrees per second, if there is a gyro
+    gyro: Option<[f32; 3]>,
+}
+
+fn pad_data(slot: u8, state: &PadState, report: &XUSBReport) -> Vec<u8> {
+    let held = |button: XButton| report.w_buttons.contains(button);
+    let analog = |button: XButton| match held(button) {
+        true => u8::MAX,
+        false => 0,
+    };
+
+    let bits = |buttons: &[(XButton, u8)]| {
+        let held = buttons.iter().filter(|&&(button, _)| held(button));
+        held.fold(0, |bits, &(_, bit)| bits | bit)
+    };
+
+    let buttons = bits(&[
+        (XButton::DpadLeft, 0x80),
+        (XButton::DpadDown, 0x40),
+        (XButton::DpadRight, 0x20),
+        (XButton::DpadUp, 0x10),
+        (XButton::Start, 0x08),
+        (XButton::RightThumb, 0x04),
+        (XButton::LeftThumb, 0x02),
+        (XButton::Back, 0x01),
+    ]);
+
+    let mut face = bits(&[
+        (XButton::Y, 0x80),
+        (XButton::B, 0x40),
+        (XButton::A, 0x20),
+        (XButton::X, 0x10),
+        (XButton::RightShoulder, 0x08),
+        (XButton::LeftShoulder, 0x04),
+    ]);
+
+    // The triggers have a bit each as well as their travel
+    if report.b_right_trigger > 0 {
+        face |= 0x02;
+    }
+
+    if report.b_left_trigger > 0 {
+        face |= 0x01;
+    }
+
+    let mut data = slot_info(slot, true, state.gyro.is_some());
+
+    data.push(1);
+    data.extend_from_slice(&state.packet_number.to_le_bytes());
+    data.extend_from_slice(&[buttons, face, analog(XButton::Guide), 0]);
+
+    // y grows upwards here, like the Xbox report
+    data.extend_from_slice(&[
+        ds4::stick(report.s_thumb_lx as i32),
+        ds4::stick(report.s_thumb_ly as i32),
+        ds4::stick(report.s_thumb_rx as i32),
+        ds4::stick(report.s_thumb_ry as i32),
+    ]);
+
+    data.extend_from_slice(&[
+        analog(XButton::DpadLeft),
+        analog(XButton::DpadDown),
+        analog(XButton::DpadRight),
+        analog(XButton::DpadUp),
+        analog(XButton::Y),
+        analog(XButton::B),
+        analog(XButton::A),
+        analog(XButton::X),
+        analog(XButton::RightShoulder),
+        analog(XButton::LeftShoulder),
+        report.b_right_trigger,
+        report.b_left_trigger,
+    ]);
+
+    // No touch pad
+    data.extend_from_slice(&[0; 12]);
+    data.extend_from_slice(&state.timestamp.to_le_bytes());
+
+    // The accelerometer only ever feels gravity, as if the controller were held level
+    let accel = [0.0f32, 1.0, 0.0];
+    let gyro = state.gyro.unwrap_or_default();
+
+    for value in accel.iter().chain(gyro.iter()) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    data
+}
+
+// Answers version and port info requests and takes subscriptions to pad data, which
+// DsuServer::update sends
+fn serve(
+    socket: UdpSocket,
+    clients: Arc<Mutex<Clients>>,
+    stop: Arc<AtomicBool>,
+    slots: u8,
+    motion: bool,
+    server_id: u32,
+) {
+    let mut buffer = [0; 1024];
+
+    while !stop.load(Ordering::Relaxed) {
+        let (length, client) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+
+            Err(error) => match error.kind() {
+                // Windows reports sends to clients that have gone away on the next receive
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ConnectionReset => continue,
+                _ => {
+                    error!("DSU server stopped: {}", error);
+                    return;
+                }
+            },
+        };
+
+        let (message_type, payload) = match parse_request(&buffer[..length]) {
+            Some(request) => request,
+            None => {
+                debug!("ignoring malformed DSU packet from {}", client);
+                continue;
+            }
+        };
+
+        let mut replies = Vec::new();
+
+        match message_type {
+            MESSAGE_VERSION => {
+                let payload = PROTOCOL_VERSION.to_le_bytes();
+                replies.push(packet(server_id, MESSAGE_VERSION, &payload));
+            }
+
+            // A count and then that many slots
+            MESSAGE_PORTS if payload.len() >= 4 => {
+                let count = i32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                let count = count.clamp(0, SLOTS as i32) as usize;
+
+                for &slot in payload[4..].iter().take(count) {
+                    let mut info = slot_info(slot, slot < slots, motion);
+                    info.push(0);
+
+                    replies.push(packet(server_id, MESSAGE_PORTS, &info));
+                }
+            }
+
+            // Flags saying whether to match by slot, MAC address, or neither for every slot
+            MESSAGE_DATA if payload.len() >= 8 => {
+                let (flags, slot, address) = (payload[0], payload[1], &payload[2..8]);
+
+                let wanted = (0..slots).filter(|&i| match flags {
+                    0 => true,
+                    _ => (flags & 1 != 0 && i == slot) || (flags & 2 != 0 && mac(i) == address),
+                });
+
+                let mut clients = clients.lock().unwrap();
+                for slot in wanted {
+                    if clients.insert((client, slot), Instant::now()).is_none() {
+                        info!("DSU client {} subscribed to slot {}", client, slot);
+                    }
+                }
+            }
+
+            _ => debug!("ignoring DSU message {:#x} from {}", message_type, client),
+        }
+
+        for reply in replies {
+            if let Err(error) = socket.send_to(&reply, client) {
+                debug!("could not reply to DSU client {}: {}", client, error);
+            }
+        }
+    }
+}
+
+pub struct DsuServer {
+    socket: UdpSocket,
+    clients: Arc<Mutex<Clients>>,
+    server_id: u32,
+    slots: usize,
+    packet_numbers: [u32; SLOTS],
+    started: Instant,
+    // Set through ControllerOutput::motion, None without a gyro
+    gyro: Option<[[f32; 3]; SLOTS]>,
+
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DsuServer {
+    // Serves the first slots controllers, at most SLOTS, with a gyro if motion is set
+    pub fn start(config: &DsuConfig, slots: usize, motion: bool) -> Result<Self, anyhow::Error> {
+        let socket = UdpSocket::bind(&config.address)?;
+        socket.set_read_timeout(Some(POLL_PERIOD))?;
+
+        let clients = Arc::new(Mutex::new(Clients::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let server_id = std::process::id();
+
+        if slots > SLOTS {
+            warn!("DSU only has room for {} of {} controllers", SLOTS, slots);
+        }
+
+        let slots = slots.min(SLOTS);
+
+        let thread = {
+            let (socket, clients, stop) = (socket.try_clone()?, clients.clone(), stop.clone());
+            thread::spawn(move || serve(socket, clients, stop, slots as u8, motion, server_id))
+        };
+
+        info!("DSU server listening on {}", socket.local_addr()?);
+
+        Ok(DsuServer {
+            socket,
+            clients,
+            server_id,
+            slots,
+            packet_numbers: [0; SLOTS],
+            started: Instant::now(),
+            gyro: motion.then_some([[0.0; 3]; SLOTS]),
+
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl ControllerOutput for DsuServer {
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error> {
+        if index >= self.slots {
+            return Ok(());
+        }
+
+        let slot = index as u8;
+
+        self.packet_numbers[index] = self.packet_numbers[index].wrapping_add(1);
+        let state = PadState {
+            packet_number: self.packet_numbers[index],
+            timestamp: self.started.elapsed().as_micros() as u64,
+            gyro: self.gyro.map(|gyro| gyro[index]),
+        };
+
+        let payload = pad_data(slot, &state, report);
+        let packet = packet(self.server_id, MESSAGE_DATA, &payload);
+
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|_, asked| asked.elapsed() < CLIENT_TIMEOUT);
+
+        for &(client, _) in clients.keys().filter(|&&(_, i)| i == slot) {
+            if let Err(error) = self.socket.send_to(&packet, client) {
+                debug!("could not send to DSU client {}: {}", client, error);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn motion(&mut self, index: usize, gyro: [f32; 3]) {
+        if let Some(slots) = self.gyro.as_mut() {
+            if index < SLOTS {
+                slots[index] = gyro;
+            }
+        }
+    }
+
+    // Nothing to lose, clients just get nothing while no reports are sent
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        for i in 0..self.slots {
+            self.motion(i, [0.0; 3]);
+            let _ = self.update(i, &XUSBReport::default());
+        }
+    }
+}
+
+impl Drop for DsuServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serializer};
+
+use std::fmt;
+use std::time::Duration;
+
+// Durations in the config, for #[serde(with = "duration")]. Read as a number of milliseconds
+// like 20, a string with a unit like "20ms", "1.5s" or "500us", or as serde writes them,
+// (secs: 0, nanos: 20000000) in RON. Written as a string
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(*duration))
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+// For Option<Duration>, with #[serde(with = "duration::option")]
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_some(&format(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_option(OptionVisitor)
+    }
+}
+
+// In the largest unit that keeps it whole, short of milliseconds which can have a fraction
+fn format(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+
+    if duration.as_secs() > 0 && duration.subsec_nanos() == 0 {
+        format!("{}s", duration.as_secs())
+    } else if duration.as_micros() * 1000 == nanos {
+        format!("{}ms", nanos as f64 / 1_000_000.0)
+    } else {
+        format!("{}us", nanos as f64 / 1000.0)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let number = text.trim_end_matches(char::is_alphabetic).trim_end();
+    let unit = text[number.len()..].trim_start();
+
+    let nanos_per_unit = match unit {
+        "s" => 1e9,
+        "ms" => 1e6,
+        "us" | "µs" => 1e3,
+        "" => return Err(format!("\"{}\" needs a unit, s, ms or us", text)),
+        _ => return Err(format!("\"{}\" isn't a unit, use s, ms or us", unit)),
+    };
+
+    match number.parse::<f64>() {
+        Ok(number) => from_nanos(number * nanos_per_unit),
+        Err(_) => Err(format!("\"{}\" isn't a number", number)),
+    }
+}
+
+fn from_nanos(nanos: f64) -> Result<Duration, String> {
+    match nanos >= 0.0 && nanos < u64::MAX as f64 {
+        true => Ok(Duration::from_nanos(nanos.round() as u64)),
+        false => Err(format!("{}ms isn't a duration", nanos / 1e6)),
+    }
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number of milliseconds, a string like \"20ms\", or (secs: .., nanos: ..)")
+    }
+
+    fn visit_u64<E: de::Error>(self, ms: u64) -> Result<Duration, E> {
+        Ok(Duration::from_millis(ms))
+    }
+
+    fn visit_i64<E: de::Error>(self, ms: i64) -> Result<Duration, E> {
+        match u64::try_from(ms) {
+            Ok(ms) => Ok(Duration::from_millis(ms)),
+            Err(_) => Err(E::custom(format!("{}ms is negative", ms))),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, ms: f64) -> Result<Duration, E> {
+        from_nanos(ms * 1e6).map_err(E::custom)
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<Duration, E> {
+        parse(text).map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Duration, A::Error> {
+        Duration::deserialize(MapAccessDeserializer::new(map))
+    }
+}
+
+struct OptionVisitor;
+
+impl<'de> Visitor<'de> for OptionVisitor {
+    type Value = Option<Duration>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("None, or a duration")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserialize(deserializer).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Serialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Durations {
+        #[serde(with = "super")]
+        duration: Duration,
+        #[serde(with = "super::option", default)]
+        option: Option<Duration>,
+    }
+
+    fn read(duration: &str) -> Result<Duration, ron::Error> {
+        let text = format!("(duration: {})", duration);
+        ron::from_str::<Durations>(&text).map(|durations| durations.duration)
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse("20ms"), Ok(Duration::from_millis(20)));
+        assert_eq!(parse("0.25ms"), Ok(Duration::from_micros(250)));
+        assert_eq!(parse("500us"), Ok(Duration::from_micros(500)));
+        assert_eq!(parse("500µs"), Ok(Duration::from_micros(500)));
+        assert_eq!(parse(" 20 ms "), Ok(Duration::from_millis(20)));
+        assert_eq!(parse("0s"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn bad_units() {
+        let error = |text| parse(text).unwrap_err();
+
+        assert_eq!(error("20"), "\"20\" needs a unit, s, ms or us");
+        assert_eq!(error("20m"), "\"m\" isn't a unit, use s, ms or us");
+        assert_eq!(error("20 min"), "\"min\" isn't a unit, use s, ms or us");
+        assert_eq!(error("20MS"), "\"MS\" isn't a unit, use s, ms or us");
+        assert_eq!(error("ms"), "\"\" isn't a number");
+        assert_eq!(error("2.5.1ms"), "\"2.5.1\" isn't a number");
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(parse("-1ms").is_err());
+        assert!(parse("NaNms").is_err());
+        // Past u64::MAX nanoseconds, about 584 years
+        assert!(parse("1e11s").is_err());
+        assert!(parse("1e10s").is_ok());
+
+        assert!(read("-5").is_err());
+        assert!(read("-0.5").is_err());
+        assert!(read("1e20").is_err());
+    }
+
+    #[test]
+    fn forms() {
+        assert_eq!(read("20").unwrap(), Duration::from_millis(20));
+        assert_eq!(read("0.5").unwrap(), Duration::from_micros(500));
+        assert_eq!(read("\"1.5s\"").unwrap(), Duration::from_millis(1500));
+        let legacy = "(secs: 1, nanos: 20000000)";
+        assert_eq!(read(legacy).unwrap(), Duration::from_millis(1020));
+
+        let error = read("\"20\"").unwrap_err().to_string();
+        assert!(error.contains("needs a unit"), "{}", error);
+        assert!(read("true").is_err());
+    }
+
+    #[test]
+    fn option() {
+        let read = |text: &str| ron::from_str::<Durations>(text).unwrap().option;
+
+        assert_eq!(read("(duration: 1)"), None);
+        assert_eq!(read("(duration: 1, option: None)"), None);
+        let some = read("(duration: 1, option: Some(\"4ms\"))");
+        assert_eq!(some, Some(Duration::from_millis(4)));
+    }
+
+    #[test]
+    fn written_as_read() {
+        let durations = [
+            Duration::from_secs(3),
+            Duration::from_millis(1500),
+            Duration::from_millis(20),
+            Duration::from_micros(250),
+            Duration::from_nanos(1500),
+            Duration::ZERO,
+        ];
+
+        for duration in durations {
+            let text = format(duration);
+            assert_eq!(parse(&text), Ok(duration), "{}", text);
+        }
+
+        assert_eq!(format(Duration::from_secs(3)), "3s");
+        assert_eq!(format(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format(Duration::from_micros(250)), "0.25ms");
+        assert_eq!(format(Duration::from_nanos(1500)), "1.5us");
+
+        let durations = Durations {
+            duration: Duration::from_millis(20),
+            option: Some(Duration::from_secs(1)),
+        };
+        let text = ron::to_string(&durations).unwrap();
+        assert_eq!(ron::from_str::<Durations>(&text).unwrap(), durations);
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GyroAxis {
+    Pitch,
+    Yaw,
+    Roll,
+}
+
+// Mouse movement as rotation of the controller, for outputs that carry motion. Only the DSU
+// server does, the DualShock 4 report in vigem 0.9.1 has no motion fields, so it needs
+// dsu_server set
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Degrees per second of rotation for every 1000 counts per second of mouse movement,
+    // separate from the stick sensitivity
+    sensitivity: f64,
+    // The rotation each mouse axis turns the controller about. Moving the mouse right turns it
+    // right and moving it up tilts it up, invert_x and invert_y flip those
+    x_axis: GyroAxis,
+    y_axis: GyroAxis,
+    invert_x: bool,
+    invert_y: bool,
+    // Whether the MouseMove stick still follows the mouse while the gyro is on
+    pub keep_stick: bool,
+    // Whether it starts switched on, ToggleGyro flips it
+    pub enabled: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sensitivity: 20.0,
+            x_axis: GyroAxis::Yaw,
+            y_axis: GyroAxis::Pitch,
+            invert_x: false,
+            invert_y: false,
+            keep_stick: false,
+            enabled: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        if self.x_axis == self.y_axis {
+            problems.push("gyro.x_axis and gyro.y_axis must be different".to_owned());
+        }
+
+        if self.sensitivity <= 0.0 || self.sensitivity.is_nan() {
+            problems.push(format!(
+                "gyro.sensitivity is {}, it must be above 0",
+                self.sensitivity
+            ));
+        }
+    }
+
+    // Pitch, yaw and roll in degrees per second for a mouse velocity in counts per second
+    pub fn rotation(&self, mouse_vel: (f64, f64)) -> [f32; 3] {
+        let sign = |invert: bool| match invert {
+            true => -1.0,
+            false => 1.0,
+        };
+
+        // Turning right is a negative yaw, and the mouse y grows downwards
+        let scale = self.sensitivity / 1000.0;
+        let x = -mouse_vel.0 * scale * sign(self.invert_x);
+        let y = -mouse_vel.1 * scale * sign(self.invert_y);
+
+        let mut rotation = [0.0; 3];
+        for (axis, value) in [(self.x_axis, x), (self.y_axis, y)] {
+            let i = match axis {
+                GyroAxis::Pitch => 0,
+                GyroAxis::Yaw => 1,
+                GyroAxis::Roll => 2,
+            };
+
+            rotation[i] = value as f32;
+        }
+
+        rotation
+    }
+}
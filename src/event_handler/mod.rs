@@ -1,17 +1,60 @@
+#[cfg(feature = "audio")]
+mod beeper;
+mod bind_map;
+mod bind_table;
+mod ds4;
+#[cfg(feature = "dsu")]
+mod dsu;
+pub mod duration;
+mod gyro;
+mod output;
+mod presets;
+#[cfg(feature = "recording")]
+mod recorder;
+#[cfg(feature = "recording")]
+mod replay;
+mod response_curve;
+mod rumble;
+mod sounds;
+#[cfg(test)]
+mod tests;
+#[cfg(feature = "audio")]
 mod tone_generator;
+#[cfg(feature = "trace")]
+mod trace;
 
 use std::cmp::PartialEq;
+use crate::event_dispatcher;
+use crate::logging;
 use crate::types::*;
-use tone_generator::ToneGenerator;
+#[cfg(feature = "audio")]
+use beeper::Beeper;
+use ds4::ControllerType;
+#[cfg(feature = "dsu")]
+use dsu::DsuServer;
+use output::{ConnectRetry, ControllerOutput, VigemOutput};
+pub use presets::PRESETS;
+#[cfg(feature = "recording")]
+use recorder::Recorder;
+#[cfg(feature = "recording")]
+pub use replay::replay;
+use sounds::AlertFallback;
+#[cfg(feature = "audio")]
+use tone_generator::{Cue, Pan, ToneGenerator};
+#[cfg(feature = "trace")]
+pub use trace::trace_summary;
+#[cfg(feature = "trace")]
+use trace::Tracer;
 
 use interception as ic;
 use vigem::*;
 
 use serde::{Deserialize, Serialize};
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
 use std::hint::spin_loop;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 
@@ -21,468 +64,5290 @@ pub enum Axis {
     Y,
 }
 
+#[derive(Serialize, Deserialize, Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Left,
+    Right,
+}
+
+impl Trigger {
+    fn button(self) -> ControllerButton {
+        match self {
+            Trigger::Left => ControllerButton::LeftTrigger,
+            Trigger::Right => ControllerButton::RightTrigger,
+        }
+    }
+}
+
+// What is holding a controller button down, a bind or one of the automatic features
+#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Bind(Bind),
+    AutoSprint,
+    AutoStickClick,
+    DpadMode,
+    // Index into Config::sequences
+    Sequence(usize),
+    // Index into Config::gestures
+    Gesture(usize),
+    // Index into Config::zones
+    Zone(usize),
+    Rumble,
+}
+
+// What a TriggerCharge does when the key is released
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChargeRelease {
+    #[default]
+    Instant,
+    // Drains at the same rate it charged
+    Ramp,
+}
+
+fn full_charge() -> f64 {
+    1.0
+}
+
 #[derive(Serialize, Deserialize, Hash, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Bind {
-    Keyboard(ic::ScanCode),
+    // The second field picks the E0 (Some(true)) or plain (Some(false)) key for scancodes
+    // shared by two keys, left out it matches either
+    Keyboard(ic::ScanCode, #[serde(default)] Option<bool>),
+    // Replaced with the matching Keyboard bind when the config is loaded
+    Modifier(Modifier),
     Mouse(MouseButton),
+    MouseWheel(WheelDirection),
     MouseMove,
+    // Movement of one mouse (the number is logged when it first moves), bound to AnalogLeft or
+    // AnalogRight. Mice without one use MouseMove
+    MouseMoveDevice(ic::Device),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum ControllerAction {
     Button(ControllerButton),
-    AnalogLeft(f64, f64),
-    AnalogRight(f64, f64),
+    // Presses all of the buttons (and triggers) together
+    Buttons(Vec<ControllerButton>),
+    // The third field replaces sensitivity for MouseMove and MouseMoveDevice binds
+    AnalogLeft(f64, f64, #[serde(default)] Option<f64>),
+    AnalogRight(f64, f64, #[serde(default)] Option<f64>),
+
+    // Adds step (a fraction of full travel, negative to subtract) to the trigger on every
+    // press, the value is kept until changed again, zeroed or reset
+    TriggerAccumulate {
+        trigger: Trigger,
+        step: f64,
+    },
+    TriggerZero(Trigger),
+
+    // Presses the button for exactly duration_ms on key down, the release is ignored
+    Pulse {
+        button: ControllerButton,
+        duration_ms: u64,
+    },
+
+    // Ramps the trigger up to hold_at over full_charge_ms while the key is held
+    TriggerCharge {
+        trigger: Trigger,
+        full_charge_ms: u64,
+        #[serde(default = "full_charge")]
+        hold_at: f64,
+        #[serde(default)]
+        release: ChargeRelease,
+    },
+
+    // Performs the inner action as a short press when the key is released
+    OnRelease(Box<ControllerAction>),
+
+    // Performs tap as a short press when the key is let go within tap_threshold_ms, otherwise
+    // holds hold from then until it is
+    TapHold {
+        tap: Box<ControllerAction>,
+        hold: Box<ControllerAction>,
+    },
+
+    // Performs double as a short press when a second tap follows within double_tap_window_ms,
+    // otherwise single once the window has passed
+    DoubleTap {
+        single: Box<ControllerAction>,
+        double: Box<ControllerAction>,
+    },
+
+    // Pushes the stick magnitude (0 to 1) towards angle degrees, anticlockwise from right.
+    // Polar binds on the same stick don't add up, the most recently pressed one is used
+    AnalogPolar {
+        stick: AnalogType,
+        angle: f64,
+        magnitude: f64,
+    },
+
+    // Keeps the button (or trigger) released while the key is held, whatever else presses it
+    Suppress(ControllerButton),
+
+    // Sends this key (E0 set by the second field) to Windows in place of the bound one
+    KeyRemap(ic::ScanCode, #[serde(default)] bool),
+
+    // Types the keys one after another (press, release, next key) on key down
+    SendKeys(Vec<ic::ScanCode>),
+
+    // Points the FlickStick mode stick back up
+    RecenterFlickStick,
+
+    // Multiplies the mouse sensitivity of axis (both if left out) by factor until restarted
+    ScaleSensitivity {
+        factor: f64,
+        #[serde(default)]
+        axis: Option<Axis>,
+    },
+
+    // Switches keyboard AnalogLeft binds between moving the stick and pressing the dpad
+    ToggleDpadMode,
+
+    // Mutes and unmutes every sound kmxpad plays
+    ToggleAudio,
+
+    // Switches between aiming with the gyro and with the MouseMove stick
+    ToggleGyro,
+
+    // Switches swap_analogs on and off
+    SwapAnalogs,
+
+    // Switches sending the first controller's report to the mirror_targets controllers
+    MirrorToggle,
+
+    // Switches to the named profile, or back to the config's own binds with "default". It holds
+    // until the window in front changes
+    Profile(String),
+
+    // Puts the named layer's binds on top of the ones in use while held
+    Layer(String),
+    // Turns the named layer on, or off again
+    ToggleLayer(String),
+
+    // Turns logging every bind press and release (logging's log_binds) on and off
+    ToggleBindLog,
+
+    // Logs the binds in use as at startup
+    PrintBinds,
+}
+
+// A bind of each kind and each action, for --generate-config. New actions want one here too
+pub fn example_binds() -> Vec<(Bind, ControllerAction)> {
+    use ic::ScanCode::*;
+    use ControllerAction::*;
+
+    vec![
+        (Bind::Keyboard(W, None), AnalogLeft(0.0, 1.0, None)),
+        (
+            Bind::Keyboard(Enter, Some(true)),
+            Button(ControllerButton::Start),
+        ),
+        (
+            Bind::Modifier(Modifier::LeftShift),
+            Button(ControllerButton::LeftThumb),
+        ),
+        (
+            Bind::Mouse(MouseButton::Left),
+            Button(ControllerButton::RightTrigger),
+        ),
+        (
+            Bind::Mouse(MouseButton::Right),
+            TriggerCharge {
+                trigger: Trigger::Left,
+                full_charge_ms: 300,
+                hold_at: full_charge(),
+                release: ChargeRelease::Instant,
+            },
+        ),
+        (
+            Bind::MouseWheel(WheelDirection::Up),
+            Pulse {
+                button: ControllerButton::DpadUp,
+                duration_ms: 50,
+            },
+        ),
+        (Bind::MouseMoveDevice(12), AnalogLeft(1.0, -1.0, Some(0.5))),
+        (
+            Bind::Keyboard(Q, None),
+            Buttons(vec![
+                ControllerButton::LeftShoulder,
+                ControllerButton::RightShoulder,
+            ]),
+        ),
+        (
+            Bind::Keyboard(E, None),
+            TriggerAccumulate {
+                trigger: Trigger::Left,
+                step: 0.25,
+            },
+        ),
+        (Bind::Keyboard(R, None), TriggerZero(Trigger::Left)),
+        (
+            Bind::Keyboard(F, None),
+            OnRelease(Box::new(Button(ControllerButton::Y))),
+        ),
+        (
+            Bind::Keyboard(Z, None),
+            AnalogPolar {
+                stick: AnalogType::Left,
+                angle: 45.0,
+                magnitude: 1.0,
+            },
+        ),
+        (Bind::Keyboard(X, None), Suppress(ControllerButton::A)),
+        (Bind::Keyboard(CapsLock, None), KeyRemap(LeftControl, false)),
+        (Bind::Keyboard(F1, None), SendKeys(vec![H, I])),
+        (Bind::Mouse(MouseButton::Middle), RecenterFlickStick),
+        (
+            Bind::Keyboard(F2, None),
+            ScaleSensitivity {
+                factor: 0.5,
+                axis: None,
+            },
+        ),
+        (Bind::Keyboard(F3, None), ToggleDpadMode),
+        (Bind::Keyboard(F4, None), ToggleAudio),
+        (Bind::Keyboard(F5, None), ToggleGyro),
+        (Bind::Keyboard(F6, None), SwapAnalogs),
+        (Bind::Keyboard(F7, None), MirrorToggle),
+        (Bind::Keyboard(F8, None), Profile("default".into())),
+        (Bind::Keyboard(F9, None), PrintBinds),
+        (Bind::Keyboard(F10, None), Layer("driving".into())),
+        (Bind::Keyboard(F11, None), ToggleLayer("driving".into())),
+        (Bind::Keyboard(F12, None), ToggleBindLog),
+    ]
+}
+
+// Optional per-bind behaviour, keyed by the same Bind as the binds map
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct BindOptions {
+    // Release the bound action after this long even if the key is still down
+    max_hold_ms: Option<u64>,
+
+    // Override the values in Config::timing for this bind
+    tap_threshold_ms: Option<u64>,
+    double_tap_window_ms: Option<u64>,
+    pulse_duration_ms: Option<u64>,
+
+    // Sensitivity for a MouseMove or MouseMoveDevice bind, the action's own takes precedence
+    sensitivity: Option<f64>,
+
+    // Whether a MouseWheel bind follows the inverted (true) or actual (false) wheel direction,
+    // in place of the event dispatcher's wheel_invert
+    wheel_invert: Option<bool>,
+
+    // The controller the bind is on, 0 the first and 1 on the first of targets
+    #[serde(default)]
+    target: usize,
+}
+
+// Durations shared by the timing based binds, each can be overridden in bind_options
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timing {
+    // TapHold presses shorter than this are taps, longer ones holds
+    tap_threshold_ms: u64,
+    // How soon a second tap has to follow the first to count as a DoubleTap
+    double_tap_window_ms: u64,
+    // How long short presses (OnRelease, sequences, gestures) hold their action
+    pulse_duration_ms: u64,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            tap_threshold_ms: 200,
+            double_tap_window_ms: 300,
+            pulse_duration_ms: 50,
+        }
+    }
+}
+
+impl Timing {
+    // Anything longer is almost certainly a typo (seconds for milliseconds etc.)
+    const MAX_MS: u64 = 10_000;
+
+    fn with_overrides(self, options: &BindOptions) -> Timing {
+        Timing {
+            tap_threshold_ms: options.tap_threshold_ms.unwrap_or(self.tap_threshold_ms),
+            double_tap_window_ms: options
+                .double_tap_window_ms
+                .unwrap_or(self.double_tap_window_ms),
+            pulse_duration_ms: options.pulse_duration_ms.unwrap_or(self.pulse_duration_ms),
+        }
+    }
+
+    fn validate(&self, owner: &str, problems: &mut Vec<String>) {
+        for (name, value) in [
+            ("tap_threshold_ms", self.tap_threshold_ms),
+            ("double_tap_window_ms", self.double_tap_window_ms),
+            ("pulse_duration_ms", self.pulse_duration_ms),
+        ] {
+            if value == 0 || value > Self::MAX_MS {
+                problems.push(format!(
+                    "{} of {} is {}, it must be between 1 and {}",
+                    name,
+                    owner,
+                    value,
+                    Self::MAX_MS
+                ));
+            }
+        }
+    }
+}
+
+// Runs action as a short press when keys are pressed in order within window_ms
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Sequence {
+    keys: Vec<Bind>,
+    window_ms: u64,
+    action: ControllerAction,
+}
+
+// Shorthand for a ring of AnalogPolar binds (a weapon wheel), the first key points at
+// start_angle and each following one is a step clockwise around the circle
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Radial {
+    stick: AnalogType,
+    keys: Vec<Bind>,
+    start_angle: f64,
+    magnitude: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// Fills trigger in proportion to how fast the mouse moves in direction, 1 (full travel) being
+// the speed that gives full stick deflection at sensitivity, shaped by curve
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MouseTrigger {
+    trigger: Trigger,
+    direction: Direction,
+    sensitivity: f64,
+    #[serde(default)]
+    curve: response_curve::Shape,
+}
+
+// Runs action as a short press when the mouse is flicked in direction faster than velocity,
+// in stick units (1 is the speed that gives full deflection)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Gesture {
+    direction: Direction,
+    velocity: f64,
+    action: ControllerAction,
+    // Minimum time between two firings
+    #[serde(default)]
+    refractory_ms: u64,
+    // Doesn't fire while this is held
+    #[serde(default)]
+    unless_held: Option<Modifier>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    // Past threshold on axis, a negative threshold is crossed going the other way
+    Axis {
+        axis: Axis,
+        threshold: f64,
+    },
+    // At least magnitude from the centre and within width degrees centred on angle
+    Sector {
+        angle: f64,
+        width: f64,
+        magnitude: f64,
+    },
+}
+
+impl Region {
+    // How far past the edge of the region the stick is, negative when outside
+    fn depth(&self, state: &AnalogState) -> f64 {
+        match *self {
+            Region::Axis { axis, threshold } => {
+                let value = match axis {
+                    Axis::X => state.x,
+                    Axis::Y => state.y,
+                };
+
+                value * threshold.signum() - threshold.abs()
+            }
+            Region::Sector {
+                angle,
+                width,
+                magnitude,
+            } => {
+                let stick_angle = state.y.atan2(state.x).to_degrees();
+                let distance = 180.0 - ((stick_angle - angle).rem_euclid(360.0) - 180.0).abs();
+                let radius = (state.x.powi(2) + state.y.powi(2)).sqrt();
+
+                // Across from the nearest side edge, in the same units as the magnitude so
+                // hysteresis holds at the sides too
+                let past_side = (distance - width / 2.0).to_radians();
+                let quarter = std::f64::consts::FRAC_PI_2;
+                let side = match width >= 360.0 {
+                    true => f64::INFINITY,
+                    false => -radius * past_side.clamp(-quarter, quarter).sin(),
+                };
+
+                (radius - magnitude).min(side)
+            }
+        }
+    }
+}
+
+// Holds action while stick has been in region for dwell_ms, it counts as left once it is
+// more than hysteresis back out of the region
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Zone {
+    stick: AnalogType,
+    region: Region,
+    #[serde(default)]
+    dwell_ms: u64,
+    #[serde(default)]
+    hysteresis: f64,
+    action: ControllerAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusivePolicy {
+    // The latest press takes over, releasing it restores a member that is still held
+    NewestWins,
+    // Presses of other members are dropped while one is held
+    IgnoreSecond,
+}
+
+// Controller buttons of which only one may be down at a time
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExclusiveGroup {
+    buttons: Vec<ControllerButton>,
+    policy: ExclusivePolicy,
+}
+
+// Clicks the left stick once forward input has been held long enough
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoSprint {
+    enabled: bool,
+    button: ControllerButton,
+    threshold: f64,
+    delay_ms: u64,
+}
+
+impl Default for AutoSprint {
+    fn default() -> Self {
+        AutoSprint {
+            enabled: false,
+            button: ControllerButton::LeftThumb,
+            threshold: 0.9,
+            delay_ms: 500,
+        }
+    }
+}
+
+// Clicks a button while a stick is pushed past threshold, releasing once it falls below
+// threshold - hysteresis so it doesn't chatter at the edge
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoStickClick {
+    enabled: bool,
+    stick: AnalogType,
+    button: ControllerButton,
+    threshold: f64,
+    hysteresis: f64,
+}
+
+impl Default for AutoStickClick {
+    fn default() -> Self {
+        AutoStickClick {
+            enabled: false,
+            stick: AnalogType::Right,
+            button: ControllerButton::RightThumb,
+            threshold: 1.0,
+            hysteresis: 0.1,
+        }
+    }
+}
+
+// Sounds once stick has stayed within floor of the centre for dwell_ms, e.g. after letting go
+// of the controls in a flight game
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct UndersteerAlert {
+    enabled: bool,
+    stick: AnalogType,
+    floor: f64,
+    dwell_ms: u64,
+    tone: sounds::UndersteerTone,
+}
+
+impl Default for UndersteerAlert {
+    fn default() -> Self {
+        UndersteerAlert {
+            enabled: false,
+            stick: AnalogType::Right,
+            floor: 0.05,
+            dwell_ms: 3000,
+            tone: sounds::UndersteerTone::default(),
+        }
+    }
+}
+
+// Turns the summed keyboard AnalogLeft vector into dpad presses (for menus)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct DpadMode {
+    // Whether it starts switched on, ToggleDpadMode flips it
+    active: bool,
+    // Degrees either side of a diagonal within which both directions are pressed,
+    // 22.5 splits the circle into eight equal directions
+    diagonal_threshold: f64,
+    // Vectors shorter than this press nothing
+    deadzone: f64,
+}
+
+impl Default for DpadMode {
+    fn default() -> Self {
+        DpadMode {
+            active: false,
+            diagonal_threshold: 22.5,
+            deadzone: 0.3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SmoothingMode {
+    // Average over the last sample_window
+    #[default]
+    Window,
+    // Exponential moving average with time constant tau_ms, decays towards zero when the
+    // mouse stops
+    Ema,
+}
+
+// How much each sample in the window counts depending on its age, all of them average out
+// to 1 over the window so a steady speed comes out the same
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum WindowWeighting {
+    #[default]
+    Uniform,
+    // Falls off in a straight line to nothing at the end of the window
+    Linear,
+    // Falls off by e^-rate across the window
+    Exponential(f64),
+}
+
+impl WindowWeighting {
+    // age is how far through the window the sample is, 0 now and 1 about to expire
+    fn weight(&self, age: f64) -> f64 {
+        match *self {
+            WindowWeighting::Uniform => 1.0,
+            WindowWeighting::Linear => 2.0 * (1.0 - age),
+            WindowWeighting::Exponential(rate) if rate.abs() < f64::EPSILON => 1.0,
+            WindowWeighting::Exponential(rate) => {
+                rate * (-rate * age).exp() / (1.0 - (-rate).exp())
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Smoothing {
+    mode: SmoothingMode,
+    tau_ms: f64,
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing {
+            mode: SmoothingMode::Window,
+            tau_ms: 15.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AntiDeadzoneScope {
+    // Only the mouse driven part of the stick
+    #[default]
+    Mouse,
+    // The stick after everything (mouse, keys, ...) is added up
+    Combined,
+}
+
+// Lifts any non-zero deflection to at least min, keeping the direction: magnitudes 0 to 1
+// become min to 1 and exact zero stays zero
+fn anti_deadzone(x: f64, y: f64, min: f64) -> (f64, f64) {
+    let magnitude = (x.powi(2) + y.powi(2)).sqrt();
+    if magnitude == 0.0 || min <= 0.0 {
+        return (x, y);
+    }
+
+    let scale = (min + magnitude * (1.0 - min)) / magnitude;
+    (x * scale, y * scale)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MouseMode {
+    // Mouse speed sets how far the stick is pushed
+    #[default]
+    Velocity,
+    // Mouse x turns a fully pushed stick around the circle, see FlickStick
+    FlickStick,
+    // Tablet and touch positions in absolute_area push the stick that far from the centre
+    Absolute,
+}
+
+// The part of the screen used by MouseMode::Absolute, 0 to 65535 across the whole screen.
+// The middle is the stick centre and the edges are full deflection
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AbsoluteArea {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+impl Default for AbsoluteArea {
+    fn default() -> Self {
+        AbsoluteArea {
+            left: 0,
+            top: 0,
+            right: 65535,
+            bottom: 65535,
+        }
+    }
+}
+
+impl AbsoluteArea {
+    // -1 to 1 on each axis, y grows downwards like the position
+    fn deflection(&self, x: i32, y: i32) -> (f64, f64) {
+        let along = |position: i32, start: i32, end: i32| {
+            let fraction = (position - start) as f64 / (end - start) as f64;
+            (fraction * 2.0 - 1.0).clamp(-1.0, 1.0)
+        };
+
+        (
+            along(x, self.left, self.right),
+            along(y, self.top, self.bottom),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlickStick {
+    // How far the stick turns per mouse count
+    degrees_per_count: f64,
+    // A turn of at least this many degrees within the x sample window is a flick, the stick then
+    // stays at the angle the flick reached for flick_hold_ms. 0 disables flicks
+    flick_threshold_deg: f64,
+    flick_hold_ms: u64,
+}
+
+impl Default for FlickStick {
+    fn default() -> Self {
+        FlickStick {
+            degrees_per_count: 0.1,
+            flick_threshold_deg: 0.0,
+            flick_hold_ms: 100,
+        }
+    }
+}
+
+// Lets current rise straight away but fall no more than max_drop below previous, keeping the
+// previous direction when current has none
+fn limit_decay(previous: (f64, f64), current: (f64, f64), max_drop: f64) -> (f64, f64) {
+    let previous_magnitude = (previous.0.powi(2) + previous.1.powi(2)).sqrt();
+    let magnitude = (current.0.powi(2) + current.1.powi(2)).sqrt();
+
+    let floor = previous_magnitude - max_drop;
+    if magnitude >= floor {
+        return current;
+    }
+
+    let (direction, length) = match magnitude > 0.0 {
+        true => (current, magnitude),
+        false => (previous, previous_magnitude),
+    };
+
+    let scale = floor / length;
+    (direction.0 * scale, direction.1 * scale)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// Converts a stick axis position to a step with remainder (the fraction of a step cut off
+// last time) added on, giving the step and the fraction cut off this time
+fn stick_step(position: f64, remainder: f64) -> (i16, f64) {
+    let ideal = position * EventHandler::ANALOG_MAX + remainder;
+    let clamped = ideal.trunc().clamp(i16::MIN as f64, i16::MAX as f64);
+
+    // Nothing is carried past the end of the range
+    let remainder = match clamped == ideal.trunc() {
+        true => ideal - clamped,
+        false => 0.0,
+    };
+    (clamped as i16, remainder)
+}
+
+// Gain for a mouse speed (in full stick deflections): 1 up to threshold, rising in a straight
+// line to multiplier at twice threshold and multiplier from there on
+fn acceleration(speed: f64, threshold: f64, multiplier: f64) -> f64 {
+    if speed <= threshold {
+        return 1.0;
+    }
+
+    let blend = match threshold > 0.0 {
+        true => ((speed - threshold) / threshold).min(1.0),
+        false => 1.0,
+    };
+
+    1.0 + (multiplier - 1.0) * blend
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct PerStick<T> {
+    left: T,
+    right: T,
+}
+
+impl<T: Copy> PerStick<T> {
+    fn get(&self, analog_type: &AnalogType) -> T {
+        match analog_type {
+            AnalogType::Left => self.left,
+            AnalogType::Right => self.right,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
+    controller_type: ControllerType,
+    // The XInput slot (0 to 3, player 1 to 4) the main controller has to get, for games that
+    // take the first controller they see. Xbox360 only
+    require_slot: Option<u32>,
+
     sensitivity: f64,
+    // Per axis sensitivity, either left out uses sensitivity
+    sensitivity_x: Option<f64>,
+    sensitivity_y: Option<f64>,
+    response_curve: response_curve::Config,
 
+    // Flip the mouse axes before they reach the MouseMove bind, mouse y grows downwards so
+    // invert_mouse_y is what makes pushing the mouse away push the stick up
+    invert_mouse_x: bool,
+    invert_mouse_y: bool,
+
+    // Mouse driven stick directions within axis_snap_deg of an axis (or a diagonal) are moved
+    // onto it, and stay there until axis_snap_hysteresis_deg further out. 0 disables it
+    axis_snap_deg: f64,
+    axis_snap_diagonals: bool,
+    axis_snap_hysteresis_deg: f64,
+
+    // The most the mouse driven stick magnitude may fall per second, so it eases to rest
+    // instead of dropping as samples leave the window. 0 disables it, rises are always instant
+    max_decay_per_sec: f64,
+
+    // Once no mouse movement has arrived for this long the samples are dropped and the mouse
+    // driven stick goes straight to zero, whatever max_decay_per_sec says. 0 disables it
+    idle_zero_ms: u64,
+
+    // Mouse speeds (1 = full deflection) above accel_threshold are multiplied by up to
+    // accel_multiplier, blending in up to twice the threshold, and the result is held below
+    // accel_cap. A multiplier of 1 and a cap of 0 turn it off
+    accel_threshold: f64,
+    accel_multiplier: f64,
+    accel_cap: f64,
+
+    // Mouse samples of at most this many counts (on either axis) are held back unless the recent
+    // average is already above it, until together they're more, hiding sensor noise at rest. 0
+    // disables it
+    jitter_filter: u32,
+
+    mouse_mode: MouseMode,
+    flick_stick: FlickStick,
+    absolute_area: AbsoluteArea,
+    mouse_trigger: Option<MouseTrigger>,
+
+    #[serde(with = "duration")]
     sample_window: Duration,
+    // Separate windows for each axis, either left out uses sample_window
+    #[serde(with = "duration::option")]
+    sample_window_x: Option<Duration>,
+    #[serde(with = "duration::option")]
+    sample_window_y: Option<Duration>,
+    // Replaces the windows above with one between sample_window_min (fast movement) and
+    // sample_window_max (slow movement), changing by at most adaptive_window_rate of the
+    // difference per second
+    adaptive_window: bool,
+    #[serde(with = "duration")]
+    sample_window_min: Duration,
+    #[serde(with = "duration")]
+    sample_window_max: Duration,
+    adaptive_window_rate: f64,
+    // Average over sample_window even for mice reporting less often than that, which makes
+    // their velocity jump between too high (while a report is in the window) and zero
+    legacy_velocity: bool,
+    window_weighting: WindowWeighting,
+    smoothing: Smoothing,
+    // Extrapolate the windowed velocity this far ahead along its trend, changing it by no more
+    // than prediction_max_overshoot percent. 0 disables it
+    prediction_ms: u64,
+    prediction_max_overshoot: f64,
 
+    #[serde(with = "duration")]
     spin_period: Duration,
+    // Reports a second to send the controller, with the stick positions worked out just before
+    // each one. 0 sends one every loop, so as often as spin_period and input allow
+    output_rate_hz: u32,
+
+    // How many times to try reconnecting when the ViGEm bus goes away before giving up, 0 gives
+    // up straight away
+    reconnect_max_retries: u32,
+    // How many times to try connecting to ViGEm and adding each controller at startup, and how
+    // long to wait between tries, for when ViGEmBus is still starting
+    vigem_connect_retries: u32,
+    vigem_connect_delay_ms: u64,
+
+    // Reports are only sent when they change, and again every keepalive_ms for games that
+    // expect a steady stream. 0 only sends changes
+    keepalive_ms: u64,
+
+    // Takes the controllers away after this long without input, and adds them back with the
+    // next input, for games that change their prompts while a controller is connected. 0 never
+    // does
+    idle_disconnect_secs: u64,
 
     oversteer_alert_enabled: bool,
     oversteer_alert_threshold: f64,
-    oversteer_alert: tone_generator::Config,
+    // Per stick thresholds, either left out uses oversteer_alert_threshold
+    oversteer_alert_threshold_left: Option<f64>,
+    oversteer_alert_threshold_right: Option<f64>,
+    // Keeps the alert on for at least this long once it starts
+    alert_min_on_ms: u64,
+    // How far under the threshold the stick has to come back before the alert stops
+    alert_hysteresis: f64,
+    oversteer_alert: sounds::Config,
+
+    // Plays rumble from the game through the speakers
+    rumble_audio_enabled: bool,
+    rumble_audio: sounds::RumbleConfig,
+    button_click_feedback: sounds::ClickConfig,
+    audio_cues: sounds::CueConfig,
+    understeer_alert: UndersteerAlert,
+    // What to do when there's no audio device to play the sounds above through
+    alert_fallback: AlertFallback,
+    // Starts with the sounds above muted, a ToggleAudio bind unmutes them
+    audio_start_muted: bool,
+
+    exclusive_groups: Vec<ExclusiveGroup>,
+    auto_sprint: AutoSprint,
+    auto_stick_click: AutoStickClick,
+    dpad_mode: DpadMode,
 
     analog_circularize: bool,
+    // Sends what binds put on the left stick to the right one and the other way around. The
+    // per-stick settings still go by the binds' stick
+    swap_analogs: bool,
+    analog_min_output: PerStick<f64>,
+    anti_deadzone: PerStick<f64>,
+    anti_deadzone_scope: AntiDeadzoneScope,
+    // The furthest (0 to 1) the stick is ever pushed, oversteer alerts still use the uncapped value
+    max_stick_output: PerStick<f64>,
     mouse_button_fix: bool,
+    // Drop the Down events Windows repeats while a key is held
+    suppress_key_repeat: bool,
 
+    // Time between each press and release typed by SendKeys
+    send_keys_delay_ms: u64,
+
+    timing: Timing,
+
+    // One of PRESETS, whose binds binds goes on top of
+    preset: Option<String>,
+    // A map, or in TOML a list of tables with key and action (options for bind_options)
+    #[serde(deserialize_with = "bind_map::actions")]
     binds: HashMap<Bind, ControllerAction>,
+    #[serde(deserialize_with = "bind_map::options")]
+    bind_options: HashMap<Bind, BindOptions>,
+    sequences: Vec<Sequence>,
+    gestures: Vec<Gesture>,
+    zones: Vec<Zone>,
+    radial: Option<Radial>,
+    // More virtual controllers after the first, each with its own binds
+    targets: Vec<TargetConfig>,
+    // More virtual controllers after those, each sent the first one's report while mirroring
+    // is on, e.g. to play both sides of split-screen. MirrorToggle turns it off and on
+    mirror_targets: u32,
+    rumble_action: Option<RumbleAction>,
+    // Serves the controllers over the DSU (cemuhook) protocol to emulators as well, or on its own
+    // when ViGEm isn't installed
+    dsu_server: Option<DsuConfig>,
+    // Mouse movement as gyro rotation of the main controller, which only the DSU server carries
+    gyro: Option<gyro::Config>,
+    // Appends every event, every report sent and each loop's mouse velocity to this file as JSON
+    // lines, for --trace-summary or a look of your own
+    trace_file: Option<PathBuf>,
+
+    // Lets all input through to Windows as if toggled off, for a profile used while another
+    // window is in front
+    passthrough: bool,
+    // Settings switched to by name, read as the settings above with the profile's own on top.
+    // Each has every layer on top of it in turn
+    profiles: HashMap<String, Config>,
+    // Settings switched on by Layer and ToggleLayer binds, the binds on top of the profile's and
+    // the other settings in place of them
+    layers: HashMap<String, Config>,
+    // The profile to switch to when a window comes to the front, by the name of its executable
+    // (e.g. game.exe) or else part of its title, "*" for any other window
+    window_profiles: HashMap<String, String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            controller_type: ControllerType::default(),
+            require_slot: None,
+
             sensitivity: 5.0,
+            sensitivity_x: None,
+            sensitivity_y: None,
+            response_curve: response_curve::Config::default(),
+
+            invert_mouse_x: false,
+            invert_mouse_y: false,
+
+            axis_snap_deg: 0.0,
+            axis_snap_diagonals: false,
+            axis_snap_hysteresis_deg: 2.0,
+
+            max_decay_per_sec: 0.0,
+
+            idle_zero_ms: 0,
+
+            accel_threshold: 0.5,
+            accel_multiplier: 1.0,
+            accel_cap: 0.0,
+
+            jitter_filter: 0,
+
+            mouse_mode: MouseMode::default(),
+            flick_stick: FlickStick::default(),
+            absolute_area: AbsoluteArea::default(),
+            mouse_trigger: None,
 
             sample_window: Duration::from_millis(20),
+            sample_window_x: None,
+            sample_window_y: None,
+            adaptive_window: false,
+            sample_window_min: Duration::from_millis(4),
+            sample_window_max: Duration::from_millis(20),
+            adaptive_window_rate: 4.0,
+            legacy_velocity: false,
+            window_weighting: WindowWeighting::default(),
+            smoothing: Smoothing::default(),
+            prediction_ms: 0,
+            prediction_max_overshoot: 25.0,
 
             spin_period: Duration::from_millis(2),
+            output_rate_hz: 0,
+            reconnect_max_retries: 10,
+            vigem_connect_retries: 0,
+            vigem_connect_delay_ms: 1000,
+            keepalive_ms: 0,
+            idle_disconnect_secs: 0,
 
             oversteer_alert_enabled: false,
             oversteer_alert_threshold: 1.5,
-            oversteer_alert: tone_generator::Config::default(),
+            oversteer_alert_threshold_left: None,
+            oversteer_alert_threshold_right: None,
+            alert_min_on_ms: 0,
+            alert_hysteresis: 0.0,
+            oversteer_alert: sounds::Config::default(),
+            rumble_audio_enabled: false,
+            rumble_audio: sounds::RumbleConfig::default(),
+            button_click_feedback: sounds::ClickConfig::default(),
+            audio_cues: sounds::CueConfig::default(),
+            understeer_alert: UndersteerAlert::default(),
+            alert_fallback: AlertFallback::default(),
+            audio_start_muted: false,
+
+            exclusive_groups: Vec::new(),
+            auto_sprint: AutoSprint::default(),
+            auto_stick_click: AutoStickClick::default(),
+            dpad_mode: DpadMode::default(),
 
             analog_circularize: false,
+            swap_analogs: false,
+            analog_min_output: PerStick::default(),
+            anti_deadzone: PerStick::default(),
+            anti_deadzone_scope: AntiDeadzoneScope::default(),
+            max_stick_output: PerStick {
+                left: 1.0,
+                right: 1.0,
+            },
             mouse_button_fix: false,
+            suppress_key_repeat: true,
+
+            send_keys_delay_ms: 20,
 
+            timing: Timing::default(),
+
+            preset: None,
             binds: HashMap::new(),
+            bind_options: HashMap::new(),
+            sequences: Vec::new(),
+            gestures: Vec::new(),
+            zones: Vec::new(),
+            radial: None,
+            targets: Vec::new(),
+            mirror_targets: 0,
+            rumble_action: None,
+            dsu_server: None,
+            gyro: None,
+            trace_file: None,
+
+            passthrough: false,
+            profiles: HashMap::new(),
+            layers: HashMap::new(),
+            window_profiles: HashMap::new(),
         }
     }
 }
 
-#[derive(Debug)]
-pub enum AnalogType {
-    Left,
-    Right,
-}
-
-
-#[derive(Debug)]
-pub struct AnalogState {
-    analog_type: AnalogType,
-    x: f64,
-    y: f64,
-}
+impl Config {
+    // The x and y sensitivity of a mouse bind, its own if its action or bind_options give one
+    fn sensitivity(&self, bind: &Bind) -> (f64, f64) {
+        let own = match self.binds.get(bind) {
+            Some(&ControllerAction::AnalogLeft(_, _, own))
+            | Some(&ControllerAction::AnalogRight(_, _, own)) => own,
+            _ => None,
+        };
 
-impl Display for AnalogType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AnalogType::Left => write!(f, "Left"),
-            AnalogType::Right => write!(f, "Right")
+        let options = self.bind_options.get(bind);
+        match own.or_else(|| options.and_then(|options| options.sensitivity)) {
+            Some(sensitivity) => (sensitivity, sensitivity),
+            None => (
+                self.sensitivity_x.unwrap_or(self.sensitivity),
+                self.sensitivity_y.unwrap_or(self.sensitivity),
+            ),
         }
     }
-}
 
+    // The timing of each bind whose bind_options change it, for the log
+    fn timing_overrides(&self) -> Vec<(Bind, Timing)> {
+        let mut overrides: Vec<(Bind, Timing)> = self
+            .bind_options
+            .iter()
+            .map(|(&bind, options)| (bind, self.timing.with_overrides(options)))
+            .filter(|&(_, timing)| timing != self.timing)
+            .collect();
 
-impl Display for AnalogState {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "AnalogType: {}, x: {:.2}, y: {:.2}",
-            self.analog_type, self.x, self.y
+        overrides.sort_by_key(|(bind, _)| bind.to_string());
+        overrides
+    }
+
+    fn oversteer_alert_threshold(&self, analog_type: AnalogType) -> f64 {
+        let threshold = match analog_type {
+            AnalogType::Left => self.oversteer_alert_threshold_left,
+            AnalogType::Right => self.oversteer_alert_threshold_right,
+        };
+
+        threshold.unwrap_or(self.oversteer_alert_threshold)
+    }
+
+    // The profile window_profiles gives window, by its executable, then the longest part of its
+    // title, then "*"
+    fn window_profile(&self, window: &Window) -> Option<&str> {
+        let by_exe = self
+            .window_profiles
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&window.exe));
+        let by_title = self
+            .window_profiles
+            .iter()
+            .filter(|(key, _)| *key != "*" && window.title.contains(key.as_str()))
+            .max_by_key(|(key, _)| key.len());
+
+        by_exe
+            .or(by_title)
+            .or_else(|| self.window_profiles.get_key_value("*"))
+            .map(|(_, profile)| profile.as_str())
+    }
+
+    // The settings while switched to profile with layers on: the last layer's, with the binds
+    // each layer changes going on top of the profile's in turn. Without profiles or layers of
+    // their own
+    fn resolved<'a>(&self, profile: &str, layers: impl IntoIterator<Item = &'a String>) -> Config {
+        let profile = self.profiles.get(profile).unwrap_or(self);
+        let layers: Vec<&Config> = layers
+            .into_iter()
+            .filter_map(|name| profile.layers.get(name))
+            .collect();
+
+        let mut config = layers.last().copied().unwrap_or(profile).clone();
+        config.binds = profile.binds.clone();
+        for layer in layers {
+            let changed = layer
+                .binds
+                .iter()
+                .filter(|&(b, a)| profile.binds.get(b) != Some(a));
+            config.binds.extend(changed.map(|(&b, a)| (b, a.clone())));
+        }
+
+        config.profiles.clear();
+        config.layers.clear();
+        config
+    }
+
+    // Each profile and layer by what to call it, a profile's layers included
+    fn overlays(&self) -> Vec<(String, &Config)> {
+        let mut overlays = Vec::new();
+
+        for (name, profile) in self.profiles.iter() {
+            overlays.push((format!("profile \"{}\"", name), profile));
+            for (layer, config) in profile.layers.iter() {
+                let name = format!("profile \"{}\" layer \"{}\"", name, layer);
+                overlays.push((name, config));
+            }
+        }
+        for (name, layer) in self.layers.iter() {
+            overlays.push((format!("layer \"{}\"", name), layer));
+        }
+
+        overlays
+    }
+
+    // The profiles and layers, a profile's own layers are reached through it
+    fn overlays_mut(&mut self) -> impl Iterator<Item = &mut Config> {
+        self.profiles.values_mut().chain(self.layers.values_mut())
+    }
+
+    // What the controllers, DSU server and tracing were started with, from old, in this and its
+    // profiles and layers, as those only change on restart
+    fn keep_started(&mut self, old: &Config) {
+        self.require_slot = old.require_slot;
+        self.mirror_targets = old.mirror_targets;
+        self.dsu_server = old.dsu_server.clone();
+        self.trace_file = old.trace_file.clone();
+        if self.targets.len() != old.targets.len() {
+            self.targets = old.targets.clone();
+        }
+
+        for overlay in self.overlays_mut() {
+            overlay.keep_started(old);
+        }
+    }
+
+    // Everything the sounds are made from, to tell whether they have to start again
+    #[cfg(feature = "audio")]
+    fn audio_settings(&self) -> String {
+        let settings = (
+            (self.oversteer_alert_enabled, self.oversteer_alert),
+            (self.rumble_audio_enabled, self.rumble_audio),
+            (self.button_click_feedback, self.audio_cues),
+            (self.understeer_alert.enabled, self.understeer_alert.tone),
+            self.alert_fallback,
+        );
+
+        ron::to_string(&settings).unwrap_or_default()
+    }
+
+    // What the profile cue beeps, once for the own binds and then counting up through the
+    // profiles by name
+    #[cfg(feature = "audio")]
+    fn profile_number(&self, profile: &str) -> u32 {
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+
+        match names.iter().position(|name| *name == profile) {
+            Some(i) => i as u32 + 2,
+            None => 1,
+        }
+    }
+
+    fn sample_windows(&self) -> (Duration, Duration) {
+        (
+            self.sample_window_x.unwrap_or(self.sample_window),
+            self.sample_window_y.unwrap_or(self.sample_window),
         )
     }
+
+    fn connect_retry(&self) -> ConnectRetry {
+        ConnectRetry {
+            retries: self.vigem_connect_retries,
+            delay: Duration::from_millis(self.vigem_connect_delay_ms),
+        }
+    }
+
+    // Checks the whole config and lists every problem found in one error, so a file with several
+    // mistakes can be fixed in one go rather than one restart at a time
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let mut problems = Vec::new();
+        self.check(&mut problems);
+
+        // What a profile or layer has from the settings around it is only said once
+        let around = problems.clone();
+        let dsu_server = ron::to_string(&self.dsu_server).ok();
+        for (name, overlay) in self.overlays() {
+            let mut own = Vec::new();
+            overlay.check(&mut own);
+            own.retain(|problem| !around.contains(problem));
+
+            for (setting, changed) in [
+                ("require_slot", overlay.require_slot != self.require_slot),
+                (
+                    "the number of targets",
+                    overlay.targets.len() != self.targets.len(),
+                ),
+                (
+                    "mirror_targets",
+                    overlay.mirror_targets != self.mirror_targets,
+                ),
+                (
+                    "dsu_server",
+                    ron::to_string(&overlay.dsu_server).ok() != dsu_server,
+                ),
+                ("trace_file", overlay.trace_file != self.trace_file),
+            ] {
+                if changed {
+                    own.push(format!(
+                        "{} can't be changed by a profile or layer",
+                        setting
+                    ));
+                }
+            }
+
+            problems.extend(
+                own.into_iter()
+                    .map(|problem| format!("{}: {}", name, problem)),
+            );
+        }
+
+        if self.profiles.contains_key(DEFAULT_PROFILE) {
+            problems.push(format!(
+                "\"{}\" is the binds without a profile",
+                DEFAULT_PROFILE
+            ));
+        }
+
+        let overlays = self.overlays();
+        let all_binds =
+            std::iter::once(&self.binds).chain(overlays.iter().map(|(_, overlay)| &overlay.binds));
+
+        let mut switched_to: Vec<&String> = self.window_profiles.values().collect();
+        let mut layered: Vec<&String> = Vec::new();
+        for binds in all_binds {
+            switched_to.extend(profile_names(binds));
+            layered.extend(layer_names(binds));
+        }
+        switched_to.sort();
+        switched_to.dedup();
+        layered.sort();
+        layered.dedup();
+
+        for name in switched_to {
+            if name != DEFAULT_PROFILE && !self.profiles.contains_key(name) {
+                problems.push(format!("there is no profile called \"{}\"", name));
+            }
+        }
+        for name in layered {
+            if !self.layers.contains_key(name) {
+                problems.push(format!("there is no layer called \"{}\"", name));
+            }
+        }
+
+        match problems.len() {
+            0 => Ok(()),
+            1 => anyhow::bail!("{}", problems[0]),
+            n => anyhow::bail!(
+                "{} problems with the config:\n    {}",
+                n,
+                problems.join("\n    ")
+            ),
+        }
+    }
+
+    // The problems with the settings of a config, or of a profile or layer, on their own
+    fn check(&self, problems: &mut Vec<String>) {
+        use Range::{Above, AtLeast, Within};
+
+        if let Some(slot) = self.require_slot {
+            if slot > 3 {
+                problems.push(format!("require_slot must be 0 to 3, not {}", slot));
+            }
+
+            if self.controller_type != ControllerType::Xbox360 {
+                problems.push("require_slot is only for Xbox360 controllers".to_owned());
+            }
+        }
+
+        let audio = ("audio", cfg!(feature = "audio"));
+        for (setting, enabled, (feature, built)) in [
+            (
+                "oversteer_alert_enabled is set",
+                self.oversteer_alert_enabled,
+                audio,
+            ),
+            (
+                "rumble_audio_enabled is set",
+                self.rumble_audio_enabled,
+                audio,
+            ),
+            (
+                "understeer_alert is enabled",
+                self.understeer_alert.enabled,
+                audio,
+            ),
+            ("audio_cues is enabled", self.audio_cues.enabled, audio),
+            (
+                "button_click_feedback is enabled",
+                self.button_click_feedback.enabled,
+                audio,
+            ),
+            (
+                "dsu_server is set",
+                self.dsu_server.is_some(),
+                ("dsu", cfg!(feature = "dsu")),
+            ),
+            (
+                "trace_file is set",
+                self.trace_file.is_some(),
+                ("trace", cfg!(feature = "trace")),
+            ),
+        ] {
+            if enabled && !built {
+                problems.push(format!(
+                    "{}, but kmxpad was built without the \"{}\" feature",
+                    setting, feature
+                ));
+            }
+        }
+
+        let (auto_stick_click, dpad_mode) = (&self.auto_stick_click, &self.dpad_mode);
+        for (name, value, range) in [
+            ("sensitivity", self.sensitivity, Above(0.0)),
+            ("axis_snap_deg", self.axis_snap_deg, Within(0.0, 45.0)),
+            (
+                "axis_snap_hysteresis_deg",
+                self.axis_snap_hysteresis_deg,
+                AtLeast(0.0),
+            ),
+            ("max_decay_per_sec", self.max_decay_per_sec, AtLeast(0.0)),
+            ("accel_threshold", self.accel_threshold, AtLeast(0.0)),
+            ("accel_multiplier", self.accel_multiplier, Above(0.0)),
+            ("accel_cap", self.accel_cap, AtLeast(0.0)),
+            (
+                "flick_stick.degrees_per_count",
+                self.flick_stick.degrees_per_count,
+                Above(0.0),
+            ),
+            (
+                "flick_stick.flick_threshold_deg",
+                self.flick_stick.flick_threshold_deg,
+                AtLeast(0.0),
+            ),
+            ("smoothing.tau_ms", self.smoothing.tau_ms, Above(0.0)),
+            (
+                "prediction_max_overshoot",
+                self.prediction_max_overshoot,
+                AtLeast(0.0),
+            ),
+            (
+                "oversteer_alert_threshold",
+                self.oversteer_alert_threshold,
+                Above(0.0),
+            ),
+            ("alert_hysteresis", self.alert_hysteresis, AtLeast(0.0)),
+            (
+                "understeer_alert.floor",
+                self.understeer_alert.floor,
+                Within(0.0, 1.0),
+            ),
+            (
+                "auto_sprint.threshold",
+                self.auto_sprint.threshold,
+                Within(0.0, 1.0),
+            ),
+            (
+                "auto_stick_click.threshold",
+                auto_stick_click.threshold,
+                Within(0.0, 1.0),
+            ),
+            (
+                "auto_stick_click.hysteresis",
+                auto_stick_click.hysteresis,
+                AtLeast(0.0),
+            ),
+            (
+                "dpad_mode.diagonal_threshold",
+                dpad_mode.diagonal_threshold,
+                Within(0.0, 45.0),
+            ),
+            ("dpad_mode.deadzone", dpad_mode.deadzone, Within(0.0, 1.0)),
+            (
+                "analog_min_output.left",
+                self.analog_min_output.left,
+                Within(0.0, 1.0),
+            ),
+            (
+                "analog_min_output.right",
+                self.analog_min_output.right,
+                Within(0.0, 1.0),
+            ),
+            (
+                "anti_deadzone.left",
+                self.anti_deadzone.left,
+                Within(0.0, 1.0),
+            ),
+            (
+                "anti_deadzone.right",
+                self.anti_deadzone.right,
+                Within(0.0, 1.0),
+            ),
+            (
+                "max_stick_output.left",
+                self.max_stick_output.left,
+                Within(0.0, 1.0),
+            ),
+            (
+                "max_stick_output.right",
+                self.max_stick_output.right,
+                Within(0.0, 1.0),
+            ),
+        ] {
+            check_range(problems, name, value, range);
+        }
+
+        for (name, value) in [
+            ("sensitivity_x", self.sensitivity_x),
+            ("sensitivity_y", self.sensitivity_y),
+            (
+                "oversteer_alert_threshold_left",
+                self.oversteer_alert_threshold_left,
+            ),
+            (
+                "oversteer_alert_threshold_right",
+                self.oversteer_alert_threshold_right,
+            ),
+        ] {
+            if let Some(value) = value {
+                check_range(problems, name, value, Above(0.0));
+            }
+        }
+
+        // At or past the threshold the stick never gets far enough back for them to let go
+        let threshold = self
+            .oversteer_alert_threshold(AnalogType::Left)
+            .min(self.oversteer_alert_threshold(AnalogType::Right));
+        if self.oversteer_alert_enabled && threshold > 0.0 && self.alert_hysteresis >= threshold {
+            problems.push(format!(
+                "alert_hysteresis ({}) must be under the oversteer alert threshold ({}), or the \
+                 alert never stops",
+                self.alert_hysteresis, threshold
+            ));
+        }
+
+        if auto_stick_click.enabled && auto_stick_click.hysteresis >= auto_stick_click.threshold {
+            problems.push(format!(
+                "auto_stick_click.hysteresis ({}) must be under its threshold ({}), or the button \
+                 is never let go",
+                auto_stick_click.hysteresis, auto_stick_click.threshold
+            ));
+        }
+
+        // A loop waits up to spin_period for input, longer than a window it's always empty
+        let mut windows = vec![("sample_window", self.sample_window)];
+        windows.extend(
+            self.sample_window_x
+                .map(|window| ("sample_window_x", window)),
+        );
+        windows.extend(
+            self.sample_window_y
+                .map(|window| ("sample_window_y", window)),
+        );
+        if self.adaptive_window {
+            windows.push(("sample_window_min", self.sample_window_min));
+        }
+
+        for (name, window) in windows {
+            if window.is_zero() {
+                problems.push(format!("{} can't be 0", name));
+            } else if self.spin_period > window {
+                problems.push(format!(
+                    "spin_period ({:?}) can't be longer than {} ({:?})",
+                    self.spin_period, name, window
+                ));
+            }
+        }
+
+        if self.adaptive_window {
+            let (min, max) = (self.sample_window_min, self.sample_window_max);
+            if max < min || self.adaptive_window_rate <= 0.0 {
+                problems.push(
+                    "adaptive_window needs sample_window_min <= sample_window_max and \
+                     adaptive_window_rate above 0"
+                        .to_owned(),
+                );
+            }
+        }
+
+        if let Some(mouse_trigger) = &self.mouse_trigger {
+            let sensitivity = mouse_trigger.sensitivity;
+            check_range(
+                problems,
+                "mouse_trigger.sensitivity",
+                sensitivity,
+                Above(0.0),
+            );
+        }
+
+        for (i, sequence) in self.sequences.iter().enumerate() {
+            if sequence.window_ms == 0 {
+                problems.push(format!("sequences[{}].window_ms can't be 0", i));
+            }
+        }
+
+        for (i, gesture) in self.gestures.iter().enumerate() {
+            let name = format!("gestures[{}].velocity", i);
+            check_range(problems, &name, gesture.velocity, Above(0.0));
+        }
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            let name = |field: &str| format!("zones[{}].{}", i, field);
+            check_range(problems, &name("hysteresis"), zone.hysteresis, AtLeast(0.0));
+
+            match zone.region {
+                Region::Axis { threshold, .. } => {
+                    let name = name("region.threshold");
+                    check_range(problems, &name, threshold, Within(-1.0, 1.0));
+                }
+                Region::Sector {
+                    width, magnitude, ..
+                } => {
+                    let (width_name, magnitude_name) =
+                        (name("region.width"), name("region.magnitude"));
+                    check_range(problems, &width_name, width, Within(0.0, 360.0));
+                    check_range(problems, &magnitude_name, magnitude, Within(0.0, 1.0));
+                }
+            }
+        }
+
+        if let Some(radial) = &self.radial {
+            check_range(
+                problems,
+                "radial.magnitude",
+                radial.magnitude,
+                Within(0.0, 1.0),
+            );
+        }
+
+        for (i, target) in self.targets.iter().enumerate() {
+            for (bind, action) in target.binds.iter() {
+                check_target_bind(&format!("targets[{}]", i), bind, action, &mut problems);
+            }
+        }
+
+        for (bind, options) in self.bind_options.iter().filter(|(_, o)| o.target > 0) {
+            let target = options.target;
+            if target > self.targets.len() {
+                problems.push(format!(
+                    "the bind_options for {:?} put it on target {}, but targets only has {}",
+                    bind,
+                    target,
+                    self.targets.len()
+                ));
+                continue;
+            }
+
+            if let Some(action) = self.binds.get(bind) {
+                check_target_bind(&format!("target {}", target), bind, action, &mut problems);
+            }
+            if self.targets[target - 1].binds.contains_key(bind) {
+                problems.push(format!(
+                    "{:?} is put on target {} by bind_options, but targets[{}] binds it too",
+                    bind,
+                    target,
+                    target - 1
+                ));
+            }
+        }
+
+        let area = self.absolute_area;
+        if area.right <= area.left || area.bottom <= area.top {
+            problems
+                .push("absolute_area must have right above left and bottom above top".to_owned());
+        }
+
+        for (bind, action) in self.binds.iter() {
+            let analog = matches!(
+                action,
+                ControllerAction::AnalogLeft(..) | ControllerAction::AnalogRight(..)
+            );
+
+            if matches!(bind, Bind::MouseMoveDevice(_)) && !analog {
+                problems.push(format!(
+                    "{:?} must be bound to AnalogLeft or AnalogRight",
+                    bind
+                ));
+            }
+        }
+
+        check_binds("binds", &self.binds, problems);
+        for (i, target) in self.targets.iter().enumerate() {
+            check_binds(&format!("targets[{}]", i), &target.binds, problems);
+        }
+
+        self.oversteer_alert.validate("oversteer_alert", problems);
+        self.rumble_audio.validate("rumble_audio", problems);
+        self.button_click_feedback
+            .validate("button_click_feedback", problems);
+        self.audio_cues.validate("audio_cues", problems);
+
+        let (understeer, alert) = (&self.understeer_alert, &self.oversteer_alert);
+        understeer
+            .tone
+            .validate("understeer_alert.tone", alert, problems);
+
+        let toggle_gyro = self
+            .binds
+            .values()
+            .any(|a| matches!(a, ControllerAction::ToggleGyro));
+        match &self.gyro {
+            Some(_) if self.dsu_server.is_none() => problems.push(
+                "gyro is set, but only dsu_server carries motion and it isn't set".to_owned(),
+            ),
+            Some(gyro) => gyro.validate(problems),
+            None if toggle_gyro => {
+                problems.push("ToggleGyro is bound, but gyro isn't set".to_owned())
+            }
+            None => {}
+        }
+
+        self.response_curve.validate(problems);
+        self.timing.validate("timing", problems);
+
+        for (bind, options) in self.bind_options.iter() {
+            let owner = format!("the bind_options for {:?}", bind);
+            self.timing
+                .with_overrides(options)
+                .validate(&owner, problems);
+
+            if let Some(sensitivity) = options.sensitivity {
+                let name = format!("{}.sensitivity", owner);
+                check_range(problems, &name, sensitivity, Above(0.0));
+            }
+        }
+    }
+
+    // Checks the config and fills in what it leaves to be worked out, before it's used
+    fn prepare(&mut self) -> Result<(), anyhow::Error> {
+        self.apply_preset()?;
+        self.validate()?;
+        self.expand();
+
+        if !self.binds.contains_key(&Bind::MouseMove) {
+            // Unless it's on another controller, which the first then leaves alone
+            if !self.mouse_move_routed() {
+                error!("MouseMove is not bound to any analog.\nTry to add:\n-> MouseMove: AnalogRight(1, 1) <-\n to the binds in your config.ron, with invert_mouse_y: true");
+                let action = ControllerAction::AnalogRight(1.0, -1.0, None);
+                self.binds.insert(Bind::MouseMove, action);
+            }
+        } else {
+            let bind = self.binds.get_mut(&Bind::MouseMove).unwrap();
+            match bind {
+                ControllerAction::Button(_) => {
+                    error!("MouseMove is not bound to any analog. Instead, it is bound to Button which is not allowed. Appropriate values are:\nAnalogRight(x, y) and AnalogLeft(x, y)");
+                    *bind = ControllerAction::AnalogRight(1.0, -1.0, None);
+                }
+                _ => {}
+            }
+        }
+        if let Some(
+            &ControllerAction::AnalogLeft(x, y, _) | &ControllerAction::AnalogRight(x, y, _),
+        ) = self.binds.get(&Bind::MouseMove)
+        {
+            if (x < 0.0 && self.invert_mouse_x) || (y < 0.0 && self.invert_mouse_y) {
+                warn!("MouseMove has a negative scale on an axis that is also inverted, the two cancel out");
+            }
+        }
+        for overlay in self.overlays_mut() {
+            overlay.default_mouse_move();
+        }
+
+        self.warn_conflicts();
+
+        for bind in self.binds.keys() {
+            if let &Bind::Keyboard(code, None) = bind {
+                if Key::has_e0_twin(code) {
+                    warn!(
+                        "Keyboard({:?}) matches two keys, add Some(false) or Some(true) to pick one",
+                        code
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // MouseMove on the right stick in a profile or layer that has it on no stick, which is only
+    // logged for the config's own binds. The -1 keeps pushing the mouse away pushing the stick up
+    // without invert_mouse_y, which is off unless the config sets it
+    fn default_mouse_move(&mut self) {
+        let bound = self.binds.get(&Bind::MouseMove);
+        let missing = bound.is_none() && !self.mouse_move_routed();
+        if missing || matches!(bound, Some(ControllerAction::Button(_))) {
+            let action = ControllerAction::AnalogRight(1.0, -1.0, None);
+            self.binds.insert(Bind::MouseMove, action);
+        }
+
+        for overlay in self.overlays_mut() {
+            overlay.default_mouse_move();
+        }
+    }
+
+    // Binds that fight over a stick: a key pushing the stick MouseMove moves, and keys pushing
+    // it the same way, which can't be told apart
+    fn warn_conflicts(&self) {
+        let stick = |action: &ControllerAction| match *action {
+            ControllerAction::AnalogLeft(x, y, _) => Some((AnalogType::Left, x, y)),
+            ControllerAction::AnalogRight(x, y, _) => Some((AnalogType::Right, x, y)),
+            _ => None,
+        };
+
+        let mouse = self.binds.get(&Bind::MouseMove).and_then(stick);
+
+        let mut pushes: Vec<(String, AnalogType, f64, f64)> = self
+            .binds
+            .iter()
+            .filter(|(bind, _)| !matches!(bind, Bind::MouseMove | Bind::MouseMoveDevice(_)))
+            .filter_map(|(bind, action)| stick(action).map(|(s, x, y)| (bind.to_string(), s, x, y)))
+            .collect();
+
+        pushes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (i, (bind, stick, x, y)) in pushes.iter().enumerate() {
+            let name = stick.to_string().to_lowercase();
+
+            if matches!(mouse, Some((mouse_stick, ..)) if mouse_stick == *stick) {
+                warn!("{} pushes the {} stick, MouseMove moves it too", bind, name);
+            }
+
+            for (other, ..) in pushes[i + 1..]
+                .iter()
+                .filter(|other| (other.1, other.2, other.3) == (*stick, *x, *y))
+            {
+                warn!("{} and {} push the {} stick alike", bind, other, name);
+            }
+        }
+    }
+
+    // The preset's binds, with binds on top, in the profiles and layers as well
+    fn apply_preset(&mut self) -> Result<(), anyhow::Error> {
+        for overlay in self.overlays_mut() {
+            overlay.apply_preset()?;
+        }
+
+        let name = match &self.preset {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        let mut binds = match presets::binds(name) {
+            Some(binds) => binds,
+            None => {
+                let names: Vec<&str> = PRESETS.iter().map(|&(name, _)| name).collect();
+                anyhow::bail!("no preset \"{}\", there's {}", name, names.join(", "));
+            }
+        };
+
+        binds.extend(self.binds.drain());
+        self.binds = binds;
+
+        Ok(())
+    }
+
+    // Fills in the radial and Modifier binds and moves the binds for other controllers to them,
+    // in the profiles and layers as well
+    fn expand(&mut self) {
+        self.expand_radial();
+        self.expand_modifiers();
+        self.route_targets();
+
+        for overlay in self.overlays_mut() {
+            overlay.expand();
+        }
+    }
+
+    // Rewrites Modifier binds as the Keyboard binds for the same key
+    fn expand_modifiers(&mut self) {
+        let expand = |bind: Bind| match bind {
+            Bind::Modifier(modifier) => {
+                let key = modifier.key();
+                Bind::Keyboard(key.code, Some(key.e0))
+            }
+            bind => bind,
+        };
+
+        self.binds = self
+            .binds
+            .drain()
+            .map(|(bind, action)| (expand(bind), action))
+            .collect();
+        self.bind_options = self
+            .bind_options
+            .drain()
+            .map(|(bind, options)| (expand(bind), options))
+            .collect();
+
+        for sequence in self.sequences.iter_mut() {
+            for key in sequence.keys.iter_mut() {
+                *key = expand(*key);
+            }
+        }
+    }
+
+    // Moves the binds bind_options put on another controller to that target's binds
+    fn route_targets(&mut self) {
+        for (bind, options) in self.bind_options.iter() {
+            let target = match options.target.checked_sub(1) {
+                Some(i) => &mut self.targets[i],
+                None => continue,
+            };
+
+            if let Some(action) = self.binds.remove(bind) {
+                target.binds.insert(*bind, action);
+            }
+        }
+    }
+
+    // Whether bind_options put MouseMove on a controller after the first
+    fn mouse_move_routed(&self) -> bool {
+        let options = self.bind_options.get(&Bind::MouseMove);
+        options.is_some_and(|options| options.target > 0)
+    }
+
+    // Turns the radial helper into the binds it stands for
+    fn expand_radial(&mut self) {
+        let radial = match self.radial.take() {
+            Some(radial) => radial,
+            None => return,
+        };
+
+        let step = 360.0 / radial.keys.len().max(1) as f64;
+
+        for (i, &key) in radial.keys.iter().enumerate() {
+            let action = ControllerAction::AnalogPolar {
+                stick: radial.stick,
+                angle: radial.start_angle - i as f64 * step,
+                magnitude: radial.magnitude,
+            };
+
+            if self.binds.insert(key, action).is_some() {
+                warn!("{:?} is also part of radial, using the radial bind", key);
+            }
+        }
+    }
 }
 
-pub struct EventHandler {
-    config: Config,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalogType {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Charge {
+    started: Instant,
+    full_charge: Duration,
+    hold_at: f64,
+    release: ChargeRelease,
+
+    // When the key was let go and the value the ramp down starts from
+    released: Option<(Instant, u8)>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TriggerState {
+    held: bool,
+    accumulated: u8,
+    // Set by mouse_trigger from the mouse speed
+    mouse: u8,
+
+    charge: Option<Charge>,
+    charged: u8,
+}
+
+impl TriggerState {
+    fn value(&self) -> u8 {
+        match self.held {
+            true => u8::MAX,
+            false => self.accumulated.max(self.charged).max(self.mouse),
+        }
+    }
+
+    fn update_charge(&mut self, now: Instant) {
+        let charge = match self.charge {
+            Some(charge) => charge,
+            None => return,
+        };
+
+        let full_charge = charge.full_charge.as_secs_f64().max(f64::EPSILON);
+
+        let level = match charge.released {
+            Some((released, from)) => {
+                let drained = (now - released).as_secs_f64() / full_charge;
+                from as f64 / u8::MAX as f64 - drained
+            }
+            None => ((now - charge.started).as_secs_f64() / full_charge).min(charge.hold_at),
+        };
+
+        self.charged = (level.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8;
+
+        if charge.released.is_some() && self.charged == 0 {
+            self.charge = None;
+        }
+    }
+}
+
+// Holds action while either rumble motor runs faster than threshold (0 to 255)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RumbleAction {
+    threshold: u8,
+    action: ControllerAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DsuConfig {
+    // 26760 is the port emulators look on by default
+    address: String,
+}
+
+impl Default for DsuConfig {
+    fn default() -> Self {
+        DsuConfig {
+            address: "127.0.0.1:26760".to_string(),
+        }
+    }
+}
+
+// The name of the config's own binds, as a profile
+const DEFAULT_PROFILE: &str = "default";
+
+// Whether a controller after the first can take action bound to bind
+fn check_target_bind(
+    owner: &str,
+    bind: &Bind,
+    action: &ControllerAction,
+    problems: &mut Vec<String>,
+) {
+    let analog = matches!(
+        action,
+        ControllerAction::AnalogLeft(..) | ControllerAction::AnalogRight(..)
+    );
+    let button = matches!(
+        action,
+        ControllerAction::Button(_) | ControllerAction::Buttons(_)
+    );
+
+    let supported = match bind {
+        Bind::Modifier(_) | Bind::MouseMoveDevice(_) => false,
+        Bind::MouseMove => analog,
+        _ => analog || button,
+    };
+
+    if !supported {
+        problems.push(format!(
+            "{} binds {:?} to {:?}, other controllers only take Keyboard, Mouse, MouseWheel \
+             and MouseMove binds to Button, Buttons, AnalogLeft and AnalogRight (MouseMove only \
+             to the last two)",
+            owner, bind, action
+        ));
+    }
+}
+
+// Stick values past full deflection, and the same key bound both by name and as a Modifier
+fn check_binds(owner: &str, binds: &HashMap<Bind, ControllerAction>, problems: &mut Vec<String>) {
+    for (bind, action) in binds.iter() {
+        if let Bind::Modifier(modifier) = bind {
+            let key = modifier.key();
+            if binds.contains_key(&Bind::Keyboard(key.code, Some(key.e0))) {
+                problems.push(format!(
+                    "{}: {} is bound by name and as a Modifier",
+                    owner, bind
+                ));
+            }
+        }
+
+        check_action(owner, bind, action, problems);
+
+        // MouseMove's are scales, not positions
+        if matches!(bind, Bind::MouseMove | Bind::MouseMoveDevice(_)) {
+            continue;
+        }
+
+        if let &ControllerAction::AnalogLeft(x, y, _) | &ControllerAction::AnalogRight(x, y, _) =
+            action
+        {
+            if !(-1.0..=1.0).contains(&x) || !(-1.0..=1.0).contains(&y) {
+                problems.push(format!(
+                    "{}: {} is bound to {}, off the stick, x and y must be -1 to 1",
+                    owner,
+                    bind,
+                    bind_table::describe(action)
+                ));
+            }
+        }
+    }
+}
+
+// The numbers within action that have a range, for check_binds
+fn check_action(owner: &str, bind: &Bind, action: &ControllerAction, problems: &mut Vec<String>) {
+    use Range::{Above, Within};
+
+    let name = |field: &str| format!("{}: the {} of {}", owner, field, bind);
+    match *action {
+        ControllerAction::AnalogLeft(_, _, Some(sensitivity))
+        | ControllerAction::AnalogRight(_, _, Some(sensitivity)) => {
+            check_range(problems, &name("sensitivity"), sensitivity, Above(0.0))
+        }
+        ControllerAction::TriggerAccumulate { step, .. } => {
+            check_range(problems, &name("step"), step, Within(-1.0, 1.0))
+        }
+        ControllerAction::TriggerCharge { hold_at, .. } => {
+            check_range(problems, &name("hold_at"), hold_at, Within(0.0, 1.0))
+        }
+        ControllerAction::AnalogPolar { magnitude, .. } => {
+            check_range(problems, &name("magnitude"), magnitude, Within(0.0, 1.0))
+        }
+        ControllerAction::ScaleSensitivity { factor, .. } => {
+            check_range(problems, &name("factor"), factor, Above(0.0))
+        }
+        ControllerAction::OnRelease(ref inner) => check_action(owner, bind, inner, problems),
+        _ => {}
+    }
+}
+
+// What a number in the config has to be
+#[derive(Clone, Copy)]
+enum Range {
+    Above(f64),
+    AtLeast(f64),
+    // Both ends included
+    Within(f64, f64),
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Range::Above(min) => write!(f, "above {}", min),
+            Range::AtLeast(min) => write!(f, "at least {}", min),
+            Range::Within(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+// Adds a problem when value (NaN included) is outside range, name being where it's set
+fn check_range(problems: &mut Vec<String>, name: &str, value: f64, range: Range) {
+    let within = match range {
+        Range::Above(min) => value > min,
+        Range::AtLeast(min) => value >= min,
+        Range::Within(min, max) => (min..=max).contains(&value),
+    };
+
+    if !within {
+        problems.push(format!("{} is {}, it must be {}", name, value, range));
+    }
+}
+
+// The profiles binds switch to
+fn profile_names(binds: &HashMap<Bind, ControllerAction>) -> impl Iterator<Item = &String> {
+    binds.values().filter_map(|action| match action {
+        ControllerAction::Profile(name) => Some(name),
+        _ => None,
+    })
+}
+
+// The layers binds turn on
+fn layer_names(binds: &HashMap<Bind, ControllerAction>) -> impl Iterator<Item = &String> {
+    binds.values().filter_map(|action| match action {
+        ControllerAction::Layer(name) | ControllerAction::ToggleLayer(name) => Some(name),
+        _ => None,
+    })
+}
+
+// The binds of the actions that differ between old and new, with what old had them do
+fn changed_binds(
+    old: &HashMap<Bind, ControllerAction>,
+    new: &HashMap<Bind, ControllerAction>,
+) -> Vec<(Bind, ControllerAction)> {
+    old.iter()
+        .filter(|&(bind, action)| new.get(bind) != Some(action))
+        .map(|(&bind, action)| (bind, action.clone()))
+        .collect()
+}
+
+// A further virtual controller with binds of its own, which can only press buttons (and
+// triggers) and push the sticks. MouseMove uses the same mouse velocity as the first controller
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TargetConfig {
+    #[serde(deserialize_with = "bind_map::actions")]
+    binds: HashMap<Bind, ControllerAction>,
+}
+
+pub struct ExtraTarget {
+    report: XUSBReport,
+    last_sent: Option<(XUSBReport, Instant)>,
+    // The inputs bound on this controller that are held down
+    held: HashSet<Bind>,
+}
+
+impl ExtraTarget {
+    // mouse_vel is in full stick deflections, like a key pushing the stick all the way
+    fn update_report(&mut self, binds: &HashMap<Bind, ControllerAction>, mouse_vel: (f64, f64)) {
+        let mut report = XUSBReport::default();
+        let mut sticks = [(0.0, 0.0); 2];
+
+        let keys = self.held.iter().map(|bind| (bind, (1.0, 1.0)));
+        let inputs = keys.chain([(&Bind::MouseMove, mouse_vel)]);
+
+        for (bind, (input_x, input_y)) in inputs {
+            match binds.get(bind) {
+                Some(&ControllerAction::Button(button)) => press(&mut report, button),
+                Some(ControllerAction::Buttons(buttons)) => {
+                    for &button in buttons.iter() {
+                        press(&mut report, button);
+                    }
+                }
+                Some(&ControllerAction::AnalogLeft(x, y, _)) => {
+                    sticks[0].0 += x * input_x;
+                    sticks[0].1 += y * input_y;
+                }
+                Some(&ControllerAction::AnalogRight(x, y, _)) => {
+                    sticks[1].0 += x * input_x;
+                    sticks[1].1 += y * input_y;
+                }
+                _ => {}
+            }
+        }
+
+        // Kept within the circle like analog_circularize
+        let [left, right] = sticks.map(|(x, y)| {
+            let radius = (x * x + y * y).sqrt().max(1.0);
+            let value = |v: f64| (v / radius * EventHandler::ANALOG_MAX).min(i16::MAX as f64);
+            (value(x) as i16, value(y) as i16)
+        });
+
+        (report.s_thumb_lx, report.s_thumb_ly) = left;
+        (report.s_thumb_rx, report.s_thumb_ry) = right;
+
+        self.report = report;
+    }
+}
+
+// XUSBReport doesn't implement PartialEq
+fn same_report(a: &XUSBReport, b: &XUSBReport) -> bool {
+    a.w_buttons == b.w_buttons
+        && a.b_left_trigger == b.b_left_trigger
+        && a.b_right_trigger == b.b_right_trigger
+        && (a.s_thumb_lx, a.s_thumb_ly) == (b.s_thumb_lx, b.s_thumb_ly)
+        && (a.s_thumb_rx, a.s_thumb_ry) == (b.s_thumb_rx, b.s_thumb_ry)
+}
+
+// Sends report unless it is the one last sent to target and keepalive_ms hasn't passed since,
+// returning whether it was sent
+fn send_changed_report(
+    output: &mut dyn ControllerOutput,
+    index: usize,
+    report: &XUSBReport,
+    last_sent: &mut Option<(XUSBReport, Instant)>,
+    config: &Config,
+) -> Result<bool, anyhow::Error> {
+    let now = Instant::now();
+    let keepalive = Duration::from_millis(config.keepalive_ms);
+
+    if let Some((last_report, sent_at)) = last_sent {
+        let expired = config.keepalive_ms > 0 && now - *sent_at >= keepalive;
+
+        if same_report(report, last_report) && !expired {
+            return Ok(false);
+        }
+    }
+
+    output.update(index, report)?;
+    *last_sent = Some((*report, now));
+
+    Ok(true)
+}
+
+fn press(report: &mut XUSBReport, button: ControllerButton) {
+    match button {
+        ControllerButton::LeftTrigger => report.b_left_trigger = u8::MAX,
+        ControllerButton::RightTrigger => report.b_right_trigger = u8::MAX,
+        button => report.w_buttons |= XButton::from_bits(button as u16).unwrap(),
+    }
+}
+
+// Mouse counts on one axis over the last sample window
+#[derive(Debug, Default)]
+pub struct MouseWindow {
+    samples: VecDeque<(i32, Instant)>,
+    // Total of the counts in samples
+    sum: i64,
+}
+
+impl MouseWindow {
+    fn with_capacity(capacity: usize) -> Self {
+        MouseWindow {
+            samples: VecDeque::with_capacity(capacity),
+            sum: 0,
+        }
+    }
+
+    fn push(&mut self, count: i32, time: Instant) {
+        self.samples.push_back((count, time));
+        self.sum += count as i64;
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+        self.sum = 0;
+    }
+
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some(&(count, time)) = self.samples.front() {
+            if now - time <= window {
+                break;
+            }
+
+            self.sum -= count as i64;
+            self.samples.pop_front();
+        }
+    }
+
+    // Uniform weights are just the running sum, the others need each sample's age
+    // Samples older than window are left out, they are kept while an adaptive window is short
+    fn total(&self, now: Instant, window: Duration, weighting: WindowWeighting) -> f64 {
+        let covered = match self.samples.front() {
+            Some(&(_, time)) => now - time <= window,
+            None => true,
+        };
+
+        if weighting == WindowWeighting::Uniform && covered {
+            return self.sum as f64;
+        }
+
+        let window_secs = window.as_secs_f64();
+        let weight = |time: Instant| {
+            let age = (now - time).as_secs_f64() / window_secs;
+            weighting.weight(age.min(1.0))
+        };
+
+        self.samples
+            .iter()
+            .filter(|&&(_, time)| now - time <= window)
+            .map(|&(count, time)| count as f64 * weight(time))
+            .sum()
+    }
+
+    // total moved on by the trend between the older and newer half of the window, as if the
+    // window ended ahead into the future, changing it by at most max_overshoot (a fraction)
+    fn extrapolate(
+        &self,
+        total: f64,
+        now: Instant,
+        window: Duration,
+        ahead: Duration,
+        max_overshoot: f64,
+    ) -> f64 {
+        let half = window / 2;
+        let (mut older, mut newer) = (0, 0);
+        for &(count, time) in self.samples.iter() {
+            match now - time > half {
+                _ if now - time > window => {}
+                true => older += count as i64,
+                false => newer += count as i64,
+            }
+        }
+
+        // The window average is the velocity at its middle, the change per second is the
+        // difference between the halves over the time between their middles
+        let half = half.as_secs_f64();
+        let trend = (newer - older) as f64 / half / half;
+
+        let change = trend * (half + ahead.as_secs_f64()) * window.as_secs_f64();
+        let limit = total.abs() * max_overshoot;
+
+        total + change.clamp(-limit, limit)
+    }
+
+    // The net movement per sample
+    fn average(&self) -> f64 {
+        match self.samples.len() {
+            0 => 0.0,
+            len => self.sum.unsigned_abs() as f64 / len as f64,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AnalogState {
+    analog_type: AnalogType,
+    x: f64,
+    y: f64,
+}
+
+impl Display for AnalogType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalogType::Left => write!(f, "Left"),
+            AnalogType::Right => write!(f, "Right")
+        }
+    }
+}
+
+
+impl Display for AnalogState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "AnalogType: {}, x: {:.2}, y: {:.2}",
+            self.analog_type, self.x, self.y
+        )
+    }
+}
+
+// The first retry is straight away, after that the wait doubles up to RECONNECT_MAX_BACKOFF
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+struct Reconnect {
+    attempts: u32,
+    next_attempt: Instant,
+    backoff: Duration,
+}
+
+pub struct EventHandler {
+    config: Config,
+
+    rx: mpsc::Receiver<Event>,
+    inject_tx: mpsc::Sender<Keystroke>,
+
+    output: Box<dyn ControllerOutput>,
+    #[cfg(feature = "trace")]
+    tracer: Option<Tracer>,
+    report: XUSBReport,
+    // The report last sent and when, to skip sending it again unchanged
+    last_sent: Option<(XUSBReport, Instant)>,
+    // Set while the bus is gone
+    reconnect: Option<Reconnect>,
+    // When the last input came, and whether the controllers are away for lack of it
+    last_input: Instant,
+    idle: bool,
+
+    #[cfg(feature = "audio")]
+    tone_generator: Option<ToneGenerator>,
+    // Only there when the tone generator couldn't be
+    #[cfg(feature = "audio")]
+    beeper: Option<Beeper>,
+    audio_muted: bool,
+    // When the oversteer alert last started, while it sounds
+    alert_on_since: Option<Instant>,
+    // Since when the understeer_alert stick has been within its floor, while input is captured
+    understeer_since: Option<Instant>,
+    // Whether the toggle key has input captured, it starts let go
+    capturing: bool,
+
+    // The config as read, with its profiles and layers, config being the settings in use of it
+    base: Config,
+    profile: String,
+    // Switched to by a Profile bind, so window_profiles leaves it be until another window is
+    // in front
+    manual_profile: bool,
+    // The layers on, the last on top, each with what holds it on, None for one ToggleLayer
+    // turned on
+    active_layers: Vec<(String, Option<Source>)>,
+    // The id of the window in front
+    foreground: Option<usize>,
+
+    // What ScaleSensitivity binds have multiplied the x and y sensitivity by
+    sensitivity_scale: (f64, f64),
+    // Recent x and y mouse counts, each kept for its own axis' window
+    mouse_windows: (MouseWindow, MouseWindow),
+    // The last MouseMoveAbsolute position
+    absolute_position: Option<(i32, i32)>,
+    // Whether rumble_action is held
+    rumbling: bool,
+    // The mouse velocity the MouseMove stick was last given, for the other controllers
+    mouse_stick_vel: (f64, f64),
+    extra_targets: Vec<ExtraTarget>,
+    // Whether the mirror controllers get the first one's report, and what each was last sent
+    mirroring: bool,
+    mirror_last_sent: Vec<Option<(XUSBReport, Instant)>>,
+    // The same for each mouse with its own MouseMoveDevice bind
+    device_windows: HashMap<ic::Device, (MouseWindow, MouseWindow)>,
+    // When the last mouse sample arrived, roughly how often the mouse reports and the window
+    // last logged for it
+    last_sample_time: Option<Instant>,
+    report_interval: Option<Duration>,
+    logged_window: Duration,
+    // Where the adaptive window is between sample_window_min (0) and sample_window_max (1) and
+    // when it was last moved
+    adaptive_level: f64,
+    adaptive_updated: Instant,
+    // Fractions of a step lost converting each stick axis (lx, ly, rx, ry) to i16
+    stick_remainders: [f64; 4],
+    // Whether each stick (left, right) is moved by mouse speed and so carries its remainders,
+    // a held key's would flicker between the two values either side
+    stick_carry: [bool; 2],
+    // Small mouse samples jitter_filter is holding back, added up
+    jitter_pending: (i32, i32),
+    // Mouse velocity in counts per second for SmoothingMode::Ema, the counts that arrived since
+    // it was last updated and when that was
+    mouse_ema: (f64, f64),
+    mouse_ema_pending: (i32, i32),
+    mouse_ema_updated: Instant,
+    // The angle (degrees) the mouse driven stick is snapped to
+    axis_snapped: Option<f64>,
+    // The mouse driven stick output of the previous loop and when it was worked out
+    mouse_output: ((f64, f64), Instant),
+    // MouseMode::FlickStick angle in degrees (90 is up), the mouse x counts not yet applied to
+    // it, the angle held by a flick until when and whether a flick is still under way
+    flick_angle: f64,
+    flick_pending: i32,
+    flick_hold: Option<(f64, Instant)>,
+    flick_turning: bool,
+    mouse_button_states: (KeyState, KeyState),
+    key_states: HashMap<Key, KeyState>,
+    // Keys pressed through KeyRemap, released on reset
+    injected_keys: HashSet<Key>,
+    // Keystrokes SendKeys still has to type and when
+    queued_keystrokes: VecDeque<(Instant, Keystroke)>,
+
+    analog_state: HashMap<Source, AnalogState>,
+    // AnalogPolar binds currently held, in press order
+    polar_holds: Vec<(Source, AnalogState)>,
+    trigger_state: HashMap<Trigger, TriggerState>,
+    button_holds: Vec<(Source, ControllerButton)>,
+    suppressions: Vec<(Source, ControllerButton)>,
+
+    auto_sprint_since: Option<Instant>,
+    dpad_mode_active: bool,
+    // Whether the sticks are swapped, and whether to centre both for a report after SwapAnalogs
+    swap_analogs_active: bool,
+    centre_sticks: bool,
+    // Whether ToggleGyro has the gyro on, its rotation and the rotation last sent
+    gyro_active: bool,
+    gyro: [f32; 3],
+    sent_gyro: [f32; 3],
+
+    hold_deadlines: HashMap<Bind, Instant>,
+    pulse_deadlines: HashMap<(Source, ControllerButton), Instant>,
+    scheduled_releases: Vec<(Instant, Source, ControllerAction)>,
+    // TapHold presses not let go of yet: when each started, its hold action and whether that's
+    // pressed yet
+    tap_holds: HashMap<Source, (Instant, ControllerAction, bool)>,
+    // DoubleTap binds tapped once: when, and the single action done if no second tap follows
+    first_taps: HashMap<Source, (Instant, ControllerAction)>,
+
+    // The last keys pressed, as many as the longest of Config::sequences has, each numbered and
+    // with when it was pressed. The number of keys pressed when each sequence last matched, as
+    // its keys can't count again
+    sequence_keys: VecDeque<(usize, Bind, Instant)>,
+    sequence_matched: Vec<usize>,
+    // Whether each of Config::gestures is past its velocity and when it last fired
+    gesture_state: Vec<(bool, Option<Instant>)>,
+    // When the stick entered each of Config::zones and whether its action is held
+    zone_state: Vec<(Option<Instant>, bool)>,
+    auto_released: HashSet<Bind>,
+
+    iteration_count: i32,
+    iteration_total: Duration,
+    // When the next report is due with output_rate_hz set, and the reports due this log window
+    next_output: Option<Instant>,
+    output_count: u32,
+    // Reports sent to the controllers and skipped as unchanged, for the same log
+    reports_sent: u32,
+    reports_skipped: u32,
+    iteration_window_start: Instant,
+}
+
+impl EventHandler {
+    const ANALOG_MAX: f64 = -(i16::MIN as f64);
+
+    // Gaps between mouse samples outside these are strokes received together or pauses in
+    // movement, neither says anything about how often the mouse reports
+    const MIN_REPORT_GAP: Duration = Duration::from_micros(100);
+    const MAX_REPORT_GAP: Duration = Duration::from_millis(50);
+
+    // main refuses --record when built without the recording feature, so record is always None
+    #[cfg_attr(not(feature = "recording"), allow(unused_variables))]
+    pub fn new(
+        rx: mpsc::Receiver<Event>,
+        rumble_tx: mpsc::Sender<Event>,
+        inject_tx: mpsc::Sender<Keystroke>,
+        config: Config,
+        record: Option<&Path>,
+    ) -> Result<Self, anyhow::Error> {
+        Self::with_output(rx, inject_tx, config, |config| {
+            let controller_type = config.controller_type;
+            let extra_targets = config.targets.len() + config.mirror_targets as usize;
+            let mut outputs: Vec<Box<dyn ControllerOutput>> = Vec::new();
+
+            let (slot, retry) = (config.require_slot, config.connect_retry());
+            match VigemOutput::connect(controller_type, extra_targets, slot, rumble_tx, retry) {
+                Ok(output) => outputs.push(Box::new(output)),
+                Err(error) if config.dsu_server.is_some() => {
+                    warn!("{}, only serving DSU", error)
+                }
+                Err(error) => return Err(error),
+            }
+
+            #[cfg(feature = "dsu")]
+            if let Some(dsu_config) = &config.dsu_server {
+                let motion = config.gyro.is_some();
+                let server = DsuServer::start(dsu_config, 1 + extra_targets, motion)?;
+                outputs.push(Box::new(server));
+            }
+
+            #[cfg(feature = "recording")]
+            if let Some(path) = record {
+                outputs.push(Box::new(Recorder::start(path)?));
+            }
+
+            Ok(Box::new(outputs))
+        })
+    }
+
+    // Like new but sending reports wherever connect says, which is called once the config has
+    // been checked
+    pub fn with_output<F>(
+        rx: mpsc::Receiver<Event>,
+        inject_tx: mpsc::Sender<Keystroke>,
+        _config: Config,
+        connect: F,
+    ) -> Result<Self, anyhow::Error>
+    where
+        F: FnOnce(&Config) -> Result<Box<dyn ControllerOutput>, anyhow::Error>,
+    {
+        let mut base = _config;
+        base.prepare()?;
+        info!("{}", base.bind_table());
+        let config = base.resolved(DEFAULT_PROFILE, []);
+
+        let output = connect(&config)?;
+
+        // Traced as well as sent wherever connect says
+        #[cfg(feature = "trace")]
+        let (output, tracer) = match &config.trace_file {
+            Some(path) => {
+                let tracer = Tracer::start(path)?;
+                let outputs: Vec<Box<dyn ControllerOutput>> =
+                    vec![output, Box::new(tracer.reports())];
+                (Box::new(outputs) as Box<dyn ControllerOutput>, Some(tracer))
+            }
+
+            None => (output, None),
+        };
+
+        let mut extra_targets = Vec::new();
+        for _ in 0..config.targets.len() {
+            extra_targets.push(ExtraTarget {
+                report: XUSBReport::default(),
+                last_sent: None,
+                held: HashSet::new(),
+            });
+        }
+
+        let mirror_last_sent = vec![None; config.mirror_targets as usize];
+
+        let sample_windows = config.sample_windows();
+        let next_output = match config.output_rate_hz {
+            0 => None,
+            _ => Some(Instant::now()),
+        };
+        let sensitivity = config.sensitivity(&Bind::MouseMove);
+
+        info!(
+            "sensitivity: (x: {}, y: {}), sample_window: (x: {:#?}, y: {:#?})",
+            sensitivity.0, sensitivity.1, sample_windows.0, sample_windows.1,
+        );
+        info!("response_curve: {:?}", config.response_curve);
+        info!(
+            "legacy_velocity: {}, mice reporting below {:.0} Hz are {}",
+            config.legacy_velocity,
+            1.0 / sample_windows.0.min(sample_windows.1).as_secs_f64(),
+            match config.legacy_velocity {
+                true => "averaged over sample_window",
+                false => "averaged over their own report interval",
+            }
+        );
+        info!("timing: {:?}", config.timing);
+        for (bind, timing) in config.timing_overrides() {
+            info!("timing for {}: {:?}", bind, timing);
+        }
+
+        let audio_muted = config.audio_start_muted;
+        #[cfg(feature = "audio")]
+        let (tone_generator, beeper) = Self::start_audio(&config, audio_muted)?;
+
+        let dpad_mode_active = config.dpad_mode.active;
+        let swap_analogs_active = config.swap_analogs;
+        let gyro_active = config.gyro.is_some_and(|gyro| gyro.enabled);
+        // Enough for a full window from an 8kHz mouse so it never grows while playing
+        let capacity = |window: Duration| {
+            let window = match config.adaptive_window {
+                true => window.max(config.sample_window_max),
+                false => window,
+            };
+
+            (window.as_secs_f64() * 8000.0) as usize + 1
+        };
+        let mouse_samples_capacity = (capacity(sample_windows.0), capacity(sample_windows.1));
+        let sequence_matched = vec![0; config.sequences.len()];
+        let gesture_state = vec![(false, None); config.gestures.len()];
+        let zone_state = vec![(None, false); config.zones.len()];
+
+        Ok(EventHandler {
+            config,
+
+            rx,
+            inject_tx,
+
+            output,
+            #[cfg(feature = "trace")]
+            tracer,
+            report: XUSBReport::default(),
+            last_sent: None,
+            reconnect: None,
+            last_input: Instant::now(),
+            idle: false,
+
+            #[cfg(feature = "audio")]
+            tone_generator,
+            #[cfg(feature = "audio")]
+            beeper,
+            audio_muted,
+            alert_on_since: None,
+            understeer_since: None,
+            capturing: false,
+
+            base,
+            profile: DEFAULT_PROFILE.to_owned(),
+            manual_profile: false,
+            active_layers: Vec::new(),
+            foreground: None,
+
+            sensitivity_scale: (1.0, 1.0),
+            mouse_windows: (
+                MouseWindow::with_capacity(mouse_samples_capacity.0),
+                MouseWindow::with_capacity(mouse_samples_capacity.1),
+            ),
+            absolute_position: None,
+            rumbling: false,
+            mouse_stick_vel: (0.0, 0.0),
+            extra_targets,
+            mirroring: true,
+            mirror_last_sent,
+            device_windows: HashMap::new(),
+            last_sample_time: None,
+            report_interval: None,
+            logged_window: sample_windows.0.min(sample_windows.1),
+            adaptive_level: 1.0,
+            adaptive_updated: Instant::now(),
+            stick_remainders: [0.0; 4],
+            stick_carry: [true; 2],
+            jitter_pending: (0, 0),
+            mouse_ema: (0.0, 0.0),
+            mouse_ema_pending: (0, 0),
+            mouse_ema_updated: Instant::now(),
+            axis_snapped: None,
+            mouse_output: ((0.0, 0.0), Instant::now()),
+            flick_angle: 90.0,
+            flick_pending: 0,
+            flick_hold: None,
+            flick_turning: false,
+            mouse_button_states: (KeyState::Up, KeyState::Up),
+            key_states: HashMap::new(),
+            injected_keys: HashSet::new(),
+            queued_keystrokes: VecDeque::new(),
+
+            analog_state: HashMap::new(),
+            polar_holds: Vec::new(),
+            trigger_state: HashMap::new(),
+            button_holds: Vec::new(),
+            suppressions: Vec::new(),
+
+            auto_sprint_since: None,
+            dpad_mode_active,
+            swap_analogs_active,
+            centre_sticks: false,
+            gyro_active,
+            gyro: [0.0; 3],
+            sent_gyro: [0.0; 3],
+
+            hold_deadlines: HashMap::new(),
+            pulse_deadlines: HashMap::new(),
+            scheduled_releases: Vec::new(),
+            tap_holds: HashMap::new(),
+            first_taps: HashMap::new(),
+
+            sequence_keys: VecDeque::new(),
+            sequence_matched,
+            gesture_state,
+            zone_state,
+            auto_released: HashSet::new(),
+
+            iteration_count: 0,
+            iteration_total: Duration::from_secs(0),
+            next_output,
+            output_count: 0,
+            reports_sent: 0,
+            reports_skipped: 0,
+            iteration_window_start: Instant::now(),
+        })
+    }
+
+    // Opens the audio device if anything plays sounds, or the beeper in its place as
+    // alert_fallback says, muted or not
+    #[cfg(feature = "audio")]
+    fn start_audio(
+        config: &Config,
+        muted: bool,
+    ) -> Result<(Option<ToneGenerator>, Option<Beeper>), anyhow::Error> {
+        let audio = config.oversteer_alert_enabled
+            || config.rumble_audio_enabled
+            || config.button_click_feedback.enabled
+            || config.audio_cues.enabled
+            || config.understeer_alert.enabled;
+        let (alert, rumble) = (config.oversteer_alert, config.rumble_audio);
+        let (click, cues) = (config.button_click_feedback, config.audio_cues);
+        let understeer = config.understeer_alert.tone;
+        let (mut tone_generator, mut beeper) = (None, None);
+
+        if audio {
+            let result = ToneGenerator::new(alert, rumble, click, cues, understeer);
+            match (result, config.alert_fallback) {
+                (Ok(tg), _) => tone_generator = Some(tg),
+                (Err(error), AlertFallback::Fail) => return Err(error),
+                (Err(error), AlertFallback::Disable) => {
+                    warn!("no audio ({}), playing no sounds", error)
+                }
+                (Err(error), AlertFallback::Beep) => {
+                    warn!("no audio ({}), beeping the oversteer alert", error);
+                    beeper = Some(Beeper::start(alert.frequency));
+                }
+            }
+        }
+
+        if let Some(tg) = tone_generator.as_mut() {
+            tg.mute(muted);
+        }
+        if let Some(beeper) = beeper.as_mut() {
+            beeper.mute(muted);
+        }
+
+        Ok((tone_generator, beeper))
+    }
+
+    pub fn run(&mut self) -> Result<(), anyhow::Error> {
+        loop {
+            let iteration_start = Instant::now();
+
+            // Stop waiting in time for the next report
+            let mut wait_until = iteration_start + self.config.spin_period;
+            if let Some(next_output) = self.next_output {
+                wait_until = wait_until.min(next_output);
+            }
+
+            let mut event = self.rx.try_recv();
+            while event.is_err() && Instant::now() < wait_until {
+                spin_loop();
+                event = self.rx.try_recv();
+            }
+            // match event {
+            //     Ok(x) => info!("{:?}", x),
+            //     Err(_) => {}
+            // }
+            if let Err(mpsc::TryRecvError::Disconnected) = event {
+                info!("event dispatcher gone, exiting");
+                return Ok(());
+            }
+
+            if let Ok(event) = event {
+                #[cfg(feature = "trace")]
+                if let Some(tracer) = &self.tracer {
+                    tracer.event(&event);
+                }
+
+                let input = !matches!(
+                    event,
+                    Event::Exit
+                        | Event::Rumble { .. }
+                        | Event::Reset
+                        | Event::Resume
+                        | Event::Reload(_)
+                        | Event::Foreground(_)
+                );
+                if input {
+                    self.wake()?;
+                }
+
+                match event {
+                    Event::MouseMove(device, x, y) => {
+                        let bind = Bind::MouseMoveDevice(device);
+                        match self.config.binds.contains_key(&bind) {
+                            true => self.handle_device_mouse_move(device, x, y),
+                            false => self.handle_mouse_move(x, y),
+                        }
+                    }
+
+                    Event::MouseMoveAbsolute(x, y) => self.absolute_position = Some((x, y)),
+
+                    Event::Exit => return Ok(()),
+
+                    Event::Reload(config) => self.reload(*config),
+
+                    Event::Foreground(window) => self.handle_foreground(window),
+
+                    Event::Rumble { large, small } => {
+                        debug!("rumble large: {}, small: {}", large, small);
+                        self.handle_rumble(large, small);
+                    }
+
+                    Event::MouseButton(button, state) => {
+                        if button == MouseButton::Left {
+                            self.mouse_button_states.0 = state;
+                        }
+
+                        if button == MouseButton::Right {
+                            self.mouse_button_states.1 = state;
+                        }
+
+                        self.handle_input(Bind::Mouse(button), state);
+
+                        if self.config.mouse_button_fix && state == KeyState::Up {
+                            if self.mouse_button_states.0 == KeyState::Down {
+                                self.handle_bind(Bind::Mouse(MouseButton::Left), KeyState::Down)
+                            }
+
+                            if self.mouse_button_states.1 == KeyState::Down {
+                                self.handle_bind(Bind::Mouse(MouseButton::Right), KeyState::Down)
+                            }
+                        }
+                    }
+
+                    Event::MouseWheel(direction, inverted) => {
+                        self.handle_wheel(direction, inverted)
+                    }
+
+                    Event::Keyboard(key, state) => {
+                        let previous = self.key_states.insert(key, state);
+                        let repeat = state == KeyState::Down && previous == Some(state);
+
+                        if !(repeat && self.config.suppress_key_repeat) {
+                            self.handle_input(Bind::Keyboard(key.code, Some(key.e0)), state)
+                        }
+                    }
+
+                    Event::Resume => {
+                        self.capturing = true;
+                        #[cfg(feature = "audio")]
+                        self.play_cue(Cue::Resume);
+                    }
+
+                    Event::Reset => {
+                        self.capturing = false;
+                        #[cfg(feature = "audio")]
+                        self.play_cue(Cue::Pause);
+                        self.release_all();
+                    }
+                }
+            }
+
+            self.expire_holds();
+            self.expire_pulses();
+            self.expire_releases();
+            self.expire_taps();
+            self.send_queued_keystrokes();
+            self.update_charges();
+            self.detach_if_idle();
+
+            if self.output_due() {
+                self.update_analog();
+
+                // Input is still handled while reconnecting so the report is current once it's
+                // back
+                match self.reconnect.is_some() {
+                    true => self.try_reconnect()?,
+                    // Nothing to send to until input brings the controllers back
+                    false if self.idle => {}
+                    false => {
+                        if let Err(error) = self.send_report() {
+                            self.lost_connection(error)?;
+                        }
+                    }
+                }
+
+                #[cfg(feature = "trace")]
+                if let Some(tracer) = &self.tracer {
+                    tracer.frame(self.mouse_stick_vel, iteration_start.elapsed());
+                }
+            }
+
+            if log_enabled!(log::Level::Info) {
+                self.iteration_count += 1;
+                self.iteration_total += iteration_start.elapsed();
+
+                let window = self.iteration_window_start.elapsed();
+                if window > Duration::from_secs(2) {
+                    debug!(
+                        "{} loops, {} per sec, avg = {:#?}, reports sent: {}, skipped: {}",
+                        self.iteration_count,
+                        self.iteration_count as f64 / 2.0,
+                        self.iteration_total.div_f64(self.iteration_count.into()),
+                        self.reports_sent,
+                        self.reports_skipped
+                    );
+
+                    if self.next_output.is_some() {
+                        let rate = self.output_count as f64 / window.as_secs_f64();
+                        debug!("output rate: {:.1}Hz", rate);
+                    }
+
+                    self.iteration_count = 0;
+                    self.iteration_total = Duration::from_secs(0);
+                    self.reports_sent = 0;
+                    self.reports_skipped = 0;
+                    self.output_count = 0;
+                    self.iteration_window_start = Instant::now();
+                }
+            }
+        }
+    }
+
+    // The bind naming exactly this key on any controller if there is one, otherwise the one
+    // matching either
+    fn key_bind(&self, key: Key) -> Bind {
+        let exact = Bind::Keyboard(key.code, Some(key.e0));
+        let targets = self.config.targets.iter().map(|target| &target.binds);
+        let mut binds = std::iter::once(&self.config.binds).chain(targets);
+
+        match binds.any(|binds| binds.contains_key(&exact)) {
+            true => exact,
+            false => Bind::Keyboard(key.code, None),
+        }
+    }
+
+    // Physical input goes through the sequence matcher before the usual bind handling. Keys come
+    // in as pressed, E0 and all, and only go to the bind key_bind picks after the sequences
+    fn handle_input(&mut self, input: Bind, state: KeyState) {
+        if state == KeyState::Down {
+            self.match_sequences(input);
+        }
+
+        let bind = match input {
+            Bind::Keyboard(code, Some(e0)) => self.key_bind(Key { code, e0 }),
+            input => input,
+        };
+
+        self.hold_extra_targets(bind, state);
+        self.handle_bind(bind, state);
+    }
+
+    // Sequences match against the last keys pressed rather than following along, so when one
+    // breaks off the keys already pressed can still finish another (or the same one started
+    // again further in)
+    fn match_sequences(&mut self, bind: Bind) {
+        let sequences = &self.config.sequences;
+        let longest = sequences.iter().map(|sequence| sequence.keys.len()).max();
+        let longest = match longest {
+            Some(longest) if longest > 0 => longest,
+            _ => return,
+        };
+
+        let now = Instant::now();
+        let count = self.sequence_keys.back().map_or(0, |&(i, _, _)| i + 1);
+
+        self.sequence_keys.push_back((count, bind, now));
+        if self.sequence_keys.len() > longest {
+            self.sequence_keys.pop_front();
+        }
+
+        let mut matched = Vec::new();
+
+        for (i, sequence) in sequences.iter().enumerate() {
+            let start = match self.sequence_keys.len().checked_sub(sequence.keys.len()) {
+                Some(start) if !sequence.keys.is_empty() => start,
+                _ => continue,
+            };
+
+            let (first, _, started) = self.sequence_keys[start];
+            let window = Duration::from_millis(sequence.window_ms);
+            if first < self.sequence_matched[i] || now - started > window {
+                continue;
+            }
+
+            // A step leaving out E0 matches either key, whatever the binds are
+            let keys = self.sequence_keys.range(start..).map(|&(_, key, _)| key);
+            let steps = keys
+                .zip(&sequence.keys)
+                .all(|(key, &step)| match (step, key) {
+                    (Bind::Keyboard(code, None), Bind::Keyboard(pressed, _)) => code == pressed,
+                    _ => step == key,
+                });
+            if steps {
+                self.sequence_matched[i] = count + 1;
+                matched.push(i);
+            }
+        }
+
+        for i in matched {
+            let action = self.config.sequences[i].action.clone();
+            self.tap_action(Source::Sequence(i), &action);
+        }
+    }
+
+    fn handle_bind(&mut self, bind: Bind, state: KeyState) {
+        // Even if the layer it held on binds the key to something else
+        if state == KeyState::Up && self.release_layer(Source::Bind(bind)) {
+            return;
+        }
+
+        let options = match self.config.bind_options.get(&bind) {
+            Some(&options) => options,
+            None => BindOptions::default(),
+        };
+
+        if let Some(max_hold_ms) = options.max_hold_ms {
+            match state {
+                KeyState::Down => {
+                    // Key repeat after an auto-release must not press again
+                    if self.auto_released.contains(&bind) {
+                        return;
+                    }
+
+                    self.hold_deadlines
+                        .entry(bind)
+                        .or_insert_with(|| Instant::now() + Duration::from_millis(max_hold_ms));
+                }
+
+                KeyState::Up => {
+                    self.hold_deadlines.remove(&bind);
+
+                    if self.auto_released.remove(&bind) {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let action = match self.config.binds.get(&bind) {
+            Some(action) => action.clone(),
+            None => {
+                if logging::bind_log() {
+                    info!("{} {:?}, not bound", bind, state);
+                }
+
+                return;
+            }
+        };
+
+        if logging::bind_log() {
+            info!("{} {:?}: {}", bind, state, bind_table::describe(&action));
+        }
+
+        self.handle_action(Source::Bind(bind), &action, state);
+    }
+
+    fn handle_action(&mut self, source: Source, action: &ControllerAction, state: KeyState) {
+        let controller_buttons = match action {
+            &ControllerAction::Button(controller_button) => vec![controller_button],
+            ControllerAction::Buttons(controller_buttons) => controller_buttons.clone(),
+            ControllerAction::AnalogLeft(x, y, _) => {
+                // Only ever a release, even with nothing held for source (let go of by a
+                // profile switch while the key was down)
+                if state == KeyState::Up {
+                    self.analog_state.remove(&source);
+                    return;
+                }
+
+                self.analog_state.insert(source, AnalogState {
+                    analog_type: AnalogType::Left,
+                    x: *x,
+                    y: *y,
+                });
+
+                return;
+            }
+            ControllerAction::AnalogRight(x, y, _) => {
+                // Only ever a release, even with nothing held for source (let go of by a
+                // profile switch while the key was down)
+                if state == KeyState::Up {
+                    self.analog_state.remove(&source);
+                    return;
+                }
+
+                self.analog_state.insert(source, AnalogState {
+                    analog_type: AnalogType::Right,
+                    x: *x,
+                    y: *y,
+                });
+                return;
+            }
+            &ControllerAction::TriggerAccumulate { trigger, step } => {
+                if state == KeyState::Down {
+                    let trigger_state = self.trigger_state.entry(trigger).or_default();
+                    let accumulated = trigger_state.accumulated as f64 + step * u8::MAX as f64;
+
+                    trigger_state.accumulated =
+                        accumulated.round().clamp(0.0, u8::MAX as f64) as u8;
+                    self.write_trigger(trigger);
+                }
+
+                return;
+            }
+            &ControllerAction::Pulse {
+                button,
+                duration_ms,
+            } => {
+                if state == KeyState::Down {
+                    self.pulse(source, button, Duration::from_millis(duration_ms));
+                }
+
+                return;
+            }
+            ControllerAction::OnRelease(inner) => {
+                // The inner action is pressed on key up and released again by
+                // expire_releases, a Pulse inside already times itself and ignores that
+                if state == KeyState::Up {
+                    self.tap_action(source, inner);
+                }
+
+                return;
+            }
+            ControllerAction::TapHold { tap, hold } => {
+                match state {
+                    // Key repeat keeps the first press
+                    KeyState::Down => {
+                        let press = (Instant::now(), (**hold).clone(), false);
+                        self.tap_holds.entry(source).or_insert(press);
+                    }
+                    // expire_taps pressed hold once the press was no longer a tap
+                    KeyState::Up => match self.tap_holds.remove(&source) {
+                        Some((_, hold, true)) => self.handle_action(source, &hold, state),
+                        Some((_, _, false)) => self.tap_action(source, tap),
+                        None => {}
+                    },
+                }
+
+                return;
+            }
+            ControllerAction::DoubleTap { single, double } => {
+                // A tap counts once it's let go, expire_taps does single when no second follows
+                if state == KeyState::Up {
+                    match self.first_taps.remove(&source) {
+                        Some(_) => self.tap_action(source, double),
+                        None => {
+                            let first = (Instant::now(), (**single).clone());
+                            self.first_taps.insert(source, first);
+                        }
+                    }
+                }
+
+                return;
+            }
+            &ControllerAction::TriggerCharge {
+                trigger,
+                full_charge_ms,
+                hold_at,
+                release,
+            } => {
+                let now = Instant::now();
+                let trigger_state = self.trigger_state.entry(trigger).or_default();
+
+                match (state, trigger_state.charge) {
+                    // Already charging, this is key repeat
+                    (KeyState::Down, Some(Charge { released: None, .. })) => {}
+
+                    (KeyState::Down, _) => {
+                        trigger_state.charge = Some(Charge {
+                            started: now,
+                            full_charge: Duration::from_millis(full_charge_ms),
+                            hold_at,
+                            release,
+                            released: None,
+                        });
+                    }
+
+                    (KeyState::Up, Some(mut charge)) => match charge.release {
+                        ChargeRelease::Instant => {
+                            trigger_state.charge = None;
+                            trigger_state.charged = 0;
+                        }
+                        ChargeRelease::Ramp => {
+                            charge.released = Some((now, trigger_state.charged));
+                            trigger_state.charge = Some(charge);
+                        }
+                    },
+
+                    (KeyState::Up, None) => {}
+                }
+
+                trigger_state.update_charge(now);
+                self.write_trigger(trigger);
+
+                return;
+            }
+            &ControllerAction::AnalogPolar {
+                stick,
+                angle,
+                magnitude,
+            } => {
+                self.polar_holds.retain(|(held, _)| *held != source);
+
+                if state == KeyState::Down {
+                    let radians = angle.to_radians();
+                    let polar = AnalogState {
+                        analog_type: stick,
+                        x: radians.cos() * magnitude,
+                        y: radians.sin() * magnitude,
+                    };
+
+                    self.polar_holds.push((source, polar));
+                }
+
+                return;
+            }
+            &ControllerAction::Suppress(button) => {
+                self.suppressions.retain(|&hold| hold != (source, button));
+
+                if state == KeyState::Down {
+                    self.suppressions.push((source, button));
+                }
+
+                // Letting go brings back whatever is still holding the button
+                self.update_buttons();
+                return;
+            }
+            &ControllerAction::KeyRemap(code, e0) => {
+                self.inject(Key { code, e0 }, state);
+                return;
+            }
+            ControllerAction::SendKeys(codes) => {
+                if state == KeyState::Down {
+                    self.queue_keys(codes);
+                }
+
+                return;
+            }
+            &ControllerAction::ScaleSensitivity { factor, axis } => {
+                if state == KeyState::Down {
+                    if axis != Some(Axis::Y) {
+                        self.sensitivity_scale.0 *= factor;
+                    }
+
+                    if axis != Some(Axis::X) {
+                        self.sensitivity_scale.1 *= factor;
+                    }
+
+                    let sensitivity = self.sensitivity(&Bind::MouseMove);
+                    info!(
+                        "sensitivity: (x: {:.3}, y: {:.3})",
+                        sensitivity.0, sensitivity.1
+                    );
+                }
+
+                return;
+            }
+            ControllerAction::RecenterFlickStick => {
+                if state == KeyState::Down {
+                    self.flick_angle = 90.0;
+                    self.flick_hold = None;
+                }
+
+                return;
+            }
+            ControllerAction::ToggleGyro => {
+                if state == KeyState::Down {
+                    self.gyro_active = !self.gyro_active;
+                    let mode = if self.gyro_active { "on" } else { "off" };
+                    info!("gyro {}", mode);
+                }
+
+                return;
+            }
+            ControllerAction::MirrorToggle => {
+                if state == KeyState::Down {
+                    self.mirroring = !self.mirroring;
+                    let mode = if self.mirroring { "on" } else { "off" };
+                    info!("mirroring {}", mode);
+                }
+
+                return;
+            }
+            ControllerAction::SwapAnalogs => {
+                if state == KeyState::Down {
+                    self.swap_analogs_active = !self.swap_analogs_active;
+                    self.centre_sticks = true;
+                    let active = self.swap_analogs_active;
+                    let mode = if active { "on" } else { "off" };
+                    info!("swap analogs {}", mode);
+                }
+
+                return;
+            }
+            ControllerAction::ToggleAudio => {
+                if state == KeyState::Down {
+                    self.audio_muted = !self.audio_muted;
+                    #[cfg(feature = "audio")]
+                    if let Some(tg) = self.tone_generator.as_mut() {
+                        tg.mute(self.audio_muted);
+                    }
+                    #[cfg(feature = "audio")]
+                    if let Some(beeper) = self.beeper.as_mut() {
+                        beeper.mute(self.audio_muted);
+                    }
+
+                    let mode = if self.audio_muted { "muted" } else { "unmuted" };
+                    info!("audio {}", mode);
+                }
+
+                return;
+            }
+            ControllerAction::ToggleBindLog => {
+                if state == KeyState::Down {
+                    let on = !logging::bind_log();
+                    logging::set_bind_log(on);
+
+                    let mode = if on { "on" } else { "off" };
+                    info!("bind logging {}", mode);
+                }
+
+                return;
+            }
+            ControllerAction::ToggleDpadMode => {
+                if state == KeyState::Down {
+                    self.dpad_mode_active = !self.dpad_mode_active;
+                    let mode = if self.dpad_mode_active { "on" } else { "off" };
+                    info!("dpad mode {}", mode);
+                }
+
+                return;
+            }
+            &ControllerAction::TriggerZero(trigger) => {
+                if state == KeyState::Down {
+                    self.trigger_state.entry(trigger).or_default().accumulated = 0;
+                    self.write_trigger(trigger);
+                }
+
+                return;
+            }
+            ControllerAction::Profile(name) => {
+                if state == KeyState::Down {
+                    self.manual_profile = true;
+                    self.switch_profile(name);
+                }
+
+                return;
+            }
+            ControllerAction::Layer(name) => {
+                match state {
+                    KeyState::Down => {
+                        // Key repeat
+                        if self.active_layers.iter().any(|(_, by)| *by == Some(source)) {
+                            return;
+                        }
+
+                        self.active_layers.push((name.clone(), Some(source)));
+                        self.update_layers();
+                    }
+
+                    KeyState::Up => {
+                        self.release_layer(source);
+                    }
+                }
+
+                return;
+            }
+            ControllerAction::ToggleLayer(name) => {
+                if state == KeyState::Down {
+                    let toggled = self
+                        .active_layers
+                        .iter()
+                        .position(|(on, by)| on == name && by.is_none());
+
+                    match toggled {
+                        Some(i) => drop(self.active_layers.remove(i)),
+                        None => self.active_layers.push((name.clone(), None)),
+                    }
+
+                    self.update_layers();
+                }
+
+                return;
+            }
+            ControllerAction::PrintBinds => {
+                if state == KeyState::Down {
+                    info!("{}", self.config.bind_table());
+                }
+
+                return;
+            }
+        };
+
+        // Buttons also held by another bind stay down, see update_buttons
+        for controller_button in controller_buttons {
+            match state {
+                KeyState::Down => self.press_button(source, controller_button),
+                KeyState::Up => self.release_button(source, controller_button),
+            }
+        }
+
+        self.update_buttons();
+    }
+
+    fn inject(&mut self, key: Key, state: KeyState) {
+        match state {
+            KeyState::Down => self.injected_keys.insert(key),
+            KeyState::Up => self.injected_keys.remove(&key),
+        };
+
+        // The dispatcher only goes away when the program exits
+        let _ = self.inject_tx.send(Keystroke { key, state });
+    }
+
+    // Typed after anything still queued, so overlapping macros don't interleave
+    fn queue_keys(&mut self, codes: &[ic::ScanCode]) {
+        let delay = Duration::from_millis(self.config.send_keys_delay_ms);
+        let mut at = match self.queued_keystrokes.back() {
+            Some(&(last, _)) => last + delay,
+            None => Instant::now(),
+        };
+
+        for &code in codes {
+            let key = Key { code, e0: false };
+
+            for state in [KeyState::Down, KeyState::Up] {
+                let keystroke = Keystroke { key, state };
+                self.queued_keystrokes.push_back((at, keystroke));
+                at += delay;
+            }
+        }
+    }
+
+    fn send_queued_keystrokes(&mut self) {
+        let now = Instant::now();
+
+        while let Some(&(at, keystroke)) = self.queued_keystrokes.front() {
+            if at > now {
+                break;
+            }
+
+            self.queued_keystrokes.pop_front();
+            self.inject(keystroke.key, keystroke.state);
+        }
+    }
+
+    // The physical releases of remapped keys aren't seen once deactivated
+    fn release_injected_keys(&mut self) {
+        let keys: Vec<Key> = self.injected_keys.iter().copied().collect();
+
+        for key in keys {
+            self.inject(key, KeyState::Up);
+        }
+    }
+
+    // Presses the action now and releases it pulse_duration_ms later
+    fn tap_action(&mut self, source: Source, action: &ControllerAction) {
+        let duration = Duration::from_millis(self.timing(source).pulse_duration_ms);
+
+        self.handle_action(source, action, KeyState::Down);
+        self.scheduled_releases
+            .push((Instant::now() + duration, source, action.clone()));
+    }
+
+    // Config::timing with the overrides from the bind's options
+    fn timing(&self, source: Source) -> Timing {
+        let options = match source {
+            Source::Bind(bind) => self.config.bind_options.get(&bind),
+            _ => None,
+        };
+
+        match options {
+            Some(options) => self.config.timing.with_overrides(options),
+            None => self.config.timing,
+        }
+    }
+
+    fn expire_releases(&mut self) {
+        if self.scheduled_releases.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let (expired, pending) = self
+            .scheduled_releases
+            .drain(..)
+            .partition(|&(deadline, _, _)| deadline <= now);
+
+        self.scheduled_releases = pending;
+
+        for (_, source, action) in expired {
+            self.handle_action(source, &action, KeyState::Up);
+        }
+    }
+
+    // Presses the hold action of TapHold presses past tap_threshold_ms, and does the single
+    // action of DoubleTap binds not tapped again within double_tap_window_ms
+    fn expire_taps(&mut self) {
+        if self.tap_holds.is_empty() && self.first_taps.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+
+        let holds: Vec<(Source, ControllerAction)> = self
+            .tap_holds
+            .iter()
+            .filter(|&(&source, &(started, _, holding))| {
+                let threshold = Duration::from_millis(self.timing(source).tap_threshold_ms);
+                !holding && now - started >= threshold
+            })
+            .map(|(&source, (_, hold, _))| (source, hold.clone()))
+            .collect();
+
+        for (source, hold) in holds {
+            if let Some(press) = self.tap_holds.get_mut(&source) {
+                press.2 = true;
+            }
+            self.handle_action(source, &hold, KeyState::Down);
+        }
+
+        let singles: Vec<Source> = self
+            .first_taps
+            .iter()
+            .filter(|&(&source, &(tapped, _))| {
+                let window = Duration::from_millis(self.timing(source).double_tap_window_ms);
+                now - tapped >= window
+            })
+            .map(|(&source, _)| source)
+            .collect();
+
+        for source in singles {
+            if let Some((_, single)) = self.first_taps.remove(&source) {
+                self.tap_action(source, &single);
+            }
+        }
+    }
+
+    fn expire_holds(&mut self) {
+        if self.hold_deadlines.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let expired: Vec<Bind> = self
+            .hold_deadlines
+            .iter()
+            .filter(|&(_, &deadline)| deadline <= now)
+            .map(|(&bind, _)| bind)
+            .collect();
+
+        for bind in expired {
+            self.hold_deadlines.remove(&bind);
+            self.handle_bind(bind, KeyState::Up);
+
+            // Swallow the physical release (and any repeats before it)
+            self.auto_released.insert(bind);
+        }
+    }
+
+    // Pressing again while a pulse is running restarts it instead of cutting it short
+    fn pulse(&mut self, source: Source, button: ControllerButton, duration: Duration) {
+        self.pulse_deadlines
+            .insert((source, button), Instant::now() + duration);
+
+        self.press_button(source, button);
+        self.update_buttons();
+    }
+
+    fn expire_pulses(&mut self) {
+        if self.pulse_deadlines.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let expired: Vec<(Source, ControllerButton)> = self
+            .pulse_deadlines
+            .iter()
+            .filter(|&(_, &deadline)| deadline <= now)
+            .map(|(&hold, _)| hold)
+            .collect();
+
+        for (source, button) in expired {
+            self.pulse_deadlines.remove(&(source, button));
+            self.release_button(source, button);
+        }
+
+        self.update_buttons();
+    }
+
+    fn press_button(&mut self, source: Source, button: ControllerButton) {
+        if self.button_holds.contains(&(source, button)) {
+            return;
+        }
+
+        let ignored = self.config.exclusive_groups.iter().any(|group| {
+            group.policy == ExclusivePolicy::IgnoreSecond
+                && group.buttons.contains(&button)
+                && self
+                    .button_holds
+                    .iter()
+                    .any(|&(_, held)| held != button && group.buttons.contains(&held))
+        });
+
+        if ignored {
+            return;
+        }
+
+        let newly_held = !self.button_holds.iter().any(|&(_, held)| held == button);
+        #[cfg(feature = "audio")]
+        if newly_held && self.config.button_click_feedback.enabled {
+            if let Some(tg) = self.tone_generator.as_mut() {
+                tg.click();
+            }
+        }
+
+        self.button_holds.push((source, button));
+    }
+
+    fn release_button(&mut self, source: Source, button: ControllerButton) {
+        self.button_holds.retain(|&hold| hold != (source, button));
+    }
+
+    // Rebuilds the button and trigger state of the report from the binds currently holding
+    // them, so a button held by several binds stays down until the last one lets go
+    fn update_buttons(&mut self) {
+        let mut pressed: Vec<ControllerButton> = self
+            .button_holds
+            .iter()
+            .map(|&(_, button)| button)
+            .collect();
+
+        for group in self.config.exclusive_groups.iter() {
+            if group.policy != ExclusivePolicy::NewestWins {
+                continue;
+            }
+
+            // button_holds is in press order, the last member held is the newest
+            let newest = pressed
+                .iter()
+                .rev()
+                .find(|button| group.buttons.contains(button))
+                .copied();
+
+            if let Some(newest) = newest {
+                pressed.retain(|&button| button == newest || !group.buttons.contains(&button));
+            }
+        }
+
+        pressed.retain(|&button| !self.suppressed(button));
+
+        self.report.w_buttons = XButton::Nothing;
+
+        for &button in pressed.iter() {
+            match button {
+                ControllerButton::LeftTrigger | ControllerButton::RightTrigger => {}
+                button => self.report.w_buttons |= XButton::from_bits(button as u16).unwrap(),
+            }
+        }
+
+        for trigger in [Trigger::Left, Trigger::Right] {
+            let held = pressed.contains(&trigger.button());
+            self.trigger_state.entry(trigger).or_default().held = held;
+            self.write_trigger(trigger);
+        }
+    }
+
+    fn suppressed(&self, button: ControllerButton) -> bool {
+        self.suppressions
+            .iter()
+            .any(|&(_, suppressed)| suppressed == button)
+    }
+
+    fn update_charges(&mut self) {
+        let now = Instant::now();
+
+        for trigger in [Trigger::Left, Trigger::Right] {
+            if let Some(trigger_state) = self.trigger_state.get_mut(&trigger) {
+                if trigger_state.charge.is_some() {
+                    trigger_state.update_charge(now);
+                    self.write_trigger(trigger);
+                }
+            }
+        }
+    }
+
+    // mouse_vel in counts per second, before the response curve and sensitivity
+    fn update_mouse_trigger(&mut self, mouse_vel: (f64, f64)) {
+        let mouse_trigger = match &self.config.mouse_trigger {
+            Some(mouse_trigger) => mouse_trigger,
+            None => return,
+        };
+
+        let speed = match mouse_trigger.direction {
+            Direction::Up => -mouse_vel.1,
+            Direction::Down => mouse_vel.1,
+            Direction::Left => -mouse_vel.0,
+            Direction::Right => mouse_vel.0,
+        };
+
+        let level = (speed * mouse_trigger.sensitivity / 1e4).clamp(0.0, 1.0);
+        let level = mouse_trigger.curve.apply(level).clamp(0.0, 1.0);
+
+        let trigger = mouse_trigger.trigger;
+        let trigger_state = self.trigger_state.entry(trigger).or_default();
+        trigger_state.mouse = (level * u8::MAX as f64).round() as u8;
+
+        self.write_trigger(trigger);
+    }
+
+    fn write_trigger(&mut self, trigger: Trigger) {
+        let value = match self.trigger_state.get(&trigger) {
+            _ if self.suppressed(trigger.button()) => 0,
+            Some(trigger_state) => trigger_state.value(),
+            None => 0,
+        };
+
+        match trigger {
+            Trigger::Left => self.report.b_left_trigger = value,
+            Trigger::Right => self.report.b_right_trigger = value,
+        }
+    }
+
+    // direction has already been through the event dispatcher's wheel_invert, either bind for
+    // the notch's axis may have been told otherwise in bind_options
+    fn handle_wheel(&mut self, direction: WheelDirection, inverted: bool) {
+        let actual = match inverted {
+            true => direction.opposite(),
+            false => direction,
+        };
+
+        for bound in [direction, direction.opposite()] {
+            let bind = Bind::MouseWheel(bound);
+            let options = self.config.bind_options.get(&bind);
+            let invert = options.and_then(|options| options.wheel_invert);
+
+            let wanted = match invert.unwrap_or(inverted) {
+                true => actual.opposite(),
+                false => actual,
+            };
+
+            // A wheel notch has no release of its own
+            if bound == wanted {
+                self.handle_input(bind, KeyState::Down);
+                self.handle_input(bind, KeyState::Up);
+            }
+        }
+    }
+
+    fn handle_mouse_move(&mut self, x: i32, y: i32) {
+        let (x, y) = match self.filter_jitter(x, y) {
+            Some(movement) => movement,
+            None => return,
+        };
+
+        let now = Instant::now();
+        if let Some(last) = self.last_sample_time {
+            self.update_report_interval(now - last);
+        }
+
+        self.last_sample_time = Some(now);
+        self.mouse_windows.0.push(x, now);
+        self.mouse_windows.1.push(y, now);
+
+        // Only update_mouse_ema and update_flick_stick take them, so nothing builds up in the
+        // other modes
+        if self.config.smoothing.mode == SmoothingMode::Ema {
+            self.mouse_ema_pending.0 += x;
+            self.mouse_ema_pending.1 += y;
+        }
+        if self.config.mouse_mode == MouseMode::FlickStick {
+            self.flick_pending += x;
+        }
+    }
+    fn update_report_interval(&mut self, gap: Duration) {
+        if gap < Self::MIN_REPORT_GAP || gap > Self::MAX_REPORT_GAP {
+            return;
+        }
+
+        // Quick to follow a faster mouse, slow to drift up when slow movement skips reports
+        let interval = match self.report_interval {
+            Some(interval) if gap > interval => interval + (gap - interval) / 100,
+            _ => gap,
+        };
+        self.report_interval = Some(interval);
+
+        // An adaptive window changes all the time by itself
+        if self.config.legacy_velocity || self.config.adaptive_window {
+            return;
+        }
+
+        // The shorter window is the first to be widened
+        let windows = self.velocity_windows();
+        let window = windows.0.min(windows.1);
+        let change = window.as_secs_f64() / self.logged_window.as_secs_f64();
+
+        if !(0.8..=1.25).contains(&change) {
+            let sample_windows = self.config.sample_windows();
+            let sample_window = sample_windows.0.min(sample_windows.1);
+            info!(
+                "mouse reports about every {:?}, averaging velocity over (x: {:?}, y: {:?}) \
+                 (legacy_velocity readings are up to {:.2}x this while a report is in the window)",
+                interval,
+                windows.0,
+                windows.1,
+                window.as_secs_f64() / sample_window.as_secs_f64()
+            );
+
+            self.logged_window = window;
+        }
+    }
+
+    // The x and y times mouse velocity is averaged over, at least one report interval so every
+    // report is counted for as long as it covers
+    fn velocity_windows(&self) -> (Duration, Duration) {
+        let windows = match self.config.adaptive_window {
+            true => {
+                let window = self.adaptive_window();
+                (window, window)
+            }
+            false => self.config.sample_windows(),
+        };
+
+        match (self.config.legacy_velocity, self.report_interval) {
+            (false, Some(interval)) => (windows.0.max(interval), windows.1.max(interval)),
+            _ => windows,
+        }
+    }
+
+    fn adaptive_window(&self) -> Duration {
+        let (min, max) = (self.config.sample_window_min, self.config.sample_window_max);
+        min + (max - min).mul_f64(self.adaptive_level)
+    }
+
+    // Moves the adaptive window towards sample_window_min as speed (in full stick deflections)
+    // reaches 1 and back to sample_window_max as it drops to 0, by at most adaptive_window_rate
+    // of the way per second so it doesn't swing back and forth
+    fn update_adaptive_window(&mut self, speed: f64, now: Instant) {
+        let dt = (now - self.adaptive_updated).as_secs_f64();
+        self.adaptive_updated = now;
+
+        let target = 1.0 - speed.clamp(0.0, 1.0);
+        let step = self.config.adaptive_window_rate * dt;
+
+        self.adaptive_level += (target - self.adaptive_level).clamp(-step, step);
+    }
+
+    fn handle_device_mouse_move(&mut self, device: ic::Device, x: i32, y: i32) {
+        let now = Instant::now();
+        let (x_window, y_window) = self.device_windows.entry(device).or_default();
+
+        x_window.push(x, now);
+        y_window.push(y, now);
+    }
+
+    // Small samples from rest are held back until together they're more than the threshold, so
+    // twitching back and forth cancels out while slow movement one way gets through. Gives the
+    // movement to use, with what was held back, or None while it's still held back
+    fn filter_jitter(&mut self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let threshold = self.config.jitter_filter;
+        if threshold == 0 {
+            return Some((x, y));
+        }
+
+        let (x_window, y_window) = &self.mouse_windows;
+        let moving = x_window.average().max(y_window.average()) > threshold as f64;
+
+        let (x, y) = (self.jitter_pending.0 + x, self.jitter_pending.1 + y);
+        if moving || x.unsigned_abs().max(y.unsigned_abs()) > threshold {
+            self.jitter_pending = (0, 0);
+            return Some((x, y));
+        }
+
+        self.jitter_pending = (x, y);
+        None
+    }
+
+    fn get_mouse_move_bind(&mut self) -> AnalogState
+    {
+        let mut analog_state: AnalogState = AnalogState {
+            analog_type: AnalogType::Left,
+            x: 0.0,
+            y: 0.0,
+        };
+        // Missing when bind_options put MouseMove on another target, the stick stays at rest
+        let bind = self.config.binds.get(&Bind::MouseMove).cloned();
+
+        match bind {
+            Some(ControllerAction::AnalogLeft(x, y, _)) => {
+                analog_state.analog_type = AnalogType::Left;
+                analog_state.x = x;
+                analog_state.y = y;
+            }
+            Some(ControllerAction::AnalogRight(x, y, _)) => {
+                analog_state.analog_type = AnalogType::Right;
+                analog_state.x = x;
+                analog_state.y = y;
+            }
+            _ => {}
+        }
+
+        analog_state
+    }
+    fn update_mouse_state(&mut self, mut mouse_vel: (f64, f64))
+    {
+        if self.config.invert_mouse_x {
+            mouse_vel.0 = -mouse_vel.0;
+        }
+
+        if self.config.invert_mouse_y {
+            mouse_vel.1 = -mouse_vel.1;
+        }
+
+        let mouse_bind = self.get_mouse_move_bind();
+
+        if self.config.mouse_mode == MouseMode::FlickStick {
+            let (x, y) = self.update_flick_stick();
+            self.set_mouse_contribution(mouse_bind.analog_type, x, y);
+            return;
+        }
+
+        if self.config.mouse_mode == MouseMode::Absolute {
+            let (x, y) = self.absolute_deflection();
+            let (x, y) = (mouse_bind.x * x, mouse_bind.y * y);
+
+            self.set_mouse_contribution(mouse_bind.analog_type, x, y);
+            return;
+        }
+
+        let (mut x, mut y) = (mouse_bind.x * mouse_vel.0, mouse_bind.y * mouse_vel.1);
+
+        if self.config.axis_snap_deg > 0.0 {
+            (x, y) = self.snap_to_axis(x, y);
+        }
+
+        let now = Instant::now();
+        if self.is_mouse_idle(now) {
+            (x, y) = (0.0, 0.0);
+        } else if self.config.max_decay_per_sec > 0.0 {
+            let (previous, at) = self.mouse_output;
+            let max_drop = self.config.max_decay_per_sec * (now - at).as_secs_f64();
+
+            (x, y) = limit_decay(previous, (x, y), max_drop);
+        }
+
+        self.mouse_output = ((x, y), now);
+
+        if self.config.anti_deadzone_scope == AntiDeadzoneScope::Mouse {
+            let min = self.config.anti_deadzone.get(&mouse_bind.analog_type);
+            (x, y) = anti_deadzone(x, y, min);
+        }
+
+        self.set_mouse_contribution(mouse_bind.analog_type, x, y);
+    }
+
+    // Centred until the first position arrives
+    fn absolute_deflection(&self) -> (f64, f64) {
+        let (mut x, mut y) = match self.absolute_position {
+            Some((x, y)) => self.config.absolute_area.deflection(x, y),
+            None => return (0.0, 0.0),
+        };
+
+        if self.config.invert_mouse_x {
+            x = -x;
+        }
+
+        if self.config.invert_mouse_y {
+            y = -y;
+        }
+
+        (x, y)
+    }
+
+    // Scaled by any ScaleSensitivity binds pressed since starting
+    fn sensitivity(&self, bind: &Bind) -> (f64, f64) {
+        let sensitivity = self.config.sensitivity(bind);
+        (
+            sensitivity.0 * self.sensitivity_scale.0,
+            sensitivity.1 * self.sensitivity_scale.1,
+        )
+    }
+
+    fn is_mouse_idle(&self, now: Instant) -> bool {
+        if self.config.idle_zero_ms == 0 {
+            return false;
+        }
+
+        match self.last_sample_time {
+            Some(last) => now - last >= Duration::from_millis(self.config.idle_zero_ms),
+            None => true,
+        }
+    }
+
+    // The plain windowed velocity of each mouse with its own bind, none of the shaping of the
+    // main mouse is applied
+    fn update_device_mice(&mut self, now: Instant) {
+        let windows = self.config.sample_windows();
+
+        for (&device, (x_window, y_window)) in self.device_windows.iter_mut() {
+            let bind = Bind::MouseMoveDevice(device);
+
+            let (analog_type, scale) = match self.config.binds.get(&bind) {
+                Some(&ControllerAction::AnalogLeft(x, y, _)) => (AnalogType::Left, (x, y)),
+                Some(&ControllerAction::AnalogRight(x, y, _)) => (AnalogType::Right, (x, y)),
+                _ => continue,
+            };
+
+            x_window.expire(now, windows.0);
+            y_window.expire(now, windows.1);
+
+            // Not self.sensitivity(), the windows are borrowed from self
+            let sensitivity = self.config.sensitivity(&bind);
+            let sensitivity = (
+                sensitivity.0 * self.sensitivity_scale.0,
+                sensitivity.1 * self.sensitivity_scale.1,
+            );
+
+            let mut x = x_window.sum as f64 / windows.0.as_secs_f64() * sensitivity.0 / 1e4;
+            let mut y = y_window.sum as f64 / windows.1.as_secs_f64() * sensitivity.1 / 1e4;
+
+            if self.config.invert_mouse_x {
+                x = -x;
+            }
+
+            if self.config.invert_mouse_y {
+                y = -y;
+            }
+
+            let state = AnalogState {
+                analog_type,
+                x: x * scale.0,
+                y: y * scale.1,
+            };
+
+            self.analog_state.insert(Source::Bind(bind), state);
+        }
+    }
+
+    fn set_mouse_contribution(&mut self, analog_type: AnalogType, x: f64, y: f64) {
+        let source = Source::Bind(Bind::MouseMove);
+        if self.analog_state.contains_key(&source)
+        {
+            let state = self.analog_state.get_mut(&source).unwrap();
+            state.x = x;
+            state.y = y;
+        } else {
+            let state = AnalogState { analog_type, x, y };
+            self.analog_state.insert(source, state);
+        }
+    }
+    // Full deflection at the flick angle, mouse y is ignored
+    fn update_flick_stick(&mut self) -> (f64, f64) {
+        let FlickStick {
+            degrees_per_count,
+            flick_threshold_deg,
+            flick_hold_ms,
+        } = self.config.flick_stick;
+
+        let counts = match self.config.invert_mouse_x {
+            true => -std::mem::take(&mut self.flick_pending) as f64,
+            false => std::mem::take(&mut self.flick_pending) as f64,
+        };
+
+        // Moving the mouse right turns the stick clockwise
+        self.flick_angle = (self.flick_angle - counts * degrees_per_count).rem_euclid(360.0);
+
+        let now = Instant::now();
+        if matches!(self.flick_hold, Some((_, until)) if now >= until) {
+            self.flick_hold = None;
+        }
+
+        let turned = self.mouse_windows.0.sum as f64 * degrees_per_count;
+        let flicking = flick_threshold_deg > 0.0 && turned.abs() >= flick_threshold_deg;
+
+        // One hold per flick, the turn has to slow down again before the next
+        if flicking && !self.flick_turning {
+            let until = now + Duration::from_millis(flick_hold_ms);
+            self.flick_hold = Some((self.flick_angle, until));
+        }
+        self.flick_turning = flicking;
+
+        let angle = match self.flick_hold {
+            Some((held, _)) => held,
+            None => self.flick_angle,
+        };
+
+        let radians = angle.to_radians();
+        (radians.cos(), radians.sin())
+    }
+
+    fn snap_to_axis(&mut self, x: f64, y: f64) -> (f64, f64) {
+        let magnitude = (x.powi(2) + y.powi(2)).sqrt();
+        if magnitude == 0.0 {
+            self.axis_snapped = None;
+            return (x, y);
+        }
+
+        let angle = y.atan2(x).to_degrees();
+        let distance = |target: f64| 180.0 - ((angle - target).rem_euclid(360.0) - 180.0).abs();
+
+        let step = match self.config.axis_snap_diagonals {
+            true => 45.0,
+            false => 90.0,
+        };
+        let nearest = (angle / step).round() * step;
+
+        // Leaving the snapped direction takes hysteresis degrees more than entering it
+        let leave = self.config.axis_snap_deg + self.config.axis_snap_hysteresis_deg;
+        self.axis_snapped = match self.axis_snapped {
+            Some(snapped) if distance(snapped) <= leave => Some(snapped),
+            _ if distance(nearest) <= self.config.axis_snap_deg => Some(nearest),
+            _ => None,
+        };
+
+        match self.axis_snapped {
+            Some(snapped) => {
+                let radians = snapped.to_radians();
+                (radians.cos() * magnitude, radians.sin() * magnitude)
+            }
+            None => (x, y),
+        }
+    }
+
+    // Runs every loop so the average decays when no samples arrive
+    fn update_mouse_ema(&mut self, now: Instant) -> (f64, f64) {
+        let dt = (now - self.mouse_ema_updated).as_secs_f64();
+        if dt <= 0.0 {
+            return self.mouse_ema;
+        }
+
+        let (x, y) = std::mem::take(&mut self.mouse_ema_pending);
+        let alpha = 1.0 - (-dt * 1e3 / self.config.smoothing.tau_ms.max(f64::EPSILON)).exp();
+
+        self.mouse_ema.0 += alpha * (x as f64 / dt - self.mouse_ema.0);
+        self.mouse_ema.1 += alpha * (y as f64 / dt - self.mouse_ema.1);
+        self.mouse_ema_updated = now;
+
+        self.mouse_ema
+    }
+
+    fn update_analog(&mut self) {
+        let now = Instant::now();
+        let windows = self.velocity_windows();
+
+        // Samples are kept for the longest window an adaptive one can grow to, so the velocity
+        // doesn't dip while it grows
+        let kept = match self.config.adaptive_window {
+            true => (
+                windows.0.max(self.config.sample_window_max),
+                windows.1.max(self.config.sample_window_max),
+            ),
+            false => windows,
+        };
+
+        self.mouse_windows.0.expire(now, kept.0);
+        self.mouse_windows.1.expire(now, kept.1);
+
+        if self.is_mouse_idle(now) {
+            self.mouse_windows.0.clear();
+            self.mouse_windows.1.clear();
+            self.mouse_ema = (0.0, 0.0);
+        }
+
+        let (window_x, window_y) = (windows.0.as_secs_f64(), windows.1.as_secs_f64());
+
+        // In counts per second
+        let mut mouse_vel = match self.config.smoothing.mode {
+            SmoothingMode::Window => {
+                let weighting = self.config.window_weighting;
+                let mut x = self.mouse_windows.0.total(now, windows.0, weighting);
+                let mut y = self.mouse_windows.1.total(now, windows.1, weighting);
+
+                if self.config.prediction_ms > 0 {
+                    let ahead = Duration::from_millis(self.config.prediction_ms);
+                    let max_overshoot = self.config.prediction_max_overshoot / 100.0;
+
+                    let (x_window, y_window) = &self.mouse_windows;
+                    x = x_window.extrapolate(x, now, windows.0, ahead, max_overshoot);
+                    y = y_window.extrapolate(y, now, windows.1, ahead, max_overshoot);
+                }
+
+                (x / window_x, y / window_y)
+            }
+            SmoothingMode::Ema => self.update_mouse_ema(now),
+        };
+
+        self.update_mouse_trigger(mouse_vel);
+
+        // The gyro has its own sensitivity, so it takes the velocity before anything else does
+        let gyro = self.config.gyro.filter(|_| self.gyro_active);
+        self.gyro = gyro.map_or([0.0; 3], |gyro| gyro.rotation(mouse_vel));
+
+        // The curve turns the speed into a deflection (1 from max_velocity up) without changing
+        // the direction, which sensitivity then scales
+        let speed = (mouse_vel.0.powi(2) + mouse_vel.1.powi(2)).sqrt();
+        if speed > 0.0 {
+            let scale = self.config.response_curve.apply(speed) / speed;
+            mouse_vel.0 *= scale;
+            mouse_vel.1 *= scale;
+        }
+
+        let sensitivity = self.sensitivity(&Bind::MouseMove);
+        mouse_vel.0 *= sensitivity.0;
+        mouse_vel.1 *= sensitivity.1;
+
+        if self.config.adaptive_window {
+            let speed = (mouse_vel.0.powi(2) + mouse_vel.1.powi(2)).sqrt();
+            self.update_adaptive_window(speed, now);
+        }
+
+        let mut states = (
+            AnalogState {
+                analog_type: AnalogType::Left,
+                x: 0.0,
+                y: 0.0,
+            }, AnalogState {
+                analog_type: AnalogType::Right,
+                x: 0.0,
+                y: 0.0,
+            }
+        );
+        // Gestures go by the speed before acceleration
+        self.mouse_stick_vel = match gyro {
+            Some(gyro) if !gyro.keep_stick => (0.0, 0.0),
+            _ => self.accelerate(mouse_vel),
+        };
+        self.update_mouse_state(self.mouse_stick_vel);
+        self.update_gestures(mouse_vel);
+        self.update_device_mice(now);
+
+        // Flick stick and absolute positions aren't speeds, so nothing of theirs adds up
+        let velocity = self.config.mouse_mode == MouseMode::Velocity;
+        let stick_carry = [AnalogType::Left, AnalogType::Right].map(|analog_type| {
+            self.analog_state.iter().any(|(source, state)| {
+                let speed = match source {
+                    Source::Bind(Bind::MouseMove) => velocity,
+                    Source::Bind(Bind::MouseMoveDevice(_)) => true,
+                    _ => false,
+                };
+                speed && state.analog_type == analog_type && (state.x != 0.0 || state.y != 0.0)
+            })
+        });
+        self.stick_carry = stick_carry;
+
+        // Only the newest polar bind on each stick counts
+        let newest = |analog_type| {
+            self.polar_holds
+                .iter()
+                .rfind(move |(_, state)| state.analog_type == analog_type)
+        };
+        let (left, right) = (newest(AnalogType::Left), newest(AnalogType::Right));
+
+        let polar = left.into_iter().chain(right).map(|(_, state)| state);
+
+        // In dpad mode keyboard AnalogLeft binds steer the dpad instead of the stick
+        let dpad_mode_active = self.dpad_mode_active;
+        let (dpad, analog): (Vec<_>, Vec<_>) =
+            self.analog_state.iter().partition(|&(source, state)| {
+                dpad_mode_active
+                    && matches!(source, Source::Bind(Bind::Keyboard(..)))
+                    && state.analog_type == AnalogType::Left
+            });
+
+        let mut dpad_vector = (0.0, 0.0);
+
+        for (_, state) in dpad {
+            dpad_vector.0 += state.x;
+            dpad_vector.1 += state.y;
+        }
+
+        for state in analog.into_iter().map(|(_, state)| state).chain(polar) {
+            match state.analog_type {
+                AnalogType::Left => {
+                    states.0.x += state.x;
+                    states.0.y += state.y;
+                }
+                AnalogType::Right => {
+                    states.1.x += state.x;
+                    states.1.y += state.y;
+                }
+            }
+        }
+        self.update_dpad_mode(dpad_vector);
+        self.update_auto_sprint(states.0.y);
+        self.update_auto_stick_click(&states);
+        self.update_zones(&states);
+        self.update_oversteer_alert(&states, now);
+        self.update_understeer_alert(&states);
+
+        // After the sticks swap neither is left pushed on the side it just left
+        if self.centre_sticks {
+            self.centre_sticks = false;
+            self.write_stick(AnalogType::Left, 0.0, 0.0);
+            self.write_stick(AnalogType::Right, 0.0, 0.0);
+
+            return;
+        }
+
+        self.set_analog(states.0);
+        self.set_analog(states.1);
+    }
+
+    // Mouse y grows downwards
+    fn accelerate(&self, mouse_vel: (f64, f64)) -> (f64, f64) {
+        let speed = (mouse_vel.0.powi(2) + mouse_vel.1.powi(2)).sqrt();
+        if speed <= 0.0 {
+            return mouse_vel;
+        }
+
+        let (threshold, multiplier) = (self.config.accel_threshold, self.config.accel_multiplier);
+        let mut output = speed * acceleration(speed, threshold, multiplier);
+
+        if self.config.accel_cap > 0.0 {
+            output = output.min(self.config.accel_cap);
+        }
+
+        let scale = output / speed;
+        (mouse_vel.0 * scale, mouse_vel.1 * scale)
+    }
+
+    fn update_gestures(&mut self, mouse_vel: (f64, f64)) {
+        let now = Instant::now();
+        let mut fired = Vec::new();
+
+        for (i, gesture) in self.config.gestures.iter().enumerate() {
+            let speed = match gesture.direction {
+                Direction::Up => -mouse_vel.1,
+                Direction::Down => mouse_vel.1,
+                Direction::Left => -mouse_vel.0,
+                Direction::Right => mouse_vel.0,
+            };
+
+            let (flicking, last_fired) = &mut self.gesture_state[i];
+            let started = speed >= gesture.velocity && !*flicking;
+            *flicking = speed >= gesture.velocity;
+
+            if !started {
+                continue;
+            }
+
+            let held = gesture.unless_held.map(|modifier| modifier.key());
+            if held.and_then(|key| self.key_states.get(&key)) == Some(&KeyState::Down) {
+                continue;
+            }
+
+            let refractory = Duration::from_millis(gesture.refractory_ms);
+            if matches!(*last_fired, Some(last) if now - last < refractory) {
+                continue;
+            }
+
+            *last_fired = Some(now);
+            fired.push(i);
+        }
+
+        for i in fired {
+            let action = self.config.gestures[i].action.clone();
+            self.tap_action(Source::Gesture(i), &action);
+        }
+    }
+
+    fn update_dpad_mode(&mut self, vector: (f64, f64)) {
+        let DpadMode {
+            diagonal_threshold,
+            deadzone,
+            ..
+        } = self.config.dpad_mode;
+
+        let magnitude = (vector.0.powi(2) + vector.1.powi(2)).sqrt();
+        let angle = vector.1.atan2(vector.0).to_degrees();
+
+        let mut changed = false;
+
+        for (direction, button) in [
+            (0.0, ControllerButton::DpadRight),
+            (90.0, ControllerButton::DpadUp),
+            (180.0, ControllerButton::DpadLeft),
+            (270.0, ControllerButton::DpadDown),
+        ] {
+            // Angular distance from this direction, 0 to 180
+            let distance = 180.0 - ((angle - direction).rem_euclid(360.0) - 180.0).abs();
+
+            // Switching back to analog lets go of everything dpad mode pressed
+            let press = self.dpad_mode_active
+                && magnitude >= deadzone
+                && distance < 45.0 + diagonal_threshold;
+            let pressed = self.button_holds.contains(&(Source::DpadMode, button));
+
+            if press != pressed {
+                match press {
+                    true => self.press_button(Source::DpadMode, button),
+                    false => self.release_button(Source::DpadMode, button),
+                }
+
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.update_buttons();
+        }
+    }
+
+    fn update_auto_sprint(&mut self, forward: f64) {
+        let AutoSprint {
+            enabled,
+            button,
+            threshold,
+            delay_ms,
+        } = self.config.auto_sprint;
+
+        if !enabled {
+            return;
+        }
+
+        // A manually held sprint button wins, leave it alone until it is released
+        let manual = self
+            .button_holds
+            .iter()
+            .any(|&(source, held)| held == button && source != Source::AutoSprint);
+
+        let sprinting = match (forward >= threshold && !manual, self.auto_sprint_since) {
+            (true, Some(since)) => since.elapsed() >= Duration::from_millis(delay_ms),
+            (true, None) => {
+                self.auto_sprint_since = Some(Instant::now());
+                false
+            }
+            (false, _) => {
+                self.auto_sprint_since = None;
+                false
+            }
+        };
+
+        let pressed = self.button_holds.contains(&(Source::AutoSprint, button));
+
+        if sprinting != pressed {
+            match sprinting {
+                true => self.press_button(Source::AutoSprint, button),
+                false => self.release_button(Source::AutoSprint, button),
+            }
+
+            self.update_buttons();
+        }
+    }
+
+    fn update_auto_stick_click(&mut self, states: &(AnalogState, AnalogState)) {
+        let AutoStickClick {
+            enabled,
+            stick,
+            button,
+            threshold,
+            hysteresis,
+        } = self.config.auto_stick_click;
+
+        if !enabled {
+            return;
+        }
+
+        let state = match stick {
+            AnalogType::Left => &states.0,
+            AnalogType::Right => &states.1,
+        };
+
+        let magnitude = (state.x.powi(2) + state.y.powi(2)).sqrt();
+        let hold = (Source::AutoStickClick, button);
+        let pressed = self.button_holds.contains(&hold);
+
+        if !pressed && magnitude >= threshold {
+            self.press_button(Source::AutoStickClick, button);
+            self.update_buttons();
+        } else if pressed && magnitude < threshold - hysteresis {
+            self.release_button(Source::AutoStickClick, button);
+            self.update_buttons();
+        }
+    }
+
+    fn update_zones(&mut self, states: &(AnalogState, AnalogState)) {
+        let now = Instant::now();
+        let mut changed = Vec::new();
+
+        for (i, zone) in self.config.zones.iter().enumerate() {
+            let state = match zone.stick {
+                AnalogType::Left => &states.0,
+                AnalogType::Right => &states.1,
+            };
+
+            let (entered, held) = &mut self.zone_state[i];
+            let depth = zone.region.depth(state);
+
+            let inside = match entered {
+                Some(_) => depth >= -zone.hysteresis,
+                None => depth >= 0.0,
+            };
+
+            match (inside, *entered) {
+                (true, None) => *entered = Some(now),
+                (false, Some(_)) => *entered = None,
+                _ => {}
+            }
+
+            let dwell = Duration::from_millis(zone.dwell_ms);
+            let hold = matches!(*entered, Some(since) if now - since >= dwell);
+
+            if hold != *held {
+                *held = hold;
+                changed.push((i, hold));
+            }
+        }
+
+        for (i, hold) in changed {
+            let action = self.config.zones[i].action.clone();
+            let state = if hold { KeyState::Down } else { KeyState::Up };
+
+            self.handle_action(Source::Zone(i), &action, state);
+        }
+    }
+
+    fn handle_rumble(&mut self, large: u8, small: u8) {
+        #[cfg(feature = "audio")]
+        if let Some(tg) = self.tone_generator.as_mut() {
+            if self.config.rumble_audio_enabled {
+                tg.rumble(large, small);
+            }
+        }
+
+        let rumble_action = match &self.config.rumble_action {
+            Some(rumble_action) => rumble_action,
+            None => return,
+        };
+
+        let rumbling = large.max(small) > rumble_action.threshold;
+        if rumbling == self.rumbling {
+            return;
+        }
+
+        self.rumbling = rumbling;
+
+        let action = rumble_action.action.clone();
+        let state = match rumbling {
+            true => KeyState::Down,
+            false => KeyState::Up,
+        };
+
+        self.handle_action(Source::Rumble, &action, state);
+    }
+
+    // Nothing is steering while input is let go, so it only counts while captured
+    fn update_understeer_alert(&mut self, states: &(AnalogState, AnalogState)) {
+        let alert = self.config.understeer_alert;
+        if !alert.enabled {
+            return;
+        }
+
+        let state = match alert.stick {
+            AnalogType::Left => &states.0,
+            AnalogType::Right => &states.1,
+        };
+
+        let centred = (state.x.powi(2) + state.y.powi(2)).sqrt() < alert.floor;
+
+        self.understeer_since = match centred && self.capturing {
+            true => self.understeer_since.or_else(|| Some(Instant::now())),
+            false => None,
+        };
+
+        #[cfg(feature = "audio")]
+        if let Some(tg) = self.tone_generator.as_mut() {
+            let dwell = Duration::from_millis(alert.dwell_ms);
+            tg.understeer(self.understeer_since.is_some_and(|t| t.elapsed() >= dwell));
+        }
+    }
+
+    // Swaps in the config changed on disk, the old one stays if the new one is invalid. The
+    // controllers and DSU server keep what they were started with until restarting, and what
+    // a bind that's gone or changed held is let go
+    fn reload(&mut self, mut config: Config) {
+        if let Err(error) = config.prepare() {
+            error!("keeping the old config, the new one is invalid: {}", error);
+            return;
+        }
+
+        let old = &self.base;
+        let restart = config.require_slot != old.require_slot
+            || config.targets.len() != old.targets.len()
+            || config.mirror_targets != old.mirror_targets
+            || config.dsu_server.is_some() != old.dsu_server.is_some()
+            || config.trace_file != old.trace_file;
+
+        if restart {
+            warn!(
+                "require_slot, the number of targets, mirror_targets, dsu_server and trace_file \
+                 only change on restart"
+            );
+        }
+
+        config.keep_started(old);
+
+        // Staying on the same profile and toggled layers if they're still there. Held ones are
+        // let go as what holds them may be gone or bound to something else
+        if !config.profiles.contains_key(&self.profile) {
+            self.profile = DEFAULT_PROFILE.to_owned();
+        }
+        self.active_layers
+            .retain(|(name, by)| by.is_none() && config.layers.contains_key(name));
+
+        // Their state is by index, which may now be another one
+        self.release_indexed();
+        self.base = config;
+        self.update_layers();
+        self.reset_indexed();
+
+        info!("config reloaded");
+        info!("{}", self.base.bind_table());
+    }
+    // Switches to window_profiles' profile for the window now in front, unless a Profile bind
+    // was used since it came to the front
+    fn handle_foreground(&mut self, window: Window) {
+        if self.foreground != Some(window.id) {
+            self.manual_profile = false;
+        }
+
+        self.foreground = Some(window.id);
+        if self.manual_profile {
+            return;
+        }
+
+        let profile = match self.base.window_profile(&window) {
+            Some(profile) if profile != self.profile => profile.to_owned(),
+            _ => return,
+        };
+
+        debug!("\"{}\" ({}) is in front", window.title, window.exe);
+        self.switch_profile(&profile);
+    }
+
+    // Switching starts the profile with no layers on
+    fn switch_profile(&mut self, name: &str) {
+        // What was held may have no release in the new binds
+        self.active_layers.clear();
+        self.release_all();
+
+        self.profile = name.to_owned();
+        self.apply(self.base.resolved(name, []));
+        info!("profile {}", name);
+
+        #[cfg(feature = "audio")]
+        self.play_cue(Cue::Profile(self.base.profile_number(name)));
+    }
+
+    // Lets go of the layers source holds on, giving whether it held any
+    fn release_layer(&mut self, source: Source) -> bool {
+        let held = self.active_layers.len();
+        self.active_layers.retain(|(_, by)| *by != Some(source));
+        if self.active_layers.len() == held {
+            return false;
+        }
+
+        self.update_layers();
+        true
+    }
+
+    // Puts the profile with the layers now on in use
+    fn update_layers(&mut self) {
+        let layers = self.active_layers.iter().map(|(name, _)| name);
+        self.apply(self.base.resolved(&self.profile, layers));
+
+        let names: Vec<&str> = self
+            .active_layers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        debug!("layers [{}]", names.join(", "));
+    }
+
+    // Puts config in use, letting go of what the binds that changed held and starting again
+    // whatever goes by settings that changed
+    fn apply(&mut self, config: Config) {
+        let indexed = |config: &Config| {
+            ron::to_string(&(&config.sequences, &config.gestures, &config.zones)).ok()
+        };
+        let reindexed = indexed(&self.config) != indexed(&config);
+        if reindexed {
+            self.release_indexed();
+        }
+
+        let old = std::mem::replace(&mut self.config, config);
+        self.release_binds(changed_binds(&old.binds, &self.config.binds));
+        if reindexed {
+            self.reset_indexed();
+        }
+
+        let config = &self.config;
+        self.next_output = match config.output_rate_hz {
+            0 => None,
+            _ => Some(self.next_output.unwrap_or_else(Instant::now)),
+        };
+
+        // Toggled state only follows the settings that changed
+        if config.dpad_mode.active != old.dpad_mode.active {
+            self.dpad_mode_active = config.dpad_mode.active;
+        }
+        if config.swap_analogs != old.swap_analogs {
+            self.swap_analogs_active = config.swap_analogs;
+            self.centre_sticks = true;
+        }
+        if config.smoothing.mode != old.smoothing.mode {
+            self.mouse_ema = (0.0, 0.0);
+            self.mouse_ema_pending = (0, 0);
+        }
+        if config.mouse_mode != old.mouse_mode {
+            self.flick_pending = 0;
+        }
+        let gyro = |config: &Config| config.gyro.is_some_and(|gyro| gyro.enabled);
+        if gyro(config) != gyro(&old) {
+            self.gyro_active = gyro(config);
+        }
+
+        if config.controller_type != old.controller_type {
+            let controller_type = config.controller_type;
+            match self.output.set_controller_type(controller_type) {
+                Ok(()) => info!("controller_type {:?}", controller_type),
+                Err(error) => error!("could not change controller_type: {}", error),
+            }
+
+            // The new controllers have had nothing yet
+            self.last_sent = None;
+        }
+
+        #[cfg(feature = "audio")]
+        if self.config.audio_settings() != old.audio_settings() {
+            self.restart_audio();
+        }
+
+        event_dispatcher::set_passthrough(self.config.passthrough);
+        self.update_buttons();
+    }
+
+    // Lets go of what the sequences, gestures and zones hold, for when which is which may change
+    fn release_indexed(&mut self) {
+        for i in 0..self.config.sequences.len() {
+            self.release_source(Source::Sequence(i));
+        }
+        for i in 0..self.config.gestures.len() {
+            self.release_source(Source::Gesture(i));
+        }
+        for i in 0..self.config.zones.len() {
+            self.release_source(Source::Zone(i));
+        }
+    }
+
+    // Starts them again from nothing, as many as there are now
+    fn reset_indexed(&mut self) {
+        self.sequence_keys.clear();
+        self.sequence_matched = vec![0; self.config.sequences.len()];
+        self.gesture_state = vec![(false, None); self.config.gestures.len()];
+        self.zone_state = vec![(None, false); self.config.zones.len()];
+    }
+
+    #[cfg(feature = "audio")]
+    fn restart_audio(&mut self) {
+        // The old stream is closed before the device is opened again
+        self.tone_generator = None;
+        self.beeper = None;
+        self.alert_on_since = None;
+        self.understeer_since = None;
+
+        match Self::start_audio(&self.config, self.audio_muted) {
+            Ok((tone_generator, beeper)) => {
+                self.tone_generator = tone_generator;
+                self.beeper = beeper;
+            }
+            Err(error) => error!("could not start audio: {}", error),
+        }
+    }
+
+    // Lets go of what binds held, given with the actions they had
+    fn release_binds(&mut self, binds: Vec<(Bind, ControllerAction)>) {
+        for (bind, action) in binds {
+            self.release_source(Source::Bind(bind));
+
+            // Its physical release won't reach the remapped key any more
+            if let ControllerAction::KeyRemap(code, e0) = action {
+                let key = Key { code, e0 };
+                if self.injected_keys.contains(&key) {
+                    self.inject(key, KeyState::Up);
+                }
+            }
+        }
+    }
+
+    // Lets go of every input and everything binds hold, as the toggle key letting go of input
+    // does
+    fn release_all(&mut self) {
+        self.mouse_button_states = (KeyState::Up, KeyState::Up);
+        self.key_states.clear();
+        self.mouse_ema = (0.0, 0.0);
+        self.mouse_ema_pending = (0, 0);
+        self.jitter_pending = (0, 0);
+        self.stick_remainders = [0.0; 4];
+        self.device_windows.clear();
+        self.absolute_position = None;
+        self.rumbling = false;
+
+        for extra in self.extra_targets.iter_mut() {
+            extra.held.clear();
+            extra.report = XUSBReport::default();
+        }
+        self.queued_keystrokes.clear();
+        self.release_injected_keys();
+        self.analog_state.clear();
+        self.polar_holds.clear();
+        self.trigger_state.clear();
+        self.button_holds.clear();
+        self.suppressions.clear();
+        self.auto_sprint_since = None;
+        self.hold_deadlines.clear();
+        self.pulse_deadlines.clear();
+        self.scheduled_releases.clear();
+        self.tap_holds.clear();
+        self.first_taps.clear();
+        self.auto_released.clear();
+        self.sequence_keys.clear();
+        self.sequence_matched.fill(0);
+        self.gesture_state.fill((false, None));
+        self.zone_state.fill((None, false));
+        self.report = XUSBReport::default();
+
+        // Nothing is held on any more, toggled layers stay
+        let held = self.active_layers.len();
+        self.active_layers.retain(|(_, by)| by.is_none());
+        if self.active_layers.len() != held {
+            self.update_layers();
+        }
+    }
+
+    // Lets go of everything source holds, without doing what its release would
+    fn release_source(&mut self, source: Source) {
+        self.analog_state.remove(&source);
+        self.polar_holds.retain(|&(held, _)| held != source);
+        self.button_holds.retain(|&(held, _)| held != source);
+        self.suppressions.retain(|&(held, _)| held != source);
+        self.pulse_deadlines.retain(|&(held, _), _| held != source);
+        self.scheduled_releases.retain(|&(_, s, _)| s != source);
+
+        if let Source::Bind(bind) = source {
+            self.hold_deadlines.remove(&bind);
+            self.auto_released.remove(&bind);
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    fn play_cue(&mut self, cue: Cue) {
+        if !self.config.audio_cues.enabled {
+            return;
+        }
+
+        if let Some(tg) = self.tone_generator.as_mut() {
+            tg.cue(cue);
+        }
+    }
+
+    // How far past its threshold the stick is pushed, below 0 while it's under
+    fn oversteer(&self, state: &AnalogState) -> f64 {
+        let threshold = self.config.oversteer_alert_threshold(state.analog_type);
+        state.x.abs().max(state.y.abs()) - threshold
+    }
 
-    rx: mpsc::Receiver<Event>,
+    // Either stick going over sounds the alert, the one furthest over sets its pitch and side.
+    // Once on it stays on for alert_min_on_ms, and until the stick is alert_hysteresis under
+    fn update_oversteer_alert(&mut self, states: &(AnalogState, AnalogState), now: Instant) {
+        let left = (self.oversteer(&states.0), &states.0);
+        let right = (self.oversteer(&states.1), &states.1);
+        let furthest = if left.0 >= right.0 { left } else { right };
+        let overshoot = furthest.0;
 
-    vigem: Vigem,
-    target: Target,
-    report: XUSBReport,
+        let min_on = Duration::from_millis(self.config.alert_min_on_ms);
 
-    tone_generator: Option<ToneGenerator>,
+        let on = self.config.oversteer_alert_enabled
+            && match self.alert_on_since {
+                None => overshoot >= 0.0,
+                Some(since) => overshoot >= -self.config.alert_hysteresis || now - since < min_on,
+            };
 
-    mouse_samples: VecDeque<(i32, i32, Instant)>,
-    mouse_button_states: (KeyState, KeyState),
+        self.alert_on_since = match on {
+            true => self.alert_on_since.or(Some(now)),
+            false => None,
+        };
 
-    analog_state: HashMap<Bind, AnalogState>,
-    iteration_count: i32,
-    iteration_total: Duration,
-    iteration_window_start: Instant,
-}
+        #[cfg(feature = "audio")]
+        if let Some(beeper) = self.beeper.as_mut() {
+            beeper.enable(on);
+        }
 
-impl EventHandler {
-    const ANALOG_MAX: f64 = -(i16::MIN as f64);
+        #[cfg(feature = "audio")]
+        if let Some(tg) = self.tone_generator.as_mut() {
+            let state = furthest.1;
+            tg.enable(on, Pan::of(state.x, state.y));
+            if on {
+                tg.overshoot(overshoot.max(0.0) as f32);
+            }
+        }
+    }
+
+    fn set_analog(&mut self, mut state: AnalogState) {
+        // Tiny deflections only fight the in-game deadzone, send a clean zero instead
+        let min_output = self.config.analog_min_output.get(&state.analog_type);
+        if (state.x.powi(2) + state.y.powi(2)).sqrt() < min_output {
+            state.x = 0.0;
+            state.y = 0.0;
+        }
 
-    pub fn new(rx: mpsc::Receiver<Event>, _config: Config) -> Result<Self, anyhow::Error> {
-        let mut config = _config;
+        if self.config.anti_deadzone_scope == AntiDeadzoneScope::Combined {
+            let min = self.config.anti_deadzone.get(&state.analog_type);
+            (state.x, state.y) = anti_deadzone(state.x, state.y, min);
+        }
 
-        if !config.binds.contains_key(&Bind::MouseMove) {
-            error!("MouseMove is not bound to any analog.\nTry to add:\n-> MouseMove: AnalogRight(1, -1) <-\n to the binds in your config.ron");
-            config.binds.insert(Bind::MouseMove, ControllerAction::AnalogRight(1.0, -1.0));
+        if self.config.analog_circularize {
+            self.set_analog_circularized(state);
         } else {
-            let bind = config.binds.get_mut(&Bind::MouseMove).unwrap();
-            match bind {
-                ControllerAction::Button(_) => {
-                    error!("MouseMove is not bound to any analog. Instead, it is bound to Button which is not allowed. Appropriate values are:\nAnalogRight(x, y) and AnalogLeft(x, y)");
-                    *bind = ControllerAction::AnalogRight(1.0, -1.0);
-                }
-                _ => {}
-            }
+            self.set_analog_linear(state);
         }
-        let mut vigem = Vigem::new();
-        vigem.connect()?;
+    }
 
-        let mut target = Target::new(TargetType::Xbox360);
-        vigem.target_add(&mut target)?;
+    fn set_analog_circularized(&mut self, state: AnalogState) {
+        let max = self.config.max_stick_output.get(&state.analog_type);
 
-        info!("ViGEm connected, controller index: {}", target.index());
+        let angle = state.y.atan2(state.x);
+        let radius = (state.x.powi(2) + state.y.powi(2)).sqrt().min(max);
 
-        info!(
-            "sensitivity: {}, sample_window: {:#?}",
-            config.sensitivity, config.sample_window,
-        );
+        let (x, y) = (angle.cos() * radius, angle.sin() * radius);
+        self.write_stick(state.analog_type, x, y);
+    }
 
-        let tone_generator = match config.oversteer_alert_enabled {
-            true => Some(ToneGenerator::new(config.oversteer_alert)?),
-            false => None,
-        };
+    fn set_analog_linear(&mut self, state: AnalogState) {
+        // At 1 the corners of the square stay reachable, only a lower max caps the radius
+        let max = self.config.max_stick_output.get(&state.analog_type);
+        let capped = max < 1.0;
 
-        Ok(EventHandler {
-            config,
+        if state.x.abs() <= 1.0 && state.y.abs() <= 1.0 {
+            let radius = (state.x.powi(2) + state.y.powi(2)).sqrt();
+            let scale = match capped && radius > max {
+                true => max / radius,
+                false => 1.0,
+            };
 
-            rx,
+            self.write_stick(state.analog_type, state.x * scale, state.y * scale);
+            return;
+        }
 
-            vigem,
-            target,
-            report: XUSBReport::default(),
+        let overshoot = state.x.abs().max(state.y.abs());
 
-            tone_generator,
+        let angle = state.y.atan2(state.x);
+        let radius = (state.x.powi(2) + state.y.powi(2)).sqrt();
 
-            mouse_samples: VecDeque::new(),
-            mouse_button_states: (KeyState::Up, KeyState::Up),
+        let new_radius = match capped {
+            true => (radius / overshoot).min(max),
+            false => radius / overshoot,
+        };
 
-            analog_state: HashMap::new(),
-            iteration_count: 0,
-            iteration_total: Duration::from_secs(0),
-            iteration_window_start: Instant::now(),
-        })
+        self.write_stick(
+            state.analog_type,
+            angle.cos() * new_radius,
+            angle.sin() * new_radius,
+        );
     }
 
-    pub fn run(&mut self) -> Result<(), anyhow::Error> {
-        loop {
-            let iteration_start = Instant::now();
+    // Whether to work out and send a report this loop, always without output_rate_hz
+    fn output_due(&mut self) -> bool {
+        let next_output = match self.next_output {
+            Some(next_output) => next_output,
+            None => return true,
+        };
 
-            let mut event = self.rx.try_recv();
-            while event.is_err() && iteration_start.elapsed() < self.config.spin_period {
-                spin_loop();
-                event = self.rx.try_recv();
-            }
-            // match event {
-            //     Ok(x) => info!("{:?}", x),
-            //     Err(_) => {}
-            // }
-            if let Ok(event) = event {
-                match event {
-                    Event::MouseMove(x, y) =>
-                        {
-                            self.handle_mouse_move(x, y)
-                        }
+        let now = Instant::now();
+        if now < next_output {
+            return false;
+        }
 
-                    Event::MouseButton(button, state) => {
-                        if button == MouseButton::Left {
-                            self.mouse_button_states.0 = state;
-                        }
+        // After falling a whole period behind, start afresh rather than send a burst
+        let period = Duration::from_secs_f64(1.0 / self.config.output_rate_hz as f64);
+        self.next_output = match now - next_output < period {
+            true => Some(next_output + period),
+            false => Some(now + period),
+        };
 
-                        if button == MouseButton::Right {
-                            self.mouse_button_states.1 = state;
-                        }
+        self.output_count += 1;
 
-                        self.handle_bind(Bind::Mouse(button), state);
+        true
+    }
 
-                        if self.config.mouse_button_fix && state == KeyState::Up {
-                            if self.mouse_button_states.0 == KeyState::Down {
-                                self.handle_bind(Bind::Mouse(MouseButton::Left), KeyState::Down)
-                            }
+    fn detach_if_idle(&mut self) {
+        let secs = self.config.idle_disconnect_secs;
+        if secs == 0 || self.idle || self.reconnect.is_some() {
+            return;
+        }
 
-                            if self.mouse_button_states.1 == KeyState::Down {
-                                self.handle_bind(Bind::Mouse(MouseButton::Right), KeyState::Down)
-                            }
-                        }
-                    }
+        if self.last_input.elapsed() < Duration::from_secs(secs) {
+            return;
+        }
 
-                    Event::Keyboard(scancode, state) => {
-                        self.handle_bind(Bind::Keyboard(scancode), state)
-                    }
+        info!("no input for {}s, removing the controllers", secs);
 
-                    Event::Reset => {
-                        self.mouse_button_states = (KeyState::Up, KeyState::Up);
-                        self.report = XUSBReport::default();
-                    }
-                }
-            }
+        self.output.detach();
+        self.idle = true;
+    }
 
-            self.update_analog();
-            self.vigem.update(&self.target, &self.report)?;
+    // Notes the input, and adds the controllers back if they went away while idle. They start
+    // zeroed and get the current report with the next output
+    fn wake(&mut self) -> Result<(), anyhow::Error> {
+        self.last_input = Instant::now();
 
-            if log_enabled!(log::Level::Info) {
-                self.iteration_count += 1;
-                self.iteration_total += iteration_start.elapsed();
+        if !self.idle {
+            return Ok(());
+        }
 
-                if self.iteration_window_start.elapsed() > Duration::from_secs(2) {
-                    debug!(
-                        "{} loops, {} per sec, avg = {:#?}",
-                        self.iteration_count,
-                        self.iteration_count as f64 / 2.0,
-                        self.iteration_total.div_f64(self.iteration_count.into())
-                    );
+        self.idle = false;
+        self.last_sent = None;
+        for extra in self.extra_targets.iter_mut() {
+            extra.last_sent = None;
+        }
+        self.mirror_last_sent.fill(None);
 
-                    self.iteration_count = 0;
-                    self.iteration_total = Duration::from_secs(0);
-                    self.iteration_window_start = Instant::now();
-                }
-            }
+        info!("input again, adding the controllers back");
+
+        // Failing to is handled like losing the bus
+        match self.output.attach() {
+            Ok(()) => Ok(()),
+            Err(error) => self.lost_connection(error),
         }
     }
 
-    fn handle_bind(&mut self, bind: Bind, state: KeyState) {
-        let controller_button = match self.config.binds.get(&bind) {
-            Some(ControllerAction::Button(controller_button)) => controller_button,
-            Some(ControllerAction::AnalogLeft(x, y)) => {
-                if self.analog_state.contains_key(&bind) && state == KeyState::Up
-                {
-                    self.analog_state.remove(&bind);
-                    return;
-                }
+    fn lost_connection(&mut self, error: anyhow::Error) -> Result<(), anyhow::Error> {
+        if self.config.reconnect_max_retries == 0 {
+            return Err(error);
+        }
 
-                self.analog_state.insert(bind, AnalogState {
-                    analog_type: AnalogType::Left,
-                    x: *x,
-                    y: *y,
-                });
+        warn!("{}, reconnecting", error);
 
-                return;
-            }
-            Some(ControllerAction::AnalogRight(x, y)) => {
-                if self.analog_state.contains_key(&bind) && state == KeyState::Up
-                {
-                    self.analog_state.remove(&bind);
-                    return;
-                }
+        self.reconnect = Some(Reconnect {
+            attempts: 0,
+            next_attempt: Instant::now(),
+            backoff: RECONNECT_MIN_BACKOFF,
+        });
 
-                self.analog_state.insert(bind, AnalogState {
-                    analog_type: AnalogType::Right,
-                    x: *x,
-                    y: *y,
-                });
-                return;
-            }
-            None => return,
+        Ok(())
+    }
+
+    fn try_reconnect(&mut self) -> Result<(), anyhow::Error> {
+        let reconnect = match self.reconnect.as_mut() {
+            Some(reconnect) => reconnect,
+            None => return Ok(()),
         };
 
-        match *controller_button {
-            ControllerButton::LeftTrigger => match state {
-                KeyState::Down => self.report.b_left_trigger = u8::MAX,
-                KeyState::Up => self.report.b_left_trigger = 0,
-            },
+        if Instant::now() < reconnect.next_attempt {
+            return Ok(());
+        }
 
-            ControllerButton::RightTrigger => match state {
-                KeyState::Down => self.report.b_right_trigger = u8::MAX,
-                KeyState::Up => self.report.b_right_trigger = 0,
-            },
+        reconnect.attempts += 1;
+        let attempts = reconnect.attempts;
+        let max = self.config.reconnect_max_retries;
 
-            button => {
-                let button_flag = XButton::from_bits(button as u16).unwrap();
+        info!("reconnecting to ViGEm, attempt {} of {}", attempts, max);
 
-                match state {
-                    KeyState::Down => self.report.w_buttons |= button_flag,
-                    KeyState::Up => self.report.w_buttons &= !button_flag,
+        // The report is sent straight away so held buttons are held again
+        let result = match self.output.reconnect() {
+            Ok(()) => {
+                self.last_sent = None;
+                for extra in self.extra_targets.iter_mut() {
+                    extra.last_sent = None;
                 }
-            }
-        }
+                self.mirror_last_sent.fill(None);
 
-        if state == KeyState::Up {
-            return;
-        }
-    }
-    fn handle_mouse_move(&mut self, x: i32, y: i32) {
-        let now = Instant::now();
-        self.mouse_samples.push_back((x, y, now));
-    }
-    fn get_mouse_move_bind(&mut self) -> AnalogState
-    {
-        let mut analog_state: AnalogState = AnalogState {
-            analog_type: AnalogType::Left,
-            x: 0.0,
-            y: 0.0,
+                self.send_report()
+            }
+            Err(error) => Err(error),
         };
-        let bind = self.config.binds.get(&Bind::MouseMove).copied().unwrap();
 
-        match bind {
-            ControllerAction::AnalogLeft(x, y) => {
-                analog_state.analog_type = AnalogType::Left;
-                analog_state.x = x;
-                analog_state.y = y;
-            }
-            ControllerAction::AnalogRight(x, y) => {
-                analog_state.analog_type = AnalogType::Right;
-                analog_state.x = x;
-                analog_state.y = y;
+        let error = match result {
+            Ok(()) => {
+                info!("reconnected to ViGEm");
+                self.reconnect = None;
+
+                return Ok(());
             }
-            _ => {}
-        }
+            Err(error) => error,
+        };
 
-        analog_state
-    }
-    fn update_mouse_state(&mut self, mouse_vel: (f64, f64))
-    {
-        let mouse_bind = self.get_mouse_move_bind();
-        if self.analog_state.contains_key(&Bind::MouseMove)
-        {
-            let state = self.analog_state.get_mut(&Bind::MouseMove).unwrap();
-            state.x = mouse_bind.x * mouse_vel.0;
-            state.y = mouse_bind.y * mouse_vel.1;
-        } else {
-            let state = AnalogState {
-                analog_type: mouse_bind.analog_type,
-                x: mouse_bind.x * mouse_vel.0,
-                y: mouse_bind.y * mouse_vel.1,
-            };
-            self.analog_state.insert(Bind::MouseMove, state);
+        if attempts >= max {
+            anyhow::bail!("gave up reconnecting to ViGEm: {}", error);
         }
-    }
-    fn update_analog(&mut self) {
-        let now = Instant::now();
 
-        loop {
-            let sample = match self.mouse_samples.front() {
-                Some(sample) => sample,
-                None => break,
-            };
+        if let Some(reconnect) = self.reconnect.as_mut() {
+            let backoff = reconnect.backoff;
+            warn!("reconnect failed: {}, waiting {:#?}", error, backoff);
 
-            if now - sample.2 > self.config.sample_window {
-                self.mouse_samples.pop_front();
-            } else {
-                break;
-            }
+            reconnect.next_attempt = Instant::now() + reconnect.backoff;
+            reconnect.backoff = (reconnect.backoff * 2).min(RECONNECT_MAX_BACKOFF);
         }
 
+        Ok(())
+    }
 
-        let mut mouse_vel = (0.0, 0.0);
-
-        for &(x, y, _) in self.mouse_samples.iter() {
-            mouse_vel.0 += x as f64;
-            mouse_vel.1 += y as f64;
+    // Sends the report to the main controller and updates and sends the other controllers'
+    fn send_report(&mut self) -> Result<(), anyhow::Error> {
+        // A change in rotation has to go out even when the report is the same
+        if self.gyro != self.sent_gyro {
+            self.output.motion(0, self.gyro);
+            self.sent_gyro = self.gyro;
+            self.last_sent = None;
         }
 
-        let multiplier = self.config.sensitivity / (1e4 * self.config.sample_window.as_secs_f64());
-        mouse_vel.0 *= multiplier;
-        mouse_vel.1 *= multiplier;
+        let output = self.output.as_mut();
+        let config = &self.config;
 
-        let mut states = (
-            AnalogState {
-                analog_type: AnalogType::Left,
-                x: 0.0,
-                y: 0.0,
-            }, AnalogState {
-                analog_type: AnalogType::Right,
-                x: 0.0,
-                y: 0.0,
-            }
+        let (report, last_sent) = (&self.report, &mut self.last_sent);
+        let main_sent = send_changed_report(output, 0, report, last_sent, config)?;
+        let mut sent = vec![main_sent];
+
+        let sign = |invert: bool| match invert {
+            true => -1.0,
+            false => 1.0,
+        };
+
+        let mouse_vel = (
+            self.mouse_stick_vel.0 * sign(config.invert_mouse_x),
+            self.mouse_stick_vel.1 * sign(config.invert_mouse_y),
         );
-        self.update_mouse_state(mouse_vel);
 
-        for (_bind, state) in &self.analog_state {
-            match state.analog_type {
-                AnalogType::Left => {
-                    states.0.x += state.x;
-                    states.0.y += state.y;
-                }
-                AnalogType::Right => {
-                    states.1.x += state.x;
-                    states.1.y += state.y;
-                }
-            }
+        let targets = self.extra_targets.iter_mut().zip(&config.targets);
+        for (i, (extra, target_config)) in targets.enumerate() {
+            extra.update_report(&target_config.binds, mouse_vel);
+
+            let (report, last_sent) = (&extra.report, &mut extra.last_sent);
+            let extra_sent = send_changed_report(output, i + 1, report, last_sent, config)?;
+            sent.push(extra_sent);
         }
-        self.set_analog(states.0);
-        self.set_analog(states.1);
-    }
 
-    fn set_analog(&mut self, state: AnalogState) {
-        let alert = state.x.abs().max(state.y.abs()) >= self.config.oversteer_alert_threshold;
-        self.tone_generator.as_mut().map(|tg| tg.enable(alert));
+        // With mirroring off they are sent a zeroed report once, as nothing changes after
+        let zeroed = XUSBReport::default();
+        let report = match self.mirroring {
+            true => &self.report,
+            false => &zeroed,
+        };
+        let first = 1 + self.extra_targets.len();
 
-        if self.config.analog_circularize {
-            self.set_analog_circularized(state);
-        } else {
-            self.set_analog_linear(state);
+        for (i, last_sent) in self.mirror_last_sent.iter_mut().enumerate() {
+            let mirror_sent = send_changed_report(output, first + i, report, last_sent, config)?;
+            sent.push(mirror_sent);
         }
-    }
 
-    fn set_analog_circularized(&mut self, state: AnalogState) {
-        let angle = state.y.atan2(state.x);
-        let radius = (state.x.powi(2) + state.y.powi(2)).sqrt();
-        match state.analog_type {
-            AnalogType::Left => {
-                self.report.s_thumb_lx = (angle.cos() * radius * Self::ANALOG_MAX) as i16;
-                self.report.s_thumb_ly = (angle.sin() * radius * Self::ANALOG_MAX) as i16;
-            }
-            AnalogType::Right => {
-                self.report.s_thumb_rx = (angle.cos() * radius * Self::ANALOG_MAX) as i16;
-                self.report.s_thumb_ry = (angle.sin() * radius * Self::ANALOG_MAX) as i16;
+        for sent in sent {
+            match sent {
+                true => self.reports_sent += 1,
+                false => self.reports_skipped += 1,
             }
         }
+
+        Ok(())
     }
 
-    fn set_analog_linear(&mut self, state: AnalogState) {
-        if state.x.abs() <= 1.0 && state.y.abs() <= 1.0 {
-            match state.analog_type {
-                AnalogType::Left => {
-                    self.report.s_thumb_lx = (state.x * Self::ANALOG_MAX) as i16;
-                    self.report.s_thumb_ly = (state.y * Self::ANALOG_MAX) as i16;
-                }
-                AnalogType::Right => {
-                    self.report.s_thumb_rx = (state.x * Self::ANALOG_MAX) as i16;
-                    self.report.s_thumb_ry = (state.y * Self::ANALOG_MAX) as i16;
-                }
-            }
-            return;
+    // Remembers which of the other controllers' binds are held
+    fn hold_extra_targets(&mut self, bind: Bind, state: KeyState) {
+        // Keys come named as the first controller's binds have them, which may be either form
+        let either = match bind {
+            Bind::Keyboard(code, Some(_)) => Bind::Keyboard(code, None),
+            bind => bind,
+        };
+
+        let targets = self.extra_targets.iter_mut().zip(&self.config.targets);
+        for (extra, config) in targets {
+            let bind = match config.binds.contains_key(&bind) {
+                true => bind,
+                false => either,
+            };
+
+            match state {
+                KeyState::Down => extra.held.insert(bind),
+                KeyState::Up => extra.held.remove(&bind),
+            };
         }
+    }
 
-        let overshoot = state.x.abs().max(state.y.abs());
+    // Writes the stick position to the report, carrying what the conversion to i16 cuts off
+    // over to the next loop so slow mouse movement adds up instead of being lost
+    fn write_stick(&mut self, analog_type: AnalogType, x: f64, y: f64) {
+        let carry = match analog_type {
+            AnalogType::Left => self.stick_carry[0],
+            AnalogType::Right => self.stick_carry[1],
+        };
 
-        let angle = state.y.atan2(state.x);
-        let radius = (state.x.powi(2) + state.y.powi(2)).sqrt();
+        let analog_type = match (self.swap_analogs_active, analog_type) {
+            (true, AnalogType::Left) => AnalogType::Right,
+            (true, AnalogType::Right) => AnalogType::Left,
+            (false, analog_type) => analog_type,
+        };
+
+        let axes = match analog_type {
+            AnalogType::Left => [(0, x), (1, y)],
+            AnalogType::Right => [(2, x), (3, y)],
+        };
+
+        let mut values = [0i16; 2];
+
+        for (value, (i, position)) in values.iter_mut().zip(axes) {
+            // A centred stick is exactly centred, whatever was left over
+            if position == 0.0 || !carry {
+                self.stick_remainders[i] = 0.0;
+            }
 
-        let new_radius = radius / overshoot;
+            (*value, self.stick_remainders[i]) = stick_step(position, self.stick_remainders[i]);
+        }
 
-        match state.analog_type {
+        match analog_type {
             AnalogType::Left => {
-                self.report.s_thumb_lx = (angle.cos() * new_radius * Self::ANALOG_MAX) as i16;
-                self.report.s_thumb_ly = (angle.sin() * new_radius * Self::ANALOG_MAX) as i16;
+                self.report.s_thumb_lx = values[0];
+                self.report.s_thumb_ly = values[1];
             }
             AnalogType::Right => {
-                self.report.s_thumb_rx = (angle.cos() * new_radius * Self::ANALOG_MAX) as i16;
-                self.report.s_thumb_ry = (angle.sin() * new_radius * Self::ANALOG_MAX) as i16;
+                self.report.s_thumb_rx = values[0];
+                self.report.s_thumb_ry = values[1];
             }
         }
     }
 }
+
+// Lets go of everything the game sees held and takes the controllers away
+impl Drop for EventHandler {
+    fn drop(&mut self) {
+        self.output.disconnect();
+    }
+}
@@ -0,0 +1,429 @@
+use super::ds4::{self, ControllerType};
+use super::rumble;
+use crate::shutdown;
+use crate::types::Event;
+
+use vigem::*;
+
+use std::sync::mpsc;
+#[cfg(test)]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::Duration;
+
+// Where the event handler's reports end up. Controller 0 is the main one, the rest follow
+// Config::targets
+pub trait ControllerOutput {
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error>;
+    // Connects afresh after update failed, the event handler sends every report again after
+    fn reconnect(&mut self) -> Result<(), anyhow::Error>;
+    // Zeroes and takes away the controllers
+    fn disconnect(&mut self);
+
+    // Rotation in degrees per second (pitch, yaw and roll) to send with the next update, for
+    // outputs that carry motion. VigemOutput doesn't, the DS4 report in vigem 0.9.1 has no
+    // motion fields
+    fn motion(&mut self, _index: usize, _gyro: [f32; 3]) {}
+
+    // Takes the controllers away for a while, e.g. when idle, for outputs that games can see
+    // come and go. Nothing is sent in between
+    fn detach(&mut self) {}
+    // Brings them back zeroed
+    fn attach(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    // Swaps the controllers for ones of another type, for outputs that have one
+    fn set_controller_type(
+        &mut self,
+        _controller_type: ControllerType,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+// Sends to each of several outputs, e.g. ViGEm and the DSU server
+impl ControllerOutput for Vec<Box<dyn ControllerOutput>> {
+    // All of them get the report even when one fails
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error> {
+        let results: Vec<_> = self.iter_mut().map(|o| o.update(index, report)).collect();
+        results.into_iter().collect()
+    }
+
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        self.iter_mut().try_for_each(|output| output.reconnect())
+    }
+
+    fn disconnect(&mut self) {
+        for output in self.iter_mut() {
+            output.disconnect();
+        }
+    }
+
+    fn motion(&mut self, index: usize, gyro: [f32; 3]) {
+        for output in self.iter_mut() {
+            output.motion(index, gyro);
+        }
+    }
+
+    fn detach(&mut self) {
+        for output in self.iter_mut() {
+            output.detach();
+        }
+    }
+
+    fn attach(&mut self) -> Result<(), anyhow::Error> {
+        self.iter_mut().try_for_each(|output| output.attach())
+    }
+
+    fn set_controller_type(
+        &mut self,
+        controller_type: ControllerType,
+    ) -> Result<(), anyhow::Error> {
+        self.iter_mut()
+            .try_for_each(|output| output.set_controller_type(controller_type))
+    }
+}
+
+// How often to try again when the bus or a controller can't be had, e.g. while ViGEmBus is
+// still starting at boot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectRetry {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl ConnectRetry {
+    fn run<T>(
+        &self,
+        what: &str,
+        mut f: impl FnMut() -> Result<T, VigemError>,
+    ) -> Result<T, VigemError> {
+        let mut retries = 0;
+
+        loop {
+            let error = match f() {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            if retries >= self.retries || shutdown::requested() {
+                return Err(error);
+            }
+
+            retries += 1;
+            warn!(
+                "could not {}: {:?}, retry {} of {} in {:#?}",
+                what, error, retries, self.retries, self.delay
+            );
+
+            thread::sleep(self.delay);
+        }
+    }
+}
+
+pub struct VigemOutput {
+    // Empty while reconnecting or detached. Before vigem so they drop first, a target removes
+    // itself through the client as it drops and the client has to still be there
+    targets: Vec<Target>,
+    vigem: Vigem,
+    extra_targets: usize,
+    slot: Option<u32>,
+    controller_type: ControllerType,
+
+    // Kept to register for rumble again after reconnecting
+    rumble_tx: mpsc::Sender<Event>,
+    // Set when an update fails, there is nothing left to zero until reconnecting
+    lost: bool,
+}
+
+impl VigemOutput {
+    // Connects to the bus and adds the main controller, in XInput slot if given, and
+    // extra_targets more, trying each again as retry says
+    pub fn connect(
+        controller_type: ControllerType,
+        extra_targets: usize,
+        slot: Option<u32>,
+        rumble_tx: mpsc::Sender<Event>,
+        retry: ConnectRetry,
+    ) -> Result<Self, anyhow::Error> {
+        let mut vigem = Vigem::new();
+
+        if let Err(error) = retry.run("connect to ViGEm", || vigem.connect()) {
+            anyhow::bail!("could not connect to ViGEm: {:?}, {}", error, CHECK_INSTALL);
+        }
+
+        info!("ViGEm connected");
+
+        let targets = add_targets(
+            &mut vigem,
+            controller_type,
+            extra_targets,
+            slot,
+            &rumble_tx,
+            retry,
+        )?;
+
+        Ok(VigemOutput {
+            targets,
+            vigem,
+            extra_targets,
+            slot,
+            controller_type,
+
+            rumble_tx,
+            lost: false,
+        })
+    }
+}
+
+const CHECK_INSTALL: &str = "check that ViGEmBus is installed and running";
+
+// A controller added to the bus
+fn add_target(
+    vigem: &mut Vigem,
+    controller_type: ControllerType,
+    retry: ConnectRetry,
+) -> Result<Target, anyhow::Error> {
+    let result = retry.run("add a controller", || {
+        let mut target = Target::new(controller_type.target_type());
+        vigem.target_add(&mut target).map(|()| target)
+    });
+
+    match result {
+        Ok(target) => Ok(target),
+        Err(error) => anyhow::bail!("could not add a controller: {:?}, {}", error, CHECK_INSTALL),
+    }
+}
+
+// Adds an Xbox 360 controller in XInput slot. Free slots below it are filled with controllers
+// that are taken away again after, as the bus hands out the lowest free one
+fn add_in_slot(vigem: &mut Vigem, slot: u32, retry: ConnectRetry) -> Result<Target, anyhow::Error> {
+    let mut placeholders = Vec::new();
+
+    let target = loop {
+        let target = add_target(vigem, ControllerType::Xbox360, retry)?;
+        let got = vigem.xbox_get_user_index(&target);
+
+        if got == slot {
+            break target;
+        }
+
+        // The placeholders are removed as they drop
+        if got > slot || placeholders.len() >= XINPUT_SLOTS {
+            anyhow::bail!(
+                "XInput slot {} is taken, the controller got slot {}. Unplug what has it or \
+                 change require_slot",
+                slot,
+                got
+            );
+        }
+
+        debug!("holding XInput slot {} to get to slot {}", got, slot);
+        placeholders.push(target);
+    };
+
+    for placeholder in placeholders.iter() {
+        if let Err(error) = vigem.target_remove(placeholder) {
+            warn!("could not remove placeholder controller: {:?}", error);
+        }
+    }
+
+    Ok(target)
+}
+
+const XINPUT_SLOTS: usize = 4;
+
+// Which player games see the controller as, only Xbox 360 controllers have one
+fn log_slot(vigem: &mut Vigem, target: &Target, controller_type: ControllerType) {
+    if controller_type == ControllerType::Xbox360 {
+        info!("XInput slot: {}", vigem.xbox_get_user_index(target));
+    }
+}
+
+// Adds the main controller, registered for rumble and in XInput slot if given, and
+// extra_targets more
+fn add_targets(
+    vigem: &mut Vigem,
+    controller_type: ControllerType,
+    extra_targets: usize,
+    slot: Option<u32>,
+    rumble_tx: &mpsc::Sender<Event>,
+    retry: ConnectRetry,
+) -> Result<Vec<Target>, anyhow::Error> {
+    let target = match slot {
+        Some(slot) => add_in_slot(vigem, slot, retry)?,
+        None => add_target(vigem, controller_type, retry)?,
+    };
+
+    info!("{:?} controller index: {}", controller_type, target.index());
+    log_slot(vigem, &target, controller_type);
+
+    // Without it rumble is only lost, as it always was
+    match rumble::register(vigem, &target, controller_type, rumble_tx.clone()) {
+        Ok(()) => info!("receiving rumble"),
+        Err(error) => warn!("could not register for rumble: {:?}", error),
+    }
+
+    let mut targets = vec![target];
+    for i in 0..extra_targets {
+        let target = add_target(vigem, controller_type, retry)?;
+
+        info!("target {} controller index: {}", i + 1, target.index());
+        log_slot(vigem, &target, controller_type);
+
+        targets.push(target);
+    }
+
+    Ok(targets)
+}
+
+impl ControllerOutput for VigemOutput {
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error> {
+        // While detached there is no target to update, and nothing lost by skipping it
+        let target = match self.targets.get(index) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+
+        let result = match self.controller_type {
+            ControllerType::Xbox360 => self.vigem.update(target, report),
+            ControllerType::DualShock4 => self.vigem.update(target, &ds4::report(report)),
+        };
+
+        if let Err(error) = result {
+            self.lost = true;
+            anyhow::bail!("ViGEm update failed: {:?}", error);
+        }
+
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(target) = self.targets.first() {
+            rumble::unregister(target);
+        }
+
+        let (controller_type, extra_targets) = (self.controller_type, self.extra_targets);
+        let rumble_tx = self.rumble_tx.clone();
+
+        // Through the old client while it's still there, rather than as *self is replaced
+        self.targets.clear();
+
+        // The event handler has its own retries
+        let retry = ConnectRetry::default();
+        *self = VigemOutput::connect(controller_type, extra_targets, self.slot, rumble_tx, retry)?;
+
+        Ok(())
+    }
+
+    // The client disconnects as vigem is dropped after this
+    fn disconnect(&mut self) {
+        match self.targets.first() {
+            Some(target) => rumble::unregister(target),
+            None => return,
+        }
+
+        // Without the bus there is nothing to let go of
+        if self.lost {
+            return;
+        }
+
+        for i in 0..self.targets.len() {
+            if let Err(error) = self.update(i, &XUSBReport::default()) {
+                warn!("could not zero controller: {}", error);
+            }
+
+            if let Err(error) = self.vigem.target_remove(&self.targets[i]) {
+                warn!("could not remove controller: {:?}", error);
+            }
+        }
+
+        info!("controllers removed");
+    }
+
+    // The client stays connected, only the controllers go
+    fn detach(&mut self) {
+        self.disconnect();
+        self.targets.clear();
+    }
+
+    fn attach(&mut self) -> Result<(), anyhow::Error> {
+        if !self.targets.is_empty() {
+            return Ok(());
+        }
+
+        let (vigem, controller_type) = (&mut self.vigem, self.controller_type);
+        let (extra_targets, slot) = (self.extra_targets, self.slot);
+        let (rumble, retry) = (&self.rumble_tx, ConnectRetry::default());
+
+        self.targets = add_targets(vigem, controller_type, extra_targets, slot, rumble, retry)?;
+
+        for i in 0..self.targets.len() {
+            self.update(i, &XUSBReport::default())?;
+        }
+
+        Ok(())
+    }
+
+    // Detached controllers come back as the new type with the next input
+    fn set_controller_type(
+        &mut self,
+        controller_type: ControllerType,
+    ) -> Result<(), anyhow::Error> {
+        if controller_type == self.controller_type {
+            return Ok(());
+        }
+
+        if self.slot.is_some() && controller_type != ControllerType::Xbox360 {
+            anyhow::bail!("require_slot is only for Xbox360 controllers");
+        }
+
+        let attached = !self.targets.is_empty();
+        self.detach();
+        self.controller_type = controller_type;
+
+        match attached {
+            true => self.attach(),
+            false => Ok(()),
+        }
+    }
+}
+
+// Keeps every report for tests to look at, shared so they can still be read once the event
+// handler has it
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockOutput {
+    pub reports: Arc<Mutex<Vec<(usize, XUSBReport)>>>,
+    pub disconnected: Arc<AtomicBool>,
+    // Each type the controllers were changed to
+    pub controller_types: Arc<Mutex<Vec<ControllerType>>>,
+}
+
+#[cfg(test)]
+impl ControllerOutput for MockOutput {
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error> {
+        self.reports.lock().unwrap().push((index, *report));
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.disconnected.store(true, Ordering::Relaxed);
+    }
+
+    fn set_controller_type(
+        &mut self,
+        controller_type: ControllerType,
+    ) -> Result<(), anyhow::Error> {
+        self.controller_types.lock().unwrap().push(controller_type);
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+// Complete sets of binds a config can start from with preset: "fps", its own binds going on top
+use super::{Bind, ControllerAction};
+use crate::types::{ControllerButton, MouseButton};
+
+use interception::ScanCode;
+
+use std::collections::HashMap;
+
+// The name of each preset and what it's for, as --list-presets prints them
+pub const PRESETS: &[(&str, &str)] = &[
+    ("fps", "WASD moves, the mouse aims, the usual face buttons"),
+    ("racing", "A and D steer, W and S are gas and brake"),
+    ("menu", "the arrows on the dpad, Enter and Esc as A and B"),
+];
+
+pub fn binds(name: &str) -> Option<HashMap<Bind, ControllerAction>> {
+    use ControllerAction::*;
+    use ControllerButton::*;
+    use ScanCode::{Enter, Esc, LeftControl, LeftShift, Space, Tab};
+
+    let key = |code| Bind::Keyboard(code, None);
+    // Keys sharing a scancode with another, by the one meant
+    let plain = |code| Bind::Keyboard(code, Some(false));
+    let e0 = |code| Bind::Keyboard(code, Some(true));
+
+    let binds = match name {
+        "fps" => vec![
+            (key(ScanCode::W), AnalogLeft(0.0, 1.0, None)),
+            (key(ScanCode::A), AnalogLeft(-1.0, 0.0, None)),
+            (key(ScanCode::S), AnalogLeft(0.0, -1.0, None)),
+            (key(ScanCode::D), AnalogLeft(1.0, 0.0, None)),
+            (Bind::MouseMove, AnalogRight(1.0, -1.0, None)),
+            (Bind::Mouse(MouseButton::Left), Button(RightTrigger)),
+            (Bind::Mouse(MouseButton::Right), Button(LeftTrigger)),
+            (Bind::Mouse(MouseButton::Middle), Button(RightThumb)),
+            (key(Space), Button(A)),
+            (plain(LeftControl), Button(B)),
+            (key(ScanCode::R), Button(X)),
+            (key(ScanCode::F), Button(Y)),
+            (key(ScanCode::Q), Button(LeftShoulder)),
+            (key(ScanCode::E), Button(RightShoulder)),
+            (key(LeftShift), Button(LeftThumb)),
+            (key(Tab), Button(Back)),
+            (key(Esc), Button(Start)),
+        ],
+
+        "racing" => vec![
+            (key(ScanCode::A), AnalogLeft(-1.0, 0.0, None)),
+            (key(ScanCode::D), AnalogLeft(1.0, 0.0, None)),
+            (key(ScanCode::W), Button(RightTrigger)),
+            (key(ScanCode::S), Button(LeftTrigger)),
+            (Bind::MouseMove, AnalogRight(1.0, -1.0, None)),
+            (key(Space), Button(A)),
+            (key(LeftShift), Button(RightShoulder)),
+            (plain(LeftControl), Button(LeftShoulder)),
+            (key(ScanCode::C), Button(RightThumb)),
+            (key(ScanCode::R), Button(Y)),
+            (key(Tab), Button(Back)),
+            (key(Esc), Button(Start)),
+        ],
+
+        "menu" => vec![
+            (e0(ScanCode::Numpad8), Button(DpadUp)),
+            (e0(ScanCode::Numpad2), Button(DpadDown)),
+            (e0(ScanCode::Numpad4), Button(DpadLeft)),
+            (e0(ScanCode::Numpad6), Button(DpadRight)),
+            (Bind::MouseMove, AnalogLeft(1.0, -1.0, None)),
+            (Bind::Mouse(MouseButton::Left), Button(A)),
+            (plain(Enter), Button(A)),
+            (key(Esc), Button(B)),
+            (key(ScanCode::Q), Button(LeftShoulder)),
+            (key(ScanCode::E), Button(RightShoulder)),
+            (key(Tab), Button(Back)),
+        ],
+
+        _ => return None,
+    };
+
+    Some(binds.into_iter().collect())
+}
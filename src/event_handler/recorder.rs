@@ -0,0 +1,140 @@
+use super::output::ControllerOutput;
+
+use vigem::XUSBReport;
+
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Written out at least this often so a crash loses little
+const FLUSH_PERIOD: Duration = Duration::from_secs(1);
+
+struct Record {
+    // Since recording started
+    micros: u128,
+    index: usize,
+    report: XUSBReport,
+}
+
+// Appends every report sent to a CSV file, one line per report with the time in microseconds
+// since recording started, the controller, the sticks, the triggers and the button bits
+pub struct Recorder {
+    tx: Option<mpsc::Sender<Record>>,
+    thread: Option<JoinHandle<()>>,
+    started: Instant,
+}
+
+fn write_records(mut file: impl Write, rx: mpsc::Receiver<Record>) -> std::io::Result<()> {
+    let mut count = 0;
+    let mut last_micros = 0;
+    let mut last_flush = Instant::now();
+
+    loop {
+        match rx.recv_timeout(FLUSH_PERIOD) {
+            Ok(Record {
+                micros,
+                index,
+                report,
+            }) => {
+                writeln!(
+                    file,
+                    "{},{},{},{},{},{},{},{},{:#06x}",
+                    micros,
+                    index,
+                    report.s_thumb_lx,
+                    report.s_thumb_ly,
+                    report.s_thumb_rx,
+                    report.s_thumb_ry,
+                    report.b_left_trigger,
+                    report.b_right_trigger,
+                    report.w_buttons.bits()
+                )?;
+
+                count += 1;
+                last_micros = micros;
+            }
+
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_flush.elapsed() >= FLUSH_PERIOD {
+            file.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+
+    let duration = last_micros as f64 / 1e6;
+    writeln!(file, "# {} reports over {:.3}s", count, duration)?;
+    file.flush()?;
+
+    info!("recorded {} reports over {:.3}s", count, duration);
+
+    Ok(())
+}
+
+impl Recorder {
+    pub fn start(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut file = BufWriter::new(file);
+
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        writeln!(file, "# recording started at unix time {}", unix_time)?;
+        writeln!(file, "time_us,controller,lx,ly,rx,ry,lt,rt,buttons")?;
+
+        let (tx, rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            if let Err(error) = write_records(file, rx) {
+                error!("recording stopped: {}", error);
+            }
+        });
+
+        info!("recording reports to \"{}\"", path.to_string_lossy());
+
+        Ok(Recorder {
+            tx: Some(tx),
+            thread: Some(thread),
+            started: Instant::now(),
+        })
+    }
+}
+
+impl ControllerOutput for Recorder {
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error> {
+        let record = Record {
+            micros: self.started.elapsed().as_micros(),
+            index,
+            report: *report,
+        };
+
+        // Only gone if writing failed, which has already been logged
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(record);
+        }
+
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    // Finishes writing and adds the summary
+    fn disconnect(&mut self) {
+        self.tx = None;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
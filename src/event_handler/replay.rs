@@ -0,0 +1,157 @@
+use super::output::{ControllerOutput, VigemOutput};
+use super::Config;
+use crate::shutdown;
+
+use vigem::{XButton, XUSBReport};
+
+use std::fs;
+use std::hint::spin_loop;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Sleeping is only accurate to a millisecond or so, the rest of the wait is spun
+const SPIN_AHEAD: Duration = Duration::from_millis(2);
+// Long gaps in a recording are slept through in steps this long, to notice Ctrl+C
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+
+struct Record {
+    // Since the replay started, at the recorded speed
+    micros: u64,
+    index: usize,
+    report: XUSBReport,
+}
+
+// One line as Recorder writes it
+fn parse_record(line: &str) -> Result<(u64, usize, XUSBReport), anyhow::Error> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+    if fields.len() != 9 {
+        anyhow::bail!("expected 9 fields, found {}", fields.len());
+    }
+
+    let buttons = u16::from_str_radix(fields[8].trim_start_matches("0x"), 16)?;
+
+    let report = XUSBReport {
+        w_buttons: XButton::from_bits_truncate(buttons),
+        b_left_trigger: fields[6].parse()?,
+        b_right_trigger: fields[7].parse()?,
+        s_thumb_lx: fields[2].parse()?,
+        s_thumb_ly: fields[3].parse()?,
+        s_thumb_rx: fields[4].parse()?,
+        s_thumb_ry: fields[5].parse()?,
+    };
+
+    Ok((fields[0].parse()?, fields[1].parse()?, report))
+}
+
+// Recordings appended to the same file play one after another
+fn load(path: &Path) -> Result<Vec<Record>, anyhow::Error> {
+    let text = fs::read_to_string(path)?;
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    let mut last = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        if line.starts_with("# recording started") {
+            offset = last;
+            continue;
+        }
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with("time_us") {
+            continue;
+        }
+
+        let (micros, index, report) = match parse_record(line) {
+            Ok(record) => record,
+            Err(error) => anyhow::bail!("line {} of the recording: {}", i + 1, error),
+        };
+
+        last = offset + micros;
+        records.push(Record {
+            micros: last,
+            index,
+            report,
+        });
+    }
+
+    Ok(records)
+}
+
+// False if shutdown was requested while waiting
+fn wait_until(deadline: Instant) -> bool {
+    loop {
+        if shutdown::requested() {
+            return false;
+        }
+
+        let now = Instant::now();
+        if deadline <= now + SPIN_AHEAD {
+            break;
+        }
+
+        thread::sleep((deadline - now - SPIN_AHEAD).min(SLEEP_STEP));
+    }
+
+    while Instant::now() < deadline {
+        spin_loop();
+    }
+
+    true
+}
+
+// Sends a recording to the controllers with its original timing divided by speed, until it
+// ends or Ctrl+C, and zeroes them after either
+pub fn replay(path: &Path, speed: f64, config: &Config) -> Result<(), anyhow::Error> {
+    if speed <= 0.0 {
+        anyhow::bail!("the replay speed must be above 0, not {}", speed);
+    }
+
+    let records = load(path)?;
+    let controllers = records.iter().map(|record| record.index + 1).max();
+
+    let controllers = match controllers {
+        Some(controllers) => controllers,
+        None => anyhow::bail!("nothing to replay in \"{}\"", path.to_string_lossy()),
+    };
+
+    // Rumble from the game has nowhere to go
+    let (rumble_tx, _) = mpsc::channel();
+    let (controller_type, extra_targets) = (config.controller_type, controllers - 1);
+    let (slot, retry) = (config.require_slot, config.connect_retry());
+    let mut output = VigemOutput::connect(controller_type, extra_targets, slot, rumble_tx, retry)?;
+
+    let duration = records.last().map_or(0, |record| record.micros) as f64 / 1e6;
+    info!(
+        "replaying {} reports over {:.3}s at {}x speed",
+        records.len(),
+        duration / speed,
+        speed
+    );
+
+    // Every deadline is from the start, so waking late never pushes the rest back
+    let start = Instant::now();
+    let mut result = Ok(());
+
+    for record in records.iter() {
+        let at = Duration::from_secs_f64(record.micros as f64 / 1e6 / speed);
+
+        if !wait_until(start + at) {
+            info!("replay stopped");
+            break;
+        }
+
+        if let Err(error) = output.update(record.index, &record.report) {
+            result = Err(error);
+            break;
+        }
+    }
+
+    info!("replayed for {:#?}", start.elapsed());
+
+    output.disconnect();
+
+    result
+}
@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub enum Shape {
+    #[default]
+    Linear,
+    // Input raised to the exponent, above 1 is slower near the centre
+    Power(f64),
+    // (base^input - 1) / (base - 1), above 1 is slower near the centre
+    Exponential(f64),
+    // (input, output) pairs between 0 and 1 with rising inputs, joined by straight lines
+    Points(Vec<(f64, f64)>),
+}
+
+impl Shape {
+    // Maps 0 to 0 (and other than Points, 1 to 1), inputs past 1 carry on along the curve
+    pub fn apply(&self, input: f64) -> f64 {
+        match *self {
+            Shape::Linear => input,
+            Shape::Power(exponent) => input.powf(exponent),
+            Shape::Exponential(base) if (base - 1.0).abs() < f64::EPSILON => input,
+            Shape::Exponential(base) => (base.powf(input) - 1.0) / (base - 1.0),
+            Shape::Points(ref points) => interpolate(points, input),
+        }
+    }
+}
+
+// Linear interpolation between points, starting from the origin and carrying on along the
+// last segment past the final point
+pub fn interpolate(points: &[(f64, f64)], input: f64) -> f64 {
+    let mut previous = (0.0, 0.0);
+
+    for (i, &point) in points.iter().enumerate() {
+        let last = i + 1 == points.len();
+
+        if input <= point.0 || last {
+            let (x0, y0) = previous;
+            let (x1, y1) = point;
+
+            if x1 - x0 <= 0.0 {
+                return y1;
+            }
+
+            return y0 + (input - x0) * (y1 - y0) / (x1 - x0);
+        }
+
+        previous = point;
+    }
+
+    input
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    shape: Shape,
+    // Mouse speed in counts per second that gives a full deflection (1), faster speeds stay there
+    max_velocity: f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            shape: Shape::Linear,
+            max_velocity: 10000.0,
+        }
+    }
+}
+
+impl Config {
+    // The deflection, 0 to 1, for a mouse speed in counts per second
+    pub fn apply(&self, speed: f64) -> f64 {
+        self.shape.apply((speed / self.max_velocity).min(1.0))
+    }
+
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        if self.max_velocity <= 0.0 {
+            problems.push("response_curve.max_velocity must be above 0".to_owned());
+        }
+
+        match self.shape {
+            Shape::Power(exponent) if exponent <= 0.0 => {
+                problems.push("the response_curve Power exponent must be above 0".to_owned())
+            }
+            Shape::Exponential(base) if base <= 0.0 => {
+                problems.push("the response_curve Exponential base must be above 0".to_owned())
+            }
+            Shape::Points(ref points) => validate_points(points, problems),
+            _ => {}
+        }
+    }
+}
+
+fn validate_points(points: &[(f64, f64)], problems: &mut Vec<String>) {
+    match points.last() {
+        Some(&(input, _)) if input > 0.0 => {}
+        _ => problems
+            .push("the response_curve Points need at least one point past input 0".to_owned()),
+    }
+
+    let mut previous = (0.0, 0.0);
+
+    for (i, &(input, output)) in points.iter().enumerate() {
+        if !(0.0..=1.0).contains(&input) || !(0.0..=1.0).contains(&output) {
+            problems.push(format!(
+                "response_curve point {} ({}, {}) is outside 0 to 1",
+                i, input, output
+            ));
+        }
+
+        // The first point may sit on the origin, after that inputs have to keep rising
+        if (i > 0 && input <= previous.0) || output < previous.1 {
+            problems.push(format!(
+                "response_curve point {} ({}, {}) is not above the point before it",
+                i, input, output
+            ));
+        }
+
+        previous = (input, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> impl Iterator<Item = f64> {
+        (0..=100).map(|i| i as f64 / 100.0)
+    }
+
+    fn shapes() -> Vec<Shape> {
+        vec![
+            Shape::Linear,
+            Shape::Power(0.5),
+            Shape::Power(2.0),
+            Shape::Exponential(0.5),
+            Shape::Exponential(10.0),
+        ]
+    }
+
+    #[test]
+    fn ends_fixed() {
+        for shape in shapes() {
+            assert_eq!(shape.apply(0.0), 0.0, "{:?}", shape);
+            assert!((shape.apply(1.0) - 1.0).abs() < 1e-12, "{:?}", shape);
+        }
+    }
+
+    #[test]
+    fn rising() {
+        for shape in shapes() {
+            let outputs: Vec<f64> = inputs().map(|input| shape.apply(input)).collect();
+            let rising = outputs.windows(2).all(|pair| pair[1] > pair[0]);
+            assert!(rising, "{:?}", shape);
+        }
+    }
+
+    #[test]
+    fn slower_near_centre_above_1() {
+        for shape in [Shape::Power(2.0), Shape::Exponential(10.0)] {
+            let mut inside = inputs().filter(|&input| input > 0.0 && input < 1.0);
+            assert!(inside.all(|input| shape.apply(input) < input));
+        }
+
+        for shape in [Shape::Power(0.5), Shape::Exponential(0.5)] {
+            let mut inside = inputs().filter(|&input| input > 0.0 && input < 1.0);
+            assert!(inside.all(|input| shape.apply(input) > input));
+        }
+
+        assert_eq!(Shape::Power(2.0).apply(0.5), 0.25);
+        assert!((Shape::Exponential(9.0).apply(0.5) - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exponential_base_1_is_linear() {
+        for input in inputs() {
+            assert_eq!(Shape::Exponential(1.0).apply(input), input);
+        }
+    }
+
+    #[test]
+    fn max_velocity_full_deflection() {
+        let config = |shape: Shape| Config {
+            shape,
+            max_velocity: 2000.0,
+        };
+
+        for shape in shapes() {
+            let config = config(shape);
+            let full = |speed: f64| (config.apply(speed) - 1.0).abs() < 1e-12;
+            assert_eq!(config.apply(0.0), 0.0, "{:?}", config.shape);
+            assert!(full(2000.0) && full(5000.0), "{:?}", config.shape);
+        }
+
+        assert_eq!(config(Shape::Linear).apply(500.0), 0.25);
+        assert_eq!(config(Shape::Power(2.0)).apply(1000.0), 0.25);
+        let points = config(Shape::Points(vec![(0.5, 0.25), (1.0, 0.5)]));
+        assert_eq!(points.apply(4000.0), 0.5);
+    }
+
+    #[test]
+    fn validate_rejects_bad_shapes() {
+        let validate = |shape: Shape, max_velocity: f64| {
+            let mut problems = Vec::new();
+            Config {
+                shape,
+                max_velocity,
+            }
+            .validate(&mut problems);
+            problems
+        };
+
+        assert!(validate(Shape::Linear, 10000.0).is_empty());
+        assert!(validate(Shape::Power(2.0), 10000.0).is_empty());
+        assert_eq!(validate(Shape::Linear, 0.0).len(), 1);
+        assert_eq!(validate(Shape::Power(0.0), 10000.0).len(), 1);
+        assert_eq!(validate(Shape::Exponential(-1.0), 10000.0).len(), 1);
+        assert_eq!(validate(Shape::Power(-1.0), -1.0).len(), 2);
+    }
+
+    #[test]
+    fn points_interpolate() {
+        let points = [(0.0, 0.0), (0.3, 0.1), (0.7, 0.6), (1.0, 1.0)];
+        let at = |input: f64| interpolate(&points, input);
+
+        assert_eq!(at(0.0), 0.0);
+        assert!((at(0.15) - 0.05).abs() < 1e-12);
+        assert!((at(0.3) - 0.1).abs() < 1e-12);
+        assert!((at(0.5) - 0.35).abs() < 1e-12);
+        assert!((at(1.0) - 1.0).abs() < 1e-12);
+
+        let outputs: Vec<f64> = inputs().map(at).collect();
+        assert!(outputs.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn points_past_the_ends() {
+        // From the origin when the first point isn't on it
+        assert!((interpolate(&[(0.5, 0.25), (1.0, 1.0)], 0.25) - 0.125).abs() < 1e-12);
+        // Along the last segment past the final point
+        assert!((interpolate(&[(0.5, 0.25), (1.0, 1.0)], 1.5) - 1.75).abs() < 1e-12);
+        assert!((interpolate(&[(0.5, 0.5)], 2.0) - 2.0).abs() < 1e-12);
+        // A first point on the origin has nothing before it to join
+        assert_eq!(interpolate(&[(0.0, 0.0)], 0.5), 0.0);
+        assert_eq!(interpolate(&[], 0.5), 0.5);
+    }
+
+    #[test]
+    fn points_validated() {
+        let validate = |points: &[(f64, f64)]| {
+            let mut problems = Vec::new();
+            validate_points(points, &mut problems);
+            problems
+        };
+
+        assert!(validate(&[(0.0, 0.0), (0.3, 0.1), (0.7, 0.6), (1.0, 1.0)]).is_empty());
+        // Flat is fine, falling isn't
+        assert!(validate(&[(0.5, 0.5), (1.0, 0.5)]).is_empty());
+        assert_eq!(validate(&[(0.5, 0.5), (1.0, 0.4)]).len(), 1);
+        assert_eq!(validate(&[(0.5, 0.5), (0.5, 0.6)]).len(), 1);
+        assert_eq!(validate(&[(0.5, 0.5), (1.5, 1.0)]).len(), 1);
+        assert_eq!(validate(&[(0.5, -0.1)]).len(), 2);
+        assert_eq!(validate(&[]).len(), 1);
+        assert_eq!(validate(&[(0.0, 0.0)]).len(), 1);
+    }
+}
@@ -0,0 +1,67 @@
+use super::ds4::ControllerType;
+use crate::types::Event;
+
+use vigem::raw::{DS4_LIGHTBAR_COLOR, LPVOID, PVIGEM_CLIENT, PVIGEM_TARGET, UCHAR};
+use vigem::{Target, Vigem, VigemError};
+
+use std::sync::{mpsc, Mutex};
+
+// ViGEm calls back on a thread of its own, and the user data the vigem crate hands it points at
+// a copy that is gone by then, so the sender is kept here instead
+static SENDER: Mutex<Option<mpsc::Sender<Event>>> = Mutex::new(None);
+
+fn send(large: u8, small: u8) {
+    // The event handler may already be gone while shutting down
+    if let Ok(sender) = SENDER.lock() {
+        if let Some(tx) = sender.as_ref() {
+            let _ = tx.send(Event::Rumble { large, small });
+        }
+    }
+}
+
+unsafe extern "C" fn x360_notification(
+    _client: PVIGEM_CLIENT,
+    _target: PVIGEM_TARGET,
+    large: UCHAR,
+    small: UCHAR,
+    _led: UCHAR,
+    _user_data: LPVOID,
+) {
+    send(large, small);
+}
+
+unsafe extern "C" fn ds4_notification(
+    _client: PVIGEM_CLIENT,
+    _target: PVIGEM_TARGET,
+    large: UCHAR,
+    small: UCHAR,
+    _light_bar: DS4_LIGHTBAR_COLOR,
+    _user_data: LPVOID,
+) {
+    send(large, small);
+}
+
+// Rumble sent to target arrives on tx as Event::Rumble until unregister is called
+pub fn register(
+    vigem: &mut Vigem,
+    target: &Target,
+    controller_type: ControllerType,
+    tx: mpsc::Sender<Event>,
+) -> Result<(), VigemError> {
+    *SENDER.lock().unwrap() = Some(tx);
+
+    match controller_type {
+        ControllerType::Xbox360 => {
+            vigem.x360_register_notification(target, Some(x360_notification), ())
+        }
+        ControllerType::DualShock4 => {
+            vigem.ds4_register_notification(target, Some(ds4_notification), 0)
+        }
+    }
+}
+
+// Stops the callbacks before dropping the sender, so none can arrive half way through
+pub fn unregister(target: &Target) {
+    target.unregister_notification();
+    *SENDER.lock().unwrap() = None;
+}
@@ -0,0 +1,255 @@
+// The settings of the sounds kmxpad plays, read from the config whether or not it was built
+// with the "audio" feature that plays them
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    Triangle,
+    Sawtooth,
+}
+
+// What the alert does as the stick goes further over the threshold. The overshoot is in stick
+// deflections, 1 being a whole stick's worth past it
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub enum OvershootScaling {
+    // The same tone however far over
+    #[default]
+    Fixed,
+    // Cents higher per unit of overshoot, 1200 is an octave
+    Pitch(f32),
+    // Volume added per unit of overshoot, up to 1
+    Volume(f32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Gain from 0 to 1, against full scale rather than the game's audio
+    pub volume: f32,
+    // At the threshold
+    pub frequency: f32,
+    pub waveform: Waveform,
+    pub scaling: OvershootScaling,
+    // Plays the alert mostly on the side the stick is pushed too far to, and in the middle for
+    // up and down. Otherwise it plays the same on every channel
+    pub stereo_pan_enabled: bool,
+    // Asked of the audio device for everything played, None leaves its default. A smaller
+    // buffer sounds sooner after the stick goes over
+    pub sample_rate: Option<u32>,
+    pub buffer_frames: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            volume: 0.2,
+            frequency: 440.0,
+            waveform: Waveform::default(),
+            scaling: OvershootScaling::default(),
+            stereo_pan_enabled: false,
+            sample_rate: None,
+            buffer_frames: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self, name: &str, problems: &mut Vec<String>) {
+        if let OvershootScaling::Volume(per_unit) = self.scaling {
+            if per_unit < 0.0 {
+                problems.push(format!("{}.scaling can't turn the volume down", name));
+            }
+        }
+
+        if self.sample_rate == Some(0) || self.buffer_frames == Some(0) {
+            problems.push(format!("{}.sample_rate and buffer_frames can't be 0", name));
+        }
+
+        validate_frequency(name, self.frequency, problems);
+        validate_volume(name, self.volume, problems);
+    }
+}
+
+fn validate_volume(name: &str, volume: f32, problems: &mut Vec<String>) {
+    if !(0.0..=1.0).contains(&volume) {
+        problems.push(format!("{}.volume is {}, it must be 0 to 1", name, volume));
+    }
+}
+
+fn validate_frequency(name: &str, frequency: f32, problems: &mut Vec<String>) {
+    if frequency <= 0.0 || frequency.is_nan() {
+        problems.push(format!(
+            "{}.frequency is {}, it must be above 0",
+            name, frequency
+        ));
+    }
+}
+
+// The understeer alert's sound, which plays as it is and mixes with everything else
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UndersteerTone {
+    pub volume: f32,
+    pub frequency: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for UndersteerTone {
+    fn default() -> Self {
+        UndersteerTone {
+            volume: 0.2,
+            frequency: 220.0,
+            waveform: Waveform::default(),
+        }
+    }
+}
+
+impl UndersteerTone {
+    pub fn validate(&self, name: &str, alert: &Config, problems: &mut Vec<String>) {
+        if self.frequency == alert.frequency {
+            problems.push(format!(
+                "{}.frequency must differ from oversteer_alert's",
+                name
+            ));
+        }
+
+        validate_frequency(name, self.frequency, problems);
+        validate_volume(name, self.volume, problems);
+    }
+}
+
+// What to do when the audio device can't be opened
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum AlertFallback {
+    // Beeps the oversteer alert through Windows at its frequency, the other sounds are lost
+    #[default]
+    Beep,
+    // Plays nothing
+    Disable,
+    // Refuses to start
+    Fail,
+}
+
+// What plays while the oversteer alert and rumble sound at the same time
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Arbitration {
+    #[default]
+    AlertWins,
+    Mix,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RumbleConfig {
+    // At full speed on the large motor
+    pub volume: f32,
+    // With the small motor stopped, full speed adds pitch_range on top
+    pub frequency: f32,
+    pub pitch_range: f32,
+    // Fades out over this long once the game stops sending rumble, 0 holds the last value
+    pub decay_ms: u64,
+    pub arbitration: Arbitration,
+}
+
+impl Default for RumbleConfig {
+    fn default() -> Self {
+        RumbleConfig {
+            volume: 0.2,
+            frequency: 60.0,
+            pitch_range: 120.0,
+            decay_ms: 500,
+            arbitration: Arbitration::default(),
+        }
+    }
+}
+
+impl RumbleConfig {
+    pub fn validate(&self, name: &str, problems: &mut Vec<String>) {
+        if self.pitch_range < 0.0 {
+            problems.push(format!("{}.pitch_range can't be below 0", name));
+        }
+
+        validate_frequency(name, self.frequency, problems);
+        validate_volume(name, self.volume, problems);
+    }
+}
+
+// A short tick played as a bound controller button goes down, to hear that it registered
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickConfig {
+    pub enabled: bool,
+    pub volume: f32,
+    pub duration_ms: u64,
+    // Presses closer together than this only click once, e.g. for turbo binds
+    pub min_interval_ms: u64,
+}
+
+impl Default for ClickConfig {
+    fn default() -> Self {
+        ClickConfig {
+            enabled: false,
+            volume: 0.2,
+            duration_ms: 10,
+            min_interval_ms: 50,
+        }
+    }
+}
+
+impl ClickConfig {
+    pub fn validate(&self, name: &str, problems: &mut Vec<String>) {
+        validate_volume(name, self.volume, problems);
+    }
+}
+
+// Short note sequences played on pause, resume and profile switches, each left out when None
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CueConfig {
+    pub enabled: bool,
+    pub volume: f32,
+    // How long each note and each gap between profile beeps lasts
+    pub note_ms: u64,
+    // Two frequencies played one after the other, pause usually falls and resume rises
+    pub pause: Option<(f32, f32)>,
+    pub resume: Option<(f32, f32)>,
+    // Beeps at this frequency once for each profile number
+    pub profile: Option<f32>,
+}
+
+impl Default for CueConfig {
+    fn default() -> Self {
+        CueConfig {
+            enabled: false,
+            volume: 0.2,
+            note_ms: 80,
+            pause: Some((660.0, 440.0)),
+            resume: Some((440.0, 660.0)),
+            profile: Some(880.0),
+        }
+    }
+}
+
+impl CueConfig {
+    pub fn validate(&self, name: &str, problems: &mut Vec<String>) {
+        let mut frequencies: Vec<f32> = self.profile.into_iter().collect();
+        for (first, second) in self.pause.into_iter().chain(self.resume) {
+            frequencies.extend([first, second]);
+        }
+
+        if frequencies
+            .iter()
+            .any(|&frequency| frequency <= 0.0 || frequency.is_nan())
+        {
+            problems.push(format!(
+                "{}: the pause, resume and profile frequencies must be above 0",
+                name
+            ));
+        }
+
+        validate_volume(name, self.volume, problems);
+    }
+}
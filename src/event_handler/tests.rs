@@ -0,0 +1,1480 @@
+// The event handler driven as the event dispatcher drives it, with what it sends kept by
+// MockOutput
+use super::output::MockOutput;
+use super::*;
+
+use ic::ScanCode::*;
+
+use std::sync::atomic::Ordering;
+
+// Runs config through events and then Exit, giving the reports sent and whether the
+// controllers were taken away after
+fn run(config: Config, events: Vec<Event>) -> (Vec<(usize, XUSBReport)>, bool) {
+    let (tx, rx) = mpsc::channel();
+    let output = MockOutput::default();
+
+    for event in events {
+        tx.send(event).unwrap();
+    }
+    tx.send(Event::Exit).unwrap();
+
+    let mut handler = handler(config, rx, output.clone());
+    handler.run().unwrap();
+    drop(handler);
+
+    let reports = output.reports.lock().unwrap().clone();
+    (reports, output.disconnected.load(Ordering::Relaxed))
+}
+
+fn handler(config: Config, rx: mpsc::Receiver<Event>, output: MockOutput) -> EventHandler {
+    let (inject_tx, _) = mpsc::channel();
+    EventHandler::with_output(rx, inject_tx, config, |_| Ok(Box::new(output))).unwrap()
+}
+
+// One to call the methods of, with nothing coming in
+fn idle_handler(config: Config) -> EventHandler {
+    handler(config, mpsc::channel().1, MockOutput::default())
+}
+
+fn config(binds: Vec<(Bind, ControllerAction)>) -> Config {
+    Config {
+        binds: binds.into_iter().collect(),
+        ..Config::default()
+    }
+}
+
+fn key(code: ic::ScanCode, state: KeyState) -> Event {
+    Event::Keyboard(Key { code, e0: false }, state)
+}
+
+fn first_controller(reports: &[(usize, XUSBReport)]) -> Vec<XUSBReport> {
+    let reports = reports.iter().filter(|(index, _)| *index == 0);
+    reports.map(|(_, report)| *report).collect()
+}
+
+#[test]
+fn key_pushes_stick_while_held() {
+    let config = config(vec![(
+        Bind::Keyboard(W, None),
+        ControllerAction::AnalogLeft(0.0, 1.0, None),
+    )]);
+    let events = vec![key(W, KeyState::Down), key(W, KeyState::Up)];
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+
+    assert!(reports.iter().any(|report| report.s_thumb_ly == i16::MAX));
+    assert_eq!(reports.last().unwrap().s_thumb_ly, 0);
+    assert!(reports.iter().all(|report| report.s_thumb_lx == 0));
+}
+
+#[test]
+fn buttons_and_triggers() {
+    let config = config(vec![
+        (
+            Bind::Keyboard(Q, None),
+            ControllerAction::Buttons(vec![
+                ControllerButton::LeftShoulder,
+                ControllerButton::RightShoulder,
+            ]),
+        ),
+        (
+            Bind::Mouse(MouseButton::Left),
+            ControllerAction::Button(ControllerButton::RightTrigger),
+        ),
+    ]);
+    let events = vec![
+        key(Q, KeyState::Down),
+        Event::MouseButton(MouseButton::Left, KeyState::Down),
+        key(Q, KeyState::Up),
+        Event::MouseButton(MouseButton::Left, KeyState::Up),
+    ];
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+    let shoulders = XButton::LeftShoulder | XButton::RightShoulder;
+
+    let both = |report: &XUSBReport| {
+        report.w_buttons.contains(shoulders) && report.b_right_trigger == u8::MAX
+    };
+    assert!(reports.iter().any(both));
+
+    let last = reports.last().unwrap();
+    assert!(last.w_buttons.is_empty());
+    assert_eq!(last.b_right_trigger, 0);
+}
+
+#[test]
+fn unchanged_report_not_sent_again() {
+    let config = config(vec![(
+        Bind::Keyboard(W, None),
+        ControllerAction::AnalogLeft(0.0, 1.0, None),
+    )]);
+    // Key repeat, and a key that isn't bound
+    let events = vec![
+        key(W, KeyState::Down),
+        key(W, KeyState::Down),
+        key(E, KeyState::Down),
+        key(W, KeyState::Up),
+    ];
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+    let held = reports.iter().filter(|report| report.s_thumb_ly != 0);
+
+    assert_eq!(held.count(), 1);
+}
+
+#[test]
+fn diagonal_reaches_corner() {
+    let config = config(vec![
+        (
+            Bind::Keyboard(W, None),
+            ControllerAction::AnalogLeft(0.0, 1.0, None),
+        ),
+        (
+            Bind::Keyboard(D, None),
+            ControllerAction::AnalogLeft(1.0, 0.0, None),
+        ),
+    ]);
+    let events = vec![key(W, KeyState::Down), key(D, KeyState::Down)];
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+
+    // Uncircularized at the default max_stick_output, the square's corner
+    assert!(reports
+        .iter()
+        .any(|report| report.s_thumb_lx == i16::MAX && report.s_thumb_ly == i16::MAX));
+}
+
+#[test]
+fn held_diagonal_not_sent_again() {
+    let mut config = config(vec![
+        (
+            Bind::Keyboard(W, None),
+            ControllerAction::AnalogLeft(0.0, 1.0, None),
+        ),
+        (
+            Bind::Keyboard(D, None),
+            ControllerAction::AnalogLeft(1.0, 0.0, None),
+        ),
+    ]);
+    // Both axes end up a fraction of a step past a whole one
+    config.analog_circularize = true;
+    let events = vec![
+        key(W, KeyState::Down),
+        key(D, KeyState::Down),
+        key(D, KeyState::Down),
+        key(W, KeyState::Down),
+        key(E, KeyState::Down),
+        key(D, KeyState::Up),
+        key(W, KeyState::Up),
+    ];
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+    let held = reports
+        .iter()
+        .filter(|report| report.s_thumb_lx != 0 && report.s_thumb_ly != 0);
+
+    assert_eq!(held.count(), 1);
+}
+
+#[test]
+fn mouse_moves_stick() {
+    let config = config(vec![(
+        Bind::MouseMove,
+        ControllerAction::AnalogRight(1.0, -1.0, None),
+    )]);
+    // Right and down, which is down on the stick
+    let events = (0..10).map(|_| Event::MouseMove(0, 20, 10)).collect();
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+
+    let moved = |report: &XUSBReport| report.s_thumb_rx > 0 && report.s_thumb_ry < 0;
+    assert!(reports.iter().any(moved));
+    assert!(reports.iter().all(|report| report.s_thumb_lx == 0));
+}
+
+#[test]
+fn reset_releases_everything() {
+    let config = config(vec![(
+        Bind::Keyboard(W, None),
+        ControllerAction::AnalogLeft(0.0, 1.0, None),
+    )]);
+    let events = vec![key(W, KeyState::Down), Event::Reset];
+
+    let (reports, disconnected) = run(config, events);
+    let reports = first_controller(&reports);
+
+    assert!(reports.iter().any(|report| report.s_thumb_ly == i16::MAX));
+    assert_eq!(reports.last().unwrap().s_thumb_ly, 0);
+    assert!(disconnected);
+}
+
+#[test]
+fn modifiers_pick_a_side() {
+    let config = config(vec![
+        (
+            Bind::Modifier(Modifier::LeftCtrl),
+            ControllerAction::Button(ControllerButton::B),
+        ),
+        (
+            Bind::Modifier(Modifier::RightCtrl),
+            ControllerAction::Button(ControllerButton::A),
+        ),
+    ]);
+    // Both Ctrl keys send the same scancode, only E0 tells the right one apart
+    let right_ctrl = Key {
+        code: LeftControl,
+        e0: true,
+    };
+    let left_ctrl = Key {
+        code: LeftControl,
+        e0: false,
+    };
+    let events = vec![
+        Event::Keyboard(right_ctrl, KeyState::Down),
+        Event::Keyboard(right_ctrl, KeyState::Up),
+        Event::Keyboard(left_ctrl, KeyState::Down),
+        Event::Keyboard(left_ctrl, KeyState::Up),
+    ];
+
+    let (reports, _) = run(config, events);
+    let reports = first_controller(&reports);
+
+    // Each side presses only its own button
+    assert!(reports.iter().any(|report| report.w_buttons == XButton::A));
+    assert!(reports.iter().any(|report| report.w_buttons == XButton::B));
+    assert!(reports.iter().all(|report| {
+        !(report.w_buttons.contains(XButton::A) && report.w_buttons.contains(XButton::B))
+    }));
+}
+
+#[test]
+fn modifiers_expanded_everywhere() {
+    let mut config = config(vec![(
+        Bind::Modifier(Modifier::RightShift),
+        ControllerAction::Button(ControllerButton::A),
+    )]);
+    let alt = Bind::Modifier(Modifier::RightAlt);
+    config.bind_options.insert(alt, BindOptions::default());
+    config.sequences.push(Sequence {
+        keys: vec![alt, Bind::Keyboard(W, None)],
+        window_ms: 100,
+        action: ControllerAction::Button(ControllerButton::B),
+    });
+
+    config.expand_modifiers();
+
+    let right_shift = Bind::Keyboard(RightShift, Some(false));
+    let right_alt = Bind::Keyboard(LeftAlt, Some(true));
+    assert_eq!(config.binds.keys().collect::<Vec<_>>(), [&right_shift]);
+    assert_eq!(config.bind_options.keys().collect::<Vec<_>>(), [&right_alt]);
+    assert_eq!(
+        config.sequences[0].keys,
+        [right_alt, Bind::Keyboard(W, None)]
+    );
+}
+
+#[test]
+fn anti_deadzone_lifts_small_deflections() {
+    assert_eq!(anti_deadzone(0.0, 0.0, 0.3), (0.0, 0.0));
+    assert_eq!(anti_deadzone(0.1, 0.0, 0.0), (0.1, 0.0));
+
+    // Anything moving starts at min, full deflection stays full
+    let (x, y) = anti_deadzone(1e-9, 0.0, 0.3);
+    assert!((x - 0.3).abs() < 1e-6 && y == 0.0);
+    let (x, y) = anti_deadzone(0.6, -0.8, 0.3);
+    assert!((x.hypot(y) - 1.0).abs() < 1e-12);
+
+    // Halfway is halfway from min to 1, in the same direction
+    let (x, y) = anti_deadzone(0.3, 0.4, 0.2);
+    assert!((x.hypot(y) - 0.6).abs() < 1e-12);
+    assert!((x / y - 0.75).abs() < 1e-12);
+}
+
+// The most W pushed the left stick up, bound to a tenth of the way with an anti-deadzone of 0.3
+fn lifted_key(scope: AntiDeadzoneScope) -> f64 {
+    let mut config = config(vec![(
+        Bind::Keyboard(W, None),
+        ControllerAction::AnalogLeft(0.0, 0.1, None),
+    )]);
+    config.anti_deadzone.left = 0.3;
+    config.anti_deadzone_scope = scope;
+
+    let (reports, _) = run(config, vec![key(W, KeyState::Down), key(W, KeyState::Up)]);
+    let most = first_controller(&reports)
+        .iter()
+        .map(|r| r.s_thumb_ly)
+        .max();
+    most.unwrap() as f64 / i16::MAX as f64
+}
+
+#[test]
+fn anti_deadzone_combined_lifts_keys() {
+    // Keys aren't the mouse, so only Combined reaches them
+    assert!((lifted_key(AntiDeadzoneScope::Mouse) - 0.1).abs() < 1e-3);
+    assert!((lifted_key(AntiDeadzoneScope::Combined) - 0.37).abs() < 1e-3);
+}
+
+#[test]
+fn decay_rises_straight_away() {
+    assert_eq!(limit_decay((0.1, 0.0), (0.0, 0.9), 0.01), (0.0, 0.9));
+    assert_eq!(limit_decay((0.6, 0.8), (0.6, 0.8), 0.0), (0.6, 0.8));
+}
+
+#[test]
+fn decay_limits_falls() {
+    // Slowing from 1 to 0.2 only falls 0.1, along the new direction
+    let (x, y) = limit_decay((0.6, 0.8), (0.0, 0.2), 0.1);
+    assert!(x.abs() < 1e-12 && (y - 0.9).abs() < 1e-12);
+
+    // Stopped, it keeps the old direction
+    let (x, y) = limit_decay((0.6, 0.8), (0.0, 0.0), 0.5);
+    assert!((x - 0.3).abs() < 1e-12 && (y - 0.4).abs() < 1e-12);
+
+    // A drop as big as the stick lets it go all the way
+    assert_eq!(limit_decay((0.3, 0.0), (0.0, 0.0), 0.5), (0.0, 0.0));
+}
+
+#[test]
+fn decay_eases_to_rest() {
+    // 2 per second in 100 loops of 5ms takes half a second from full to nothing
+    let mut output = (1.0, 0.0);
+    let mut loops = 0;
+    while output != (0.0, 0.0) {
+        output = limit_decay(output, (0.0, 0.0), 2.0 * 0.005);
+        loops += 1;
+        assert!(loops <= 101);
+    }
+    assert!(loops >= 100);
+}
+
+#[test]
+fn stick_step_carries_fractions() {
+    let quarter = 0.25 / EventHandler::ANALOG_MAX;
+
+    // A quarter step a loop moves a step every fourth loop
+    let (mut moved, mut remainder) = (0, 0.0);
+    for _ in 0..400 {
+        let (step, left) = stick_step(quarter, remainder);
+        moved += step as i32;
+        remainder = left;
+    }
+    assert_eq!(moved, 100);
+
+    let (step, remainder) = stick_step(-quarter, -0.75);
+    assert_eq!((step, remainder), (-1, 0.0));
+}
+
+#[test]
+fn stick_step_nothing_past_the_end() {
+    assert_eq!(stick_step(1.0, 0.0), (i16::MAX, 0.0));
+    assert_eq!(stick_step(-1.0, -1.5), (i16::MIN, 0.0));
+
+    let (step, remainder) = stick_step(0.5, 0.0);
+    assert_eq!(step, 16384);
+    assert_eq!(remainder, 0.0);
+}
+
+#[test]
+fn stick_carries_fractions() {
+    let mut handler = idle_handler(Config::default());
+    let quarter = 0.25 / EventHandler::ANALOG_MAX;
+
+    // A quarter step a loop moves a step every fourth loop
+    let mut moved = 0;
+    for _ in 0..400 {
+        handler.write_stick(AnalogType::Right, quarter, -quarter);
+        moved += handler.report.s_thumb_rx as i32;
+        assert!(handler.report.s_thumb_ry <= 0);
+    }
+    assert_eq!(moved, 100);
+    assert_eq!(handler.report.s_thumb_lx, 0);
+}
+
+#[test]
+fn stick_carry_cleared() {
+    let mut handler = idle_handler(Config::default());
+    let most = 0.75 / EventHandler::ANALOG_MAX;
+
+    // Centred, nothing left over comes through after
+    handler.write_stick(AnalogType::Left, most, 0.0);
+    handler.write_stick(AnalogType::Left, 0.0, 0.0);
+    handler.write_stick(AnalogType::Left, 0.5 / EventHandler::ANALOG_MAX, 0.0);
+    assert_eq!(handler.report.s_thumb_lx, 0);
+
+    // Held at the end of the range, none of the excess is kept
+    handler.write_stick(AnalogType::Left, 1.0, -1.0);
+    assert_eq!(handler.report.s_thumb_lx, i16::MAX);
+    assert_eq!(handler.report.s_thumb_ly, i16::MIN);
+    assert_eq!(handler.stick_remainders[0], 0.0);
+    handler.write_stick(AnalogType::Left, most, 0.0);
+    assert_eq!(handler.report.s_thumb_lx, 0);
+}
+
+#[test]
+fn ema_counts_only_kept_for_ema() {
+    let mut handler = idle_handler(Config::default());
+    handler.handle_mouse_move(5, 5);
+    assert_eq!(handler.mouse_ema_pending, (0, 0));
+
+    let smoothing = Smoothing {
+        mode: SmoothingMode::Ema,
+        ..Smoothing::default()
+    };
+    let ema = Config {
+        smoothing,
+        ..Config::default()
+    };
+    let mut handler = idle_handler(ema);
+    handler.handle_mouse_move(5, 5);
+    assert_eq!(handler.mouse_ema_pending, (5, 5));
+
+    // What was waiting for Ema is dropped when it's switched away from
+    let mut window = Config::default();
+    window.prepare().unwrap();
+    handler.apply(window);
+    assert_eq!(handler.mouse_ema_pending, (0, 0));
+}
+
+#[test]
+fn flick_counts_only_kept_for_flick_stick() {
+    let mut handler = idle_handler(Config::default());
+    handler.handle_mouse_move(5, 0);
+    assert_eq!(handler.flick_pending, 0);
+
+    let mut handler = idle_handler(Config {
+        mouse_mode: MouseMode::FlickStick,
+        ..Config::default()
+    });
+    handler.handle_mouse_move(5, 0);
+    assert_eq!(handler.flick_pending, 5);
+
+    let mut velocity = Config::default();
+    velocity.prepare().unwrap();
+    handler.apply(velocity);
+    assert_eq!(handler.flick_pending, 0);
+}
+
+#[test]
+fn sector_side_edge_has_depth() {
+    let region = Region::Sector {
+        angle: 90.0,
+        width: 90.0,
+        magnitude: 0.5,
+    };
+    let at = |degrees: f64, radius: f64| AnalogState {
+        analog_type: AnalogType::Left,
+        x: degrees.to_radians().cos() * radius,
+        y: degrees.to_radians().sin() * radius,
+    };
+
+    // Either side of the edge at 45 degrees, a degree out is only a little way out, so a
+    // hysteresis of 0.1 keeps a zone that was entered
+    assert!(region.depth(&at(46.0, 1.0)) > 0.0);
+    let outside = region.depth(&at(44.0, 1.0));
+    assert!(outside < 0.0 && outside > -0.1, "{}", outside);
+    assert!(region.depth(&at(-90.0, 1.0)) < -0.9);
+
+    // Well inside the sides, the magnitude is what counts
+    let short = region.depth(&at(90.0, 0.4));
+    assert!((short + 0.1).abs() < 1e-9, "{}", short);
+}
+
+#[test]
+fn jitter_dropped_at_rest() {
+    let mut handler = idle_handler(Config {
+        jitter_filter: 2,
+        ..Config::default()
+    });
+
+    // Back and forth never adds up to more than the threshold
+    for _ in 0..4 {
+        handler.handle_mouse_move(1, -2);
+        handler.handle_mouse_move(-1, 2);
+    }
+    assert_eq!(handler.mouse_windows.0.samples.len(), 0);
+
+    // Bigger than the threshold always gets through
+    handler.handle_mouse_move(3, 0);
+    assert_eq!(handler.mouse_windows.0.samples.len(), 1);
+}
+
+#[test]
+fn jitter_slow_movement_adds_up() {
+    let mut handler = idle_handler(Config {
+        jitter_filter: 2,
+        ..Config::default()
+    });
+
+    handler.handle_mouse_move(1, 0);
+    handler.handle_mouse_move(1, 1);
+    assert_eq!(handler.mouse_windows.0.samples.len(), 0);
+
+    // Past the threshold together, everything held back goes in at once
+    handler.handle_mouse_move(1, 0);
+    assert_eq!(handler.mouse_windows.0.samples.len(), 1);
+    assert_eq!(handler.mouse_windows.0.sum, 3);
+    assert_eq!(handler.mouse_windows.1.sum, 1);
+    assert_eq!(handler.jitter_pending, (0, 0));
+}
+
+#[test]
+fn jitter_kept_while_moving() {
+    let mut handler = idle_handler(Config {
+        jitter_filter: 2,
+        ..Config::default()
+    });
+
+    // Moving faster than the threshold, small samples are part of the movement
+    for _ in 0..4 {
+        handler.handle_mouse_move(0, 10);
+    }
+    handler.handle_mouse_move(1, 1);
+    assert_eq!(handler.mouse_windows.1.samples.len(), 5);
+    assert_eq!(handler.mouse_windows.1.sum, 41);
+
+    // And off, nothing is dropped
+    let mut handler = idle_handler(Config::default());
+    handler.handle_mouse_move(1, 0);
+    assert_eq!(handler.mouse_windows.0.sum, 1);
+}
+
+#[test]
+fn acceleration_blends_in() {
+    // Off up to the threshold
+    assert_eq!(acceleration(0.0, 0.5, 2.0), 1.0);
+    assert_eq!(acceleration(0.5, 0.5, 2.0), 1.0);
+    // Halfway to twice the threshold is halfway to the multiplier
+    assert!((acceleration(0.75, 0.5, 2.0) - 1.5).abs() < 1e-12);
+    assert_eq!(acceleration(1.0, 0.5, 2.0), 2.0);
+    assert_eq!(acceleration(5.0, 0.5, 2.0), 2.0);
+    // Below 1 slows fast movement down instead
+    assert!((acceleration(0.75, 0.5, 0.5) - 0.75).abs() < 1e-12);
+    // With no threshold all of it at once
+    assert_eq!(acceleration(0.01, 0.0, 2.0), 2.0);
+}
+
+#[test]
+fn acceleration_keeps_output_rising() {
+    let speeds = (0..=300).map(|i| i as f64 / 100.0);
+    let outputs: Vec<f64> = speeds.map(|s| s * acceleration(s, 0.5, 3.0)).collect();
+
+    assert!(outputs.windows(2).all(|pair| pair[1] > pair[0]));
+}
+
+#[test]
+fn accelerate_caps_speed_not_direction() {
+    let config = Config {
+        accel_threshold: 0.5,
+        accel_multiplier: 2.0,
+        accel_cap: 1.5,
+        ..Config::default()
+    };
+    let mut handler = idle_handler(config);
+
+    // Under the threshold unchanged
+    assert_eq!(handler.accelerate((0.3, -0.4)), (0.3, -0.4));
+    assert_eq!(handler.accelerate((0.0, 0.0)), (0.0, 0.0));
+
+    // 1.0 doubled to 2.0, then held at the cap along the same line
+    let (x, y) = handler.accelerate((0.6, -0.8));
+    assert!((x.hypot(y) - 1.5).abs() < 1e-12);
+    assert!((x / y - 0.6 / -0.8).abs() < 1e-12);
+
+    // Without a cap
+    handler.config.accel_cap = 0.0;
+    let (x, y) = handler.accelerate((0.6, -0.8));
+    assert!((x.hypot(y) - 2.0).abs() < 1e-12);
+}
+
+#[test]
+fn window_sum_kept_with_samples() {
+    let mut now = Instant::now();
+    let span = Duration::from_millis(20);
+    let mut window = MouseWindow::default();
+    let mut sent = Vec::new();
+    let mut seed = 1u32;
+
+    // Uneven counts and gaps, expired as update_analog does each loop
+    for _ in 0..2000 {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let count = (seed >> 16) as i32 % 200 - 100;
+        now += Duration::from_micros(125 + (seed as u64 % 3) * 1000);
+
+        window.push(count, now);
+        window.expire(now, span);
+        sent.push((count, now));
+
+        // The naive sum over everything sent within the window
+        let recent = sent.iter().filter(|&&(_, time)| now - time <= span);
+        let naive: i64 = recent.map(|&(count, _)| count as i64).sum();
+        assert_eq!(window.sum, naive);
+        assert_eq!(
+            window.total(now, span, WindowWeighting::Uniform),
+            naive as f64
+        );
+    }
+
+    window.clear();
+    assert_eq!((window.sum, window.samples.len()), (0, 0));
+}
+
+#[test]
+fn window_total_leaves_out_old_samples() {
+    // Kept for a longer window than the one asked for
+    let now = Instant::now();
+    let mut window = MouseWindow::default();
+    window.push(1000, now - Duration::from_millis(30));
+    window.push(7, now - Duration::from_millis(5));
+
+    let short = Duration::from_millis(20);
+    assert_eq!(window.total(now, short, WindowWeighting::Uniform), 7.0);
+    assert_eq!(
+        window.total(now, short * 2, WindowWeighting::Uniform),
+        1007.0
+    );
+}
+
+// The weights of 20 evenly spread samples over the window, each with the count given for its
+// age, older first
+fn ramp_total(weighting: WindowWeighting, count: impl Fn(u64) -> i32) -> f64 {
+    (0..20)
+        .rev()
+        .map(|age| count(age) as f64 * weighting.weight((age as f64 + 0.5) / 20.0))
+        .sum()
+}
+
+#[test]
+fn weighting_steady_unchanged() {
+    for weighting in [
+        WindowWeighting::Uniform,
+        WindowWeighting::Linear,
+        WindowWeighting::Exponential(3.0),
+        WindowWeighting::Exponential(0.0),
+    ] {
+        let total = ramp_total(weighting, |_| 10);
+        assert!(
+            (total - 200.0).abs() < 0.5,
+            "{:?} gave {}",
+            weighting,
+            total
+        );
+    }
+}
+
+#[test]
+fn weighting_favours_recent() {
+    for weighting in [WindowWeighting::Linear, WindowWeighting::Exponential(3.0)] {
+        assert!(weighting.weight(0.0) > 1.0);
+        assert!(weighting.weight(1.0) < 1.0);
+    }
+    assert_eq!(WindowWeighting::Linear.weight(1.0), 0.0);
+    assert_eq!(WindowWeighting::Exponential(0.0).weight(0.3), 1.0);
+
+    // Stopped half a window ago, less of the movement is left
+    let stopped = |age| if age >= 10 { 10 } else { 0 };
+
+    assert_eq!(ramp_total(WindowWeighting::Uniform, stopped), 100.0);
+    let linear = ramp_total(WindowWeighting::Linear, stopped);
+    assert!((linear - 50.0).abs() < 1e-9);
+    assert!(ramp_total(WindowWeighting::Exponential(3.0), stopped) < linear);
+}
+
+// A window of 20ms with a sample every ms, older at the front, each with the count given for
+// its age
+fn ramp(now: Instant, count: impl Fn(u64) -> i32) -> MouseWindow {
+    let mut window = MouseWindow::default();
+
+    for age in (0..20).rev() {
+        let time = now - Duration::from_micros(age * 1000 + 500);
+        window.push(count(age), time);
+    }
+
+    window
+}
+
+#[test]
+fn extrapolate_steady_unchanged() {
+    let now = Instant::now();
+    let window = ramp(now, |_| 10);
+    let (span, ahead) = (Duration::from_millis(20), Duration::from_millis(5));
+
+    let extrapolated = window.extrapolate(200.0, now, span, ahead, 1.0);
+    assert!((extrapolated - 200.0).abs() < 1e-9);
+}
+
+#[test]
+fn extrapolate_follows_trend() {
+    let now = Instant::now();
+    let span = Duration::from_millis(20);
+    // 10000 counts/s in the older half and 20000 in the newer, 300 counts in all
+    let rising = ramp(now, |age| if age >= 10 { 10 } else { 20 });
+    let falling = ramp(now, |age| if age >= 10 { 20 } else { 10 });
+
+    // The window average is the middle's velocity, its end is half a window of the trend on
+    let at_end = rising.extrapolate(300.0, now, span, Duration::ZERO, 10.0);
+    assert!((at_end - 500.0).abs() < 1e-9);
+    let ahead = rising.extrapolate(300.0, now, span, Duration::from_millis(5), 10.0);
+    assert!((ahead - 600.0).abs() < 1e-9);
+
+    let at_end = falling.extrapolate(300.0, now, span, Duration::ZERO, 10.0);
+    assert!((at_end - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn extrapolate_clamped_to_overshoot() {
+    let now = Instant::now();
+    let span = Duration::from_millis(20);
+    let rising = ramp(now, |age| if age >= 10 { 10 } else { 20 });
+    let falling = ramp(now, |age| if age >= 10 { 20 } else { 10 });
+
+    let capped = rising.extrapolate(300.0, now, span, Duration::ZERO, 0.25);
+    assert!((capped - 375.0).abs() < 1e-9);
+    let capped = falling.extrapolate(300.0, now, span, Duration::ZERO, 0.25);
+    assert!((capped - 225.0).abs() < 1e-9);
+
+    // Nothing moved, nothing to overshoot
+    let still = MouseWindow::default();
+    assert_eq!(still.extrapolate(0.0, now, span, span, 0.25), 0.0);
+}
+
+#[test]
+fn extrapolate_ignores_expired_samples() {
+    let now = Instant::now();
+    let span = Duration::from_millis(20);
+    let mut window = MouseWindow::default();
+    window.push(1000, now - Duration::from_millis(25));
+
+    let steady = ramp(now, |_| 10);
+    for &(count, time) in &steady.samples {
+        window.push(count, time);
+    }
+
+    let extrapolated = window.extrapolate(200.0, now, span, Duration::from_millis(5), 1.0);
+    assert!((extrapolated - 200.0).abs() < 1e-9);
+}
+
+// The left stick pushed right this far, the right one centred
+fn left_at(x: f64) -> (AnalogState, AnalogState) {
+    let state = |analog_type, x| AnalogState {
+        analog_type,
+        x,
+        y: 0.0,
+    };
+
+    (state(AnalogType::Left, x), state(AnalogType::Right, 0.0))
+}
+
+// Threshold 0.5, so on from 0.5 and off under 0.4 once 100ms have passed. Turned on after
+// starting as it would open the audio device
+fn alert_handler() -> EventHandler {
+    let mut handler = idle_handler(Config {
+        oversteer_alert_threshold: 0.5,
+        alert_min_on_ms: 100,
+        alert_hysteresis: 0.1,
+        ..Config::default()
+    });
+    handler.config.oversteer_alert_enabled = true;
+    handler
+}
+
+#[test]
+fn alert_rides_out_chatter() {
+    let mut handler = alert_handler();
+    let start = Instant::now();
+
+    // Hovering around the threshold every 5ms for a second
+    for i in 0..200 {
+        let x = if i % 2 == 0 { 0.51 } else { 0.49 };
+        let now = start + Duration::from_millis(i * 5);
+
+        handler.update_oversteer_alert(&left_at(x), now);
+        assert_eq!(handler.alert_on_since, Some(start));
+    }
+}
+
+#[test]
+fn alert_stays_on_for_min_on() {
+    let mut handler = alert_handler();
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+
+    handler.update_oversteer_alert(&left_at(0.6), at(0));
+    assert!(handler.alert_on_since.is_some());
+
+    // Well under the threshold, but not on for long enough yet
+    handler.update_oversteer_alert(&left_at(0.0), at(50));
+    assert!(handler.alert_on_since.is_some());
+
+    handler.update_oversteer_alert(&left_at(0.0), at(100));
+    assert!(handler.alert_on_since.is_none());
+}
+
+#[test]
+fn alert_hysteresis() {
+    let mut handler = alert_handler();
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+
+    // Under the threshold it doesn't start
+    handler.update_oversteer_alert(&left_at(0.45), at(0));
+    assert!(handler.alert_on_since.is_none());
+
+    handler.update_oversteer_alert(&left_at(0.5), at(0));
+    assert!(handler.alert_on_since.is_some());
+
+    // Long past min_on, it only goes off under threshold - hysteresis
+    handler.update_oversteer_alert(&left_at(0.41), at(500));
+    assert!(handler.alert_on_since.is_some());
+    handler.update_oversteer_alert(&left_at(0.39), at(510));
+    assert!(handler.alert_on_since.is_none());
+
+    // And needs the full threshold to start again
+    handler.update_oversteer_alert(&left_at(0.45), at(520));
+    assert!(handler.alert_on_since.is_none());
+}
+
+#[test]
+fn timing_overrides_listed() {
+    let mut config = Config::default();
+    let (g, h) = (Bind::Keyboard(G, None), Bind::Keyboard(H, None));
+    let tap = BindOptions {
+        tap_threshold_ms: Some(150),
+        ..BindOptions::default()
+    };
+    let wheel = BindOptions {
+        wheel_invert: Some(true),
+        ..BindOptions::default()
+    };
+    config.bind_options.insert(g, tap);
+    config.bind_options.insert(h, wheel);
+
+    // H's options leave its timing as it was
+    let timing = Timing {
+        tap_threshold_ms: 150,
+        ..Timing::default()
+    };
+    assert_eq!(config.timing_overrides(), [(g, timing)]);
+}
+
+// A handler with G on TapHold (A or B) and H on DoubleTap (X or Y), and whether a bind holds
+// each button
+fn tap_handler() -> (
+    EventHandler,
+    impl Fn(&EventHandler, Bind, ControllerButton) -> bool,
+) {
+    let handler = idle_handler(config(vec![
+        (
+            Bind::Keyboard(G, None),
+            ControllerAction::TapHold {
+                tap: Box::new(ControllerAction::Button(ControllerButton::A)),
+                hold: Box::new(ControllerAction::Button(ControllerButton::B)),
+            },
+        ),
+        (
+            Bind::Keyboard(H, None),
+            ControllerAction::DoubleTap {
+                single: Box::new(ControllerAction::Button(ControllerButton::X)),
+                double: Box::new(ControllerAction::Button(ControllerButton::Y)),
+            },
+        ),
+    ]));
+
+    let holds = |handler: &EventHandler, bind: Bind, button: ControllerButton| {
+        handler.button_holds.contains(&(Source::Bind(bind), button))
+    };
+    (handler, holds)
+}
+
+#[test]
+fn tap_hold_by_threshold() {
+    let (mut handler, holds) = tap_handler();
+    let bind = Bind::Keyboard(G, None);
+    let source = Source::Bind(bind);
+
+    handler.handle_bind(bind, KeyState::Down);
+    handler.handle_bind(bind, KeyState::Up);
+    assert!(holds(&handler, bind, ControllerButton::A));
+    assert!(!holds(&handler, bind, ControllerButton::B));
+
+    let mut handler = tap_handler().0;
+    handler.handle_bind(bind, KeyState::Down);
+    handler.expire_taps();
+    assert!(!holds(&handler, bind, ControllerButton::B));
+
+    // Held past tap_threshold_ms
+    handler.tap_holds.get_mut(&source).unwrap().0 -= Duration::from_millis(200);
+    handler.expire_taps();
+    assert!(holds(&handler, bind, ControllerButton::B));
+
+    handler.handle_bind(bind, KeyState::Up);
+    assert!(handler.button_holds.is_empty());
+    assert!(handler.scheduled_releases.is_empty());
+}
+
+#[test]
+fn double_tap_within_window() {
+    let (mut handler, holds) = tap_handler();
+    let bind = Bind::Keyboard(H, None);
+    let source = Source::Bind(bind);
+
+    for _ in 0..2 {
+        handler.handle_bind(bind, KeyState::Down);
+        handler.handle_bind(bind, KeyState::Up);
+    }
+    handler.expire_taps();
+    assert!(holds(&handler, bind, ControllerButton::Y));
+    assert!(!holds(&handler, bind, ControllerButton::X));
+
+    // Once double_tap_window_ms passes without a second tap
+    let mut handler = tap_handler().0;
+    handler.handle_bind(bind, KeyState::Down);
+    handler.handle_bind(bind, KeyState::Up);
+    handler.expire_taps();
+    assert!(handler.button_holds.is_empty());
+
+    handler.first_taps.get_mut(&source).unwrap().0 -= Duration::from_millis(300);
+    handler.expire_taps();
+    assert!(holds(&handler, bind, ControllerButton::X));
+    assert!(!holds(&handler, bind, ControllerButton::Y));
+}
+
+#[test]
+fn sequences_overlap() {
+    let sequence = |keys: &[ic::ScanCode], button| Sequence {
+        keys: keys
+            .iter()
+            .map(|&code| Bind::Keyboard(code, None))
+            .collect(),
+        window_ms: 1000,
+        action: ControllerAction::Button(button),
+    };
+    let mut handler = idle_handler(Config {
+        sequences: vec![
+            sequence(&[Num1, Num1, Num2], ControllerButton::A),
+            sequence(&[Num1, Num2, Num3], ControllerButton::B),
+            sequence(&[Num2, Num4], ControllerButton::X),
+            sequence(&[Num5, Num5], ControllerButton::Y),
+        ],
+        ..Config::default()
+    });
+    let matched = |handler: &mut EventHandler, keys: &[ic::ScanCode]| {
+        handler.button_holds.clear();
+        for &code in keys {
+            handler.match_sequences(Bind::Keyboard(code, Some(false)));
+        }
+        let holds = handler.button_holds.iter();
+        holds.map(|&(source, _)| source).collect::<Vec<_>>()
+    };
+
+    // The third 1 breaks off the first sequence, the last two 1s still start it
+    assert_eq!(
+        matched(&mut handler, &[Num1, Num1, Num1, Num2]),
+        [Source::Sequence(0)]
+    );
+
+    // The second sequence shares the first's keys, the third starts where it breaks off
+    assert_eq!(matched(&mut handler, &[Num3]), [Source::Sequence(1)]);
+    assert_eq!(
+        matched(&mut handler, &[Num1, Num2, Num4]),
+        [Source::Sequence(2)]
+    );
+
+    // Keys that matched don't count towards the same sequence again
+    assert_eq!(
+        matched(&mut handler, &[Num5, Num5, Num5]),
+        [Source::Sequence(3)]
+    );
+}
+
+#[test]
+fn sequence_modifier_without_bind() {
+    let mut config = config(vec![]);
+    config.sequences.push(Sequence {
+        keys: vec![Bind::Modifier(Modifier::RightCtrl), Bind::Keyboard(W, None)],
+        window_ms: 1000,
+        action: ControllerAction::Button(ControllerButton::A),
+    });
+    let mut handler = idle_handler(config);
+
+    let mut matched = |ctrl_e0| {
+        handler.button_holds.clear();
+        for bind in [
+            Bind::Keyboard(LeftControl, Some(ctrl_e0)),
+            Bind::Keyboard(W, Some(false)),
+        ] {
+            handler.handle_input(bind, KeyState::Down);
+            handler.handle_input(bind, KeyState::Up);
+        }
+        handler
+            .button_holds
+            .iter()
+            .any(|&(source, _)| source == Source::Sequence(0))
+    };
+
+    // Nothing binds either Ctrl, so only the step tells them apart
+    assert!(!matched(false));
+    assert!(matched(true));
+}
+
+#[test]
+fn profile_switch_while_stick_held() {
+    let text = r#"(
+        binds: {
+            MouseMove: AnalogRight(1, -1),
+            Keyboard(W): AnalogLeft(0, 1),
+            Keyboard(F1): Profile("slow"),
+        },
+        profiles: { "slow": (sensitivity: 2) },
+    )"#;
+    let events = vec![
+        key(W, KeyState::Down),
+        key(F1, KeyState::Down),
+        key(F1, KeyState::Up),
+        key(W, KeyState::Up),
+    ];
+
+    let (reports, _) = run(read_config("switch", text), events);
+    let reports = first_controller(&reports);
+
+    // The release after the switch lets go rather than pushing the stick again
+    assert!(reports.iter().any(|report| report.s_thumb_ly == i16::MAX));
+    assert_eq!(reports.last().unwrap().s_thumb_ly, 0);
+}
+
+#[test]
+fn bind_table_names_keys() {
+    let config = config(vec![
+        (
+            Bind::Keyboard(Numpad1, None),
+            ControllerAction::Button(ControllerButton::A),
+        ),
+        (
+            Bind::Keyboard(LeftControl, Some(true)),
+            ControllerAction::TapHold {
+                tap: Box::new(ControllerAction::Button(ControllerButton::B)),
+                hold: Box::new(ControllerAction::Button(ControllerButton::X)),
+            },
+        ),
+    ]);
+
+    let table = config.bind_table();
+    assert!(table.starts_with("2 binds:\n"));
+    assert!(table.contains("Numpad1 or End"));
+    assert!(table.contains("RightControl"));
+    assert!(table.contains("on tap"));
+}
+
+#[test]
+fn binds_checked() {
+    let problems = |binds: Vec<(Bind, ControllerAction)>| {
+        let mut problems = Vec::new();
+        check_binds("binds", &binds.into_iter().collect(), &mut problems);
+        problems
+    };
+
+    let button = ControllerAction::Button(ControllerButton::A);
+    let both = problems(vec![
+        (Bind::Modifier(Modifier::LeftShift), button.clone()),
+        (Bind::Keyboard(LeftShift, Some(false)), button),
+    ]);
+    assert_eq!(both.len(), 1);
+    assert!(both[0].contains("is bound by name and as a Modifier"));
+
+    let past_full = ControllerAction::AnalogLeft(0.0, 1.5, None);
+    assert_eq!(
+        problems(vec![(Bind::Keyboard(W, None), past_full)]).len(),
+        1
+    );
+
+    // MouseMove's are scales
+    let scale = ControllerAction::AnalogRight(2.0, 2.0, None);
+    assert!(problems(vec![(Bind::MouseMove, scale)]).is_empty());
+}
+
+#[test]
+fn preset_under_own_binds() {
+    let space = Bind::Keyboard(Space, None);
+    let mut config = config(vec![(space, ControllerAction::Button(ControllerButton::Y))]);
+    config.preset = Some("fps".to_owned());
+
+    config.apply_preset().unwrap();
+    assert_eq!(
+        config.binds[&space],
+        ControllerAction::Button(ControllerButton::Y)
+    );
+    assert_eq!(
+        config.binds[&Bind::Keyboard(W, None)],
+        ControllerAction::AnalogLeft(0.0, 1.0, None)
+    );
+
+    config.preset = Some("driving".to_owned());
+    assert!(config.apply_preset().is_err());
+}
+
+#[test]
+fn presets_pass_the_bind_checks() {
+    for &(name, _) in PRESETS {
+        let mut problems = Vec::new();
+        check_binds(name, &presets::binds(name).unwrap(), &mut problems);
+        assert_eq!(problems, Vec::<String>::new(), "{}", name);
+    }
+}
+
+// Each with a setting out of range and what validate says about it
+const BROKEN: &[(&str, &str)] = &[
+    ("sensitivity: 0", "sensitivity is 0, it must be above 0"),
+    (
+        "sensitivity_x: Some(-1.0)",
+        "sensitivity_x is -1, it must be above 0",
+    ),
+    (
+        "sensitivity_y: Some(0.0)",
+        "sensitivity_y is 0, it must be above 0",
+    ),
+    (
+        "axis_snap_deg: 46",
+        "axis_snap_deg is 46, it must be 0 to 45",
+    ),
+    (
+        "axis_snap_hysteresis_deg: -1",
+        "axis_snap_hysteresis_deg is -1, it must be at least 0",
+    ),
+    (
+        "max_decay_per_sec: -1",
+        "max_decay_per_sec is -1, it must be at least 0",
+    ),
+    (
+        "accel_threshold: -1",
+        "accel_threshold is -1, it must be at least 0",
+    ),
+    (
+        "accel_multiplier: 0",
+        "accel_multiplier is 0, it must be above 0",
+    ),
+    ("accel_cap: -1", "accel_cap is -1, it must be at least 0"),
+    (
+        "flick_stick: (degrees_per_count: 0)",
+        "flick_stick.degrees_per_count is 0, it must be above 0",
+    ),
+    (
+        "flick_stick: (flick_threshold_deg: -1)",
+        "flick_stick.flick_threshold_deg is -1, it must be at least 0",
+    ),
+    (
+        "smoothing: (tau_ms: 0)",
+        "smoothing.tau_ms is 0, it must be above 0",
+    ),
+    (
+        "prediction_max_overshoot: -1",
+        "prediction_max_overshoot is -1, it must be at least 0",
+    ),
+    (
+        "oversteer_alert_threshold: 0",
+        "oversteer_alert_threshold is 0, it must be above 0",
+    ),
+    (
+        "oversteer_alert_threshold_left: Some(0.0)",
+        "oversteer_alert_threshold_left is 0, it must be above 0",
+    ),
+    (
+        "oversteer_alert_threshold_right: Some(-1.0)",
+        "oversteer_alert_threshold_right is -1, it must be above 0",
+    ),
+    (
+        "alert_hysteresis: -1",
+        "alert_hysteresis is -1, it must be at least 0",
+    ),
+    (
+        "oversteer_alert_threshold: 0.5, alert_hysteresis: 0.5, oversteer_alert_enabled: true",
+        "alert_hysteresis (0.5) must be under the oversteer alert threshold (0.5)",
+    ),
+    (
+        "understeer_alert: (floor: 1.5)",
+        "understeer_alert.floor is 1.5, it must be 0 to 1",
+    ),
+    (
+        "auto_sprint: (threshold: 2)",
+        "auto_sprint.threshold is 2, it must be 0 to 1",
+    ),
+    (
+        "auto_stick_click: (threshold: -0.5)",
+        "auto_stick_click.threshold is -0.5, it must be 0 to 1",
+    ),
+    (
+        "auto_stick_click: (hysteresis: -1)",
+        "auto_stick_click.hysteresis is -1, it must be at least 0",
+    ),
+    (
+        "auto_stick_click: (enabled: true, threshold: 0.5, hysteresis: 0.5)",
+        "auto_stick_click.hysteresis (0.5) must be under its threshold (0.5)",
+    ),
+    (
+        "dpad_mode: (diagonal_threshold: 50)",
+        "dpad_mode.diagonal_threshold is 50, it must be 0 to 45",
+    ),
+    (
+        "dpad_mode: (deadzone: 1.5)",
+        "dpad_mode.deadzone is 1.5, it must be 0 to 1",
+    ),
+    (
+        "analog_min_output: (left: 1.5)",
+        "analog_min_output.left is 1.5, it must be 0 to 1",
+    ),
+    (
+        "anti_deadzone: (right: -0.5)",
+        "anti_deadzone.right is -0.5, it must be 0 to 1",
+    ),
+    (
+        "max_stick_output: (left: 2, right: 1)",
+        "max_stick_output.left is 2, it must be 0 to 1",
+    ),
+    ("sample_window: 0", "sample_window can't be 0"),
+    ("sample_window_y: Some(0)", "sample_window_y can't be 0"),
+    (
+        "spin_period: \"30ms\"",
+        "spin_period (30ms) can't be longer than sample_window (20ms)",
+    ),
+    (
+        "adaptive_window: true, sample_window_min: \"30ms\", sample_window_max: \"10ms\"",
+        "adaptive_window needs sample_window_min <= sample_window_max",
+    ),
+    (
+        "mouse_trigger: Some((trigger: Left, direction: Down, sensitivity: 0))",
+        "mouse_trigger.sensitivity is 0, it must be above 0",
+    ),
+    (
+        "sequences: [(keys: [Keyboard(A)], window_ms: 0, action: Button(A))]",
+        "sequences[0].window_ms can't be 0",
+    ),
+    (
+        "gestures: [(direction: Up, velocity: 0, action: Button(A))]",
+        "gestures[0].velocity is 0, it must be above 0",
+    ),
+    (
+        "zones: [(stick: Left, region: Axis(axis: X, threshold: 2), action: Button(A))]",
+        "zones[0].region.threshold is 2, it must be -1 to 1",
+    ),
+    (
+        "zones: [(stick: Left, region: Sector(angle: 0, width: 400, magnitude: 0.5), \
+         action: Button(A))]",
+        "zones[0].region.width is 400, it must be 0 to 360",
+    ),
+    (
+        "radial: Some((stick: Left, keys: [Keyboard(A)], start_angle: 0, magnitude: 2))",
+        "radial.magnitude is 2, it must be 0 to 1",
+    ),
+    (
+        "absolute_area: (left: 100, right: 100)",
+        "absolute_area must have right above left and bottom above top",
+    ),
+    (
+        "binds: { Keyboard(W): AnalogLeft(0, 2) }",
+        "binds: W is bound to Left stick (0, 2), off the stick, x and y must be -1 to 1",
+    ),
+    (
+        "targets: [()], bind_options: { Keyboard(W): (target: 2) }",
+        "put it on target 2, but targets only has 1",
+    ),
+    (
+        "binds: { Keyboard(W): SwapAnalogs }, targets: [()], \
+         bind_options: { Keyboard(W): (target: 1) }",
+        "other controllers only take",
+    ),
+    (
+        "gyro: Some(())",
+        "gyro is set, but only dsu_server carries motion",
+    ),
+];
+
+fn validate(settings: &str) -> Result<(), String> {
+    let text = format!("({})", settings);
+    let config: Config = crate::loader::from_str(&text).map_err(|error| error.to_string())?;
+    config.validate().map_err(|error| error.to_string())
+}
+
+#[test]
+fn defaults_valid() {
+    assert_eq!(validate(""), Ok(()));
+}
+
+#[test]
+fn each_range_rule() {
+    for &(settings, problem) in BROKEN {
+        match validate(settings) {
+            Ok(()) => panic!("{} was let through", settings),
+            Err(error) => assert!(error.contains(problem), "{}: {}", settings, error),
+        }
+    }
+}
+
+#[test]
+fn all_problems_at_once() {
+    let settings = [
+        "sensitivity: 0",
+        "accel_multiplier: 0",
+        "smoothing: (tau_ms: 0)",
+        "dpad_mode: (deadzone: 1.5)",
+        "sample_window_y: Some(0)",
+        "gestures: [(direction: Up, velocity: 0, action: Button(A))]",
+        "binds: { Keyboard(W): AnalogLeft(0, 2) }",
+    ];
+
+    let error = validate(&settings.join(", ")).unwrap_err();
+    assert!(
+        error.starts_with("7 problems with the config:"),
+        "{}",
+        error
+    );
+
+    for setting in settings {
+        let (_, problem) = BROKEN
+            .iter()
+            .find(|(broken, _)| *broken == setting)
+            .unwrap();
+        assert!(
+            error.contains(problem),
+            "{} is missing from {}",
+            problem,
+            error
+        );
+    }
+}
+
+// Refused only when the feature they need wasn't built
+#[test]
+fn features_built_without() {
+    for (settings, feature, built) in [
+        (
+            "audio_cues: (enabled: true)",
+            "audio",
+            cfg!(feature = "audio"),
+        ),
+        ("dsu_server: Some(())", "dsu", cfg!(feature = "dsu")),
+        (
+            "trace_file: Some(\"trace.jsonl\")",
+            "trace",
+            cfg!(feature = "trace"),
+        ),
+    ] {
+        match (validate(settings), built) {
+            (result, true) => assert_eq!(result, Ok(())),
+            (result, false) => {
+                let without = format!("but kmxpad was built without the \"{}\" feature", feature);
+                assert!(result.unwrap_err().contains(&without));
+            }
+        }
+    }
+}
+
+// Read from a file as configs are, so its profiles and layers have the settings around them
+fn read_config(name: &str, text: &str) -> Config {
+    let name = format!("kmxpad-handler-{}-{}.ron", name, std::process::id());
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, text).unwrap();
+
+    crate::loader::load_layered(&path, &[]).unwrap().0
+}
+
+// Presses and lets go of each key in turn, then runs the handler until it gets to them all
+fn tap(handler: &mut EventHandler, tx: &mpsc::Sender<Event>, keys: &[ic::ScanCode]) {
+    for &code in keys {
+        tx.send(key(code, KeyState::Down)).unwrap();
+        tx.send(key(code, KeyState::Up)).unwrap();
+    }
+    tx.send(Event::Exit).unwrap();
+
+    handler.run().unwrap();
+}
+
+#[test]
+fn profiles_and_layers_change_settings() {
+    let text = r#"(
+        sensitivity: 10,
+        binds: {
+            MouseMove: AnalogRight(1, -1),
+            Keyboard(F1): Profile("pad"),
+            Keyboard(F2): Profile("default"),
+            Keyboard(F3): ToggleLayer("aim"),
+        },
+        profiles: {
+            "pad": (controller_type: DualShock4, mouse_mode: FlickStick, sensitivity: 5),
+        },
+        layers: { "aim": (sensitivity: 2) },
+    )"#;
+    let (tx, rx) = mpsc::channel();
+    let output = MockOutput::default();
+    let mut handler = handler(read_config("profiles", text), rx, output.clone());
+    let controller_types = || output.controller_types.lock().unwrap().clone();
+
+    tap(&mut handler, &tx, &[F1]);
+    assert_eq!(handler.profile, "pad");
+    assert_eq!(handler.config.sensitivity, 5.0);
+    assert_eq!(handler.config.mouse_mode, MouseMode::FlickStick);
+    assert_eq!(controller_types(), [ControllerType::DualShock4]);
+
+    // The layer's sensitivity on top of the rest of the profile
+    tap(&mut handler, &tx, &[F3]);
+    assert_eq!(handler.config.sensitivity, 2.0);
+    assert_eq!(handler.config.mouse_mode, MouseMode::FlickStick);
+    assert!(handler.config.binds.contains_key(&Bind::Keyboard(F2, None)));
+
+    tap(&mut handler, &tx, &[F3]);
+    assert_eq!(handler.config.sensitivity, 5.0);
+
+    tap(&mut handler, &tx, &[F2]);
+    assert_eq!(handler.profile, DEFAULT_PROFILE);
+    assert_eq!(handler.config.sensitivity, 10.0);
+    assert_eq!(handler.config.mouse_mode, MouseMode::default());
+    let types = [ControllerType::DualShock4, ControllerType::Xbox360];
+    assert_eq!(controller_types(), types);
+}
+
+#[test]
+fn profiles_keep_started_settings() {
+    let text = r#"(
+        mirror_targets: 1,
+        binds: { MouseMove: AnalogRight(1, -1), Keyboard(F1): Profile("split") },
+        profiles: { "split": (mirror_targets: 2) },
+    )"#;
+    let error = read_config("started", text).validate().err().unwrap();
+    let expected = "profile \"split\": mirror_targets can't be changed by a profile or layer";
+    assert_eq!(error.to_string(), expected);
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn trace_written() {
+    let path = std::env::temp_dir().join(format!("kmxpad-trace-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = Config {
+        trace_file: Some(path.clone()),
+        ..config(vec![(
+            Bind::Keyboard(A, None),
+            ControllerAction::Button(ControllerButton::A),
+        )])
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut handler = handler(config, rx, MockOutput::default());
+    tap(&mut handler, &tx, &[A]);
+    // The trace is finished once the handler is gone
+    drop(handler);
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let types: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let line: serde_json::Value = serde_json::from_str(line).unwrap();
+            line["type"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    let events = types.iter().filter(|t| *t == "event").count();
+    assert_eq!(types[0], "start");
+    assert!(events >= 2, "{:?}", types);
+}
@@ -1,79 +1,371 @@
+use super::sounds::{Arbitration, ClickConfig, Config, CueConfig, OvershootScaling};
+use super::sounds::{RumbleConfig, UndersteerTone, Waveform};
+
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Sample, SampleFormat, Stream, StreamConfig};
+use cpal::{BufferSize, Device, Sample, SampleFormat, SampleRate, Stream, StreamConfig};
+use cpal::{SupportedBufferSize, SupportedStreamConfig};
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+impl Waveform {
+    // From -1 to 1 at phase (0 to 1) of a cycle. Square and Sawtooth are turned down to sound
+    // about as loud as Sine at the same volume
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Square => match phase < 0.5 {
+                true => 0.5,
+                false => -0.5,
+            },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 0.6 * (2.0 * phase - 1.0),
+        }
+    }
+}
+
+// Where the stick went over the threshold, for stereo_pan_enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pan {
+    Left,
+    Centre,
+    Right,
+}
 
-use serde::{Deserialize, Serialize};
+impl Pan {
+    // From the axis pushed furthest
+    pub fn of(x: f64, y: f64) -> Self {
+        match x.abs() >= y.abs() {
+            true if x < 0.0 => Pan::Left,
+            true if x > 0.0 => Pan::Right,
+            _ => Pan::Centre,
+        }
+    }
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+    // -1 is all the way left and 1 all the way right, "mostly" leaves some in the other ear
+    fn position(self) -> f32 {
+        match self {
+            Pan::Left => -0.8,
+            Pan::Centre => 0.0,
+            Pan::Right => 0.8,
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
-#[serde(default, deny_unknown_fields)]
-pub struct Config {
+impl Config {
+    // Frequency and volume overshoot past the threshold
+    fn scaled(&self, overshoot: f32) -> (f32, f32) {
+        match self.scaling {
+            OvershootScaling::Fixed => (self.frequency, self.volume),
+            OvershootScaling::Pitch(cents) => {
+                let frequency = self.frequency * 2f32.powf(cents * overshoot / 1200.0);
+                (frequency, self.volume)
+            }
+            OvershootScaling::Volume(per_unit) => {
+                let volume = (self.volume + per_unit * overshoot).min(1.0);
+                (self.frequency, volume)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cue {
+    Pause,
+    Resume,
+    Profile(u32),
+}
+
+impl CueConfig {
+    // The notes of cue, None being a rest
+    fn notes(&self, cue: Cue) -> Option<Vec<Option<f32>>> {
+        match cue {
+            Cue::Pause => self.pause.map(|(a, b)| vec![Some(a), Some(b)]),
+            Cue::Resume => self.resume.map(|(a, b)| vec![Some(a), Some(b)]),
+            Cue::Profile(number) => {
+                let beep = [Some(self.profile?), None];
+                Some(beep.repeat(number as usize))
+            }
+        }
+    }
+}
+
+const CLICK_FREQUENCY: f32 = 2000.0;
+
+// Plays the cues sent to it one after another, each waits for the last to finish
+struct CuePlayer {
+    rx: mpsc::Receiver<Vec<Option<f32>>>,
+    notes: Vec<Option<f32>>,
+    note: usize,
+    // In samples
+    note_length: f32,
+    note_left: f32,
+    phase: f32,
     volume: f32,
-    frequency: f32,
+    sample_rate: f32,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            volume: 0.5,
-            frequency: 440.0,
+// Each note fades in and out over this long so it doesn't click
+const CUE_EDGE_MS: f32 = 3.0;
+
+impl CuePlayer {
+    fn next_sample(&mut self) -> f32 {
+        if self.note_left <= 0.0 {
+            if self.note + 1 < self.notes.len() {
+                self.note += 1;
+                self.note_left = self.note_length;
+            } else if let Ok(notes) = self.rx.try_recv() {
+                self.notes = notes;
+                self.note = 0;
+                self.note_left = self.note_length;
+            }
+        }
+
+        if self.note_left <= 0.0 || self.notes.is_empty() {
+            return 0.0;
         }
+
+        self.note_left -= 1.0;
+
+        let frequency = match self.notes[self.note] {
+            Some(frequency) => frequency,
+            None => return 0.0,
+        };
+
+        self.phase = (self.phase + frequency / self.sample_rate).fract();
+
+        let edge = CUE_EDGE_MS * self.sample_rate / 1000.0;
+        let played = self.note_length - self.note_left;
+        let envelope = (played.min(self.note_left) / edge).min(1.0);
+
+        self.volume * envelope * (self.phase * 2.0 * std::f32::consts::PI).sin()
     }
 }
 
+// How long the alert takes to fade in and out, switching it straight on and off clicks
+const ALERT_RAMP_MS: f32 = 5.0;
+// Roughly how long the alert takes to glide to a new overshoot
+const OVERSHOOT_GLIDE_MS: f32 = 30.0;
+
+// Written by the event handler and read by the audio thread, updates counts the notifications
+// so a repeat of the same motor speeds still restarts the decay
+#[derive(Default)]
+struct Rumble {
+    large: AtomicU8,
+    small: AtomicU8,
+    updates: AtomicU32,
+}
+
 pub struct ToneGenerator {
     _device: Device,
     _stream: Stream,
 
     active: Arc<AtomicBool>,
+    understeer: Arc<AtomicBool>,
+    // Pan as a u8
+    pan: Arc<AtomicU8>,
+    // The f32 bits of the overshoot, see OvershootScaling
+    overshoot: Arc<AtomicU32>,
+    rumble: Arc<Rumble>,
+    // Counts the clicks asked for
+    clicks: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+    click_config: ClickConfig,
+    last_click: Option<Instant>,
+    cue_tx: mpsc::Sender<Vec<Option<f32>>>,
+    cue_config: CueConfig,
 }
 
 impl ToneGenerator {
-    pub fn new(config: Config) -> Result<Self, anyhow::Error> {
+    pub fn new(
+        config: Config,
+        rumble_config: RumbleConfig,
+        click_config: ClickConfig,
+        cue_config: CueConfig,
+        understeer_tone: UndersteerTone,
+    ) -> Result<Self, anyhow::Error> {
         let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .expect("no default audio device");
-
-        let stream_config = device.default_output_config()?;
+        let device = match host.default_output_device() {
+            Some(device) => device,
+            None => anyhow::bail!("no default audio device"),
+        };
 
         match device.name() {
             Ok(name) => info!("using: {}", name),
             Err(error) => error!("unable to get audio device name: {}", error),
         }
 
-        debug!("{:?}", stream_config);
+        let (stream_config, sample_format) = Self::stream_config(&device, &config)?;
 
         let active = Arc::new(AtomicBool::new(false));
         let active_clone = active.clone();
+        let understeer = Arc::new(AtomicBool::new(false));
+        let understeer_clone = understeer.clone();
+        let pan = Arc::new(AtomicU8::new(Pan::Centre as u8));
+        let pan_clone = pan.clone();
+        let overshoot = Arc::new(AtomicU32::new(0));
+        let overshoot_clone = overshoot.clone();
+        let rumble = Arc::new(Rumble::default());
+        let rumble_clone = rumble.clone();
+        let clicks = Arc::new(AtomicU32::new(0));
+        let clicks_clone = clicks.clone();
+        let muted = Arc::new(AtomicBool::new(false));
+        let muted_clone = muted.clone();
 
-        let sample_rate = stream_config.sample_rate().0 as f32;
-        let mut sample_clock = 0f32;
+        let sample_rate = stream_config.sample_rate.0 as f32;
 
-        let Config { volume, frequency } = config;
-        info!("volume: {}, frequency: {}", volume, frequency);
+        info!("volume: {}, frequency: {}", config.volume, config.frequency);
+        debug!("{:?}", config);
 
-        let next_sample = move || {
-            sample_clock = (sample_clock + 1.0) % sample_rate;
+        // The alert pitch glides too, so it also keeps a phase
+        let mut alert_phase = 0f32;
+        let mut alert_overshoot = 0f32;
+        let mut alert_pan = 0f32;
+        let glide = 1.0 - (-1000.0 / (OVERSHOOT_GLIDE_MS * sample_rate)).exp();
+        debug!("{:?}", rumble_config);
 
-            if !active_clone.load(Ordering::Relaxed) {
-                return 0.0;
-            }
+        // The rumble pitch changes while playing, so it keeps a phase rather than a clock
+        let mut rumble_phase = 0f32;
+        let mut rumble_fade = 0f32;
+        let mut rumble_updates = 0;
+        let fade_step = match rumble_config.decay_ms {
+            0 => 0.0,
+            decay_ms => 1000.0 / (decay_ms as f32 * sample_rate),
+        };
+
+        let mut alert_gain = 0f32;
+        let mut understeer_gain = 0f32;
+        let mut understeer_phase = 0f32;
+        let ramp_step = 1000.0 / (ALERT_RAMP_MS * sample_rate);
 
-            volume * (sample_clock * frequency * 2.0 * std::f32::consts::PI / sample_rate).sin()
+        // Samples left of the click playing, it fades out over its length
+        let click_length = (click_config.duration_ms as f32 * sample_rate / 1000.0).max(1.0);
+        let mut click_left = 0f32;
+        let mut click_count = 0;
+
+        let (cue_tx, cue_rx) = mpsc::channel();
+        let mut cues = CuePlayer {
+            rx: cue_rx,
+            notes: Vec::new(),
+            note: 0,
+            note_length: (cue_config.note_ms as f32 * sample_rate / 1000.0).max(1.0),
+            note_left: 0.0,
+            phase: 0.0,
+            volume: cue_config.volume,
+            sample_rate,
         };
 
-        let stream = match stream_config.sample_format() {
-            SampleFormat::F32 => {
-                Self::init_stream::<f32, _>(&device, &stream_config.into(), next_sample)?
+        let mut mixed_sample = move || {
+            let count = clicks_clone.load(Ordering::Relaxed);
+            if count != click_count {
+                click_count = count;
+                click_left = click_length;
             }
-            SampleFormat::I16 => {
-                Self::init_stream::<i16, _>(&device, &stream_config.into(), next_sample)?
+
+            let click = match click_left > 0.0 {
+                true => {
+                    click_left -= 1.0;
+                    let phase = (click_length - click_left) * CLICK_FREQUENCY / sample_rate;
+                    let wave = (phase.fract() * 2.0 * std::f32::consts::PI).sin();
+                    click_config.volume * (click_left / click_length) * wave
+                }
+                false => 0.0,
+            };
+
+            understeer_gain = match understeer_clone.load(Ordering::Relaxed) {
+                true => (understeer_gain + ramp_step).min(1.0),
+                false => (understeer_gain - ramp_step).max(0.0),
+            };
+
+            let frequency = understeer_tone.frequency;
+            understeer_phase = (understeer_phase + frequency / sample_rate).fract();
+            let wave = understeer_tone.waveform.sample(understeer_phase);
+            let understeer = understeer_gain * understeer_tone.volume * wave;
+
+            // These go on top of whatever else plays
+            let overlay = click + cues.next_sample() + understeer;
+
+            let updates = rumble_clone.updates.load(Ordering::Relaxed);
+            if updates != rumble_updates {
+                rumble_updates = updates;
+                rumble_fade = 1.0;
+            } else {
+                rumble_fade = (rumble_fade - fade_step).max(0.0);
             }
-            SampleFormat::U16 => {
-                Self::init_stream::<u16, _>(&device, &stream_config.into(), next_sample)?
+
+            let large = rumble_clone.large.load(Ordering::Relaxed) as f32 / 255.0;
+            let small = rumble_clone.small.load(Ordering::Relaxed) as f32 / 255.0;
+            let rumble_frequency = rumble_config.frequency + rumble_config.pitch_range * small;
+            rumble_phase = (rumble_phase + rumble_frequency / sample_rate) % 1.0;
+
+            let rumble_volume = rumble_config.volume * large * rumble_fade;
+            let rumble = rumble_volume * (rumble_phase * 2.0 * std::f32::consts::PI).sin();
+
+            alert_gain = match active_clone.load(Ordering::Relaxed) {
+                true => (alert_gain + ramp_step).min(1.0),
+                false => (alert_gain - ramp_step).max(0.0),
+            };
+
+            let overshoot = f32::from_bits(overshoot_clone.load(Ordering::Relaxed));
+            let pan = match pan_clone.load(Ordering::Relaxed) {
+                0 => Pan::Left,
+                2 => Pan::Right,
+                _ => Pan::Centre,
+            };
+
+            // A fresh alert starts where it is rather than gliding from the last one
+            if alert_gain == 0.0 {
+                alert_overshoot = overshoot;
+                alert_pan = pan.position();
+
+                let value = (rumble + overlay).clamp(-1.0, 1.0);
+                return (value, value);
             }
+
+            alert_overshoot += (overshoot - alert_overshoot) * glide;
+            alert_pan += (pan.position() - alert_pan) * glide;
+
+            let (frequency, volume) = config.scaled(alert_overshoot);
+            alert_phase = (alert_phase + frequency / sample_rate).fract();
+
+            let alert = alert_gain * volume * config.waveform.sample(alert_phase);
+
+            // Equal power, so it sounds as loud wherever it is
+            let (left, right) = match config.stereo_pan_enabled {
+                true => {
+                    let angle = (alert_pan + 1.0) * std::f32::consts::FRAC_PI_4;
+                    (angle.cos(), angle.sin())
+                }
+                false => (1.0, 1.0),
+            };
+
+            // The rumble fades out as the alert fades in
+            let rumble = match rumble_config.arbitration {
+                Arbitration::AlertWins => (1.0 - alert_gain) * rumble,
+                Arbitration::Mix => rumble,
+            };
+
+            let mix = |alert: f32| (alert + rumble + overlay).clamp(-1.0, 1.0);
+            (mix(left * alert), mix(right * alert))
+        };
+
+        // Everything keeps going while muted, so unmuting carries on where it would have been
+        let next_sample = move || {
+            let sample = mixed_sample();
+            match muted_clone.load(Ordering::Relaxed) {
+                true => (0.0, 0.0),
+                false => sample,
+            }
+        };
+
+        // Shared to build the stream again if the device won't take buffer_frames
+        let next_sample = Arc::new(Mutex::new(next_sample));
+
+        let stream = match sample_format {
+            SampleFormat::F32 => Self::init_stream::<f32, _>(&device, stream_config, next_sample)?,
+            SampleFormat::I16 => Self::init_stream::<i16, _>(&device, stream_config, next_sample)?,
+            SampleFormat::U16 => Self::init_stream::<u16, _>(&device, stream_config, next_sample)?,
         };
 
         stream.play()?;
@@ -83,27 +375,137 @@ impl ToneGenerator {
             _stream: stream,
 
             active: active,
+            understeer,
+            pan,
+            overshoot,
+            rumble,
+            clicks,
+            muted,
+            click_config,
+            last_click: None,
+            cue_tx,
+            cue_config,
         })
     }
 
+    // The device's default config with sample_rate and buffer_frames from config, each left at
+    // the default with a warning when the device can't do it
+    fn stream_config(
+        device: &Device,
+        config: &Config,
+    ) -> Result<(StreamConfig, SampleFormat), anyhow::Error> {
+        let default = device.default_output_config()?;
+        debug!("{:?}", default);
+
+        let supported = match config.sample_rate {
+            Some(rate) => Self::with_sample_rate(device, &default, SampleRate(rate))?,
+            None => default,
+        };
+
+        let mut stream_config = supported.config();
+
+        if let Some(frames) = config.buffer_frames {
+            match *supported.buffer_size() {
+                SupportedBufferSize::Range { min, max } if frames < min || frames > max => warn!(
+                    "audio device can't take buffer_frames: {}, only {} to {}",
+                    frames, min, max
+                ),
+                _ => stream_config.buffer_size = BufferSize::Fixed(frames),
+            }
+        }
+
+        Ok((stream_config, supported.sample_format()))
+    }
+
+    // default at rate, if the device has it with the same channels and sample format
+    fn with_sample_rate(
+        device: &Device,
+        default: &SupportedStreamConfig,
+        rate: SampleRate,
+    ) -> Result<SupportedStreamConfig, anyhow::Error> {
+        let range = device.supported_output_configs()?.find(|range| {
+            range.channels() == default.channels()
+                && range.sample_format() == default.sample_format()
+                && (range.min_sample_rate()..=range.max_sample_rate()).contains(&rate)
+        });
+
+        match range {
+            Some(range) => Ok(range.with_sample_rate(rate)),
+            None => {
+                let default_rate = default.sample_rate().0;
+                warn!(
+                    "audio device can't play at {} Hz, using {} Hz",
+                    rate.0, default_rate
+                );
+                Ok(default.clone())
+            }
+        }
+    }
+
+    // Builds the stream with config, and again with the device's buffer size if it won't take
+    // the one asked for
     fn init_stream<T, F>(
+        device: &Device,
+        mut config: StreamConfig,
+        next_sample: Arc<Mutex<F>>,
+    ) -> Result<Stream, anyhow::Error>
+    where
+        T: Sample,
+        F: FnMut() -> (f32, f32) + Send + 'static,
+    {
+        if let BufferSize::Fixed(frames) = config.buffer_size {
+            match Self::build_stream::<T, F>(device, &config, next_sample.clone()) {
+                Ok(stream) => return Ok(stream),
+                Err(error) => {
+                    warn!("audio device rejected buffer_frames: {}: {}", frames, error);
+                    config.buffer_size = BufferSize::Default;
+                }
+            }
+        }
+
+        Self::build_stream::<T, F>(device, &config, next_sample)
+    }
+
+    fn build_stream<T, F>(
         device: &Device,
         config: &StreamConfig,
-        mut next_sample: F,
+        next_sample: Arc<Mutex<F>>,
     ) -> Result<Stream, anyhow::Error>
     where
         T: Sample,
-        F: FnMut() -> f32 + Send + 'static,
+        F: FnMut() -> (f32, f32) + Send + 'static,
     {
         let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+
+        // The buffer size only shows in the callback
+        let mut logged = false;
 
         let err_fn = |err| error!("error in audio stream: {}", err);
         let write_data = move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            if !logged {
+                let frames = output.len() / channels;
+                info!(
+                    "audio: {} Hz, {} channels, {} frames buffered",
+                    sample_rate, channels, frames
+                );
+                logged = true;
+            }
+
+            let mut next_sample = next_sample.lock().unwrap();
+
+            // Anything past the first two channels, or a single one, gets the middle
             for frame in output.chunks_mut(channels) {
-                let value: T = cpal::Sample::from::<f32>(&next_sample());
+                let (left, right) = next_sample();
+
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    let value = match (channels, i) {
+                        (2.., 0) => left,
+                        (2.., 1) => right,
+                        _ => (left + right) / 2.0,
+                    };
 
-                for sample in frame.iter_mut() {
-                    *sample = value;
+                    *sample = cpal::Sample::from::<f32>(&value);
                 }
             }
         };
@@ -111,7 +513,51 @@ impl ToneGenerator {
         Ok(device.build_output_stream(config, write_data, err_fn)?)
     }
 
-    pub fn enable(&mut self, active: bool) {
+    // Sounds the understeer alert while active
+    pub fn understeer(&mut self, active: bool) {
+        self.understeer.store(active, Ordering::Relaxed);
+    }
+
+    // Plays cue after any still playing, unless the config leaves it out
+    pub fn cue(&mut self, cue: Cue) {
+        if let Some(notes) = self.cue_config.notes(cue) {
+            let _ = self.cue_tx.send(notes);
+        }
+    }
+
+    // Silences everything it plays, leaving the stream running
+    pub fn mute(&mut self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn enable(&mut self, active: bool, pan: Pan) {
         self.active.store(active, Ordering::Relaxed);
+        self.pan.store(pan as u8, Ordering::Relaxed);
+    }
+
+    // How far past the threshold the stick is, in stick deflections
+    pub fn overshoot(&mut self, overshoot: f32) {
+        self.overshoot.store(overshoot.to_bits(), Ordering::Relaxed);
+    }
+
+    // Motor speeds from a rumble notification, the large one sets the volume and the small one
+    // the pitch
+    pub fn rumble(&mut self, large: u8, small: u8) {
+        self.rumble.large.store(large, Ordering::Relaxed);
+        self.rumble.small.store(small, Ordering::Relaxed);
+        self.rumble.updates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Plays a click unless one started within min_interval_ms
+    pub fn click(&mut self) {
+        let interval = Duration::from_millis(self.click_config.min_interval_ms);
+        if let Some(last_click) = self.last_click {
+            if last_click.elapsed() < interval {
+                return;
+            }
+        }
+
+        self.last_click = Some(Instant::now());
+        self.clicks.fetch_add(1, Ordering::Relaxed);
     }
 }
@@ -0,0 +1,335 @@
+// A trace of a run for working out settings afterwards: every event, every report sent and the
+// mouse velocity each loop, one JSON object a line with t_us, the microseconds since the trace
+// started. The event loop only sends what happened to a thread which writes it out
+use super::output::ControllerOutput;
+use crate::types::{Event, Key, KeyState, MouseButton, WheelDirection};
+
+use serde_json::{json, Value};
+use vigem::XUSBReport;
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Written out at least this often so a crash loses little
+const FLUSH_PERIOD: Duration = Duration::from_secs(1);
+
+// Each with the microseconds since the trace started
+enum Record {
+    Event(u64, Traced),
+    Report(u64, usize, XUSBReport),
+    // The velocity the MouseMove stick was given (1 = full deflection) and how long the loop
+    // took
+    Frame(u64, (f64, f64), u64),
+}
+
+// An event as far as the trace needs it, the config a reload brings left behind
+enum Traced {
+    MouseMove(i32, i32, i32),
+    MouseMoveAbsolute(i32, i32),
+    MouseButton(MouseButton, KeyState),
+    MouseWheel(WheelDirection, bool),
+    Keyboard(Key, KeyState),
+    Rumble(u8, u8),
+    Reset,
+    Resume,
+    Exit,
+    Reload,
+    Foreground(String, String),
+}
+
+impl From<&Event> for Traced {
+    fn from(event: &Event) -> Self {
+        match event {
+            &Event::MouseMove(device, x, y) => Traced::MouseMove(device, x, y),
+            &Event::MouseMoveAbsolute(x, y) => Traced::MouseMoveAbsolute(x, y),
+            &Event::MouseButton(button, state) => Traced::MouseButton(button, state),
+            &Event::MouseWheel(direction, inverted) => Traced::MouseWheel(direction, inverted),
+            &Event::Keyboard(key, state) => Traced::Keyboard(key, state),
+            &Event::Rumble { large, small } => Traced::Rumble(large, small),
+            Event::Reset => Traced::Reset,
+            Event::Resume => Traced::Resume,
+            Event::Exit => Traced::Exit,
+            Event::Reload(_) => Traced::Reload,
+            Event::Foreground(window) => {
+                Traced::Foreground(window.title.clone(), window.exe.clone())
+            }
+        }
+    }
+}
+
+impl Record {
+    fn to_json(&self) -> Value {
+        match self {
+            Record::Event(t_us, event) => {
+                let mut line = match event {
+                    &Traced::MouseMove(device, x, y) => {
+                        json!({ "event": "MouseMove", "device": device, "x": x, "y": y })
+                    }
+                    &Traced::MouseMoveAbsolute(x, y) => {
+                        json!({ "event": "MouseMoveAbsolute", "x": x, "y": y })
+                    }
+                    Traced::MouseButton(button, state) => json!({
+                        "event": "MouseButton",
+                        "button": button.to_string(),
+                        "state": format!("{:?}", state),
+                    }),
+                    Traced::MouseWheel(direction, inverted) => json!({
+                        "event": "MouseWheel",
+                        "direction": direction.to_string(),
+                        "inverted": inverted,
+                    }),
+                    Traced::Keyboard(key, state) => json!({
+                        "event": "Keyboard",
+                        "key": key.to_string(),
+                        "state": format!("{:?}", state),
+                    }),
+                    Traced::Rumble(large, small) => {
+                        json!({ "event": "Rumble", "large": large, "small": small })
+                    }
+                    Traced::Reset => json!({ "event": "Reset" }),
+                    Traced::Resume => json!({ "event": "Resume" }),
+                    Traced::Exit => json!({ "event": "Exit" }),
+                    Traced::Reload => json!({ "event": "Reload" }),
+                    Traced::Foreground(title, exe) => {
+                        json!({ "event": "Foreground", "title": title, "exe": exe })
+                    }
+                };
+
+                line["type"] = json!("event");
+                line["t_us"] = json!(t_us);
+                line
+            }
+
+            Record::Report(t_us, index, report) => json!({
+                "type": "report",
+                "t_us": t_us,
+                "controller": index,
+                "lx": report.s_thumb_lx,
+                "ly": report.s_thumb_ly,
+                "rx": report.s_thumb_rx,
+                "ry": report.s_thumb_ry,
+                "lt": report.b_left_trigger,
+                "rt": report.b_right_trigger,
+                "buttons": report.w_buttons.bits(),
+            }),
+
+            Record::Frame(t_us, (vx, vy), loop_us) => json!({
+                "type": "frame",
+                "t_us": t_us,
+                "vx": vx,
+                "vy": vy,
+                "loop_us": loop_us,
+            }),
+        }
+    }
+}
+
+fn write_records(mut file: impl Write, rx: mpsc::Receiver<Record>) -> anyhow::Result<()> {
+    let mut last_flush = Instant::now();
+
+    loop {
+        match rx.recv_timeout(FLUSH_PERIOD) {
+            Ok(record) => writeln!(file, "{}", serde_json::to_string(&record.to_json())?)?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_flush.elapsed() >= FLUSH_PERIOD {
+            file.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+// Appends the trace to trace_file, runs traced one after another in the same file each starting
+// with a line of type start
+pub struct Tracer {
+    tx: Option<mpsc::Sender<Record>>,
+    thread: Option<JoinHandle<()>>,
+    started: Instant,
+}
+
+impl Tracer {
+    pub fn start(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut file = BufWriter::new(file);
+
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let start = json!({ "type": "start", "t_us": 0, "unix_time": unix_time });
+        writeln!(file, "{}", serde_json::to_string(&start)?)?;
+
+        let (tx, rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            if let Err(error) = write_records(file, rx) {
+                error!("tracing stopped: {}", error);
+            }
+        });
+
+        info!("tracing to \"{}\"", path.to_string_lossy());
+
+        Ok(Tracer {
+            tx: Some(tx),
+            thread: Some(thread),
+            started: Instant::now(),
+        })
+    }
+
+    // An output that traces the reports sent, alongside the real ones
+    pub fn reports(&self) -> TraceReports {
+        TraceReports {
+            tx: self.tx.clone(),
+            started: self.started,
+        }
+    }
+
+    pub fn event(&self, event: &Event) {
+        self.send(Record::Event(self.micros(), Traced::from(event)));
+    }
+
+    pub fn frame(&self, velocity: (f64, f64), loop_time: Duration) {
+        let loop_us = loop_time.as_micros() as u64;
+        self.send(Record::Frame(self.micros(), velocity, loop_us));
+    }
+
+    fn micros(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+
+    fn send(&self, record: Record) {
+        // Only gone if writing failed, which has already been logged
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(record);
+        }
+    }
+}
+
+// Finishes writing, once the reports output has been disconnected
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.tx = None;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct TraceReports {
+    tx: Option<mpsc::Sender<Record>>,
+    started: Instant,
+}
+
+impl ControllerOutput for TraceReports {
+    fn update(&mut self, index: usize, report: &XUSBReport) -> Result<(), anyhow::Error> {
+        let micros = self.started.elapsed().as_micros() as u64;
+
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(Record::Report(micros, index, *report));
+        }
+
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    // The writer finishes once nothing can send to it
+    fn disconnect(&mut self) {
+        self.tx = None;
+    }
+}
+
+// For --trace-summary, what a trace says about the run: how many events came in a second, how
+// long the event loop took and how far the first controller's sticks were pushed
+pub fn trace_summary(path: &Path) -> Result<(), anyhow::Error> {
+    let text = fs::read_to_string(path)?;
+
+    let mut runs = 0;
+    let mut duration_us = 0;
+    let mut run_end_us = 0;
+    let mut events = 0;
+    let mut loop_us = Vec::new();
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let line: Value = match serde_json::from_str(line) {
+            Ok(line) => line,
+            Err(error) => anyhow::bail!("line {}: {}", i + 1, error),
+        };
+
+        let t_us = line["t_us"].as_u64().unwrap_or(0);
+        let field = |name: &str| line[name].as_f64().unwrap_or(0.0);
+
+        match line["type"].as_str() {
+            Some("start") => {
+                runs += 1;
+                duration_us += run_end_us;
+                run_end_us = 0;
+            }
+
+            Some("event") => events += 1,
+            Some("frame") => loop_us.push(field("loop_us")),
+
+            Some("report") if line["controller"].as_u64() == Some(0) => {
+                left.push(field("lx").hypot(field("ly")) / i16::MAX as f64);
+                right.push(field("rx").hypot(field("ry")) / i16::MAX as f64);
+            }
+
+            _ => {}
+        }
+
+        run_end_us = run_end_us.max(t_us);
+    }
+
+    duration_us += run_end_us;
+    let seconds = duration_us as f64 / 1e6;
+
+    println!("{} run(s) over {:.3}s", runs, seconds);
+
+    match seconds > 0.0 {
+        true => println!("events: {}, {:.1}/sec", events, events as f64 / seconds),
+        false => println!("events: {}", events),
+    }
+
+    if !loop_us.is_empty() {
+        let average = loop_us.iter().sum::<f64>() / loop_us.len() as f64;
+        println!("loops: {}, average {:.1}us", loop_us.len(), average);
+    }
+
+    for (name, magnitudes) in [("left", &mut left), ("right", &mut right)] {
+        if magnitudes.is_empty() {
+            continue;
+        }
+
+        magnitudes.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            let i = ((magnitudes.len() - 1) as f64 * p / 100.0).round() as usize;
+            magnitudes[i].min(1.0)
+        };
+
+        println!(
+            "{} stick magnitude: p50 {:.3}, p90 {:.3}, p99 {:.3}, max {:.3}",
+            name,
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            percentile(100.0)
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use crate::shutdown;
+use crate::types::{Event, Window};
+
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::processthreadsapi::OpenProcess;
+use winapi::um::winbase::QueryFullProcessImageNameW;
+use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+use winapi::um::winuser::{GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId};
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// How often the window in front is looked at
+const POLL_PERIOD: Duration = Duration::from_millis(250);
+
+// Sends the window in front each time it changes, until shutting down
+pub fn watch(tx: mpsc::Sender<Event>) {
+    let mut last = None;
+
+    while !shutdown::requested() {
+        let window = foreground();
+
+        if window != last {
+            if let Some(window) = window.clone() {
+                if tx.send(Event::Foreground(window)).is_err() {
+                    return;
+                }
+            }
+
+            last = window;
+        }
+
+        thread::sleep(POLL_PERIOD);
+    }
+}
+
+fn foreground() -> Option<Window> {
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.is_null() {
+        return None;
+    }
+
+    let mut title = [0u16; 512];
+    let len = unsafe { GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32) };
+    let title = String::from_utf16_lossy(&title[..len.max(0) as usize]);
+
+    let mut process_id: DWORD = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, &mut process_id) };
+
+    Some(Window {
+        id: hwnd as usize,
+        exe: exe_name(process_id).unwrap_or_default(),
+        title,
+    })
+}
+
+// The file name of the process's executable, which needs no more access than any window's
+// owner gives, elevated processes aside
+fn exe_name(process_id: DWORD) -> Option<String> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, process_id) };
+    if process.is_null() {
+        return None;
+    }
+
+    let mut path = [0u16; 1024];
+    let mut len = path.len() as DWORD;
+    let read = unsafe { QueryFullProcessImageNameW(process, 0, path.as_mut_ptr(), &mut len) };
+    unsafe { CloseHandle(process) };
+
+    if read == 0 {
+        return None;
+    }
+
+    let path = String::from_utf16_lossy(&path[..len as usize]);
+    path.rsplit('\\').next().map(str::to_owned)
+}
@@ -0,0 +1,129 @@
+// Adds a bind to the event handler's binds in a config file, for --capture --append. RON has it
+// put in the binds map where it is and TOML has a [[event_handler.binds]] table added at the end
+use super::layers::Parser;
+
+use serde::de::DeserializeOwned;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+// bind and action are as written in RON, the file has to read as a T afterwards
+pub fn append_bind<T>(path: &Path, bind: &str, action: &str) -> Result<(), anyhow::Error>
+where
+    T: DeserializeOwned,
+{
+    let path_str = path.to_string_lossy();
+    let text = super::read(path)?;
+
+    let text = match path.extension().and_then(OsStr::to_str) {
+        Some("toml") => {
+            let (bind, action) = (toml::Value::from(bind), toml::Value::from(action));
+            let table = format!(
+                "[[event_handler.binds]]\nkey = {}\naction = {}\n",
+                bind, action
+            );
+            format!("{}\n\n{}", text.trim_end(), table)
+        }
+
+        Some("json") => anyhow::bail!("binds can only be added to RON and TOML configs"),
+
+        _ => match Parser::map_end(&text, &["event_handler", "binds"]) {
+            Ok((close, missing_comma)) => insert(&text, close, missing_comma, bind, action),
+            Err(error) => anyhow::bail!("\"{}\" has no binds to add to: {}", path_str, error),
+        },
+    };
+
+    // It may well be bound already
+    super::parse::<T>(path, &super::migrate::upgrade(path, text.clone())?)?;
+
+    fs::write(path, text)?;
+    Ok(())
+}
+
+// The entry put before the } at close, one level further in than the line that's on
+fn insert(
+    text: &str,
+    close: usize,
+    missing_comma: Option<usize>,
+    bind: &str,
+    action: &str,
+) -> String {
+    let line_start = text[..close].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..close];
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let entry = format!("{}    {}: {},\n", indent, bind, action);
+
+    let mut out = String::new();
+    let mut copied = 0;
+
+    if let Some(at) = missing_comma {
+        out += &text[..at];
+        out.push(',');
+        copied = at;
+    }
+
+    // A } on a line of its own has the entry go on the line before it
+    match line.trim().is_empty() {
+        true => {
+            out += &text[copied..line_start];
+            out += &entry;
+            out += &text[line_start..];
+        }
+
+        false => {
+            out += &text[copied..close];
+            out.push('\n');
+            out += &entry;
+            out += indent;
+            out += &text[close..];
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // text with a bind put in event_handler.binds
+    fn appended(text: &str) -> Result<String, String> {
+        let (close, missing_comma) = Parser::map_end(text, &["event_handler", "binds"])?;
+        let (bind, action) = ("Keyboard(E)", "Button(X)");
+        Ok(insert(text, close, missing_comma, bind, action))
+    }
+
+    #[test]
+    fn on_a_line_of_its_own() {
+        let text = "(
+    event_handler: (
+        binds: {
+            Keyboard(W): Button(A)
+        },
+    ),
+)
+";
+        let expected = "(
+    event_handler: (
+        binds: {
+            Keyboard(W): Button(A),
+            Keyboard(E): Button(X),
+        },
+    ),
+)
+";
+        assert_eq!(appended(text).unwrap(), expected);
+    }
+
+    #[test]
+    fn after_the_last_entry() {
+        let text = "(event_handler: (binds: {Keyboard(W): Button(A),}))";
+        let expected =
+            "(event_handler: (binds: {Keyboard(W): Button(A),\n    Keyboard(E): Button(X),\n}))";
+        assert_eq!(appended(text).unwrap(), expected);
+
+        let error = appended("(event_handler: ())").unwrap_err();
+        assert_eq!(error, "there is no event_handler.binds");
+    }
+}
@@ -0,0 +1,1290 @@
+// Configs that extend another, e.g. a game's with extends: "base.ron". The base is loaded first
+// and each file's settings go on top of the last's: structs are merged field by field and maps
+// (like binds) entry by entry, the later file winning, anything else is replaced whole
+use super::lenient::Unknowns;
+use super::overrides::{self, Override};
+
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Loads path on top of the configs it extends with overrides set in that, also giving every file
+// read, the base first
+pub fn load_layered<T>(
+    path: &Path,
+    overrides: &[Override],
+) -> Result<(T, Vec<PathBuf>), anyhow::Error>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let mut file = path.to_owned();
+    let mut files = Vec::new();
+    let mut trees = Vec::new();
+    // Whether any of them is read leniently, the merged config then has to be too
+    let mut lenient = false;
+
+    loop {
+        let text = super::migrate::upgrade(&file, super::read(&file)?)?;
+        lenient |= super::lenient::wanted(&file, &text);
+
+        // Each file on its own first, so a mistake is reported in the file it's in
+        let value: T = super::parse(&file, &text)?;
+        let mut tree = Tree::read(&file, &text)?;
+        let extends = tree.take_extends()?;
+
+        files.push(file.clone());
+        trees.push(tree);
+
+        // Relative to the file extending it
+        let parent = match extends {
+            Some(parent) => file.parent().unwrap_or_else(|| Path::new("")).join(parent),
+            None if files.len() == 1 && overrides.is_empty() => {
+                let mut tree = trees.pop().unwrap();
+                return match tree.resolve_overlays()? {
+                    true => Ok((tree.into_value(lenient)?, files)),
+                    false => Ok((value, files)),
+                };
+            }
+            None => break,
+        };
+
+        if let Some(i) = files.iter().position(|file| same_file(file, &parent)) {
+            let chain: Vec<String> = files[i..]
+                .iter()
+                .chain([&parent])
+                .map(|file| format!("\"{}\"", file.to_string_lossy()))
+                .collect();
+
+            anyhow::bail!(
+                "configs extend each other in a loop: {}",
+                chain.join(" extends ")
+            );
+        }
+
+        file = parent;
+    }
+
+    files.reverse();
+    trees.reverse();
+
+    let mut merger = Merger::default();
+    let mut layers = trees.into_iter().zip(&files);
+    let (mut merged, _) = layers.next().unwrap();
+
+    for (tree, file) in layers {
+        merger.file = file.to_string_lossy().into_owned();
+        merged = merger.tree(merged, tree)?;
+    }
+
+    for (field, file) in &merger.sources {
+        debug!("config: {} is from \"{}\"", field, file);
+    }
+
+    // Before the profiles and layers get the settings around them, so they get overrides too
+    overrides::apply::<T>(&mut merged, overrides)?;
+    merged.resolve_overlays()?;
+    Ok((merged.into_value(lenient)?, files))
+}
+
+// For the extends field, a path or an Option of one
+pub fn extends<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(ExtendsVisitor)
+}
+
+struct ExtendsVisitor;
+
+impl<'de> Visitor<'de> for ExtendsVisitor {
+    type Value = Option<PathBuf>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the path of a config to extend")
+    }
+
+    fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+        Ok(Some(PathBuf::from(path)))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        PathBuf::deserialize(deserializer).map(Some)
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// A whole file as far as merging needs
+pub(super) enum Tree {
+    // The extensions (#![enable(..)] lines) and the document. RON isn't read into a generic
+    // value as that loses the enum variants, so only structs and maps are taken apart
+    Ron(String, Node),
+    // TOML, where binds are lists of entries with a key
+    Value(toml::Value),
+    // JSON, like TOML but with null, which toml::Value has no room for
+    Json(serde_json::Value),
+}
+
+// Maps of settings that go on top of those around them the way a config goes on top of the one
+// it extends, e.g. a profile changing mouse_mode. Each profile also gets every layer on top of it
+pub(super) const OVERLAYS: [&str; 2] = ["profiles", "layers"];
+
+#[derive(Clone)]
+pub(super) enum Node {
+    // The name (which may be left out) and fields
+    Struct(String, Vec<(String, Node)>),
+    // Keys as written
+    Map(Vec<(String, Node)>),
+    // Anything else as written
+    Raw(String),
+}
+
+impl Tree {
+    pub(super) fn read(path: &Path, text: &str) -> Result<Tree, anyhow::Error> {
+        let path_str = path.to_string_lossy();
+
+        let tree = match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => toml::from_str(text).map(Tree::Value)?,
+            Some("json") => match serde_json::from_str(text) {
+                Ok(value) => Tree::Json(value),
+                Err(error) => anyhow::bail!("\"{}\" can't be merged: {}", path_str, error),
+            },
+
+            _ => match Parser::document(text) {
+                Ok((extensions, node)) => Tree::Ron(extensions, node),
+                Err(error) => anyhow::bail!("{}:{}", path_str, error),
+            },
+        };
+
+        Ok(tree)
+    }
+
+    // Removes the extends field, giving the path in it
+    fn take_extends(&mut self) -> Result<Option<PathBuf>, anyhow::Error> {
+        match self {
+            Tree::Ron(_, Node::Struct(_, fields)) => {
+                let i = match fields.iter().position(|(name, _)| name == "extends") {
+                    Some(i) => i,
+                    None => return Ok(None),
+                };
+
+                match fields.remove(i).1 {
+                    Node::Raw(text) => Ok(extends(&mut ron::Deserializer::from_str(&text)?)?),
+                    _ => anyhow::bail!("extends isn't a path"),
+                }
+            }
+
+            Tree::Value(toml::Value::Table(table)) => match table.remove("extends") {
+                Some(path) => Ok(extends(path)?),
+                None => Ok(None),
+            },
+
+            Tree::Json(serde_json::Value::Object(object)) => match object.remove("extends") {
+                Some(path) => Ok(extends(path)?),
+                None => Ok(None),
+            },
+
+            _ => Ok(None),
+        }
+    }
+
+    // Puts the settings around each profile and layer under its own, giving whether there were
+    // any. TOML is turned into JSON for it, which has room for everything TOML has
+    fn resolve_overlays(&mut self) -> Result<bool, anyhow::Error> {
+        match self {
+            Tree::Ron(_, node) => node.resolve_overlays(),
+            Tree::Json(value) => resolve_json(value),
+            Tree::Value(value) => {
+                let mut json = serde_json::to_value(&*value)?;
+                let resolved = resolve_json(&mut json)?;
+                if resolved {
+                    *self = Tree::Json(json);
+                }
+
+                Ok(resolved)
+            }
+        }
+    }
+
+    // Unknown settings were already warned about as each file was read
+    pub(super) fn into_value<T: DeserializeOwned>(self, lenient: bool) -> Result<T, anyhow::Error> {
+        let unknown = Unknowns::default();
+
+        match self {
+            Tree::Ron(mut text, node) => {
+                node.write(0, &mut text);
+
+                match super::from_str_with(&text, lenient.then_some(&unknown)) {
+                    Ok(value) => Ok(value),
+                    Err(error) => anyhow::bail!("the merged config doesn't read: {}", error),
+                }
+            }
+
+            Tree::Value(value) => {
+                let result = match lenient {
+                    true => super::lenient::deserialize(value, &unknown),
+                    false => T::deserialize(value),
+                };
+
+                match result {
+                    Ok(value) => Ok(value),
+                    Err(error) => anyhow::bail!("the merged config doesn't read: {}", error),
+                }
+            }
+
+            Tree::Json(value) => {
+                let result = match lenient {
+                    true => super::lenient::deserialize(value, &unknown),
+                    false => T::deserialize(value),
+                };
+
+                match result {
+                    Ok(value) => Ok(value),
+                    Err(error) => anyhow::bail!("the merged config doesn't read: {}", error),
+                }
+            }
+        }
+    }
+}
+
+impl Node {
+    pub(super) fn write(&self, indent: usize, out: &mut String) {
+        let (inner, outer) = ("    ".repeat(indent + 1), "    ".repeat(indent));
+
+        let (open, close, entries) = match self {
+            Node::Struct(name, fields) => (name.clone() + "(", ')', fields),
+            Node::Map(entries) => ("{".to_owned(), '}', entries),
+            Node::Raw(text) => {
+                *out += text;
+                return;
+            }
+        };
+
+        *out += &open;
+        out.push('\n');
+
+        for (key, value) in entries {
+            *out += &inner;
+            *out += key;
+            *out += ": ";
+            value.write(indent + 1, out);
+            *out += ",\n";
+        }
+
+        *out += &outer;
+        out.push(close);
+    }
+
+    fn resolve_overlays(&mut self) -> Result<bool, anyhow::Error> {
+        let (name, fields) = match self {
+            Node::Struct(name, fields) => (name, fields),
+            _ => return Ok(false),
+        };
+
+        if !fields
+            .iter()
+            .any(|(field, _)| OVERLAYS.contains(&field.as_str()))
+        {
+            let mut resolved = false;
+            for (_, field) in fields.iter_mut() {
+                resolved |= field.resolve_overlays()?;
+            }
+
+            return Ok(resolved);
+        }
+
+        let around: Vec<(String, Node)> = fields
+            .iter()
+            .filter(|(field, _)| !OVERLAYS.contains(&field.as_str()))
+            .cloned()
+            .collect();
+        let around = Node::Struct(name.clone(), around);
+        let layers = match fields.iter().find(|(field, _)| field == "layers") {
+            Some((_, Node::Map(layers))) => layers.clone(),
+            _ => Vec::new(),
+        };
+
+        let on_top = |base: &Node, over: &Node| {
+            let mut node = base.clone();
+            Merger::default().node(&mut node, over.clone(), "");
+            node
+        };
+
+        for (field, value) in fields.iter_mut() {
+            let entries = match value {
+                Node::Map(entries) if OVERLAYS.contains(&field.as_str()) => entries,
+                _ => continue,
+            };
+
+            for (key, entry) in entries.iter_mut() {
+                // Written with its quotes
+                if let Node::Struct(_, inner) = entry {
+                    let overlay = inner.iter().find(|(f, _)| OVERLAYS.contains(&f.as_str()));
+                    if let Some((inner, _)) = overlay {
+                        anyhow::bail!(
+                            "{} {} can't have {} of its own",
+                            singular(field),
+                            key,
+                            inner
+                        );
+                    }
+                }
+
+                let mut resolved = on_top(&around, entry);
+                if field == "profiles" {
+                    let layers = layers
+                        .iter()
+                        .map(|(key, layer)| (key.clone(), on_top(&resolved, layer)))
+                        .collect();
+                    if let Node::Struct(_, inner) = &mut resolved {
+                        inner.push(("layers".to_owned(), Node::Map(layers)));
+                    }
+                }
+
+                *entry = resolved;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+// As Node::resolve_overlays, for JSON
+fn resolve_json(value: &mut serde_json::Value) -> Result<bool, anyhow::Error> {
+    use serde_json::Value;
+
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return Ok(false),
+    };
+
+    if !OVERLAYS.iter().any(|&field| object.contains_key(field)) {
+        let mut resolved = false;
+        for (_, field) in object.iter_mut() {
+            resolved |= resolve_json(field)?;
+        }
+
+        return Ok(resolved);
+    }
+
+    let mut around = object.clone();
+    for field in OVERLAYS {
+        around.remove(field);
+    }
+    let around = Value::Object(around);
+    let layers = match object.get("layers") {
+        Some(Value::Object(layers)) => layers.clone(),
+        _ => serde_json::Map::new(),
+    };
+
+    let on_top = |base: &Value, over: &Value| {
+        let mut value = base.clone();
+        Merger::default().json(&mut value, over.clone(), "");
+        value
+    };
+
+    for field in OVERLAYS {
+        let entries = match object.get_mut(field) {
+            Some(Value::Object(entries)) => entries,
+            _ => continue,
+        };
+
+        for (key, entry) in entries.iter_mut() {
+            if let Some(inner) = OVERLAYS.iter().find(|&&f| entry.get(f).is_some()) {
+                anyhow::bail!(
+                    "{} \"{}\" can't have {} of its own",
+                    singular(field),
+                    key,
+                    inner
+                );
+            }
+
+            let mut resolved = on_top(&around, entry);
+            if field == "profiles" {
+                let layers = layers
+                    .iter()
+                    .map(|(key, layer)| (key.clone(), on_top(&resolved, layer)))
+                    .collect();
+                if let Value::Object(inner) = &mut resolved {
+                    inner.insert("layers".to_owned(), Value::Object(layers));
+                }
+            }
+
+            *entry = resolved;
+        }
+    }
+
+    Ok(true)
+}
+
+// profile for profiles
+fn singular(field: &str) -> &str {
+    field.strip_suffix('s').unwrap_or(field)
+}
+
+#[derive(Default)]
+struct Merger {
+    // The one going on top
+    file: String,
+    // The file each setting given in more than one ended up from, by its path in the config
+    sources: BTreeMap<String, String>,
+}
+
+impl Merger {
+    fn tree(&mut self, base: Tree, over: Tree) -> Result<Tree, anyhow::Error> {
+        match (base, over) {
+            (Tree::Ron(mut extensions, mut base), Tree::Ron(over_extensions, over)) => {
+                for line in over_extensions.lines() {
+                    if !extensions.lines().any(|existing| existing == line) {
+                        extensions += line;
+                        extensions.push('\n');
+                    }
+                }
+
+                self.node(&mut base, over, "");
+                Ok(Tree::Ron(extensions, base))
+            }
+
+            (Tree::Value(mut base), Tree::Value(over)) => {
+                self.value(&mut base, over, "");
+                Ok(Tree::Value(base))
+            }
+
+            (Tree::Json(mut base), Tree::Json(over)) => {
+                self.json(&mut base, over, "");
+                Ok(Tree::Json(base))
+            }
+
+            // Through JSON, which has room for everything TOML has
+            (Tree::Value(base), Tree::Json(over)) => {
+                let mut base = serde_json::to_value(base)?;
+                self.json(&mut base, over, "");
+                Ok(Tree::Json(base))
+            }
+
+            (Tree::Json(mut base), Tree::Value(over)) => {
+                self.json(&mut base, serde_json::to_value(over)?, "");
+                Ok(Tree::Json(base))
+            }
+
+            _ => anyhow::bail!(
+                "\"{}\" can't extend a config in another format, except TOML and JSON",
+                self.file
+            ),
+        }
+    }
+
+    fn overridden(&mut self, path: String) {
+        self.sources.insert(path, self.file.clone());
+    }
+
+    fn node(&mut self, base: &mut Node, over: Node, path: &str) {
+        match (base, over) {
+            // A struct variant going on top of another variant replaces it, its fields mean
+            // something else
+            (Node::Struct(name, fields), Node::Struct(over_name, over))
+                if name.is_empty() || over_name.is_empty() || *name == over_name =>
+            {
+                if name.is_empty() {
+                    *name = over_name;
+                }
+
+                for (name, value) in over {
+                    let path = join(path, &name);
+
+                    match fields.iter_mut().find(|(field, _)| *field == name) {
+                        Some((_, field)) => self.node(field, value, &path),
+                        None => fields.push((name, value)),
+                    }
+                }
+            }
+
+            (Node::Map(entries), Node::Map(over)) => {
+                for (key, value) in over {
+                    match entries
+                        .iter_mut()
+                        .find(|(existing, _)| same_key(existing, &key))
+                    {
+                        Some(entry) => {
+                            self.overridden(format!("{}[{}]", path, key));
+                            *entry = (key, value);
+                        }
+
+                        None => entries.push((key, value)),
+                    }
+                }
+            }
+
+            (base, over) => {
+                self.overridden(path.to_owned());
+                *base = over;
+            }
+        }
+    }
+
+    fn value(&mut self, base: &mut toml::Value, over: toml::Value, path: &str) {
+        use toml::Value;
+
+        match (base, over) {
+            (Value::Table(table), Value::Table(over)) => {
+                for (name, value) in over {
+                    let path = join(path, &name);
+
+                    match table.get_mut(&name) {
+                        Some(field) => self.value(field, value, &path),
+                        None => {
+                            table.insert(name, value);
+                        }
+                    }
+                }
+            }
+
+            // Bind entries, by their key
+            (Value::Array(entries), Value::Array(over))
+                if entries
+                    .iter()
+                    .chain(&over)
+                    .all(|entry| entry_key(entry).is_some()) =>
+            {
+                for entry in over {
+                    let key = entry_key(&entry);
+
+                    match entries
+                        .iter_mut()
+                        .find(|existing| entry_key(existing) == key)
+                    {
+                        Some(existing) => {
+                            self.overridden(format!("{}[{}]", path, key.unwrap_or_default()));
+                            *existing = entry;
+                        }
+
+                        None => entries.push(entry),
+                    }
+                }
+            }
+
+            (base, over) => {
+                self.overridden(path.to_owned());
+                *base = over;
+            }
+        }
+    }
+
+    // As value, for JSON
+    fn json(&mut self, base: &mut serde_json::Value, over: serde_json::Value, path: &str) {
+        use serde_json::Value;
+
+        match (base, over) {
+            (Value::Object(object), Value::Object(over)) => {
+                for (name, value) in over {
+                    let path = join(path, &name);
+
+                    match object.get_mut(&name) {
+                        Some(field) => self.json(field, value, &path),
+                        None => {
+                            object.insert(name, value);
+                        }
+                    }
+                }
+            }
+
+            (Value::Array(entries), Value::Array(over))
+                if entries
+                    .iter()
+                    .chain(&over)
+                    .all(|entry| json_entry_key(entry).is_some()) =>
+            {
+                for entry in over {
+                    let key = json_entry_key(&entry);
+
+                    match entries
+                        .iter_mut()
+                        .find(|existing| json_entry_key(existing) == key)
+                    {
+                        Some(existing) => {
+                            self.overridden(format!("{}[{}]", path, key.unwrap_or_default()));
+                            *existing = entry;
+                        }
+
+                        None => entries.push(entry),
+                    }
+                }
+            }
+
+            (base, over) => {
+                self.overridden(path.to_owned());
+                *base = over;
+            }
+        }
+    }
+}
+
+pub(super) fn join(path: &str, name: &str) -> String {
+    match path.is_empty() {
+        true => name.to_owned(),
+        false => format!("{}.{}", path, name),
+    }
+}
+
+// Spacing aside
+fn same_key(a: &str, b: &str) -> bool {
+    let a = a.chars().filter(|c| !c.is_whitespace());
+    let b = b.chars().filter(|c| !c.is_whitespace());
+    a.eq(b)
+}
+
+// The key (or bind) of a binds or bind_options entry, without spaces
+fn entry_key(entry: &toml::Value) -> Option<String> {
+    let key = entry.get("key").or_else(|| entry.get("bind"))?;
+
+    match key {
+        toml::Value::String(key) => Some(key.chars().filter(|c| !c.is_whitespace()).collect()),
+        key => Some(key.to_string()),
+    }
+}
+
+// As entry_key, for JSON
+fn json_entry_key(entry: &serde_json::Value) -> Option<String> {
+    let key = entry.get("key").or_else(|| entry.get("bind"))?;
+
+    match key {
+        serde_json::Value::String(key) => {
+            Some(key.chars().filter(|c| !c.is_whitespace()).collect())
+        }
+        key => Some(key.to_string()),
+    }
+}
+
+// Takes RON apart into structs, maps and what's in between as written. Only run on files that
+// have already read as a config, so it can be loose about the rest of the syntax
+pub(super) struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(super) fn document(text: &'a str) -> Result<(String, Node), String> {
+        let mut parser = Parser { text, pos: 0 };
+        let extensions = parser.extensions();
+        let node = parser.value()?;
+
+        parser.skip_space();
+        if !parser.rest().is_empty() {
+            return Err(parser.error("the end of the file"));
+        }
+
+        match node {
+            Node::Struct(..) => Ok((extensions, node)),
+            _ => Err("expected the config to be a struct".to_owned()),
+        }
+    }
+
+    // Where the } closing the map at path (struct fields down from the top) is, and where the
+    // last entry ends if it has no comma after it, for adding entries
+    pub(super) fn map_end(text: &'a str, path: &[&str]) -> Result<(usize, Option<usize>), String> {
+        let mut parser = Parser { text, pos: 0 };
+        parser.extensions();
+
+        for (i, name) in path.iter().enumerate() {
+            parser.skip_space();
+            parser.pos += parser.ident().len();
+            parser.expect('(')?;
+
+            loop {
+                parser.skip_space();
+                if parser.rest().starts_with(')') {
+                    return Err(format!("there is no {}", path[..=i].join(".")));
+                }
+
+                let field = parser.ident();
+                if field.is_empty() {
+                    return Err(parser.error("a field name"));
+                }
+
+                parser.pos += field.len();
+                parser.expect(':')?;
+                if field == *name {
+                    break;
+                }
+
+                parser.value()?;
+                parser.separator(')')?;
+            }
+        }
+
+        parser.expect('{')?;
+        let mut missing_comma = None;
+
+        loop {
+            parser.skip_space();
+            if parser.rest().starts_with('}') {
+                return Ok((parser.pos, missing_comma));
+            }
+
+            parser.raw()?;
+            parser.expect(':')?;
+            parser.value()?;
+
+            let end = parser.pos;
+            parser.skip_space();
+            missing_comma = match parser.rest().starts_with(',') {
+                true => None,
+                false => Some(end),
+            };
+
+            parser.separator('}')?;
+        }
+    }
+
+    // Just past the ( the top struct's fields start after
+    pub(super) fn fields_start(text: &'a str) -> Result<usize, String> {
+        let mut parser = Parser { text, pos: 0 };
+        parser.extensions();
+
+        parser.skip_space();
+        parser.pos += parser.ident().len();
+        parser.expect('(')?;
+
+        Ok(parser.pos)
+    }
+
+    // The #![enable(..)] lines at the top, skipping them
+    fn extensions(&mut self) -> String {
+        let mut extensions = String::new();
+
+        self.skip_space();
+        while self.rest().starts_with("#!") {
+            let end = self.rest().find(']').map_or(self.rest().len(), |i| i + 1);
+            extensions += &self.rest()[..end];
+            extensions.push('\n');
+
+            self.pos += end;
+            self.skip_space();
+        }
+
+        extensions
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, expected: &str) -> String {
+        let (line, col) = super::line_col(self.text, self.pos);
+        format!("{}:{}: expected {}", line, col, expected)
+    }
+
+    // Whitespace and comments
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            self.pos += if trimmed.starts_with("//") {
+                trimmed.find('\n').unwrap_or(trimmed.len())
+            } else if trimmed.starts_with("/*") {
+                trimmed.find("*/").map_or(trimmed.len(), |i| i + 2)
+            } else {
+                return;
+            };
+        }
+    }
+
+    // The identifier at pos, if there is one
+    fn ident(&self) -> &'a str {
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return "";
+        }
+
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_space();
+
+        match self.rest().starts_with(expected) {
+            true => {
+                self.pos += expected.len_utf8();
+                Ok(())
+            }
+
+            false => Err(self.error(&format!("`{}`", expected))),
+        }
+    }
+
+    // After a field or entry, a comma or the end of them
+    fn separator(&mut self, end: char) -> Result<(), String> {
+        self.skip_space();
+
+        if self.rest().starts_with(',') {
+            self.pos += 1;
+        } else if !self.rest().starts_with(end) {
+            return Err(self.error(&format!("`,` or `{}`", end)));
+        }
+
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Node, String> {
+        self.skip_space();
+        let start = self.pos;
+
+        let name = self.ident();
+        self.pos += name.len();
+        self.skip_space();
+
+        if self.rest().starts_with('(') && self.opens_struct() {
+            self.pos += 1;
+            return self.fields(name);
+        }
+
+        if name.is_empty() && self.rest().starts_with('{') {
+            self.pos += 1;
+            return self.entries();
+        }
+
+        self.pos = start;
+        self.raw().map(Node::Raw)
+    }
+
+    // Whether the bracket at pos starts a struct's fields rather than a tuple
+    fn opens_struct(&self) -> bool {
+        let mut parser = Parser {
+            text: self.text,
+            pos: self.pos + 1,
+        };
+
+        parser.skip_space();
+        let field = parser.ident();
+        parser.pos += field.len();
+        parser.skip_space();
+
+        match field.is_empty() {
+            true => parser.rest().starts_with(')'),
+            false => parser.rest().starts_with(':'),
+        }
+    }
+
+    fn fields(&mut self, name: &str) -> Result<Node, String> {
+        let mut fields = Vec::new();
+
+        loop {
+            self.skip_space();
+            if self.rest().starts_with(')') {
+                self.pos += 1;
+                return Ok(Node::Struct(name.to_owned(), fields));
+            }
+
+            let field = self.ident();
+            if field.is_empty() {
+                return Err(self.error("a field name"));
+            }
+
+            self.pos += field.len();
+            self.expect(':')?;
+            fields.push((field.to_owned(), self.value()?));
+            self.separator(')')?;
+        }
+    }
+
+    fn entries(&mut self) -> Result<Node, String> {
+        let mut entries = Vec::new();
+
+        loop {
+            self.skip_space();
+            if self.rest().starts_with('}') {
+                self.pos += 1;
+                return Ok(Node::Map(entries));
+            }
+
+            let key = self.raw()?;
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            self.separator('}')?;
+        }
+    }
+
+    // Up to the comma, colon or closing bracket after it, less any comments at the end
+    fn raw(&mut self) -> Result<String, String> {
+        self.skip_space();
+
+        let bytes = self.text.as_bytes();
+        let (start, mut end) = (self.pos, self.pos);
+        let mut i = self.pos;
+        let mut depth = 0;
+
+        while i < bytes.len() {
+            match bytes[i] {
+                b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                    i += self.text[i..].find('\n').unwrap_or(bytes.len() - i);
+                    continue;
+                }
+
+                b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                    i += self.text[i..].find("*/").map_or(bytes.len() - i, |j| j + 2);
+                    continue;
+                }
+
+                quote @ (b'"' | b'\'') => {
+                    i = quoted(bytes, i, quote);
+                    end = i;
+                    continue;
+                }
+
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' if depth == 0 => break,
+                b')' | b']' | b'}' => depth -= 1,
+                b',' | b':' if depth == 0 => break,
+
+                byte if byte.is_ascii_whitespace() => {
+                    i += 1;
+                    continue;
+                }
+
+                _ => {}
+            }
+
+            i += 1;
+            end = i;
+        }
+
+        if end == start {
+            return Err(self.error("a value"));
+        }
+
+        self.pos = end;
+        Ok(self.text[start..end].to_owned())
+    }
+}
+
+// Just past the end of the string or char starting at start
+fn quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            byte if byte == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+    #[serde(default, deny_unknown_fields)]
+    struct Config {
+        #[serde(deserialize_with = "extends")]
+        extends: Option<PathBuf>,
+        sensitivity: Option<f64>,
+        name: String,
+        binds: BTreeMap<String, Action>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Action {
+        A,
+        B,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+    #[serde(default, deny_unknown_fields)]
+    struct Overlaid {
+        #[serde(deserialize_with = "extends")]
+        extends: Option<PathBuf>,
+        sensitivity: f64,
+        name: String,
+        binds: BTreeMap<String, String>,
+        profiles: BTreeMap<String, Overlaid>,
+        layers: BTreeMap<String, Overlaid>,
+    }
+
+    fn overlaid(sensitivity: f64, name: &str, binds: &[(&str, &str)]) -> Overlaid {
+        Overlaid {
+            sensitivity,
+            name: name.to_owned(),
+            binds: binds
+                .iter()
+                .map(|&(bind, action)| (bind.to_owned(), action.to_owned()))
+                .collect(),
+            ..Overlaid::default()
+        }
+    }
+
+    // A directory of its own for each test, as they run at the same time
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kmxpad-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn json_with_null() {
+        let dir = dir("json-null");
+        let path = dir.join("config.json");
+        fs::write(
+            &path,
+            r#"{ "extends": null, "sensitivity": null, "name": "a" }"#,
+        )
+        .unwrap();
+
+        let (config, files) = load_layered::<Config>(&path, &[]).unwrap();
+        assert_eq!(config.sensitivity, None);
+        assert_eq!(config.name, "a");
+        assert_eq!(files, [path]);
+    }
+
+    #[test]
+    fn json_extending_with_null() {
+        let dir = dir("json-null-extends");
+        fs::write(
+            dir.join("base.json"),
+            r#"{ "sensitivity": 2.0, "name": "base" }"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("base.toml"),
+            "sensitivity = 2.0\nname = \"base\"\n",
+        )
+        .unwrap();
+
+        // null over a value clears it, as it would in the file on its own
+        for base in ["base.json", "base.toml"] {
+            let path = dir.join("game.json");
+            let text = format!(r#"{{ "extends": "{}", "sensitivity": null }}"#, base);
+            fs::write(&path, text).unwrap();
+
+            let (config, files) = load_layered::<Config>(&path, &[]).unwrap();
+            assert_eq!(config.sensitivity, None);
+            assert_eq!(config.name, "base");
+            assert_eq!(files.len(), 2);
+        }
+    }
+
+    #[test]
+    fn ron_child_overrides_base() {
+        let dir = dir("ron-override");
+        let base = dir.join("base.ron");
+        fs::write(&base, r#"(sensitivity: Some(2.0), name: "base")"#).unwrap();
+        let path = dir.join("game.ron");
+        fs::write(&path, r#"(extends: "base.ron", sensitivity: Some(3.0))"#).unwrap();
+
+        // What the child leaves out is kept from the base
+        let (config, files) = load_layered::<Config>(&path, &[]).unwrap();
+        assert_eq!(config.sensitivity, Some(3.0));
+        assert_eq!(config.name, "base");
+        assert_eq!(files, [base, path]);
+    }
+
+    #[test]
+    fn ron_child_bind_wins() {
+        let dir = dir("ron-binds");
+        fs::write(dir.join("base.ron"), r#"(binds: { "W": A, "S": B })"#).unwrap();
+        let path = dir.join("game.ron");
+        fs::write(&path, r#"(extends: "base.ron", binds: { "W": B, "D": A })"#).unwrap();
+
+        // Merged entry by entry, not replaced whole
+        let (config, _) = load_layered::<Config>(&path, &[]).unwrap();
+        let binds: Vec<_> = config
+            .binds
+            .iter()
+            .map(|(key, action)| (key.as_str(), action))
+            .collect();
+        assert_eq!(
+            binds,
+            [("D", &Action::A), ("S", &Action::B), ("W", &Action::B)]
+        );
+    }
+
+    #[test]
+    fn extends_relative_to_file() {
+        let dir = dir("relative");
+        let shared = dir.join("shared");
+        fs::create_dir_all(&shared).unwrap();
+        fs::write(dir.join("base.ron"), r#"(name: "wrong")"#).unwrap();
+        fs::write(shared.join("base.ron"), r#"(name: "shared")"#).unwrap();
+        fs::write(shared.join("mid.ron"), r#"(extends: "base.ron")"#).unwrap();
+        let path = dir.join("game.ron");
+        fs::write(&path, r#"(extends: "shared/mid.ron")"#).unwrap();
+
+        // mid.ron's base.ron is the one next to it, not the one next to game.ron
+        let (config, files) = load_layered::<Config>(&path, &[]).unwrap();
+        assert_eq!(config.name, "shared");
+        assert_eq!(
+            files,
+            [shared.join("base.ron"), shared.join("mid.ron"), path]
+        );
+    }
+
+    #[test]
+    fn extends_loop() {
+        let dir = dir("loop");
+        let (a, b) = (dir.join("a.ron"), dir.join("b.ron"));
+        fs::write(&a, r#"(extends: "b.ron")"#).unwrap();
+        fs::write(&b, r#"(extends: "a.ron")"#).unwrap();
+
+        let error = load_layered::<Config>(&a, &[]).unwrap_err();
+        let (a, b) = (a.to_string_lossy(), b.to_string_lossy());
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "configs extend each other in a loop: \"{}\" extends \"{}\" extends \"{}\"",
+                a, b, a
+            )
+        );
+    }
+
+    #[test]
+    fn overrides_set_what_files_leave_out() {
+        let dir = dir("overrides");
+        let overrides = [Override::parse("sensitivity=2").unwrap()];
+
+        for (name, text) in [
+            ("config.ron", "(name: \"a\")"),
+            ("config.toml", "name = \"a\"\n"),
+            ("config.json", r#"{ "name": "a" }"#),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+
+            let (config, _) = load_layered::<Config>(&path, &overrides).unwrap();
+            assert_eq!(config.sensitivity, Some(2.0), "{}", name);
+            assert_eq!(config.name, "a");
+        }
+    }
+
+    #[test]
+    fn overrides_reach_profiles() {
+        let dir = dir("overrides-profiles");
+        let path = dir.join("config.ron");
+        let text = r#"(profiles: { "same": (name: "same"), "own": (sensitivity: 4) })"#;
+        fs::write(&path, text).unwrap();
+
+        // Profiles that set their own keep it, whatever the value around them was
+        let overrides = [Override::parse("sensitivity=2").unwrap()];
+        let (config, _) = load_layered::<Overlaid>(&path, &overrides).unwrap();
+        assert_eq!(config.sensitivity, 2.0);
+        assert_eq!(config.profiles["same"].sensitivity, 2.0);
+        assert_eq!(config.profiles["own"].sensitivity, 4.0);
+    }
+
+    #[test]
+    fn other_variant_replaced() {
+        let text = "(mode: Absolute(x: 1, y: 2), size: Size(w: 1, h: 2))";
+        let (_, mut base) = Parser::document(text).unwrap();
+        let text = "(mode: Relative(dx: 3), size: (h: 4))";
+        let (_, over) = Parser::document(text).unwrap();
+
+        Merger::default().node(&mut base, over, "");
+        let mut out = String::new();
+        base.write(0, &mut out);
+
+        let text = "(mode: Relative(dx: 3), size: Size(w: 1, h: 4))";
+        let (_, expected) = Parser::document(text).unwrap();
+        let mut written = String::new();
+        expected.write(0, &mut written);
+        assert_eq!(out, written);
+    }
+
+    #[test]
+    fn overlays_on_top() {
+        let dir = dir("overlays");
+        let files = [
+            (
+                "config.ron",
+                r#"(
+                    sensitivity: 1,
+                    name: "base",
+                    binds: { "a": "x" },
+                    profiles: { "slow": (sensitivity: 0.5, binds: { "b": "y" }) },
+                    layers: { "aim": (name: "aim", binds: { "a": "z" }) },
+                )"#,
+            ),
+            (
+                "config.toml",
+                "sensitivity = 1.0\nname = \"base\"\nbinds = { a = \"x\" }\n\
+                 [profiles.slow]\nsensitivity = 0.5\nbinds = { b = \"y\" }\n\
+                 [layers.aim]\nname = \"aim\"\nbinds = { a = \"z\" }\n",
+            ),
+            (
+                "config.json",
+                r#"{
+                    "sensitivity": 1.0, "name": "base", "binds": { "a": "x" },
+                    "profiles": { "slow": { "sensitivity": 0.5, "binds": { "b": "y" } } },
+                    "layers": { "aim": { "name": "aim", "binds": { "a": "z" } } }
+                }"#,
+            ),
+        ];
+
+        let mut slow = overlaid(0.5, "base", &[("a", "x"), ("b", "y")]);
+        let slow_aim = overlaid(0.5, "aim", &[("a", "z"), ("b", "y")]);
+        slow.layers.insert("aim".to_owned(), slow_aim);
+
+        let mut expected = overlaid(1.0, "base", &[("a", "x")]);
+        expected.profiles.insert("slow".to_owned(), slow);
+        let aim = overlaid(1.0, "aim", &[("a", "z")]);
+        expected.layers.insert("aim".to_owned(), aim);
+
+        for (name, text) in files {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+
+            let (config, _) = load_layered::<Overlaid>(&path, &[]).unwrap();
+            assert_eq!(config, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn overlays_on_top_of_extended() {
+        let dir = dir("overlays-extends");
+        let text = r#"(name: "base", profiles: { "slow": (name: "slow") })"#;
+        fs::write(dir.join("base.ron"), text).unwrap();
+        let path = dir.join("game.ron");
+        fs::write(&path, r#"(extends: "base.ron", sensitivity: 3)"#).unwrap();
+
+        // The profile is read on top of everything the game's config ends up with
+        let (config, _) = load_layered::<Overlaid>(&path, &[]).unwrap();
+        assert_eq!(config.profiles["slow"], overlaid(3.0, "slow", &[]));
+    }
+
+    #[test]
+    fn overlays_not_nested() {
+        let dir = dir("overlays-nested");
+        let path = dir.join("config.ron");
+        let text = r#"(profiles: { "slow": (layers: { "aim": (name: "aim") }) })"#;
+        fs::write(&path, text).unwrap();
+
+        let error = load_layered::<Overlaid>(&path, &[])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, r#"profile "slow" can't have layers of its own"#);
+    }
+}
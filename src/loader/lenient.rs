@@ -0,0 +1,467 @@
+// Reading configs with settings this kmxpad doesn't know, from a newer version or a typo, with
+// --lenient or strict: false at the top of the file. deny_unknown_fields can't be turned off
+// once built in, so instead the deserializer is wrapped and every struct is only shown the
+// fields it has, the rest being skipped and noted so they can be warned about
+use super::layers::{join, Node, Tree};
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IgnoredAny, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+use serde::Deserialize;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// serde leaves aliases out of the fields a struct gives, these are the ones in the config
+const ALIASES: &[&str] = &["bind"];
+
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+// For --lenient, every config is read leniently whatever its strict says
+pub fn set_lenient(lenient: bool) {
+    LENIENT.store(lenient, Ordering::Relaxed);
+}
+
+// Whether the file at path, which is text, is read leniently
+pub(super) fn wanted(path: &Path, text: &str) -> bool {
+    if LENIENT.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    match Tree::read(path, text) {
+        Ok(Tree::Ron(_, Node::Struct(_, fields))) => fields.iter().any(|(name, value)| {
+            name == "strict" && matches!(value, Node::Raw(raw) if raw == "false")
+        }),
+
+        Ok(Tree::Value(toml::Value::Table(table))) => {
+            table.get("strict").and_then(toml::Value::as_bool) == Some(false)
+        }
+
+        Ok(Tree::Json(serde_json::Value::Object(object))) => {
+            object.get("strict").and_then(serde_json::Value::as_bool) == Some(false)
+        }
+
+        _ => false,
+    }
+}
+
+// A setting that was skipped
+pub(super) struct Unknown {
+    name: String,
+    // Of the struct it's in, empty at the top
+    path: String,
+    nearest: Option<String>,
+}
+
+impl fmt::Display for Unknown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let place = match self.path.is_empty() {
+            true => "the config",
+            false => &self.path,
+        };
+
+        write!(
+            f,
+            "ignoring `{}` in {}, it isn't a setting",
+            self.name, place
+        )?;
+
+        match &self.nearest {
+            Some(nearest) => write!(f, ", did you mean `{}`?", nearest),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(super) type Unknowns = RefCell<Vec<Unknown>>;
+
+// T from deserializer, leaving out the fields its structs don't have and adding them to unknown
+pub(super) fn deserialize<'de, T, D>(deserializer: D, unknown: &Unknowns) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let at = At {
+        path: String::new(),
+        unknown,
+    };
+
+    T::deserialize(Lenient {
+        inner: deserializer,
+        at,
+    })
+}
+
+// Where in the config a wrapper is
+#[derive(Clone)]
+struct At<'u> {
+    path: String,
+    unknown: &'u Unknowns,
+}
+
+impl<'u> At<'u> {
+    fn field(&self, name: &str) -> At<'u> {
+        At {
+            path: join(&self.path, name),
+            unknown: self.unknown,
+        }
+    }
+}
+
+struct Lenient<'u, D> {
+    inner: D,
+    at: At<'u>,
+}
+
+// The visitor wrapped in Wrap and handed on
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                $($arg: $ty,)*
+                visitor: V,
+            ) -> Result<V::Value, D::Error> {
+                let visitor = Wrap {
+                    inner: visitor,
+                    at: self.at,
+                    fields: None,
+                };
+
+                self.inner.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'u, D: Deserializer<'de>> Deserializer<'de> for Lenient<'u, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any(), deserialize_bool(), deserialize_i8(), deserialize_i16(),
+        deserialize_i32(), deserialize_i64(), deserialize_u8(), deserialize_u16(),
+        deserialize_u32(), deserialize_u64(), deserialize_f32(), deserialize_f64(),
+        deserialize_char(), deserialize_str(), deserialize_string(), deserialize_bytes(),
+        deserialize_byte_buf(), deserialize_option(), deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str), deserialize_seq(),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize), deserialize_map(),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(), deserialize_ignored_any(),
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, D::Error> {
+        let visitor = Wrap {
+            inner: visitor,
+            at: self.at,
+            fields: Some(fields),
+        };
+
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+// A visitor whose maps, sequences and so on are wrapped in turn, fields being set for a struct
+struct Wrap<'u, V> {
+    inner: V,
+    at: At<'u>,
+    fields: Option<&'static [&'static str]>,
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<E: de::Error>(self, value: $ty) -> Result<V::Value, E> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<'de, 'u, V: Visitor<'de>> Visitor<'de> for Wrap<'u, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool), visit_i8(i8), visit_i16(i16), visit_i32(i32), visit_i64(i64),
+        visit_u8(u8), visit_u16(u16), visit_u32(u32), visit_u64(u64), visit_f32(f32),
+        visit_f64(f64), visit_char(char), visit_str(&str), visit_borrowed_str(&'de str),
+        visit_string(String), visit_bytes(&[u8]), visit_borrowed_bytes(&'de [u8]),
+        visit_byte_buf(Vec<u8>),
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<V::Value, E> {
+        self.inner.visit_none()
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<V::Value, E> {
+        self.inner.visit_unit()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        self.inner.visit_some(Lenient {
+            inner: deserializer,
+            at: self.at,
+        })
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.inner.visit_newtype_struct(Lenient {
+            inner: deserializer,
+            at: self.at,
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_seq(Seq {
+            inner: seq,
+            at: self.at,
+            index: 0,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_map(Map {
+            inner: map,
+            at: self.at,
+            fields: self.fields,
+            key: None,
+        })
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_enum(Enum {
+            inner: data,
+            at: self.at,
+        })
+    }
+}
+
+struct Seed<'u, S> {
+    inner: S,
+    at: At<'u>,
+}
+
+impl<'de, 'u, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Seed<'u, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<S::Value, D::Error> {
+        self.inner.deserialize(Lenient {
+            inner: deserializer,
+            at: self.at,
+        })
+    }
+}
+
+struct Seq<'u, A> {
+    inner: A,
+    at: At<'u>,
+    index: usize,
+}
+
+impl<'de, 'u, A: SeqAccess<'de>> SeqAccess<'de> for Seq<'u, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let seed = Seed {
+            inner: seed,
+            at: At {
+                path: format!("{}[{}]", self.at.path, self.index),
+                unknown: self.at.unknown,
+            },
+        };
+
+        self.index += 1;
+        self.inner.next_element_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+// A map, or a struct when fields is set, which skips the keys that aren't in them
+struct Map<'u, A> {
+    inner: A,
+    at: At<'u>,
+    fields: Option<&'static [&'static str]>,
+    // The struct field whose value is next
+    key: Option<String>,
+}
+
+impl<'de, 'u, A: MapAccess<'de>> MapAccess<'de> for Map<'u, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let fields = match self.fields {
+            Some(fields) => fields,
+            None => {
+                let seed = Seed {
+                    inner: seed,
+                    at: self.at.clone(),
+                };
+
+                return self.inner.next_key_seed(seed);
+            }
+        };
+
+        loop {
+            let name = match self.inner.next_key_seed(FieldName)? {
+                Some(name) => name,
+                None => return Ok(None),
+            };
+
+            if fields.contains(&name.as_str()) || ALIASES.contains(&name.as_str()) {
+                self.key = Some(name.clone());
+                let name: de::value::StringDeserializer<A::Error> = name.into_deserializer();
+                return seed.deserialize(name).map(Some);
+            }
+
+            self.inner.next_value::<IgnoredAny>()?;
+
+            let expected: Vec<String> = fields.iter().map(|&field| field.to_owned()).collect();
+            let nearest = super::nearest(&name, &expected).map(str::to_owned);
+
+            self.at.unknown.borrow_mut().push(Unknown {
+                name,
+                path: self.at.path.clone(),
+                nearest,
+            });
+        }
+    }
+
+    fn next_value_seed<S>(&mut self, seed: S) -> Result<S::Value, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let at = match self.key.take() {
+            Some(key) => self.at.field(&key),
+            None => self.at.clone(),
+        };
+
+        self.inner.next_value_seed(Seed { inner: seed, at })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+// The name of a struct field as written
+struct FieldName;
+
+impl<'de> DeserializeSeed<'de> for FieldName {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldName {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a field name")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<String, E> {
+        Ok(name.to_owned())
+    }
+}
+
+struct Enum<'u, A> {
+    inner: A,
+    at: At<'u>,
+}
+
+impl<'de, 'u, A: EnumAccess<'de>> EnumAccess<'de> for Enum<'u, A> {
+    type Error = A::Error;
+    type Variant = Variant<'u, A::Variant>;
+
+    fn variant_seed<S>(self, seed: S) -> Result<(S::Value, Self::Variant), A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let (value, variant) = self.inner.variant_seed(seed)?;
+
+        let variant = Variant {
+            inner: variant,
+            at: self.at,
+        };
+
+        Ok((value, variant))
+    }
+}
+
+struct Variant<'u, A> {
+    inner: A,
+    at: At<'u>,
+}
+
+impl<'de, 'u, A: VariantAccess<'de>> VariantAccess<'de> for Variant<'u, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<S>(self, seed: S) -> Result<S::Value, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        self.inner.newtype_variant_seed(Seed {
+            inner: seed,
+            at: self.at,
+        })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        let visitor = Wrap {
+            inner: visitor,
+            at: self.at,
+            fields: None,
+        };
+
+        self.inner.tuple_variant(len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        let visitor = Wrap {
+            inner: visitor,
+            at: self.at,
+            fields: Some(fields),
+        };
+
+        self.inner.struct_variant(fields, visitor)
+    }
+}
@@ -0,0 +1,501 @@
+// Config versions. A file says which it's written for with version: 2 at the top, left out
+// meaning 1 as that's what configs from before the field are. Older files are upgraded a step at
+// a time as they're read, with a warning saying what changed, and --upgrade-config saves that.
+// Files for a newer kmxpad are refused rather than half read
+use super::layers::{Node, Parser, Tree, OVERLAYS};
+
+use serde::de::DeserializeOwned;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+// The version this build reads and writes
+pub const VERSION: u32 = 2;
+
+// Each upgrades a document of version i + 1 to i + 2, giving a line for each thing it changed.
+// Renaming or retyping a field means bumping VERSION and adding the step for it here
+type Step = fn(&mut Tree) -> Vec<String>;
+const STEPS: [Step; VERSION as usize - 1] = [timing];
+
+// 1 to 2: on_release_pulse_ms went into the timing block as pulse_duration_ms, in each profile
+// and layer as well
+fn timing(tree: &mut Tree) -> Vec<String> {
+    let mut changes = Vec::new();
+    for at in overlaid(tree, vec!["event_handler".to_owned()]) {
+        let at = at.iter().map(String::as_str);
+        let from: Vec<_> = at.clone().chain(["on_release_pulse_ms"]).collect();
+        let to: Vec<_> = at.chain(["timing", "pulse_duration_ms"]).collect();
+
+        if move_field(tree, &from, &to) {
+            changes.push(format!("{} is now {}", dotted(&from), dotted(&to)));
+        }
+    }
+
+    changes
+}
+
+// at and the path to each profile and layer under it, and under those in turn
+fn overlaid(tree: &Tree, at: Vec<String>) -> Vec<Vec<String>> {
+    let mut paths = Vec::new();
+    for field in OVERLAYS {
+        let mut overlays = at.clone();
+        overlays.push(field.to_owned());
+
+        for key in keys(tree, &overlays) {
+            let mut overlay = overlays.clone();
+            overlay.push(key);
+            paths.extend(overlaid(tree, overlay));
+        }
+    }
+
+    paths.insert(0, at);
+    paths
+}
+
+// A path for a message, RON's map keys without their quotes
+fn dotted(path: &[&str]) -> String {
+    let path: Vec<_> = path.iter().map(|name| name.trim_matches('"')).collect();
+    path.join(".")
+}
+
+// text as version VERSION, upgraded if it's older
+pub(super) fn upgrade(path: &Path, text: String) -> Result<String, anyhow::Error> {
+    // One that doesn't parse is left for parse to say where it's wrong
+    let mut tree = match Tree::read(path, &text) {
+        Ok(tree) => tree,
+        Err(_) => return Ok(text),
+    };
+
+    let from = version(path, &tree)?.unwrap_or(1);
+    if from == VERSION {
+        return Ok(text);
+    }
+
+    // Nothing to say for a file the steps leave as it was
+    let changes = migrate(&mut tree, from);
+    if changes.is_empty() {
+        return Ok(text);
+    }
+
+    let path_str = path.to_string_lossy();
+    warn!(
+        "\"{}\" is for config version {}, reading it as version {}:",
+        path_str, from, VERSION
+    );
+
+    for change in changes {
+        warn!("    {}", change);
+    }
+
+    warn!(
+        "--upgrade-config --config \"{}\" saves it upgraded",
+        path_str
+    );
+    write(tree)
+}
+
+// For --upgrade-config, writes path back as version VERSION with the old file kept beside it,
+// .bak added to its name. Comments are only kept when adding the version is all there is to do
+pub fn upgrade_file<T: DeserializeOwned>(path: &Path) -> Result<(), anyhow::Error> {
+    let path_str = path.to_string_lossy();
+    let text = super::read(path)?;
+    let mut tree = Tree::read(path, &text)?;
+
+    let from = version(path, &tree)?;
+    if from == Some(VERSION) {
+        info!("\"{}\" is already config version {}", path_str, VERSION);
+        return Ok(());
+    }
+
+    let changes = migrate(&mut tree, from.unwrap_or(1));
+    let upgraded = match (from, changes.is_empty()) {
+        (None, true) => stamp(path, &text)?,
+
+        _ => {
+            for change in changes {
+                info!("{}", change);
+            }
+
+            write(tree)?
+        }
+    };
+
+    // Before anything is written
+    super::parse::<T>(path, &upgraded)?;
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    fs::copy(path, &backup)?;
+    fs::write(path, upgraded)?;
+
+    info!(
+        "upgraded \"{}\" to config version {}, the old one is \"{}\"",
+        path_str,
+        VERSION,
+        backup.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+// The version the file gives, if it gives one
+fn version(path: &Path, tree: &Tree) -> Result<Option<u32>, anyhow::Error> {
+    let path_str = path.to_string_lossy();
+
+    let version = match tree {
+        Tree::Ron(_, Node::Struct(_, fields)) => {
+            match fields.iter().find(|(name, _)| name == "version") {
+                Some((_, Node::Raw(raw))) => raw.parse::<u32>().ok(),
+                Some(_) => None,
+                None => return Ok(None),
+            }
+        }
+
+        Tree::Value(toml::Value::Table(table)) => match table.get("version") {
+            Some(version) => version.as_integer().and_then(|v| u32::try_from(v).ok()),
+            None => return Ok(None),
+        },
+
+        Tree::Json(serde_json::Value::Object(object)) => match object.get("version") {
+            Some(version) => version.as_u64().and_then(|v| u32::try_from(v).ok()),
+            None => return Ok(None),
+        },
+
+        _ => return Ok(None),
+    };
+
+    match version {
+        Some(0) | None => anyhow::bail!("\"{}\": version is a whole number from 1", path_str),
+        Some(version) if version > VERSION => anyhow::bail!(
+            "\"{}\" is for config version {}, this kmxpad is too old for it and reads up to \
+             version {}",
+            path_str,
+            version,
+            VERSION
+        ),
+
+        Some(version) => Ok(Some(version)),
+    }
+}
+
+// Runs the steps from version from on, leaving it at VERSION, giving what they changed
+fn migrate(tree: &mut Tree, from: u32) -> Vec<String> {
+    let mut changes = Vec::new();
+    for step in &STEPS[from as usize - 1..] {
+        changes.extend(step(tree));
+    }
+
+    match tree {
+        Tree::Ron(_, Node::Struct(_, fields)) => {
+            let version = Node::Raw(VERSION.to_string());
+            match fields.iter_mut().find(|(name, _)| name == "version") {
+                Some((_, field)) => *field = version,
+                None => fields.insert(0, ("version".to_owned(), version)),
+            }
+        }
+
+        Tree::Value(toml::Value::Table(table)) => {
+            table.insert("version".to_owned(), toml::Value::from(VERSION));
+        }
+
+        Tree::Json(serde_json::Value::Object(object)) => {
+            object.insert("version".to_owned(), serde_json::Value::from(VERSION));
+        }
+
+        _ => {}
+    }
+
+    changes
+}
+
+// Moves the value at path from (field names and map keys from the top) to path to, making the
+// tables on the way that aren't there. One already at to wins. Gives whether there was one at from
+fn move_field(tree: &mut Tree, from: &[&str], to: &[&str]) -> bool {
+    match tree {
+        Tree::Ron(_, node) => match take_node(node, from) {
+            Some(value) => {
+                put_node(node, to, value);
+                true
+            }
+            None => false,
+        },
+
+        Tree::Value(value) => match take_toml(value, from) {
+            Some(moved) => {
+                put_toml(value, to, moved);
+                true
+            }
+            None => false,
+        },
+
+        Tree::Json(value) => match take_json(value, from) {
+            Some(moved) => {
+                put_json(value, to, moved);
+                true
+            }
+            None => false,
+        },
+    }
+}
+
+// The names or keys in the table at path (field names and map keys from the top)
+fn keys(tree: &Tree, path: &[String]) -> Vec<String> {
+    match tree {
+        Tree::Ron(_, node) => {
+            let table = path.iter().try_fold(node, |node, name| {
+                let (_, field) = fields(node)?.iter().find(|(field, _)| field == name)?;
+                Some(field)
+            });
+
+            match table.and_then(fields) {
+                Some(fields) => fields.iter().map(|(key, _)| key.clone()).collect(),
+                None => Vec::new(),
+            }
+        }
+
+        Tree::Value(value) => {
+            let table = path.iter().try_fold(value, |value, name| value.get(name));
+            match table.and_then(toml::Value::as_table) {
+                Some(table) => table.keys().cloned().collect(),
+                None => Vec::new(),
+            }
+        }
+
+        Tree::Json(value) => {
+            let object = path.iter().try_fold(value, |value, name| value.get(name));
+            match object.and_then(serde_json::Value::as_object) {
+                Some(object) => object.keys().cloned().collect(),
+                None => Vec::new(),
+            }
+        }
+    }
+}
+
+fn fields(node: &Node) -> Option<&Vec<(String, Node)>> {
+    match node {
+        Node::Struct(_, fields) | Node::Map(fields) => Some(fields),
+        Node::Raw(_) => None,
+    }
+}
+
+fn take_node(node: &mut Node, path: &[&str]) -> Option<Node> {
+    let fields = match node {
+        Node::Struct(_, fields) | Node::Map(fields) => fields,
+        _ => return None,
+    };
+
+    let i = fields.iter().position(|(name, _)| name == path[0])?;
+    match path.len() {
+        1 => Some(fields.remove(i).1),
+        _ => take_node(&mut fields[i].1, &path[1..]),
+    }
+}
+
+fn put_node(node: &mut Node, path: &[&str], value: Node) {
+    let fields = match node {
+        Node::Struct(_, fields) | Node::Map(fields) => fields,
+        _ => return,
+    };
+
+    let i = match fields.iter().position(|(name, _)| name == path[0]) {
+        Some(_) if path.len() == 1 => return,
+        Some(i) => i,
+        None if path.len() == 1 => return fields.push((path[0].to_owned(), value)),
+        None => {
+            fields.push((path[0].to_owned(), Node::Struct(String::new(), Vec::new())));
+            fields.len() - 1
+        }
+    };
+
+    put_node(&mut fields[i].1, &path[1..], value);
+}
+
+fn take_toml(value: &mut toml::Value, path: &[&str]) -> Option<toml::Value> {
+    let table = value.as_table_mut()?;
+    match path.len() {
+        1 => table.remove(path[0]),
+        _ => take_toml(table.get_mut(path[0])?, &path[1..]),
+    }
+}
+
+fn put_toml(value: &mut toml::Value, path: &[&str], moved: toml::Value) {
+    let table = match value.as_table_mut() {
+        Some(table) => table,
+        None => return,
+    };
+
+    let entry = table.entry(path[0]);
+    match path.len() {
+        1 => {
+            entry.or_insert(moved);
+        }
+        _ => {
+            let empty = toml::Value::Table(toml::value::Table::new());
+            put_toml(entry.or_insert(empty), &path[1..], moved);
+        }
+    }
+}
+
+fn take_json(value: &mut serde_json::Value, path: &[&str]) -> Option<serde_json::Value> {
+    let object = value.as_object_mut()?;
+    match path.len() {
+        1 => object.remove(path[0]),
+        _ => take_json(object.get_mut(path[0])?, &path[1..]),
+    }
+}
+
+fn put_json(value: &mut serde_json::Value, path: &[&str], moved: serde_json::Value) {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+
+    let entry = object.entry(path[0]);
+    match path.len() {
+        1 => {
+            entry.or_insert(moved);
+        }
+        _ => {
+            let empty = serde_json::Value::Object(serde_json::Map::new());
+            put_json(entry.or_insert(empty), &path[1..], moved);
+        }
+    }
+}
+
+// The tree back as text in the file's format
+fn write(tree: Tree) -> Result<String, anyhow::Error> {
+    match tree {
+        Tree::Ron(mut text, node) => {
+            node.write(0, &mut text);
+            Ok(text)
+        }
+
+        Tree::Value(value) => Ok(toml::to_string(&value)?),
+        Tree::Json(value) => Ok(serde_json::to_string_pretty(&value)?),
+    }
+}
+
+// text with the version added at the top and the rest as it was
+fn stamp(path: &Path, text: &str) -> Result<String, anyhow::Error> {
+    let (at, field) = match path.extension().and_then(OsStr::to_str) {
+        Some("toml") => return Ok(format!("version = {}\n\n{}", VERSION, text)),
+        Some("json") => match text.find('{') {
+            Some(i) => (i + 1, format!("\n    \"version\": {},", VERSION)),
+            None => anyhow::bail!("\"{}\" isn't an object", path.to_string_lossy()),
+        },
+
+        _ => match Parser::fields_start(text) {
+            Ok(at) => (at, format!("\n    version: {},", VERSION)),
+            Err(error) => anyhow::bail!("{}:{}", path.to_string_lossy(), error),
+        },
+    };
+
+    // JSON has no comma before an empty object's }
+    let field = match text[at..].trim_start().starts_with('}') {
+        true => field.trim_end_matches(',').to_owned() + "\n",
+        false => field,
+    };
+
+    Ok(format!("{}{}{}", &text[..at], field, &text[at..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgraded(name: &str, text: &str) -> String {
+        let text = upgrade(Path::new(name), text.to_owned()).unwrap();
+        super::super::parse::<crate::Config>(Path::new(name), &text).unwrap();
+        text
+    }
+
+    #[test]
+    fn version_1_upgraded() {
+        let text = upgraded(
+            "config.ron",
+            "(event_handler: (sensitivity: 3, on_release_pulse_ms: 80))",
+        );
+        assert!(text.contains("version: 2"), "{}", text);
+        assert!(!text.contains("on_release_pulse_ms"), "{}", text);
+        assert!(text.contains("pulse_duration_ms: 80"), "{}", text);
+
+        // Into a timing block that's already there, without replacing what it gives
+        let text = upgraded(
+            "config.ron",
+            "(version: 1, event_handler: (on_release_pulse_ms: 80, \
+             timing: (tap_threshold_ms: 150)))",
+        );
+        assert!(text.contains("tap_threshold_ms: 150"), "{}", text);
+        assert!(text.contains("pulse_duration_ms: 80"), "{}", text);
+
+        let text = upgraded(
+            "config.ron",
+            "(event_handler: (on_release_pulse_ms: 80, timing: (pulse_duration_ms: 20)))",
+        );
+        assert!(text.contains("pulse_duration_ms: 20"), "{}", text);
+        assert!(!text.contains("80"), "{}", text);
+
+        // Profiles and layers have it too
+        let text = upgraded(
+            "config.ron",
+            "(event_handler: (profiles: {\"slow\": (on_release_pulse_ms: 80)}, \
+             layers: {\"fast\": (sensitivity: 2, on_release_pulse_ms: 40)}))",
+        );
+        assert!(!text.contains("on_release_pulse_ms"), "{}", text);
+        assert!(text.contains("pulse_duration_ms: 80"), "{}", text);
+        assert!(text.contains("pulse_duration_ms: 40"), "{}", text);
+    }
+
+    #[test]
+    fn version_1_upgraded_toml_and_json() {
+        let text = upgraded("config.toml", "[event_handler]\non_release_pulse_ms = 80\n");
+        let value: toml::Value = toml::from_str(&text).unwrap();
+        assert_eq!(value["version"].as_integer(), Some(2));
+        let timing = &value["event_handler"]["timing"];
+        assert_eq!(timing["pulse_duration_ms"].as_integer(), Some(80));
+
+        let text = upgraded(
+            "config.toml",
+            "[event_handler.profiles.slow]\non_release_pulse_ms = 80\n",
+        );
+        let value: toml::Value = toml::from_str(&text).unwrap();
+        let timing = &value["event_handler"]["profiles"]["slow"]["timing"];
+        assert_eq!(timing["pulse_duration_ms"].as_integer(), Some(80));
+
+        let text = upgraded(
+            "config.json",
+            r#"{ "event_handler": { "on_release_pulse_ms": 80 } }"#,
+        );
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value.get("version").and_then(|v| v.as_u64()), Some(2));
+        let timing = value.get("event_handler").and_then(|h| h.get("timing"));
+        let pulse = timing.and_then(|timing| timing.get("pulse_duration_ms"));
+        assert_eq!(pulse.and_then(|pulse| pulse.as_u64()), Some(80));
+
+        let text = upgraded(
+            "config.json",
+            r#"{ "event_handler": { "layers": { "fast": { "on_release_pulse_ms": 40 } } } }"#,
+        );
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let layer = value.get("event_handler").and_then(|h| h.get("layers"));
+        let timing = layer
+            .and_then(|l| l.get("fast"))
+            .and_then(|f| f.get("timing"));
+        let pulse = timing.and_then(|timing| timing.get("pulse_duration_ms"));
+        assert_eq!(pulse.and_then(|pulse| pulse.as_u64()), Some(40));
+    }
+
+    #[test]
+    fn nothing_to_change_left_alone() {
+        let text = "(event_handler: (sensitivity: 3)) // a comment";
+        assert_eq!(upgraded("config.ron", text), text);
+    }
+
+    #[test]
+    fn newer_version_refused() {
+        let error = upgrade(Path::new("config.ron"), "(version: 3)".to_owned());
+        let error = error.err().unwrap().to_string();
+        assert!(error.contains("too old"), "{}", error);
+    }
+}
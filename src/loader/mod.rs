@@ -0,0 +1,565 @@
+// Reads config files, RON, TOML or JSON by the extension, turning errors into messages that say where
+// the mistake is and what might fix it
+mod append;
+mod layers;
+mod lenient;
+mod migrate;
+mod overrides;
+mod schema;
+
+pub use append::append_bind;
+pub use layers::{extends, load_layered};
+pub use lenient::set_lenient;
+pub use migrate::{upgrade_file, VERSION};
+pub use overrides::{apply_overrides, Override};
+pub use schema::json_schema;
+
+use lenient::Unknowns;
+
+use ron::de::Deserializer;
+use ron::error::ErrorCode;
+use serde::de::DeserializeOwned;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// How many letters off a name can be and still be suggested for a misspelt one
+const MAX_TYPO_EDITS: usize = 3;
+
+#[derive(Debug)]
+pub struct Error {
+    line: usize,
+    col: usize,
+    message: String,
+    // The binds (or bind_options) key of the entry it's in
+    bind: Option<String>,
+    hint: Option<String>,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)?;
+
+        if let Some(bind) = &self.bind {
+            write!(f, ", in the bind for {}", bind)?;
+        }
+
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn read(path: &Path) -> Result<String, anyhow::Error> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(error) => anyhow::bail!("\"{}\": {}", path.to_string_lossy(), error),
+    }
+}
+
+// text is what's in the file at path, in the format its extension says. When it's read leniently
+// the settings it has that the config doesn't are warned about rather than refused
+fn parse<T: DeserializeOwned>(path: &Path, text: &str) -> Result<T, anyhow::Error> {
+    let path_str = path.to_string_lossy();
+    let unknown = Unknowns::default();
+    let lenient = lenient::wanted(path, text).then_some(&unknown);
+
+    let result = match path.extension().and_then(OsStr::to_str) {
+        Some("toml") => from_toml(text, lenient),
+        Some("json") => from_json(text, lenient),
+        _ => from_str_with(text, lenient),
+    };
+
+    let value = match result {
+        Ok(value) => value,
+        Err(error) => anyhow::bail!("{}:{}", path_str, error),
+    };
+
+    for unknown in unknown.into_inner() {
+        warn!("\"{}\": {}", path_str, unknown);
+    }
+
+    Ok(value)
+}
+
+pub fn from_str<T: DeserializeOwned>(text: &str) -> Result<T, Error> {
+    from_str_with(text, None)
+}
+
+// Leaving out the settings the config doesn't have and adding them to unknown when it's given,
+// which from_toml and from_json do as well
+fn from_str_with<T: DeserializeOwned>(text: &str, unknown: Option<&Unknowns>) -> Result<T, Error> {
+    // Extensions only come at the top
+    let mut deserializer = match Deserializer::from_str(text) {
+        Ok(deserializer) => deserializer,
+        Err(error) => return Err(describe(text, 0, error.code)),
+    };
+
+    let result = match unknown {
+        Some(unknown) => lenient::deserialize(&mut deserializer, unknown),
+        None => T::deserialize(&mut deserializer),
+    };
+
+    // serde's own errors (unknown fields and variants) have no position, but the input left
+    // says how far it got
+    let result = result.and_then(|value| {
+        deserializer.end()?;
+        Ok(value)
+    });
+
+    result.map_err(|error| {
+        let offset = text.len() - deserializer.remainder().len();
+        describe(text, offset, error.code)
+    })
+}
+
+fn from_toml<T: DeserializeOwned>(text: &str, unknown: Option<&Unknowns>) -> Result<T, Error> {
+    let mut deserializer = toml::Deserializer::new(text);
+    let result = match unknown {
+        Some(unknown) => lenient::deserialize(&mut deserializer, unknown),
+        None => T::deserialize(&mut deserializer),
+    };
+
+    let result = result.and_then(|value| {
+        deserializer.end()?;
+        Ok(value)
+    });
+
+    result.map_err(|error| {
+        let (line, col) = error.line_col().unwrap_or_default();
+        let message = without_position(error.to_string());
+
+        Error {
+            line: line + 1,
+            col: col + 1,
+            bind: None,
+            hint: typo_hint(&message, &quoted_names(&message)),
+            message,
+        }
+    })
+}
+
+fn from_json<T: DeserializeOwned>(text: &str, unknown: Option<&Unknowns>) -> Result<T, Error> {
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    let result = match unknown {
+        Some(unknown) => lenient::deserialize(&mut deserializer, unknown),
+        None => T::deserialize(&mut deserializer),
+    };
+
+    let result = result.and_then(|value| {
+        deserializer.end()?;
+        Ok(value)
+    });
+
+    result.map_err(|error| {
+        let (line, col) = (error.line(), error.column());
+        let offset = offset(text, line, col);
+        let message = without_position(error.to_string());
+
+        let hint = match message.starts_with("expected `,`") {
+            true => comma_hint(text, offset),
+            false => typo_hint(&message, &quoted_names(&message)),
+        };
+
+        Error {
+            line,
+            col,
+            bind: json_bind_key(text, offset).map(str::to_owned),
+            hint,
+            message,
+        }
+    })
+}
+
+// TOML and JSON errors end with the position, which is given separately
+fn without_position(message: String) -> String {
+    match message.rfind(" at line ") {
+        Some(i) => message[..i].to_owned(),
+        None => message,
+    }
+}
+
+fn describe(text: &str, offset: usize, code: ErrorCode) -> Error {
+    let message = code.to_string();
+    let names = quoted_names(&message);
+
+    // Point at the start of the name the error is about rather than just past it
+    let offset = match names.first() {
+        Some(name) if text[..offset].ends_with(name.as_str()) => offset - name.len(),
+        _ => offset,
+    };
+
+    let (line, col) = line_col(text, offset);
+
+    Error {
+        line,
+        col,
+        bind: bind_key(text, offset).map(str::to_owned),
+        hint: hint(text, offset, &code, &names),
+        message,
+    }
+}
+
+// The first is the name it got, the rest the ones it expected
+fn quoted_names(message: &str) -> Vec<String> {
+    message
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(str::to_owned)
+        .collect()
+}
+
+// Both from 1, as editors count them
+fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+// The other way around, clamped to the text
+fn offset(text: &str, line: usize, col: usize) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+
+    text[line_start..]
+        .char_indices()
+        .nth(col.saturating_sub(1))
+        .map_or(text.len(), |(i, _)| line_start + i)
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find("//") {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+fn hint(text: &str, offset: usize, code: &ErrorCode, names: &[String]) -> Option<String> {
+    match code {
+        ErrorCode::Message(message) => typo_hint(message, names),
+
+        ErrorCode::ExpectedComma
+        | ErrorCode::ExpectedStructEnd
+        | ErrorCode::ExpectedMapEnd
+        | ErrorCode::ExpectedArrayEnd
+        | ErrorCode::ExpectedOptionEnd => comma_hint(text, offset),
+
+        _ => None,
+    }
+}
+
+// When what's at offset starts a new line, the one before probably wanted a comma
+fn comma_hint(text: &str, offset: usize) -> Option<String> {
+    let (line, _) = line_col(text, offset);
+    let previous = previous_line(text, offset)?;
+
+    match previous < line {
+        true => Some(format!(
+            "is there a comma missing at the end of line {}?",
+            previous
+        )),
+        false => None,
+    }
+}
+
+fn typo_hint(message: &str, names: &[String]) -> Option<String> {
+    if !message.contains("unknown field") && !message.contains("unknown variant") {
+        return None;
+    }
+
+    let (name, expected) = names.split_first()?;
+    let nearest = nearest(name, expected)?;
+    Some(format!("did you mean `{}`?", nearest))
+}
+
+// The line the last thing before offset is on, past blank lines and comments
+fn previous_line(text: &str, offset: usize) -> Option<usize> {
+    let lines: Vec<&str> = text[..offset].split('\n').collect();
+
+    lines
+        .iter()
+        .rposition(|line| !strip_comment(line).trim().is_empty())
+        .map(|i| i + 1)
+}
+
+// The expected name closest to name, if any is close enough to be a typo
+fn nearest<'a>(name: &str, expected: &'a [String]) -> Option<&'a str> {
+    let name = name.to_lowercase();
+
+    expected
+        .iter()
+        .map(|candidate| (edits(&name, &candidate.to_lowercase()), candidate))
+        .filter(|&(edits, _)| edits <= MAX_TYPO_EDITS.min(name.len() / 2 + 1))
+        .min_by_key(|&(edits, _)| edits)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+// Letters to insert, delete or change to get from a to b
+fn edits(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+// The key of the binds or bind_options entry offset is in, taken from the line it starts on
+fn bind_key(text: &str, offset: usize) -> Option<&str> {
+    let before = &text[..offset];
+    let start = before.rfind("binds:").max(before.rfind("bind_options:"))?;
+
+    // Closed again before offset
+    let mut depth = 0;
+    for line in before[start..].split('\n') {
+        for c in strip_comment(line).chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    if depth <= 0 {
+        return None;
+    }
+
+    // The whole of the line offset is on, the key may be after it
+    let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+
+    text[start..line_end]
+        .split('\n')
+        .rev()
+        .find_map(|line| entry_key(strip_comment(line)))
+}
+
+// What comes before the line's first colon outside of brackets, if it's an enum variant like
+// a bind is
+fn entry_key(line: &str) -> Option<&str> {
+    let mut depth = 0;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ':' if depth == 0 => {
+                let key = line[..i].trim();
+                return key.starts_with(char::is_uppercase).then_some(key);
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// The bind (or key) of the JSON binds or bind_options entry offset is in, as written. Only found
+// when it comes before offset in the entry
+fn json_bind_key(text: &str, offset: usize) -> Option<&str> {
+    let before = &text[..offset];
+    let start = before.rfind("\"bind\"").max(before.rfind("\"key\""))?;
+
+    // Closed again before offset
+    let depth: i32 = before[start..]
+        .chars()
+        .map(|c| match c {
+            '{' | '[' => 1,
+            '}' | ']' => -1,
+            _ => 0,
+        })
+        .sum();
+
+    if depth < 0 {
+        return None;
+    }
+
+    let value = text[start..].split_once(':')?.1.trim_start();
+    if let Some(string) = value.strip_prefix('"') {
+        return string.split('"').next();
+    }
+
+    // An object with the variant as its key, up to where it's closed
+    let mut depth = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            _ => {}
+        }
+
+        if depth == 0 {
+            return Some(&value[..=i]);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> Error {
+        from_str::<crate::Config>(text).err().unwrap()
+    }
+
+    #[test]
+    fn typos() {
+        assert_eq!(edits("sensitivty", "sensitivity"), 1);
+        assert_eq!(edits("", "abc"), 3);
+        assert_eq!(edits("kitten", "sitting"), 3);
+
+        let expected = ["Left".to_owned(), "Right".to_owned()];
+        assert_eq!(nearest("rigth", &expected), Some("Right"));
+        assert_eq!(nearest("Up", &expected), None);
+    }
+
+    #[test]
+    fn positions() {
+        let text = "ab\ncdé\nf";
+        assert_eq!(line_col(text, 0), (1, 1));
+        assert_eq!(line_col(text, 5), (2, 3));
+        assert_eq!(offset(text, 2, 3), 5);
+        assert_eq!(offset(text, 3, 1), text.len() - 1);
+        assert_eq!(offset(text, 9, 1), text.len());
+    }
+
+    #[test]
+    fn misspelt_field() {
+        let error = error(
+            r#"(
+            event_handler: (
+                sensitivty: 20.0,
+            ),
+        )"#,
+        );
+
+        assert_eq!((error.line, error.col), (3, 17));
+        assert!(error.message.starts_with("unknown field `sensitivty`"));
+        assert_eq!(error.bind, None);
+        assert_eq!(error.hint.as_deref(), Some("did you mean `sensitivity`?"));
+    }
+
+    #[test]
+    fn missing_comma() {
+        let error = error(
+            r#"(
+            event_handler: (
+                sensitivity: 20.0
+
+                // The comma goes on the line above
+                swap_analogs: true,
+            ),
+        )"#,
+        );
+
+        let expected =
+            "6:17: Expected end of struct\n  is there a comma missing at the end of line 3?";
+        assert_eq!(error.to_string(), expected);
+    }
+
+    #[test]
+    fn in_a_bind() {
+        let error = error(
+            r#"(
+            event_handler: (
+                binds: {
+                    MouseMove: AnalogRight(1.0, -1.0, None),
+                    Keyboard(W): Buton(A),
+                },
+            ),
+        )"#,
+        );
+
+        assert_eq!((error.line, error.col), (5, 34));
+        assert!(error.message.starts_with("unknown variant `Buton`"));
+        assert_eq!(error.bind.as_deref(), Some("Keyboard(W)"));
+        assert_eq!(error.hint.as_deref(), Some("did you mean `Button`?"));
+    }
+
+    #[test]
+    fn bind_given_twice() {
+        let error = error(
+            r#"(
+            event_handler: (
+                binds: {
+                    Keyboard(W): Button(A),
+                    Keyboard(W): Button(B),
+                },
+            ),
+        )"#,
+        );
+
+        assert_eq!(error.line, 5);
+        assert!(error.message.contains("W is given twice"));
+    }
+
+    #[test]
+    fn unknown_settings() {
+        let text = r#"(
+            event_handler: (
+                sensitivty: 20.0,
+                swap_analogs: true,
+            ),
+        )"#;
+
+        let unknown = Unknowns::default();
+        from_str_with::<crate::Config>(text, Some(&unknown)).unwrap();
+
+        let skipped: Vec<String> = unknown.borrow().iter().map(|u| u.to_string()).collect();
+        let expected = "ignoring `sensitivty` in event_handler, it isn't a setting, did you mean \
+            `sensitivity`?";
+        assert_eq!(skipped, [expected]);
+
+        assert!(from_str::<crate::Config>(text).is_err());
+    }
+
+    #[test]
+    fn toml_and_json() {
+        let toml = "[event_handler]\nsensitivty = 20.0\n";
+        let error = from_toml::<crate::Config>(toml, None).err().unwrap();
+        assert_eq!(error.hint.as_deref(), Some("did you mean `sensitivity`?"));
+
+        let json = r#"{ "event_handler": { "swap_analog": true } }"#;
+        let error = from_json::<crate::Config>(json, None).err().unwrap();
+        assert_eq!(error.hint.as_deref(), Some("did you mean `swap_analogs`?"));
+    }
+
+    #[test]
+    fn json_bind_keys() {
+        let json = r#"[{ "bind": "Keyboard(W)", "action": { "Buton": "A" } },
+            { "key": { "Mouse": "Left" }, "action": "Button(A)" }]"#;
+
+        let offset = json.find("Buton").unwrap();
+        assert_eq!(json_bind_key(json, offset), Some("Keyboard(W)"));
+        let offset = json.find("Button(A)").unwrap();
+        assert_eq!(json_bind_key(json, offset), Some(r#"{ "Mouse": "Left" }"#));
+
+        // Past the end of the entries
+        assert_eq!(json_bind_key(json, json.len()), None);
+    }
+}
@@ -0,0 +1,401 @@
+// Single settings changed without editing the config, from --set path=value or KMXPAD_ variables
+// in the environment. They go into the config as it's read, as if the file had set them, and
+// only numbers, bools and durations can be set, by their path like event_handler.sensitivity
+use super::layers::{join, Node, Parser, Tree};
+use crate::event_handler::duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::env;
+
+// What environment variables need to start with to be read as overrides
+const ENV_PREFIX: &str = "KMXPAD_";
+
+pub struct Override {
+    path: String,
+    value: String,
+    // Where it was given, for errors and the log
+    source: String,
+}
+
+impl Override {
+    // path=value, as given to --set
+    pub fn parse(arg: &str) -> Result<Override, anyhow::Error> {
+        let (path, value) = match arg.split_once('=') {
+            Some((path, value)) => (path.trim(), value.trim()),
+            None => anyhow::bail!("--set {} needs a value, like --set {}=1", arg, arg),
+        };
+
+        Ok(Override {
+            path: path.to_owned(),
+            value: value.to_owned(),
+            source: format!("--set {}", arg),
+        })
+    }
+
+    pub fn from_env() -> Vec<Override> {
+        Override::from_vars(env::vars())
+    }
+
+    // Variables like KMXPAD_SENSITIVITY=6.5, or KMXPAD_EVENT_HANDLER__SENSITIVITY with __ for the
+    // dots, out of all of vars
+    fn from_vars<I>(vars: I) -> Vec<Override>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut overrides: Vec<Override> = vars
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let path = name.strip_prefix(ENV_PREFIX)?;
+
+                Some(Override {
+                    path: path.to_lowercase().replace("__", "."),
+                    value: value.trim().to_owned(),
+                    source: name.clone(),
+                })
+            })
+            .collect();
+
+        // Not in whatever order the environment has them
+        overrides.sort_by(|a, b| a.source.cmp(&b.source));
+        overrides
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Bool,
+    Number,
+    Duration,
+}
+
+// config with overrides on top, for when there's no file to read them into
+pub fn apply_overrides<T>(config: T, overrides: &[Override]) -> Result<T, anyhow::Error>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let text = ron::to_string(&config)?;
+    let (extensions, root) = Parser::document(&text).map_err(anyhow::Error::msg)?;
+
+    let mut tree = Tree::Ron(extensions, root);
+    apply::<T>(&mut tree, overrides)?;
+    tree.into_value(false)
+}
+
+// Sets each of overrides in tree, a config as read from its files, logging what they set. What
+// can be set and what it holds comes from T's defaults, as a file needn't have the field at all
+pub(super) fn apply<T>(tree: &mut Tree, overrides: &[Override]) -> Result<(), anyhow::Error>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let defaults = ron::to_string(&T::default())?;
+
+    for o in overrides {
+        let (mut out, mut root) = Parser::document(&defaults).map_err(anyhow::Error::msg)?;
+
+        // Nearly everything is in event_handler, so it can be left out
+        let shorthand = format!("event_handler.{}", o.path);
+        let path = match find(&mut root, &o.path) {
+            Some(_) => o.path.as_str(),
+            None => shorthand.as_str(),
+        };
+
+        let raw = match find(&mut root, path) {
+            Some(raw) => raw,
+            None => {
+                let mut paths = Vec::new();
+                settable(&root, "", &mut paths);
+
+                anyhow::bail!(
+                    "{}: there is no {} to set, these can be (event_handler. can be left out): {}",
+                    o.source,
+                    o.path,
+                    paths.join(", ")
+                );
+            }
+        };
+
+        // A field that's None by default says nothing of its type, so the value has to
+        let optional = raw.starts_with("Some(") || raw == "None";
+        let kind = match raw.as_str() {
+            "None" => value_kind(&o.value),
+            raw => kind(raw),
+        };
+
+        let kind = match kind {
+            Some(kind) => kind,
+            None => anyhow::bail!("{}: only numbers, bools and durations can be set", o.source),
+        };
+
+        match kind {
+            Kind::Bool if o.value.parse::<bool>().is_err() => {
+                anyhow::bail!("{}: {} is true or false", o.source, path)
+            }
+
+            Kind::Number if o.value.parse::<f64>().is_err() => {
+                anyhow::bail!("{}: {} is a number", o.source, path)
+            }
+
+            // Milliseconds as a number, otherwise a string with the unit
+            Kind::Duration if o.value.parse::<f64>().is_err() => {
+                if let Err(error) = duration::parse(&o.value) {
+                    anyhow::bail!("{}: {}", o.source, error);
+                }
+            }
+
+            _ => {}
+        }
+
+        let value = match kind {
+            Kind::Duration if o.value.parse::<f64>().is_err() => format!("{:?}", o.value),
+            _ => o.value.clone(),
+        };
+
+        *raw = match optional {
+            true => format!("Some({})", value),
+            false => value,
+        };
+
+        // The defaults with just this set, to find out if it fits the field before it goes
+        // into the files' settings. RON writes 5.0 as 5, so which numbers are whole is only
+        // found out here
+        root.write(0, &mut out);
+        if let Err(error) = super::from_str::<T>(&out) {
+            match kind {
+                Kind::Number if o.value.parse::<i64>().is_err() => {
+                    anyhow::bail!("{}: {} is a whole number", o.source, path)
+                }
+
+                _ => anyhow::bail!("{}: {}", o.source, error.message),
+            }
+        }
+
+        set(tree, path, kind, optional, &o.value)
+            .map_err(|error| anyhow::anyhow!("{}: {}", o.source, error))?;
+
+        info!("config: {} = {} (from {})", path, o.value, o.source);
+    }
+
+    Ok(())
+}
+
+// The value of the field at the dotted path, if it's there
+fn find<'a>(root: &'a mut Node, path: &str) -> Option<&'a mut String> {
+    let mut node = root;
+
+    for name in path.split('.') {
+        node = match node {
+            Node::Struct(_, fields) => &mut fields.iter_mut().find(|(field, _)| field == name)?.1,
+            _ => return None,
+        };
+    }
+
+    match node {
+        Node::Raw(raw) => Some(raw),
+        _ => None,
+    }
+}
+
+// What a field holds by its value as RON writes it, Some of one included. None says nothing of
+// what it could be
+fn kind(raw: &str) -> Option<Kind> {
+    let raw = match raw.strip_prefix("Some(") {
+        Some(inner) => inner.strip_suffix(')')?,
+        None => raw,
+    };
+
+    if raw == "true" || raw == "false" {
+        Some(Kind::Bool)
+    } else if raw.parse::<f64>().is_ok() {
+        Some(Kind::Number)
+    } else if duration::parse(raw.strip_prefix('"')?.strip_suffix('"')?).is_ok() {
+        Some(Kind::Duration)
+    } else {
+        None
+    }
+}
+
+// What value is meant to be, for fields that are None by default
+fn value_kind(value: &str) -> Option<Kind> {
+    if value.parse::<bool>().is_ok() {
+        Some(Kind::Bool)
+    } else if value.parse::<f64>().is_ok() {
+        Some(Kind::Number)
+    } else if duration::parse(value).is_ok() {
+        Some(Kind::Duration)
+    } else {
+        None
+    }
+}
+
+// The paths of every field under node that can be set
+fn settable(node: &Node, path: &str, out: &mut Vec<String>) {
+    match node {
+        Node::Struct(_, fields) => {
+            for (name, field) in fields {
+                settable(field, &join(path, name), out);
+            }
+        }
+
+        Node::Raw(raw) if kind(raw).is_some() || raw == "None" => out.push(path.to_owned()),
+        _ => {}
+    }
+}
+
+// Sets the field at path in tree, adding it (and the structs it's in) where the files leave it
+// out
+fn set(tree: &mut Tree, path: &str, kind: Kind, optional: bool, value: &str) -> Result<(), String> {
+    let json = match kind {
+        Kind::Bool => serde_json::Value::Bool(value == "true"),
+        _ => match (value.parse::<i64>(), value.parse::<f64>()) {
+            (Ok(number), _) => number.into(),
+            (_, Ok(number)) => number.into(),
+            _ => value.into(),
+        },
+    };
+
+    let (parents, name) = match path.rsplit_once('.') {
+        Some((parents, name)) => (parents.split('.').collect(), name),
+        None => (Vec::new(), path),
+    };
+    let written_in = || format!("{} can't be set in the config as it's written", path);
+
+    match tree {
+        Tree::Ron(_, root) => {
+            let mut node = root;
+
+            for parent in parents {
+                let fields = match node {
+                    Node::Struct(_, fields) => fields,
+                    _ => return Err(written_in()),
+                };
+
+                node = match fields.iter().position(|(field, _)| field == parent) {
+                    Some(i) => &mut fields[i].1,
+                    None => {
+                        let empty = Node::Struct(String::new(), Vec::new());
+                        fields.push((parent.to_owned(), empty));
+                        &mut fields.last_mut().unwrap().1
+                    }
+                };
+            }
+
+            let fields = match node {
+                Node::Struct(_, fields) => fields,
+                _ => return Err(written_in()),
+            };
+
+            let raw = match optional {
+                true => Node::Raw(format!("Some({})", json)),
+                false => Node::Raw(json.to_string()),
+            };
+
+            match fields.iter_mut().find(|(field, _)| field == name) {
+                Some((_, field)) => *field = raw,
+                None => fields.push((name.to_owned(), raw)),
+            }
+        }
+
+        Tree::Value(root) => {
+            let mut node = root;
+
+            for parent in parents {
+                node = match node {
+                    toml::Value::Table(table) => table
+                        .entry(parent.to_owned())
+                        .or_insert_with(|| toml::Value::Table(Default::default())),
+                    _ => return Err(written_in()),
+                };
+            }
+
+            let value = toml::Value::try_from(&json).map_err(|error| error.to_string())?;
+            match node {
+                toml::Value::Table(table) => table.insert(name.to_owned(), value),
+                _ => return Err(written_in()),
+            };
+        }
+
+        Tree::Json(root) => {
+            let mut node = root;
+
+            for parent in parents {
+                node = match node {
+                    serde_json::Value::Object(object) => object
+                        .entry(parent)
+                        .or_insert_with(|| serde_json::Value::Object(Default::default())),
+                    _ => return Err(written_in()),
+                };
+            }
+
+            match node {
+                serde_json::Value::Object(object) => object.insert(name.to_owned(), json),
+                _ => return Err(written_in()),
+            };
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Config {
+        sensitivity: f64,
+        circularize: bool,
+        deadzone: Option<f64>,
+        steps: u32,
+    }
+
+    fn overrides(args: &[&str]) -> Vec<Override> {
+        args.iter()
+            .map(|arg| Override::parse(arg).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn from_vars() {
+        let vars = [
+            ("PATH", "C:\\Windows"),
+            ("KMXPAD_SENSITIVITY", " 2 "),
+            ("KMXPAD_EVENT_HANDLER__STEPS", "3"),
+        ];
+        let overrides = Override::from_vars(
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+
+        let read: Vec<(&str, &str)> = overrides
+            .iter()
+            .map(|o| (o.path.as_str(), o.value.as_str()))
+            .collect();
+        assert_eq!(read, [("event_handler.steps", "3"), ("sensitivity", "2")]);
+    }
+
+    #[test]
+    fn sets_by_kind() {
+        let args = ["sensitivity=2.5", "circularize=true", "deadzone=0.1"];
+        let config = apply_overrides(Config::default(), &overrides(&args)).unwrap();
+        assert_eq!(config.sensitivity, 2.5);
+        assert!(config.circularize);
+        assert_eq!(config.deadzone, Some(0.1));
+    }
+
+    #[test]
+    fn wrong_values() {
+        for (arg, message) in [
+            ("circularize=1", "is true or false"),
+            ("steps=1.5", "is a whole number"),
+            ("sensitivity=fast", "is a number"),
+            ("speed=1", "sensitivity, circularize, deadzone, steps"),
+        ] {
+            let error = apply_overrides(Config::default(), &overrides(&[arg])).unwrap_err();
+            assert!(error.to_string().contains(message), "{}: {}", arg, error);
+        }
+    }
+}
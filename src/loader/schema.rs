@@ -0,0 +1,888 @@
+// A JSON Schema of the config for --schema, for editors to check and complete JSON and TOML
+// configs with. It's made by reading the config's types with a deserializer that writes down
+// what each asks for instead of giving it anything real, so it can't go stale. Enums are read
+// over and over until every variant has been, and go in definitions as they can hold themselves
+use super::layers::{join, Node, Parser};
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, Visitor};
+use serde::de::{EnumAccess, Expected, IntoDeserializer, MapAccess, SeqAccess, VariantAccess};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// T's schema as JSON, with the defaults from T::default() in it
+pub fn json_schema<T>() -> Result<String, anyhow::Error>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let mut state = State::default();
+
+    let mut schema = loop {
+        state.explored = false;
+
+        let mut schema = Value::Null;
+        let tracer = Tracer {
+            state: &mut state,
+            out: &mut schema,
+            field: "",
+        };
+        if let Err(error) = T::deserialize(tracer) {
+            anyhow::bail!("could not read the config's types: {}", error);
+        }
+
+        let left = state
+            .enums
+            .keys()
+            .find(|name| state.incomplete(name, &mut BTreeSet::new()));
+        match (left, state.explored) {
+            (None, _) => break schema,
+            (Some(_), true) => continue,
+            (Some(name), false) => anyhow::bail!("could not reach every variant of {}", name),
+        }
+    };
+
+    // Every field the default config has has to be in the schema, which also checks the schema
+    // against the serialized config rather than only the types
+    let text = ron::to_string(&T::default())?;
+    let (_, root) = Parser::document(&text).map_err(anyhow::Error::msg)?;
+    defaults(&root, &mut schema, "")?;
+
+    let definitions: Map<String, Value> = state
+        .enums
+        .iter()
+        .map(|(name, e)| (name.to_string(), e.schema()))
+        .collect();
+
+    let object = schema.as_object_mut().unwrap();
+    object.insert(
+        "$schema".to_owned(),
+        json!("http://json-schema.org/draft-07/schema#"),
+    );
+    object.insert("title".to_owned(), json!("kmxpad config"));
+    object.insert("definitions".to_owned(), Value::Object(definitions));
+
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+#[derive(Default)]
+struct State {
+    enums: BTreeMap<&'static str, Enum>,
+    // The enums being read and the variant of each, innermost last
+    stack: Vec<(&'static str, usize)>,
+    // Whether a variant not read before was this time round
+    explored: bool,
+    // Where in the schema what's being read goes, and the structs being read with where each
+    // went, so one holding itself (like a config's profiles) can refer back to it. Pointers
+    // only go through structs, maps, lists and options, which is as far as any struct holds
+    // itself
+    pointer: Vec<String>,
+    structs: Vec<(&'static str, &'static [&'static str], String)>,
+}
+
+struct Enum {
+    variants: &'static [&'static str],
+    // What each variant holds once it's been read, null for nothing
+    payloads: Vec<Option<Value>>,
+    // The enums in what each variant holds
+    reaches: Vec<BTreeSet<&'static str>>,
+}
+
+impl State {
+    // Which variant of name to read: one not read yet, otherwise one leading to an enum with
+    // variants not read yet. Never one holding an enum already being read unless it has to, as
+    // that could go on forever
+    fn choose(&self, name: &str) -> usize {
+        let e = &self.enums[name];
+        if let Some(i) = e.payloads.iter().position(Option::is_none) {
+            return i;
+        }
+
+        let nested = |i: usize| {
+            let reaches = &e.reaches[i];
+            self.stack.iter().any(|(name, _)| reaches.contains(name))
+        };
+
+        let leads = |i: usize| {
+            let mut seen = BTreeSet::new();
+            e.reaches[i]
+                .iter()
+                .any(|inner| self.incomplete(inner, &mut seen))
+        };
+
+        let n = e.variants.len();
+        (0..n)
+            .find(|&i| !nested(i) && leads(i))
+            .or_else(|| (0..n).find(|&i| !nested(i)))
+            .unwrap_or(0)
+    }
+
+    // Whether name, or an enum in it, has variants not read yet
+    fn incomplete(&self, name: &'static str, seen: &mut BTreeSet<&'static str>) -> bool {
+        if !seen.insert(name) {
+            return false;
+        }
+
+        let e = &self.enums[name];
+        e.payloads.iter().any(Option::is_none)
+            || e.reaches
+                .iter()
+                .flatten()
+                .any(|inner| self.incomplete(inner, seen))
+    }
+
+    // For binds and actions written as RON in a string
+    fn ron_string(&self, schema: &Value) -> Value {
+        let name = schema
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|r| r.rsplit('/').next());
+        let variants = match name.and_then(|name| self.enums.get(name)) {
+            Some(e) => e.variants.join("|"),
+            None => return json!({"type": "string"}),
+        };
+
+        json!({
+            "type": "string",
+            "pattern": (format!("^\\s*({})\\b", variants)),
+            "description": "Written as in RON, like \"Keyboard(W)\" or \"Button(A)\""
+        })
+    }
+}
+
+impl Enum {
+    // Variants holding nothing are their name, the others an object with the name as its only
+    // key, as serde writes them in JSON and TOML
+    fn schema(&self) -> Value {
+        let mut units = Vec::new();
+        let mut alternatives = Vec::new();
+
+        for (variant, payload) in self.variants.iter().zip(&self.payloads) {
+            match payload {
+                Some(Value::Null) | None => units.push(json!(*variant)),
+                Some(payload) => alternatives.push(json!({
+                    "type": "object",
+                    "properties": {(*variant): payload},
+                    "required": [(*variant)],
+                    "additionalProperties": false
+                })),
+            }
+        }
+
+        if !units.is_empty() {
+            alternatives.insert(0, json!({"type": "string", "enum": units}));
+        }
+
+        match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => json!({ "oneOf": alternatives }),
+        }
+    }
+}
+
+// The fields read with deserialize_any take what their visitor expects, which is all there is
+// to tell them apart by. Anything not here is an error, so none can be left out unnoticed
+const DURATION: &str = "a number of milliseconds";
+const BIND_MAP: &str = "a map of binds";
+const EXTENDS: &str = "the path of a config";
+
+fn duration() -> Value {
+    json!({
+        "anyOf": [
+            {"type": "number", "minimum": 0, "description": "Milliseconds"},
+            {
+                "type": "string",
+                "pattern": "^\\s*[0-9.]+\\s*(s|ms|us|µs)\\s*$",
+                "description": "With a unit, like \"20ms\", \"1.5s\" or \"500us\""
+            }
+        ]
+    })
+}
+
+// Binds in JSON and TOML are a list of entries with the bind under key (or bind) and the
+// action or options under name
+fn bind_map(key: Value, name: &str, value: Value) -> Value {
+    json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {"key": (key.clone()), "bind": key, name: value},
+            "oneOf": [{"required": ["key"]}, {"required": ["bind"]}],
+            "required": [name],
+            "additionalProperties": false
+        }
+    })
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Error(message.to_string())
+    }
+}
+
+struct Tracer<'a> {
+    state: &'a mut State,
+    // Where the schema of what's read goes
+    out: &'a mut Value,
+    // The field it's in, for deserialize_any
+    field: &'static str,
+}
+
+macro_rules! scalars {
+    ($($method:ident => $visit:ident($($value:expr)?), $schema:tt;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                *self.out = json!($schema);
+                visitor.$visit($($value)?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for Tracer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let expecting = (&visitor as &dyn Expected).to_string();
+
+        if expecting.starts_with(DURATION) {
+            *self.out = duration();
+            visitor.visit_u64(0)
+        } else if expecting.starts_with(BIND_MAP) {
+            let (mut key, mut value) = (Value::Null, Value::Null);
+            let entry = Entry {
+                state: &mut *self.state,
+                field: self.field,
+                key: &mut key,
+                value: &mut value,
+            };
+            let map = visitor.visit_map(entry)?;
+
+            // Options are a struct, actions can be RON in a string like binds
+            let (name, value) = match self.field {
+                "bind_options" => ("options", value),
+                _ => (
+                    "action",
+                    json!({ "anyOf": [(self.state.ron_string(&value)), value] }),
+                ),
+            };
+
+            let key = json!({ "anyOf": [(self.state.ron_string(&key)), key] });
+            *self.out = bind_map(key, name, value);
+            Ok(map)
+        } else if expecting.starts_with(EXTENDS) {
+            *self.out = json!({"type": ["string", "null"], "description": "Relative to this file"});
+            visitor.visit_unit()
+        } else {
+            Err(Error(format!(
+                "no schema for {}, which takes {}",
+                self.field, expecting
+            )))
+        }
+    }
+
+    scalars! {
+        deserialize_bool => visit_bool(false), {"type": "boolean"};
+        deserialize_i8 => visit_i8(0), {"type": "integer"};
+        deserialize_i16 => visit_i16(0), {"type": "integer"};
+        deserialize_i32 => visit_i32(0), {"type": "integer"};
+        deserialize_i64 => visit_i64(0), {"type": "integer"};
+        deserialize_u8 => visit_u8(0), {"type": "integer", "minimum": 0};
+        deserialize_u16 => visit_u16(0), {"type": "integer", "minimum": 0};
+        deserialize_u32 => visit_u32(0), {"type": "integer", "minimum": 0};
+        deserialize_u64 => visit_u64(0), {"type": "integer", "minimum": 0};
+        deserialize_f32 => visit_f32(0.0), {"type": "number"};
+        deserialize_f64 => visit_f64(0.0), {"type": "number"};
+        deserialize_char => visit_char(' '), {"type": "string", "minLength": 1, "maxLength": 1};
+        deserialize_str => visit_str(""), {"type": "string"};
+        deserialize_string => visit_str(""), {"type": "string"};
+        deserialize_bytes => visit_bytes(&[]), {"type": "array", "items": {"type": "integer"}};
+        deserialize_byte_buf => visit_bytes(&[]), {"type": "array", "items": {"type": "integer"}};
+        deserialize_unit => visit_unit(), {"type": "null"};
+        deserialize_ignored_any => visit_unit(), {};
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut inner = Value::Null;
+        self.state.pointer.push("anyOf/0".to_owned());
+        let value = visitor.visit_some(Tracer {
+            state: &mut *self.state,
+            out: &mut inner,
+            field: self.field,
+        });
+        self.state.pointer.pop();
+        let value = value?;
+
+        *self.out = json!({ "anyOf": [inner, {"type": "null"}] });
+        Ok(value)
+    }
+
+    fn deserialize_unit_struct<V>(self, _: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut items = vec![Value::Null];
+        let value = visitor.visit_seq(Elements {
+            state: self.state,
+            field: self.field,
+            items: &mut items,
+            tuple: false,
+            i: 0,
+        })?;
+
+        *self.out = json!({"type": "array", "items": (items.remove(0))});
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        let mut items = vec![Value::Null; len];
+        let value = visitor.visit_seq(Elements {
+            state: self.state,
+            field: self.field,
+            items: &mut items,
+            tuple: true,
+            i: 0,
+        })?;
+
+        *self.out = json!({"type": "array", "items": items, "minItems": len, "maxItems": len});
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    // Only maps with string keys are in the config
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let (mut key, mut value) = (Value::Null, Value::Null);
+        let entry = Entry {
+            state: self.state,
+            field: self.field,
+            key: &mut key,
+            value: &mut value,
+        };
+        let map = visitor.visit_map(entry)?;
+
+        *self.out = json!({"type": "object", "additionalProperties": value});
+        Ok(map)
+    }
+
+    // Every struct in the config denies unknown fields and has a default for each, so one
+    // holding itself is read again with none
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        let outer = self
+            .state
+            .structs
+            .iter()
+            .find(|(outer, outer_fields, _)| (*outer, *outer_fields) == (name, fields))
+            .map(|(_, _, pointer)| pointer.clone());
+        if let Some(pointer) = outer {
+            *self.out = json!({ "$ref": (format!("#{}", pointer)) });
+            return visitor.visit_map(Fields {
+                state: self.state,
+                fields: &[],
+                properties: &mut Map::new(),
+                i: 0,
+            });
+        }
+
+        let pointer: String = self
+            .state
+            .pointer
+            .iter()
+            .map(|p| format!("/{}", p))
+            .collect();
+        self.state.structs.push((name, fields, pointer));
+
+        let mut properties = Map::new();
+        let value = visitor.visit_map(Fields {
+            state: &mut *self.state,
+            fields,
+            properties: &mut properties,
+            i: 0,
+        });
+        self.state.structs.pop();
+        let value = value?;
+
+        *self.out = json!({
+            "type": "object",
+            "properties": properties,
+            "additionalProperties": false
+        });
+
+        Ok(value)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        let state = self.state;
+
+        let e = state.enums.entry(name).or_insert_with(|| Enum {
+            variants,
+            payloads: vec![None; variants.len()],
+            reaches: vec![BTreeSet::new(); variants.len()],
+        });
+
+        if e.variants != variants {
+            return Err(Error(format!("there are two enums called {}", name)));
+        }
+
+        if let Some(&(outer, i)) = state.stack.last() {
+            state.enums.get_mut(outer).unwrap().reaches[i].insert(name);
+        }
+
+        let i = state.choose(name);
+        let e = state.enums.get_mut(name).unwrap();
+        if e.payloads[i].is_none() {
+            // Marked read before it is, so the same variant inside it isn't chosen again
+            e.payloads[i] = Some(Value::Null);
+            state.explored = true;
+        }
+
+        state.stack.push((name, i));
+        let value = visitor.visit_enum(Variant {
+            state: &mut *state,
+            field: self.field,
+            name,
+            i,
+        });
+        state.stack.pop();
+
+        *self.out = json!({ "$ref": (format!("#/definitions/{}", name)) });
+        value
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error(format!(
+            "{} asks for a name where none is given",
+            self.field
+        )))
+    }
+}
+
+// The fields of a struct, each read once
+struct Fields<'a> {
+    state: &'a mut State,
+    fields: &'static [&'static str],
+    properties: &'a mut Map<String, Value>,
+    i: usize,
+}
+
+impl<'de, 'a> MapAccess<'de> for Fields<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.fields.get(self.i) {
+            Some(&field) => seed.deserialize(field.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let field = self.fields[self.i];
+        self.i += 1;
+
+        let mut schema = Value::Null;
+        self.state.pointer.push(format!("properties/{}", field));
+        let tracer = Tracer {
+            state: &mut *self.state,
+            out: &mut schema,
+            field,
+        };
+        let value = seed.deserialize(tracer);
+        self.state.pointer.pop();
+        let value = value?;
+
+        self.properties.insert(field.to_owned(), schema);
+        Ok(value)
+    }
+}
+
+// A map with a single entry, for the schemas of its keys and values
+struct Entry<'a> {
+    state: &'a mut State,
+    field: &'static str,
+    key: &'a mut Value,
+    value: &'a mut Value,
+}
+
+impl<'de, 'a> MapAccess<'de> for Entry<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if !self.key.is_null() {
+            return Ok(None);
+        }
+
+        let tracer = Tracer {
+            state: &mut *self.state,
+            out: &mut *self.key,
+            field: self.field,
+        };
+        seed.deserialize(tracer).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        self.state.pointer.push("additionalProperties".to_owned());
+        let tracer = Tracer {
+            state: &mut *self.state,
+            out: &mut *self.value,
+            field: self.field,
+        };
+        let value = seed.deserialize(tracer);
+        self.state.pointer.pop();
+        value
+    }
+}
+
+// The elements of a list, the first of which stands for all of them, or of a tuple
+struct Elements<'a> {
+    state: &'a mut State,
+    field: &'static str,
+    items: &'a mut Vec<Value>,
+    tuple: bool,
+    i: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for Elements<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        let out = match self.items.get_mut(self.i) {
+            Some(out) => out,
+            None => return Ok(None),
+        };
+
+        // A list's one schema, or each of a tuple's
+        self.state.pointer.push(match self.tuple {
+            false => "items".to_owned(),
+            true => format!("items/{}", self.i),
+        });
+        self.i += 1;
+        let tracer = Tracer {
+            state: &mut *self.state,
+            out,
+            field: self.field,
+        };
+        let value = seed.deserialize(tracer).map(Some);
+        self.state.pointer.pop();
+        value
+    }
+}
+
+// The chosen variant of an enum, what it holds going in the enum's payloads
+struct Variant<'a> {
+    state: &'a mut State,
+    field: &'static str,
+    name: &'static str,
+    i: usize,
+}
+
+impl<'a> Variant<'a> {
+    fn tracer<'b>(&'b mut self, out: &'b mut Value) -> Tracer<'b> {
+        Tracer {
+            state: &mut *self.state,
+            out,
+            field: self.field,
+        }
+    }
+
+    fn holds(self, payload: Value) {
+        self.state.enums.get_mut(self.name).unwrap().payloads[self.i] = Some(payload);
+    }
+}
+
+impl<'de, 'a> EnumAccess<'de> for Variant<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), Error> {
+        let index = IntoDeserializer::<Error>::into_deserializer(self.i as u32);
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for Variant<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        self.holds(Value::Null);
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(mut self, seed: S) -> Result<S::Value, Error> {
+        let mut payload = Value::Null;
+        let value = seed.deserialize(self.tracer(&mut payload))?;
+
+        self.holds(payload);
+        Ok(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(mut self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        let mut payload = Value::Null;
+        let value = self.tracer(&mut payload).deserialize_tuple(len, visitor)?;
+
+        self.holds(payload);
+        Ok(value)
+    }
+
+    fn struct_variant<V>(
+        mut self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut payload = Value::Null;
+        let value = self
+            .tracer(&mut payload)
+            .deserialize_struct("", fields, visitor)?;
+
+        self.holds(payload);
+        Ok(value)
+    }
+}
+
+// Puts the default of each field in the schema, from the default config as RON. A field the
+// schema doesn't have is an error
+fn defaults(node: &Node, schema: &mut Value, path: &str) -> Result<(), anyhow::Error> {
+    match node {
+        Node::Struct(_, fields) => {
+            for (name, field) in fields {
+                let path = join(path, name);
+                let property = match schema.pointer_mut(&format!("/properties/{}", name)) {
+                    Some(property) => property,
+                    None => anyhow::bail!("the default config has {}, the schema doesn't", path),
+                };
+
+                defaults(field, property, &path)?;
+            }
+        }
+
+        Node::Raw(raw) => {
+            if let (Some(default), Some(schema)) = (default(raw), schema.as_object_mut()) {
+                schema.insert("default".to_owned(), default);
+            }
+        }
+
+        Node::Map(_) => {}
+    }
+
+    Ok(())
+}
+
+// A value as RON writes it as JSON, if it's a plain one
+fn default(raw: &str) -> Option<Value> {
+    if let Some(inner) = raw.strip_prefix("Some(") {
+        return default(inner.strip_suffix(')')?);
+    }
+
+    if raw == "None" {
+        Some(Value::Null)
+    } else if let Ok(b) = raw.parse::<bool>() {
+        Some(json!(b))
+    } else if let Ok(n) = raw.parse::<i64>() {
+        Some(json!(n))
+    } else if let Ok(n) = raw.parse::<f64>() {
+        Some(json!(n))
+    } else if raw.starts_with('"') {
+        ron::from_str::<String>(raw).ok().map(Value::from)
+    } else if raw.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        // A variant holding nothing
+        Some(json!(raw))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    use std::fs;
+
+    #[derive(Serialize, Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Small {
+        count: u32,
+        name: Option<String>,
+        kind: Kind,
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    enum Kind {
+        #[default]
+        Plain,
+        Scaled(f64),
+    }
+
+    // Written out but never read, as a field only half renamed would be
+    #[derive(Serialize, Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Drifted {
+        count: u32,
+        #[serde(skip_deserializing)]
+        old_count: u32,
+    }
+
+    // Holding itself, as a config's profiles do, inside another struct
+    #[derive(Serialize, Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Outer {
+        inner: Nested,
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Nested {
+        count: u32,
+        nested: BTreeMap<String, Nested>,
+    }
+
+    fn schema<T: Serialize + DeserializeOwned + Default>() -> Value {
+        serde_json::from_str(&json_schema::<T>().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn small() {
+        let schema = schema::<Small>();
+
+        assert_eq!(schema["properties"]["count"]["type"], json!("integer"));
+        assert_eq!(schema["properties"]["count"]["default"], json!(0));
+        assert_eq!(schema["properties"]["name"]["default"], Value::Null);
+        assert_eq!(
+            schema["properties"]["kind"],
+            json!({ "$ref": "#/definitions/Kind", "default": "Plain" })
+        );
+
+        let kind = &schema["definitions"]["Kind"]["oneOf"];
+        assert_eq!(kind[0], json!({ "type": "string", "enum": ["Plain"] }));
+        assert_eq!(kind[1]["properties"]["Scaled"], json!({ "type": "number" }));
+    }
+
+    #[test]
+    fn drifted() {
+        let error = json_schema::<Drifted>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the default config has old_count, the schema doesn't"
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let schema = schema::<Outer>();
+        let inner = &schema["properties"]["inner"]["properties"];
+        assert_eq!(inner["count"]["type"], json!("integer"));
+        let nested = &inner["nested"]["additionalProperties"];
+        assert_eq!(nested, &json!({ "$ref": "#/properties/inner" }));
+    }
+
+    // Each struct in the schema has exactly the fields the default config is written with
+    fn same_fields(node: &Node, schema: &Value, path: &str) {
+        if let Node::Struct(_, fields) = node {
+            let properties = schema["properties"].as_object();
+            let properties: BTreeSet<_> =
+                properties.into_iter().flatten().map(|(k, _)| k).collect();
+            let written: BTreeSet<_> = fields.iter().map(|(name, _)| name).collect();
+            assert_eq!(properties, written, "the fields of {:?}", path);
+
+            for (name, field) in fields {
+                same_fields(
+                    field,
+                    &schema["properties"][name.as_str()],
+                    &join(path, name),
+                );
+            }
+        }
+    }
+
+    // The schema's defaults as a config of their own
+    fn defaults_only(schema: &Value) -> Option<Value> {
+        if let Some(default) = schema.get("default") {
+            return Some(default.clone());
+        }
+
+        let properties = schema.get("properties")?.as_object()?;
+        let object: Map<String, Value> = properties
+            .iter()
+            .filter_map(|(name, property)| Some((name.clone(), defaults_only(property)?)))
+            .collect();
+        Some(Value::Object(object))
+    }
+
+    #[test]
+    fn config() {
+        let schema = schema::<crate::Config>();
+
+        let text = ron::to_string(&crate::Config::default()).unwrap();
+        let (_, root) = Parser::document(&text).unwrap();
+        same_fields(&root, &schema, "");
+
+        let dir = std::env::temp_dir().join(format!("kmxpad-schema-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        let defaults = defaults_only(&schema).unwrap();
+        fs::write(&path, serde_json::to_string(&defaults).unwrap()).unwrap();
+
+        let (config, _) = super::super::load_layered::<crate::Config>(&path, &[]).unwrap();
+        assert_eq!(ron::to_string(&config).unwrap(), text);
+    }
+}
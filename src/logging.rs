@@ -0,0 +1,249 @@
+// The logger, set up from the config's logging block once it's read. Until then, and for
+// anything logged while reading it, it logs at info to the console. RUST_LOG when set still
+// picks what's logged over level
+use env_logger::Env;
+use log::{Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Swapped for a new one by configure, as the log crate only takes one logger for good
+static CURRENT: RwLock<Option<env_logger::Logger>> = RwLock::new(None);
+
+// Set from log_binds, a ToggleBindLog bind flips it while running
+static BIND_LOG: AtomicBool = AtomicBool::new(false);
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn filter(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    level: Level,
+    // Starts each line with the time it was logged
+    timestamps: bool,
+    // Also writes the log to this file, without colours. Relative to the working directory
+    file: Option<PathBuf>,
+    // Starts the file over once it's grown past this many KiB, the last one kept beside it with
+    // .1 added to its name. 0 lets it grow
+    rotate_size_kb: u64,
+    // Logs every press and release of a bound key, and of keys that aren't bound. Too much to
+    // leave on, but it shows whether a key that does nothing reaches kmxpad at all
+    log_binds: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            level: Level::Info,
+            timestamps: true,
+            file: None,
+            rotate_size_kb: 1024,
+            log_binds: false,
+        }
+    }
+}
+
+// Logs at info to the console until configure is called
+pub fn init() {
+    let logger = builder(&Config::default(), None).build();
+    log::set_max_level(logger.filter());
+    *CURRENT.write().unwrap() = Some(logger);
+
+    log::set_logger(&LOGGER).expect("the logger is only set once");
+}
+
+// Logs as config says from here on, as far as it can: without the file if it can't be opened
+pub fn configure(config: &Config) -> Result<(), anyhow::Error> {
+    set_bind_log(config.log_binds);
+
+    let file = config
+        .file
+        .as_deref()
+        .map(|path| RotatingFile::open(path, config.rotate_size_kb * 1024))
+        .transpose();
+
+    let (file, result) = match file {
+        Ok(file) => (file.map(|file| Arc::new(Mutex::new(file))), Ok(())),
+        Err(error) => (None, Err(error)),
+    };
+
+    let logger = builder(config, file).build();
+    log::set_max_level(logger.filter());
+    *CURRENT.write().unwrap() = Some(logger);
+
+    result
+}
+
+pub fn bind_log() -> bool {
+    BIND_LOG.load(Ordering::Relaxed)
+}
+
+pub fn set_bind_log(on: bool) {
+    BIND_LOG.store(on, Ordering::Relaxed);
+}
+
+// Lines as env_logger's own, each also written to file if there is one
+fn builder(config: &Config, file: Option<Arc<Mutex<RotatingFile>>>) -> env_logger::Builder {
+    let env = Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, config.level.filter());
+    let mut builder = env_logger::Builder::from_env(env);
+    let timestamps = config.timestamps;
+
+    builder.format(move |buf, record| {
+        let timestamp = match timestamps {
+            true => format!("{} ", buf.timestamp()),
+            false => String::new(),
+        };
+        let module = record.module_path().unwrap_or_default();
+
+        if let Some(file) = &file {
+            let line = format!(
+                "[{}{:<5} {}] {}\n",
+                timestamp,
+                record.level(),
+                module,
+                record.args()
+            );
+
+            // There's nowhere to log that it couldn't be logged
+            let _ = file.lock().unwrap().write_all(line.as_bytes());
+        }
+
+        let level = buf.default_styled_level(record.level());
+        writeln!(
+            buf,
+            "[{}{:<5} {}] {}",
+            timestamp,
+            level,
+            module,
+            record.args()
+        )
+    });
+
+    builder
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match &*CURRENT.read().unwrap() {
+            Some(logger) => logger.enabled(metadata),
+            None => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = &*CURRENT.read().unwrap() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = &*CURRENT.read().unwrap() {
+            logger.flush();
+        }
+    }
+}
+
+// The log file, moved aside once it's limit bytes long. Each line is written whole, so none are
+// split between the files
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    limit: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, limit: u64) -> Result<Self, anyhow::Error> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(path);
+        let file = match file {
+            Ok(file) => file,
+            Err(error) => anyhow::bail!("could not open \"{}\": {}", path.to_string_lossy(), error),
+        };
+
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            written: file.metadata()?.len(),
+            file,
+            limit,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut old = self.path.as_os_str().to_owned();
+        old.push(".1");
+
+        fs::rename(&self.path, old)?;
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.limit > 0 && self.written > 0 && self.written + buf.len() as u64 > self.limit {
+            self.rotate()?;
+        }
+
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_between_lines() {
+        let dir = std::env::temp_dir().join(format!("kmxpad-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kmxpad.log");
+        let _ = fs::remove_file(&path);
+
+        let mut file = RotatingFile::open(&path, 10).unwrap();
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        file.write_all(b"3\n").unwrap();
+
+        let rotated = fs::read_to_string(dir.join("kmxpad.log.1")).unwrap();
+        assert_eq!(rotated, "first\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n3\n");
+    }
+}
@@ -1,71 +1,538 @@
 #[macro_use]
 extern crate log;
 
+mod capture;
 mod event_dispatcher;
 mod event_handler;
+mod foreground;
+mod loader;
+mod logging;
+mod shutdown;
 mod types;
 
 use event_dispatcher::EventDispatcher;
 use event_handler::EventHandler;
+use types::Event;
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 
-use std::fs::File;
-use std::path::Path;
-use std::sync::mpsc;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
 use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Optional cargo features and whether they were compiled into this binary
+const FEATURES: &[(&str, bool)] = &[
+    ("audio", cfg!(feature = "audio")),
+    ("dsu", cfg!(feature = "dsu")),
+    ("recording", cfg!(feature = "recording")),
+    ("trace", cfg!(feature = "trace")),
+];
+
+fn version() -> &'static str {
+    // clap wants a &'static str, made the first time it's asked for
+    static VERSION: OnceLock<String> = OnceLock::new();
+
+    VERSION.get_or_init(|| {
+        let features: Vec<String> = FEATURES
+            .iter()
+            .map(|&(name, enabled)| format!("{}{}", if enabled { '+' } else { '-' }, name))
+            .collect();
+
+        format!("{} ({})", env!("CARGO_PKG_VERSION"), features.join(" "))
+    })
+}
 
 #[derive(Parser, Debug)]
-#[clap(version = "0.1.2", author = "Joe K. <joe.kaushal@gmail.com>")]
+#[clap(version = version(), author = "Joe K. <joe.kaushal@gmail.com>")]
 struct Opts {
-    #[clap(short, long, default_value = "config.ron")]
-    config: String,
+    /// RON, or TOML or JSON with a .toml or .json extension [default: the first config.toml,
+    /// config.json or config.ron in the current directory, the executable's, then
+    /// %APPDATA%\kmxpad]
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+
+    /// Set a number, bool or duration in the config without editing it, by its path (the
+    /// event_handler. can be left out). Can be given more than once, and goes on top of
+    /// KMXPAD_ environment variables, e.g. KMXPAD_SENSITIVITY=6.5
+    #[clap(long = "set", value_name = "PATH=VALUE")]
+    set: Vec<String>,
+
+    /// Read configs with settings this kmxpad doesn't know (from a newer version, or misspelt),
+    /// warning about each instead of refusing the config, as strict: false in a file does
+    #[clap(long)]
+    lenient: bool,
+
+    /// Append every report sent to the controller to this CSV file
+    #[clap(long)]
+    record: Option<PathBuf>,
+
+    /// Send a --record file to the controller instead of reading input
+    #[clap(long)]
+    replay: Option<PathBuf>,
+
+    /// How many times faster than recorded to replay
+    #[cfg(feature = "recording")]
+    #[clap(long, default_value = "1.0")]
+    replay_speed: f64,
+
+    /// Print events per second, the average loop time and stick magnitude percentiles from a
+    /// trace_file, then exit
+    #[clap(long, value_name = "FILE")]
+    trace_summary: Option<PathBuf>,
+
+    /// Write a config with every option at its default and example binds to --config, then exit
+    #[clap(long)]
+    generate_config: bool,
+
+    /// Print the presets a config can start from with preset: "name", then exit
+    #[clap(long)]
+    list_presets: bool,
+
+    /// Print a JSON Schema of the config, for editors to check and complete JSON and TOML configs
+    /// with, then exit
+    #[clap(long)]
+    schema: bool,
+
+    /// Let --generate-config overwrite an existing file
+    #[clap(long)]
+    force: bool,
+
+    /// Rewrite --config (or the config found) in the current config format, keeping the old
+    /// file with .bak added, then exit
+    #[clap(long)]
+    upgrade_config: bool,
+
+    /// Print the bind for each key, mouse button or wheel notch pressed instead of running a
+    /// controller, until Escape
+    #[clap(long)]
+    capture: bool,
+
+    /// Ask for an action for each bind --capture prints and add it to this config
+    #[clap(long, requires = "capture")]
+    append: Option<PathBuf>,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 struct Config {
+    // The config format the file is written for, older ones are upgraded as they're read
+    version: u32,
+
+    // false reads the file even when it has settings this kmxpad doesn't know, warning about each
+    // rather than refusing it, as --lenient does for every file
+    strict: bool,
+
+    // Another config to load first, this one's settings going on top of it. Relative to this file
+    #[serde(deserialize_with = "loader::extends")]
+    extends: Option<PathBuf>,
+
+    // Applies event_handler and logging again whenever the file (or one it extends) is saved
+    watch_config: bool,
+
+    logging: logging::Config,
+
     event_dispatcher: event_dispatcher::Config,
     event_handler: event_handler::Config,
 }
 
-fn load_config<P: AsRef<Path>>(path: P) -> Config {
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: loader::VERSION,
+            strict: true,
+            extends: None,
+            watch_config: true,
+            logging: logging::Config::default(),
+            event_dispatcher: event_dispatcher::Config::default(),
+            event_handler: event_handler::Config::default(),
+        }
+    }
+}
+
+// The config at path with overrides on top, and the files it was read from, the base first
+fn read_config(
+    path: &Path,
+    overrides: &[loader::Override],
+) -> Result<(Config, Vec<PathBuf>), anyhow::Error> {
+    loader::load_layered(path, overrides)
+}
+
+// Also gives the files it was read from, the base first
+fn load_config<P: AsRef<Path>>(path: P, overrides: &[loader::Override]) -> (Config, Vec<PathBuf>) {
     let path_str = path.as_ref().to_string_lossy();
 
-    match File::open(&path).map(ron::de::from_reader) {
-        Ok(Ok(config)) => {
+    match read_config(path.as_ref(), overrides) {
+        Ok((config, files)) => {
             info!("loaded config from \"{}\"", path_str);
-            return config;
+            for file in files.iter().rev().skip(1) {
+                info!("extending \"{}\"", file.to_string_lossy());
+            }
+
+            return (config, files);
         }
 
-        Err(error) => error!("could open config \"{}\": {}", path_str, error),
-        Ok(Err(error)) => error!("could not process config \"{}\": {}", path_str, error),
+        Err(error) => error!("could not load config: {}", error),
     }
 
     error!("using default config");
-    Config::default()
+    (Config::default(), vec![path.as_ref().to_owned()])
+}
+
+// Config::default() as RON, with MouseMove bound as it has to be (and invert_mouse_y for it) and
+// the example binds commented out. It's read back before being written so it can't drift from
+// Config
+fn generate_config(path: &Path, force: bool) -> Result<(), anyhow::Error> {
+    let path_str = path.to_string_lossy();
+    if path.extension() != Some(OsStr::new("ron")) {
+        anyhow::bail!("only RON can be generated, give --config a .ron path");
+    }
+
+    if path.exists() && !force {
+        anyhow::bail!("\"{}\" exists, add --force to overwrite it", path_str);
+    }
+
+    let pretty = ron::ser::PrettyConfig::new().indentor("    ".to_owned());
+    let text = ron::ser::to_string_pretty(&Config::default(), pretty)?;
+
+    // The first is the event handler's own, the targets have none by default
+    let empty = "        binds: {},\n";
+    if !text.contains(empty) {
+        anyhow::bail!("no binds in the default config");
+    }
+
+    // Mouse y grows downwards, the MouseMove bind needs it flipped to push the stick up
+    let invert = "        invert_mouse_y: false,\n";
+    if !text.contains(invert) {
+        anyhow::bail!("no invert_mouse_y in the default config");
+    }
+
+    let mut examples = String::new();
+    for (bind, action) in event_handler::example_binds() {
+        // Compact RON has no spaces, and there are no strings for this to reach into
+        let example = format!("{}:{}", ron::to_string(&bind)?, ron::to_string(&action)?);
+        let example = example.replace(',', ", ").replace(':', ": ");
+        examples += &format!("            // {},\n", example);
+    }
+
+    let binds = |examples: &str| {
+        "        binds: {\n".to_owned()
+            + "            MouseMove: AnalogRight(1.0, 1.0, None),\n\n"
+            + "            // One of each kind of bind and action, remove the // to use one\n"
+            + examples
+            + "        },\n"
+    };
+
+    let header = "// Every option at its default, kmxpad's own config.ron says what each does\n";
+    let text = text.replacen(invert, "        invert_mouse_y: true,\n", 1);
+    let text = header.to_owned() + &text.replacen(empty, &binds(&examples), 1);
+
+    // With the examples in use as well, so they can't go stale either
+    let uncommented = text.replacen(&examples, &examples.replace("// ", ""), 1);
+
+    for text in [&text, &uncommented] {
+        if let Err(error) = loader::from_str::<Config>(text) {
+            anyhow::bail!("the generated config doesn't read back: {}", error);
+        }
+    }
+
+    fs::write(path, text)?;
+    Ok(())
+}
+
+// %APPDATA%\kmxpad, where a config is looked for last and the default one is written
+fn appdata_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(|appdata| Path::new(&appdata).join("kmxpad"))
+}
+
+// The first config.toml, config.json or config.ron in the current directory, the executable's
+// (which differs when started from a shortcut or Steam), then appdata_dir
+fn find_config() -> Option<PathBuf> {
+    let exe = env::current_exe().ok();
+    let exe_dir = exe.as_deref().and_then(Path::parent).map(Path::to_owned);
+    let dirs = [env::current_dir().ok(), exe_dir, appdata_dir()];
+
+    for dir in dirs.iter().flatten() {
+        for name in ["config.toml", "config.json", "config.ron"] {
+            let path = dir.join(name);
+            if path.exists() {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+// Asks whether to write the default config to appdata_dir when there is none, giving its path
+// if it was
+fn offer_default_config() -> Option<PathBuf> {
+    let path = appdata_dir()?.join("config.ron");
+    let path_str = path.to_string_lossy();
+
+    warn!("no config found");
+    print!("write the default config to \"{}\"? [y/N] ", path_str);
+    io::stdout().flush().ok()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return None;
+    }
+
+    let written = fs::create_dir_all(appdata_dir()?)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| generate_config(&path, false));
+
+    match written {
+        Ok(()) => {
+            info!("wrote the default config to \"{}\"", path_str);
+            Some(path)
+        }
+
+        Err(error) => {
+            error!("could not write the default config: {}", error);
+            None
+        }
+    }
+}
+
+// How often watch_config looks at the file's modified time
+const WATCH_PERIOD: Duration = Duration::from_millis(500);
+
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+// Sends the event handler part of the config at path (with overrides) each time it or one of
+// the files it extends changes, until shutting down. A file that doesn't read is logged and left
+// for the next save
+fn watch_config(
+    path: PathBuf,
+    mut files: Vec<PathBuf>,
+    overrides: Vec<loader::Override>,
+    tx: mpsc::Sender<Event>,
+) {
+    let path_str = path.to_string_lossy().into_owned();
+    let mut last_modified = modified(&files);
+
+    while !shutdown::requested() {
+        thread::sleep(WATCH_PERIOD);
+
+        let modified = modified(&files);
+        if modified.contains(&None) || modified == last_modified {
+            continue;
+        }
+
+        last_modified = modified;
+
+        let config = match read_config(&path, &overrides) {
+            Ok((config, now_files)) => {
+                // extends may have changed
+                if now_files != files {
+                    files = now_files;
+                    last_modified = self::modified(&files);
+                }
+
+                config
+            }
+
+            Err(error) => {
+                error!("could not reload config: {}", error);
+                continue;
+            }
+        };
+
+        info!("config \"{}\" changed, reloading", path_str);
+        if let Err(error) = logging::configure(&config.logging) {
+            error!("{}", error);
+        }
+
+        let event_handler_config = Box::new(config.event_handler);
+        if tx.send(Event::Reload(event_handler_config)).is_err() {
+            return;
+        }
+    }
 }
 
 fn main() {
-    env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
-    );
+    logging::init();
 
-    info!("rlm2c 0.1.2 - github.com/bozbez/rlm2c");
+    info!("kmxpad {}", version());
 
     let opts: Opts = Opts::parse();
+    loader::set_lenient(opts.lenient);
+
+    let recording = ("recording", cfg!(feature = "recording"));
+    for (option, given, (feature, built)) in [
+        ("--record", opts.record.is_some(), recording),
+        ("--replay", opts.replay.is_some(), recording),
+        (
+            "--trace-summary",
+            opts.trace_summary.is_some(),
+            ("trace", cfg!(feature = "trace")),
+        ),
+    ] {
+        if given && !built {
+            error!(
+                "{} was given, but kmxpad was built without the \"{}\" feature",
+                option, feature
+            );
+            return;
+        }
+    }
+
+    if opts.list_presets {
+        for (name, description) in event_handler::PRESETS {
+            println!("{:8} {}", name, description);
+        }
+
+        return;
+    }
+
+    #[cfg(feature = "trace")]
+    if let Some(path) = &opts.trace_summary {
+        if let Err(error) = event_handler::trace_summary(path) {
+            error!("could not read \"{}\": {}", path.to_string_lossy(), error);
+        }
+
+        return;
+    }
+
+    if opts.schema {
+        match loader::json_schema::<Config>() {
+            Ok(schema) => println!("{}", schema),
+            Err(error) => error!("could not make the schema: {}", error),
+        }
+
+        return;
+    }
+
+    if opts.generate_config {
+        let path = opts.config.unwrap_or_else(|| PathBuf::from("config.ron"));
+        let path_str = path.to_string_lossy();
+
+        match generate_config(&path, opts.force) {
+            Ok(()) => info!("wrote the default config to \"{}\"", path_str),
+            Err(error) => error!("could not generate config: {}", error),
+        }
+
+        return;
+    }
+
+    if opts.upgrade_config {
+        match opts.config.or_else(find_config) {
+            Some(path) => {
+                if let Err(error) = loader::upgrade_file::<Config>(&path) {
+                    error!("could not upgrade the config: {}", error);
+                }
+            }
+
+            None => error!("there is no config to upgrade, give one with --config"),
+        }
+
+        return;
+    }
+
+    let config_path = match opts.config {
+        Some(path) => Some(path),
+        None => find_config().or_else(offer_default_config),
+    };
+
+    let mut overrides = loader::Override::from_env();
+    for arg in &opts.set {
+        match loader::Override::parse(arg) {
+            Ok(o) => overrides.push(o),
+            Err(error) => {
+                error!("{}", error);
+                return;
+            }
+        }
+    }
+
+    let (config, config_files) = match &config_path {
+        Some(path) => load_config(path, &overrides),
+        None => {
+            warn!("using the default config");
+
+            match loader::apply_overrides(Config::default(), &overrides) {
+                Ok(config) => (config, Vec::new()),
+                Err(error) => {
+                    error!("could not apply overrides: {}", error);
+                    (Config::default(), Vec::new())
+                }
+            }
+        }
+    };
+    if let Err(error) = logging::configure(&config.logging) {
+        error!("{}", error);
+    }
 
     let Config {
+        watch_config: watch,
         event_dispatcher: event_dispatcher_config,
         event_handler: event_handler_config,
-    } = load_config(opts.config);
+        ..
+    } = config;
 
-    println!("{:?}", event_handler_config);
     let (tx, rx) = mpsc::channel();
+    let (inject_tx, inject_rx) = mpsc::channel();
+    let rumble_tx = tx.clone();
+
+    shutdown::install(tx.clone());
+
+    #[cfg(feature = "recording")]
+    if let Some(path) = opts.replay {
+        if let Err(error) = event_handler::replay(&path, opts.replay_speed, &event_handler_config) {
+            error!("could not replay \"{}\": {}", path.to_string_lossy(), error);
+        }
+
+        shutdown::finish();
+        return;
+    }
+
+    if opts.capture {
+        let append = opts.append;
+        let capture_thread = thread::spawn(move || capture::run(rx, append.as_deref()));
 
-    let event_handler_thread = thread::spawn(|| {
-        match EventHandler::new(rx, event_handler_config) {
+        match EventDispatcher::new(tx, inject_rx, event_dispatcher_config) {
+            Some(mut event_dispatcher) => {
+                event_dispatcher.capture_all();
+                event_dispatcher.run();
+            }
+
+            None => error!("could not create event dispatcher"),
+        };
+
+        shutdown::request();
+        capture_thread.join().unwrap();
+        shutdown::finish();
+        return;
+    }
+
+    if let (true, Some(path)) = (watch, config_path) {
+        let tx = tx.clone();
+        thread::spawn(move || watch_config(path, config_files, overrides, tx));
+    }
+
+    let foreground_tx = tx.clone();
+    thread::spawn(move || foreground::watch(foreground_tx));
+
+    let record = opts.record;
+
+    // Dropping the event handler zeroes and removes the controllers
+    let event_handler_thread = thread::spawn(move || {
+        let record = record.as_deref();
+
+        match EventHandler::new(rx, rumble_tx, inject_tx, event_handler_config, record) {
             Ok(mut event_handler) => match event_handler.run() {
                 Ok(()) => {}
                 Err(error) => error!("could not run event handler: {}", error),
@@ -73,12 +540,159 @@ fn main() {
 
             Err(error) => error!("could not create event handler: {}", error),
         };
+
+        shutdown::request();
     });
 
-    match EventDispatcher::new(tx, event_dispatcher_config) {
+    match EventDispatcher::new(tx, inject_rx, event_dispatcher_config) {
         Some(mut event_dispatcher) => event_dispatcher.run(),
         None => error!("could not create event dispatcher"),
     };
 
+    shutdown::request();
     event_handler_thread.join().unwrap();
+    shutdown::finish();
+
+    info!("exited cleanly");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own for each test, as they run at the same time
+    fn dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("kmxpad-main-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Compared as RON, as Config has no PartialEq
+    fn ron(config: &Config) -> String {
+        ron::to_string(config).unwrap()
+    }
+
+    #[test]
+    fn generated_config_reads_back() {
+        let path = dir("generate").join("config.ron");
+        let _ = fs::remove_file(&path);
+        generate_config(&path, false).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let (config, _) = read_config(&path, &[]).unwrap();
+        assert_ne!(ron(&config), ron(&Config::default()));
+
+        // Every option but the one bind added and invert_mouse_y is the default
+        let bind = "            MouseMove: AnalogRight(1.0, 1.0, None),\n";
+        let invert = "        invert_mouse_y: true,\n";
+        assert!(text.contains(bind) && text.contains(invert));
+        let text = text.replacen(bind, "", 1);
+        let text = text.replacen(invert, "        invert_mouse_y: false,\n", 1);
+        fs::write(&path, text).unwrap();
+        let (config, _) = read_config(&path, &[]).unwrap();
+        assert_eq!(ron(&config), ron(&Config::default()));
+    }
+
+    #[test]
+    fn generate_config_refuses() {
+        let dir = dir("generate-refuses");
+        let error = generate_config(&dir.join("config.toml"), false).unwrap_err();
+        assert!(error.to_string().contains("only RON can be generated"));
+
+        let path = dir.join("config.ron");
+        fs::write(&path, "kept").unwrap();
+        let error = generate_config(&path, false).unwrap_err();
+        assert!(error.to_string().contains("add --force to overwrite it"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "kept");
+
+        generate_config(&path, true).unwrap();
+        assert!(read_config(&path, &[]).is_ok());
+    }
+
+    // The same settings written as RON, with durations and binds in each format's own way
+    const RON: &str = r#"(
+        logging: (level: Debug),
+        event_handler: (
+            sensitivity: 25.5,
+            sample_window: "10ms",
+            mouse_mode: FlickStick,
+            binds: {
+                Keyboard(W): AnalogLeft(0.0, 1.0, None),
+                Mouse(Left): Button(A),
+            },
+        ),
+    )"#;
+
+    // Read back through RON's own Value, whose maps are sorted where the HashMaps aren't
+    fn read_as_ron(path: &Path) -> ron::Value {
+        let (config, _) = read_config(path, &[]).unwrap();
+        ron::from_str(&ron(&config)).unwrap()
+    }
+
+    #[test]
+    fn toml_reads_as_ron() {
+        let dir = dir("toml");
+        fs::write(dir.join("config.ron"), RON).unwrap();
+        fs::write(
+            dir.join("config.toml"),
+            r#"
+            [logging]
+            level = "Debug"
+
+            [event_handler]
+            sensitivity = 25.5
+            sample_window = 10
+            mouse_mode = "FlickStick"
+
+            [[event_handler.binds]]
+            key = "Keyboard(W)"
+            action = "AnalogLeft(0.0, 1.0, None)"
+
+            [[event_handler.binds]]
+            key = { Mouse = "Left" }
+            action = { Button = "A" }
+            "#,
+        )
+        .unwrap();
+
+        let expected = read_as_ron(&dir.join("config.ron"));
+        assert_eq!(read_as_ron(&dir.join("config.toml")), expected);
+        assert_ne!(expected, ron::from_str(&ron(&Config::default())).unwrap());
+    }
+
+    #[test]
+    fn json_reads_as_ron() {
+        let dir = dir("json");
+        fs::write(dir.join("config.ron"), RON).unwrap();
+        fs::write(
+            dir.join("config.json"),
+            r#"{
+                "logging": { "level": "Debug" },
+                "event_handler": {
+                    "sensitivity": 25.5,
+                    "sample_window": "10ms",
+                    "mouse_mode": "FlickStick",
+                    "binds": [
+                        { "bind": "Keyboard(W)", "action": "AnalogLeft(0.0, 1.0, None)" },
+                        { "bind": { "Mouse": "Left" }, "action": { "Button": "A" } }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let expected = read_as_ron(&dir.join("config.ron"));
+        assert_eq!(read_as_ron(&dir.join("config.json")), expected);
+    }
+
+    // Every default written out as JSON reads back the same
+    #[test]
+    fn json_round_trip() {
+        let path = dir("json-round-trip").join("config.json");
+        let text = serde_json::to_string_pretty(&Config::default()).unwrap();
+        fs::write(&path, text).unwrap();
+
+        let (config, _) = read_config(&path, &[]).unwrap();
+        assert_eq!(ron(&config), ron(&Config::default()));
+    }
 }
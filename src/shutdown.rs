@@ -0,0 +1,67 @@
+use crate::types::Event;
+
+use winapi::shared::minwindef::{BOOL, DWORD, TRUE};
+use winapi::um::consoleapi::SetConsoleCtrlHandler;
+use winapi::um::wincon::CTRL_CLOSE_EVENT;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Set once anything asks to quit, the dispatcher stops reading input when it sees it
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+// Set once the event handler has let go of the controllers
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+// Tells the event handler to quit, which it does by dropping out of its loop
+static SENDER: Mutex<Option<mpsc::Sender<Event>>> = Mutex::new(None);
+
+// Windows ends the process soon after the console window closes whatever the handler does,
+// this is how long it gets to finish first
+const CLOSE_GRACE: Duration = Duration::from_secs(2);
+
+unsafe extern "system" fn console_handler(ctrl_type: DWORD) -> BOOL {
+    info!("console event {}, exiting", ctrl_type);
+    request();
+
+    // Returning lets Windows end the process straight away for anything but Ctrl+C and
+    // Ctrl+Break, so wait for the controllers to be taken away
+    if ctrl_type >= CTRL_CLOSE_EVENT {
+        let start = Instant::now();
+        while !FINISHED.load(Ordering::Relaxed) && start.elapsed() < CLOSE_GRACE {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    TRUE
+}
+
+// Ctrl+C, Ctrl+Break and closing the console all shut down cleanly from here on
+pub fn install(tx: mpsc::Sender<Event>) {
+    *SENDER.lock().unwrap() = Some(tx);
+
+    if unsafe { SetConsoleCtrlHandler(Some(console_handler), TRUE) } == 0 {
+        warn!("could not install the console handler, Ctrl+C will not shut down cleanly");
+    }
+}
+
+pub fn request() {
+    if REQUESTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    if let Ok(mut sender) = SENDER.lock() {
+        if let Some(tx) = sender.take() {
+            let _ = tx.send(Event::Exit);
+        }
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+pub fn finish() {
+    FINISHED.store(true, Ordering::Relaxed);
+}
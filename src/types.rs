@@ -1,3 +1,5 @@
+use crate::event_handler;
+
 use interception as ic;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -27,6 +29,113 @@ impl From<ic::KeyState> for KeyState {
     }
 }
 
+// A scancode and whether it came with the E0 prefix, which is all that tells e.g. the arrow
+// keys apart from the numpad or right control from left control
+#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: ic::ScanCode,
+    pub e0: bool,
+}
+
+impl Key {
+    // Whether another key sends the same scancode with the E0 flag flipped
+    pub fn has_e0_twin(code: ic::ScanCode) -> bool {
+        use ic::ScanCode::*;
+
+        matches!(
+            code,
+            Enter
+                | LeftControl
+                | Slash
+                | NumpadMultiply
+                | LeftAlt
+                | Numpad7
+                | Numpad8
+                | Numpad9
+                | Numpad4
+                | Numpad6
+                | Numpad1
+                | Numpad2
+                | Numpad3
+                | Numpad0
+                | NumpadPeriod
+        )
+    }
+
+    pub fn is_windows_key(&self) -> bool {
+        *self == Modifier::LeftWin.key() || *self == Modifier::RightWin.key()
+    }
+
+    // The name of the key that sends code with E0, where the scancode's own name is the other
+    // key's (or nothing like it)
+    pub fn e0_name(code: ic::ScanCode) -> Option<&'static str> {
+        use ic::ScanCode::*;
+
+        let name = match code {
+            Enter => "NumpadEnter",
+            LeftControl => "RightControl",
+            Slash => "NumpadDivide",
+            NumpadMultiply => "PrintScreen",
+            LeftAlt => "RightAlt",
+            Numpad7 => "Home",
+            Numpad8 => "Up",
+            Numpad9 => "PageUp",
+            Numpad4 => "Left",
+            Numpad6 => "Right",
+            Numpad1 => "End",
+            Numpad2 => "Down",
+            Numpad3 => "PageDown",
+            Numpad0 => "Insert",
+            NumpadPeriod => "Delete",
+            Oem2 => "LeftWindows",
+            Oem3 => "RightWindows",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.e0, Key::e0_name(self.code)) {
+            (true, Some(name)) => write!(f, "{}", name),
+            (true, None) => write!(f, "{:?} (E0)", self.code),
+            (false, _) => write!(f, "{:?}", self.code),
+        }
+    }
+}
+
+// Names for the modifier keys, the right Ctrl and Alt only differ from the left by E0
+#[derive(Serialize, Deserialize, Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    RightAlt,
+    LeftWin,
+    RightWin,
+}
+
+impl Modifier {
+    pub fn key(self) -> Key {
+        let (code, e0) = match self {
+            Modifier::LeftShift => (ic::ScanCode::LeftShift, false),
+            Modifier::RightShift => (ic::ScanCode::RightShift, false),
+            Modifier::LeftCtrl => (ic::ScanCode::LeftControl, false),
+            Modifier::RightCtrl => (ic::ScanCode::LeftControl, true),
+            Modifier::LeftAlt => (ic::ScanCode::LeftAlt, false),
+            Modifier::RightAlt => (ic::ScanCode::LeftAlt, true),
+            Modifier::LeftWin => (ic::ScanCode::Oem2, true),
+            Modifier::RightWin => (ic::ScanCode::Oem3, true),
+        };
+
+        Key { code, e0 }
+    }
+}
+
 #[derive(Serialize, Deserialize, Hash, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MouseButton {
     Left,
@@ -48,6 +157,36 @@ impl fmt::Display for MouseButton {
     }
 }
 
+#[derive(Serialize, Deserialize, Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WheelDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl WheelDirection {
+    pub fn opposite(self) -> Self {
+        match self {
+            WheelDirection::Up => WheelDirection::Down,
+            WheelDirection::Down => WheelDirection::Up,
+            WheelDirection::Left => WheelDirection::Right,
+            WheelDirection::Right => WheelDirection::Left,
+        }
+    }
+}
+
+impl fmt::Display for WheelDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WheelDirection::Up => write!(f, "Up"),
+            WheelDirection::Down => write!(f, "Down"),
+            WheelDirection::Left => write!(f, "Left"),
+            WheelDirection::Right => write!(f, "Right"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Hash, Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum ControllerButton {
@@ -100,21 +239,62 @@ impl fmt::Display for ControllerButton {
     }
 }
 
+// A key press or release the event handler wants sent on to Windows
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keystroke {
+    pub key: Key,
+    pub state: KeyState,
+}
+
+// The window in front, for window_profiles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    // Tells windows apart, a window keeps it when its title changes
+    pub id: usize,
+    // The file name of its executable, e.g. game.exe, empty if it couldn't be found
+    pub exe: String,
+    pub title: String,
+}
+
+#[derive(Debug)]
 pub enum Event {
-    MouseMove(i32, i32),
+    // The mouse it came from and the x and y counts
+    MouseMove(ic::Device, i32, i32),
+    // A position from a tablet or touch screen, 0 to 65535 across the screen
+    MouseMoveAbsolute(i32, i32),
     MouseButton(MouseButton, KeyState),
-    Keyboard(ic::ScanCode, KeyState),
+    // The direction after wheel_invert and whether it was inverted
+    MouseWheel(WheelDirection, bool),
+    Keyboard(Key, KeyState),
+    // Motor speeds the game sent to the virtual controller
+    Rumble { large: u8, small: u8 },
+    // The toggle key let go of input, and took it back
     Reset,
+    Resume,
+    // Ctrl+C, the console closing or the other thread going away
+    Exit,
+    // The config file changed and read fine
+    Reload(Box<event_handler::Config>),
+    // Another window came to the front, or its title changed
+    Foreground(Window),
 }
 
 impl fmt::Display for Event {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Event::MouseMove(x, y) => write!(f, "MouseMove({}, {})", x, y),
+            Event::MouseMove(device, x, y) => write!(f, "MouseMove({}, {}, {})", device, x, y),
+            Event::MouseMoveAbsolute(x, y) => write!(f, "MouseMoveAbsolute({}, {})", x, y),
             Event::MouseButton(button, state) => write!(f, "MouseButton({}, {:?})", button, state),
-            Event::Keyboard(scan_code, state) => write!(f, "Keyboard({:?}, {:?})", scan_code, state),
+            Event::MouseWheel(direction, inverted) => {
+                write!(f, "MouseWheel({}, {})", direction, inverted)
+            }
+            Event::Keyboard(key, state) => write!(f, "Keyboard({}, {:?})", key, state),
+            Event::Rumble { large, small } => write!(f, "Rumble({}, {})", large, small),
             Event::Reset => write!(f, "Reset"),
+            Event::Resume => write!(f, "Resume"),
+            Event::Exit => write!(f, "Exit"),
+            Event::Reload(_) => write!(f, "Reload"),
+            Event::Foreground(window) => write!(f, "Foreground({})", window.exe),
         }
     }
 }
\ No newline at end of file